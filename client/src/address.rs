@@ -0,0 +1,180 @@
+//! Resolution of targets, which can be IP addresses or hostnames.
+//!
+//! Hostnames are resolved by the system, which orders their addresses by preference. Of the addresses of the wanted
+//! family, a strategy picks the first one, the next one in turn, or the first IPv6 address.
+//! Link-local IPv6 targets need the interface to send on as their zone, like `fe80::1%eth0`.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, ValueEnum};
+use rand::Rng;
+
+/// Which of the addresses of a hostname is used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Strategy {
+    /// The address the system prefers.
+    #[default]
+    First,
+    /// The next address every time a hostname is resolved, starting at a random one,
+    /// so that clients given the same hostname spread over its addresses.
+    RoundRobin,
+    /// The first IPv6 address, or the first address if there is none.
+    PreferV6,
+}
+
+/// How hostname targets are resolved.
+#[derive(Clone, Copy, Debug, Default, Args)]
+pub struct Resolution {
+    /// Only use IPv4 addresses of hostname targets.
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    pub ipv4: bool,
+    /// Only use IPv6 addresses of hostname targets.
+    #[arg(short = '6', long)]
+    pub ipv6: bool,
+    /// Which of the addresses of a hostname target is used.
+    #[arg(long, value_name = "STRATEGY", default_value = "first")]
+    pub resolve: Strategy,
+}
+
+impl Resolution {
+    fn allows(self, address: IpAddr) -> bool {
+        match address {
+            IpAddr::V4(_) => !self.ipv6,
+            IpAddr::V6(_) => !self.ipv4,
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.ipv4, self.ipv6) {
+            (true, _) => f.write_str("IPv4"),
+            (_, true) => f.write_str("IPv6"),
+            _ => f.write_str("IP"),
+        }
+    }
+}
+
+/// Resolves a target to the socket address that pings are sent to. Its port is unused.
+pub fn resolve(target: &str, resolution: Resolution) -> Result<SocketAddr> {
+    // IPv6 addresses may be given in brackets, like in URLs.
+    let host = target
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(target);
+    let addresses: Vec<_> = (host, 0)
+        .to_socket_addrs()
+        .with_context(|| format!("could not resolve {target}"))?
+        .filter(|address| resolution.allows(address.ip()))
+        .collect();
+    let address = match resolution.resolve {
+        Strategy::First => addresses.first(),
+        Strategy::RoundRobin if !addresses.is_empty() => {
+            static NEXT: OnceLock<AtomicUsize> = OnceLock::new();
+            let next = NEXT.get_or_init(|| AtomicUsize::new(rand::thread_rng().gen()));
+            addresses.get(next.fetch_add(1, Ordering::Relaxed) % addresses.len())
+        }
+        Strategy::RoundRobin => None,
+        Strategy::PreferV6 => addresses
+            .iter()
+            .find(|address| address.is_ipv6())
+            .or(addresses.first()),
+    };
+    let address = *address.ok_or_else(|| anyhow!("{target} has no {resolution} address"))?;
+    if let SocketAddr::V6(address) = address {
+        if is_link_local(address.ip().segments()[0]) && address.scope_id() == 0 {
+            bail!(
+                "link-local target {target} needs the interface to send on, like {}%eth0",
+                address.ip()
+            );
+        }
+    }
+    Ok(address)
+}
+
+/// Whether an IPv6 address with the given first segment is in fe80::/10.
+fn is_link_local(first_segment: u16) -> bool {
+    first_segment & 0xffc0 == 0xfe80
+}
+
+/// An IPv6 prefix like `2001:db8:1::/64`.
+#[derive(Clone, Copy, Debug)]
+pub struct Prefix {
+    address: Ipv6Addr,
+    length: u8,
+}
+
+impl Prefix {
+    /// A random address within the prefix.
+    pub fn random_address(&self, rng: &mut impl Rng) -> Ipv6Addr {
+        let mask = u128::MAX
+            .checked_shl(128 - u32::from(self.length))
+            .unwrap_or(0);
+        Ipv6Addr::from(u128::from(self.address) & mask | rng.gen::<u128>() & !mask)
+    }
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.split_once('/')
+            .and_then(|(address, length)| {
+                Some(Prefix {
+                    address: address.parse().ok()?,
+                    length: length.parse().ok().filter(|length| *length <= 128)?,
+                })
+            })
+            .ok_or_else(|| format!("not an IPv6 prefix like 2001:db8::/64: {text}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(text: &str) -> Prefix {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn prefixes_are_parsed() {
+        let parsed = prefix("2001:db8:1::/48");
+        assert_eq!(parsed.address, "2001:db8:1::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(parsed.length, 48);
+        for invalid in ["2001:db8::", "2001:db8::/129", "10.0.0.0/8", "2001:db8::/x"] {
+            assert!(invalid.parse::<Prefix>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn random_addresses_are_within_the_prefix() {
+        let mut rng = rand::thread_rng();
+        let parsed = prefix("2001:db8:1:2::/64");
+        for _ in 0..100 {
+            let segments = parsed.random_address(&mut rng).segments();
+            assert_eq!(segments[..4], [0x2001, 0xdb8, 1, 2]);
+        }
+        // Host bits of the given address don't matter.
+        let segments = prefix("2001:db8:ffff::1/32")
+            .random_address(&mut rng)
+            .segments();
+        assert_eq!(segments[..2], [0x2001, 0xdb8]);
+        let single: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(prefix("2001:db8::1/128").random_address(&mut rng), single);
+    }
+
+    #[test]
+    fn zero_length_prefixes_cover_everything() {
+        let mut rng = rand::thread_rng();
+        let addresses: Vec<_> = (0..10)
+            .map(|_| prefix("::/0").random_address(&mut rng))
+            .collect();
+        assert!(addresses.iter().any(|address| address.segments()[0] != 0));
+    }
+}
@@ -0,0 +1,59 @@
+//! Brightness, contrast and gamma adjustments, which work like FFmpeg's eq filter,
+//! so that images and videos are adjusted the same way.
+
+use image::RgbaImage;
+
+/// Adjustments of the color channels, while alpha is left alone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Adjustments {
+    /// Added to every channel, from -1 to 1.
+    pub brightness: f64,
+    /// Factor by which channels are moved away from the middle gray.
+    pub contrast: f64,
+    /// Gamma correction, where values above 1 brighten dark colors.
+    pub gamma: f64,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Self {
+            brightness: 0.0,
+            contrast: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustments {
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The adjusted value of every channel value.
+    fn table(&self) -> [u8; 256] {
+        std::array::from_fn(|value| {
+            let value = (value as f64 / 255.0 - 0.5) * self.contrast + 0.5 + self.brightness;
+            (value.clamp(0.0, 1.0).powf(1.0 / self.gamma) * 255.0).round() as u8
+        })
+    }
+
+    pub fn apply(&self, image: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+        let table = self.table();
+        for pixel in image.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = table[usize::from(*channel)];
+            }
+        }
+    }
+
+    /// FFmpeg's filter for the same adjustments.
+    pub fn ffmpeg_filter(&self) -> String {
+        format!(
+            "eq=brightness={}:contrast={}:gamma={}",
+            self.brightness, self.contrast, self.gamma
+        )
+    }
+}
@@ -0,0 +1,284 @@
+//! Playback of the source image frame by frame, which supports animated GIFs.
+//!
+//! In delta mode, only the pixels that changed since the previous frame are sent,
+//! with a periodic full refresh that repairs pixels lost on the way or overdrawn by others.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use image::codecs::gif::GifDecoder;
+use image::imageops::{self, FilterType};
+use image::{AnimationDecoder, DynamicImage, ImageFormat, RgbaImage};
+use pingxelflut::format::{color_from_rgba, Color};
+use rand::rngs::ThreadRng;
+use rand::seq::SliceRandom;
+use rayon::iter::ParallelIterator;
+use rayon::slice::ParallelSlice;
+
+use crate::control::Control;
+use crate::priority::Priority;
+use crate::probe::{Prober, Resized};
+use crate::remote;
+
+/// How many prioritized pixels are sent in parallel before the next ones are started.
+const PRIORITY_CHUNK: usize = 4096;
+
+/// One frame of the source, with how long it is shown.
+pub struct Frame {
+    pub image: RgbaImage,
+    pub delay: Duration,
+}
+
+/// Loads the frames of an animated GIF, or the single frame of any other image, from a file or an HTTP(S) URL.
+/// Frames are scaled to fit into the given size if there is one, keeping the aspect ratio, and cropped to the canvas size.
+/// Single frames have no delay, so they are sent over and over as fast as possible.
+pub fn load(
+    path: &Path,
+    scale: Option<(u32, u32)>,
+    filter: FilterType,
+    (width, height): (u32, u32),
+) -> Result<Vec<Frame>> {
+    let data = match remote::url(path) {
+        Some(url) => remote::download(url)?,
+        None => fs::read(path).with_context(|| format!("could not read {}", path.display()))?,
+    };
+    let mut frames = Vec::new();
+    if image::guess_format(&data).ok() == Some(ImageFormat::Gif) {
+        let decoder = GifDecoder::new(Cursor::new(&data))?;
+        for frame in decoder.into_frames() {
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            frames.push(Frame {
+                delay: Duration::from_secs_f64(numerator as f64 / denominator as f64 / 1000.0),
+                image: frame.into_buffer(),
+            });
+        }
+    }
+    if frames.len() <= 1 {
+        frames = vec![Frame {
+            image: image::load_from_memory(&data)?.into_rgba8(),
+            delay: Duration::ZERO,
+        }];
+    }
+    fit(&mut frames, scale, filter, (width, height));
+    Ok(frames)
+}
+
+/// Scales the frames to fit into the given size if there is one, keeping the aspect ratio,
+/// and crops them to the canvas size.
+pub fn fit(
+    frames: &mut [Frame],
+    scale: Option<(u32, u32)>,
+    filter: FilterType,
+    (width, height): (u32, u32),
+) {
+    for frame in frames {
+        if let Some((width, height)) = scale {
+            frame.image = DynamicImage::from(std::mem::take(&mut frame.image))
+                .resize(width, height, filter)
+                .into_rgba8();
+        }
+        let (frame_width, frame_height) = frame.image.dimensions();
+        frame.image = imageops::crop_imm(
+            &frame.image,
+            0,
+            0,
+            frame_width.min(width),
+            frame_height.min(height),
+        )
+        .to_image();
+    }
+}
+
+/// Scales all frames up by an integer factor, so that every pixel becomes a sharp block of factor×factor pixels.
+pub fn upscale(frames: &mut [Frame], factor: u32) {
+    for frame in frames {
+        let (width, height) = frame.image.dimensions();
+        frame.image = imageops::resize(
+            &frame.image,
+            width * factor,
+            height * factor,
+            FilterType::Nearest,
+        );
+    }
+}
+
+/// Crops all frames to the given rectangle, which may be cut off at their edges.
+pub fn crop(frames: &mut [Frame], x: u16, y: u16, width: u16, height: u16) {
+    for frame in frames {
+        frame.image = imageops::crop_imm(
+            &frame.image,
+            x.into(),
+            y.into(),
+            width.into(),
+            height.into(),
+        )
+        .to_image();
+    }
+}
+
+/// How frames are sent.
+#[derive(Clone)]
+pub struct Playback {
+    /// Only send the pixels that changed since the previous frame.
+    pub delta: bool,
+    /// How often all pixels are sent in delta mode.
+    pub full_refresh: Duration,
+    /// Send the pixels of every frame in a random order.
+    pub shuffle: bool,
+    /// Send the most important pixels of every frame first.
+    pub priority: Option<Arc<Priority>>,
+    /// How many times all frames are sent, which is practically forever by default.
+    pub passes: u64,
+    /// Pauses and stops the playback between frames.
+    pub control: Arc<Control>,
+    /// Ends the playback between frames once the canvas size changed.
+    pub prober: Option<Arc<Prober>>,
+}
+
+/// The coordinates of the pixels to send for a frame: all of them, or only those that differ from the previous frame.
+fn pixels_to_send(frame: &RgbaImage, previous: Option<&RgbaImage>) -> Vec<(u16, u16)> {
+    frame
+        .enumerate_pixels()
+        .filter(|&(x, y, pixel)| {
+            previous.map_or(true, |previous| previous.get_pixel(x, y) != pixel)
+        })
+        .map(|(x, y, _)| (x as u16, y as u16))
+        .collect()
+}
+
+/// Where pixels are sent to, from several threads at once.
+pub trait Sink: Sync {
+    fn send(&self, x: u16, y: u16, color: Color);
+
+    /// Sends out the pixels that the current thread held back to pack them with the next ones.
+    fn flush(&self) {}
+}
+
+impl<F: Fn(u16, u16, Color) + Sync> Sink for F {
+    fn send(&self, x: u16, y: u16, color: Color) {
+        self(x, y, color)
+    }
+}
+
+/// A sink that sends pixels with the first function and flushes the current thread with the second.
+pub struct Flushing<S, F>(pub S, pub F);
+
+impl<S: Fn(u16, u16, Color) + Sync, F: Fn() + Sync> Sink for Flushing<S, F> {
+    fn send(&self, x: u16, y: u16, color: Color) {
+        (self.0)(x, y, color)
+    }
+
+    fn flush(&self) {
+        (self.1)()
+    }
+}
+
+/// Sends frames one after another, keeping track of when all pixels were last sent.
+pub struct Player<'a> {
+    playback: &'a Playback,
+    last_full_refresh: Instant,
+    rng: ThreadRng,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(playback: &'a Playback) -> Self {
+        Self {
+            playback,
+            last_full_refresh: Instant::now(),
+            rng: rand::thread_rng(),
+        }
+    }
+
+    /// Sends the pixels of the frame in parallel with the given function, after waiting while the playback is paused.
+    /// In delta mode, only the pixels that differ from the previous frame are sent, unless a full refresh is due.
+    /// Fails once the playback is stopped or the canvas size changed.
+    pub fn show(
+        &mut self,
+        frame: &RgbaImage,
+        previous: Option<&RgbaImage>,
+        send: &impl Sink,
+    ) -> Result<()> {
+        self.playback.control.check()?;
+        if self
+            .playback
+            .prober
+            .as_ref()
+            .is_some_and(|prober| prober.has_changed())
+        {
+            return Err(Resized.into());
+        }
+        let full_refresh = !self.playback.delta
+            || previous.is_none()
+            || self.last_full_refresh.elapsed() >= self.playback.full_refresh;
+        let mut pixels = if full_refresh {
+            self.last_full_refresh = Instant::now();
+            pixels_to_send(frame, None)
+        } else {
+            pixels_to_send(frame, previous)
+        };
+        if self.playback.shuffle {
+            pixels.shuffle(&mut self.rng);
+        }
+        // The parallel workers would otherwise each start on their own part of the pixels, including the unimportant ones.
+        let chunk_size = match &self.playback.priority {
+            Some(priority) => {
+                priority.sort(frame, &mut pixels);
+                PRIORITY_CHUNK
+            }
+            None => pixels.len().max(1),
+        };
+        // Every thread sends one contiguous part of a chunk, so that it doesn't take pixels from the others.
+        let threads = rayon::current_num_threads();
+        for chunk in pixels.chunks(chunk_size) {
+            chunk
+                .par_chunks(chunk.len().div_ceil(threads))
+                .for_each(|part| {
+                    for &(x, y) in part {
+                        send.send(x, y, color_from_rgba(frame.get_pixel(x.into(), y.into()).0));
+                    }
+                    send.flush();
+                });
+        }
+        Ok(())
+    }
+
+    /// How long until the next full refresh is due.
+    pub fn until_full_refresh(&self) -> Duration {
+        self.playback
+            .full_refresh
+            .saturating_sub(self.last_full_refresh.elapsed())
+    }
+}
+
+/// Plays the frames the configured number of times, sending pixels in parallel with the given function.
+pub fn play(frames: &[Frame], playback: &Playback, send: impl Sink) -> Result<()> {
+    let mut player = Player::new(playback);
+    let mut previous: Option<&RgbaImage> = None;
+    for pass in 1..=playback.passes {
+        for (index, frame) in frames.iter().enumerate() {
+            let started = Instant::now();
+            player.show(&frame.image, previous, &send)?;
+            previous = Some(&frame.image);
+            if pass == playback.passes && index == frames.len() - 1 {
+                return Ok(());
+            }
+
+            // A static image doesn’t change until the next full refresh.
+            let delay = if frames.len() == 1 && playback.delta {
+                player.until_full_refresh()
+            } else {
+                frame.delay.saturating_sub(started.elapsed())
+            };
+            if !delay.is_zero() {
+                thread::sleep(delay);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! Asynchronous send engine on tokio, as an alternative to the rayon workers.
+//!
+//! A fixed number of tasks send batches of pixels on non-blocking sockets, and yield to other tasks after
+//! every batch, so that sending can be mixed with other work on the same runtime.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView};
+use pingxelflut::format::{color_from_rgba, Packet};
+use pingxelflut::icmp::{EchoDirection, Icmp};
+use rand::seq::SliceRandom;
+use tokio::io::{unix::AsyncFd, Interest};
+
+use crate::animation::Playback;
+use crate::failure::{backoff, Class, Policy, MAX_RETRIES};
+use crate::pacing::{set_pixel_size, Pacer};
+use crate::stats::Stats;
+
+/// Number of pixels a task sends before yielding.
+const BATCH_SIZE: usize = 1024;
+
+/// What all tasks share.
+struct Work {
+    image: Arc<DynamicImage>,
+    target: SocketAddr,
+    offset: (u16, u16),
+    /// The order in which pixels are sent, as indices in row-major order.
+    order: RwLock<Vec<u32>>,
+    /// Whether the order is shuffled again for every pass over the image.
+    shuffle: bool,
+    batches: usize,
+    /// Number of batches to send in all passes together.
+    total_batches: u64,
+    next_batch: AtomicUsize,
+    pacer: Option<Arc<Pacer>>,
+    stats: Arc<Stats>,
+}
+
+impl Work {
+    /// Takes the next batch of pixels, and shuffles the order first if a new pass over the image begins.
+    /// Tasks still sending the previous pass may see the new order already, which doesn’t matter for a random order.
+    /// Returns `None` once all passes have been sent.
+    fn next_batch(&self) -> Option<Vec<u32>> {
+        let count = self.next_batch.fetch_add(1, Ordering::Relaxed);
+        if count as u64 >= self.total_batches {
+            return None;
+        }
+        let batch = count % self.batches;
+        if self.shuffle && batch == 0 && count > 0 {
+            self.order.write().unwrap().shuffle(&mut rand::thread_rng());
+        }
+        let order = self.order.read().unwrap();
+        let start = batch * BATCH_SIZE;
+        Some(order[start..(start + BATCH_SIZE).min(order.len())].to_vec())
+    }
+}
+
+/// Sends the image the configured number of times with the given number of batches in flight.
+pub async fn run(
+    image: Arc<DynamicImage>,
+    target: SocketAddr,
+    offset: (u16, u16),
+    concurrency: usize,
+    playback: &Playback,
+    pacer: Option<Arc<Pacer>>,
+    stats: Arc<Stats>,
+) -> Result<()> {
+    let pixel_count = image.width() * image.height();
+    if pixel_count == 0 {
+        return Ok(());
+    }
+    let mut order: Vec<u32> = (0..pixel_count).collect();
+    if playback.shuffle {
+        order.shuffle(&mut rand::thread_rng());
+    }
+    let batches = (pixel_count as usize).div_ceil(BATCH_SIZE);
+    let work = Arc::new(Work {
+        image,
+        target,
+        offset,
+        order: RwLock::new(order),
+        shuffle: playback.shuffle,
+        batches,
+        total_batches: (batches as u64).saturating_mul(playback.passes),
+        next_batch: AtomicUsize::new(0),
+        pacer,
+        stats,
+    });
+    let size = set_pixel_size(target.ip());
+    let tasks: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let phase_offset = work.pacer.as_ref().map_or(Duration::ZERO, |pacer| {
+                pacer.phase_offset(size, concurrency)
+            });
+            tokio::spawn(send_batches(work.clone(), phase_offset))
+        })
+        .collect();
+    for task in tasks {
+        task.await??;
+    }
+    Ok(())
+}
+
+/// Takes the next batch of pixels and sends it, until all passes have been sent, starting after the given offset.
+async fn send_batches(work: Arc<Work>, phase_offset: Duration) -> Result<()> {
+    tokio::time::sleep(phase_offset).await;
+    let socket = Icmp::socket_for(work.target)?;
+    socket.set_nonblocking(true)?;
+    let socket = AsyncFd::with_interest(socket, Interest::WRITABLE)?;
+    let mut icmp = Icmp::new(work.target, 0, EchoDirection::Request);
+    let width = work.image.width();
+    let size = set_pixel_size(work.target.ip());
+    while let Some(batch) = work.next_batch() {
+        for index in batch {
+            let (x, y) = (index % width, index / width);
+            let pixel = work.image.get_pixel(x, y);
+            // Pixels moved past the largest coordinate can't be on the canvas.
+            let (Some(x), Some(y)) = (
+                (x as u16).checked_add(work.offset.0),
+                (y as u16).checked_add(work.offset.1),
+            ) else {
+                continue;
+            };
+            icmp.set_payload(
+                Packet::SetPixel {
+                    x,
+                    y,
+                    color: color_from_rgba(pixel.0),
+                }
+                .to_bytes(),
+            );
+            if let Some(pacer) = &work.pacer {
+                let delay = pacer.reserve(size);
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            let mut retry = 0;
+            loop {
+                let Err(error) = socket
+                    .async_io(Interest::WRITABLE, |socket| icmp.send_on(socket))
+                    .await
+                else {
+                    work.stats.sent(size, 1);
+                    break;
+                };
+                match Class::of(&error).policy() {
+                    Policy::Backoff if retry < MAX_RETRIES => {
+                        tokio::time::sleep(backoff(retry)).await;
+                        retry += 1;
+                    }
+                    Policy::Abort => {
+                        work.stats.failed(&error, 1);
+                        return Err(error.into());
+                    }
+                    Policy::Backoff | Policy::Drop => {
+                        work.stats.failed(&error, 1);
+                        break;
+                    }
+                }
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
@@ -0,0 +1,227 @@
+//! Blasting mode, which encodes the packets for every pixel of the image once,
+//! and then sends them over and over with only their sequence number and checksum updated.
+//!
+//! With a source prefix, every packet is sent from a random address of the prefix, with its IPv6 header encoded.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::thread;
+
+use anyhow::{bail, Result};
+use image::{DynamicImage, GenericImageView};
+use pingxelflut::format::{color_from_rgba, Packet};
+use pingxelflut::icmp::{
+    add_pseudo_header_v6, update_sequence_number, EchoDirection, Icmp, ICMP_HEADER_SIZE,
+    IPV4_HEADER_SIZE, IPV6_HEADER_SIZE, NEXT_HEADER_ICMPV6,
+};
+use rand::seq::SliceRandom;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::address::Prefix;
+use crate::animation::Playback;
+use crate::cores;
+use crate::failure;
+use crate::pacing::{packet_size, Pacer};
+use crate::stats::Stats;
+
+const ICMP_PROTOCOL: u8 = 1;
+const TTL: u8 = 64;
+/// Raw sockets of this protocol send packets whose IP header is included, also for IPv6.
+const RAW_PROTOCOL: i32 = 255;
+
+/// An IPv4 header for a packet of the given total size.
+/// The kernel fills in the checksum, source address and packet ID, since they are left zero.
+fn ipv4_header(target: Ipv4Addr, total_size: usize) -> [u8; IPV4_HEADER_SIZE] {
+    let mut header = [0; IPV4_HEADER_SIZE];
+    // version 4, 5 words of header
+    header[0] = 0x45;
+    header[1] = Icmp::DSCP_LOW_PRIORITY as u8;
+    header[2..4].copy_from_slice(&(total_size as u16).to_be_bytes());
+    header[8] = TTL;
+    header[9] = ICMP_PROTOCOL;
+    header[16..20].copy_from_slice(&target.octets());
+    header
+}
+
+/// An IPv6 header for an ICMPv6 packet of the given size.
+fn ipv6_header(source: Ipv6Addr, target: Ipv6Addr, payload_size: usize) -> [u8; IPV6_HEADER_SIZE] {
+    let traffic_class = Icmp::DSCP_LOW_PRIORITY as u8;
+    let mut header = [0; IPV6_HEADER_SIZE];
+    // version 6, then the traffic class and a flow label of 0
+    header[0] = 0x60 | traffic_class >> 4;
+    header[1] = traffic_class << 4;
+    header[4..6].copy_from_slice(&(payload_size as u16).to_be_bytes());
+    header[6] = NEXT_HEADER_ICMPV6;
+    header[7] = TTL;
+    header[8..24].copy_from_slice(&source.octets());
+    header[24..40].copy_from_slice(&target.octets());
+    header
+}
+
+/// Which IP header is encoded in front of every packet, instead of being built by the kernel.
+#[derive(Clone, Copy, Debug)]
+pub enum Header {
+    None,
+    /// An IPv4 header, sent with IP_HDRINCL.
+    Ipv4,
+    /// An IPv6 header from a random address of the prefix.
+    Ipv6 {
+        sources: Prefix,
+    },
+}
+
+/// Pre-encoded packets for all pixels of an image, which all have the same size.
+struct Ring {
+    frames: Vec<u8>,
+    frame_size: usize,
+    /// Where the ICMP packet starts within a frame, after the IP header if there is one.
+    icmp_offset: usize,
+}
+
+impl Ring {
+    fn new(
+        image: &DynamicImage,
+        target: SocketAddr,
+        offset: (u16, u16),
+        header: Header,
+        shuffle: bool,
+    ) -> Self {
+        let mut frames = Vec::new();
+        let mut frame_size = 0;
+        let mut icmp_offset = 0;
+        let mut rng = rand::thread_rng();
+        let mut pixels: Vec<_> = image.pixels().collect();
+        // Every thread blasts a contiguous part of the ring, which should be spread over the whole image.
+        if shuffle {
+            pixels.shuffle(&mut rng);
+        }
+        for (x, y, pixel) in pixels {
+            // Pixels moved past the largest coordinate can't be on the canvas.
+            let (Some(x), Some(y)) = (
+                (x as u16).checked_add(offset.0),
+                (y as u16).checked_add(offset.1),
+            ) else {
+                continue;
+            };
+            let mut icmp = Icmp::new(target, 0, EchoDirection::Request);
+            icmp.set_payload(
+                Packet::SetPixel {
+                    x,
+                    y,
+                    color: color_from_rgba(pixel.0),
+                }
+                .to_bytes(),
+            );
+            let icmp = icmp.encoded();
+            match (target.ip(), header) {
+                (IpAddr::V4(target), Header::Ipv4) => {
+                    icmp_offset = IPV4_HEADER_SIZE;
+                    frames.extend(ipv4_header(target, IPV4_HEADER_SIZE + icmp.len()));
+                    frames.extend(icmp);
+                }
+                (IpAddr::V6(target), Header::Ipv6 { sources }) => {
+                    let source = sources.random_address(&mut rng);
+                    icmp_offset = IPV6_HEADER_SIZE;
+                    frames.extend(ipv6_header(source, target, icmp.len()));
+                    let start = frames.len();
+                    frames.extend(icmp);
+                    add_pseudo_header_v6(&mut frames[start..], source, target);
+                }
+                _ => frames.extend(icmp),
+            }
+            frame_size = icmp_offset + icmp.len();
+        }
+        Self {
+            frames,
+            frame_size,
+            icmp_offset,
+        }
+    }
+}
+
+/// Sends the image on one thread per CPU, each blasting its own part of the ring the configured number of times.
+/// If the order is shuffled, every thread sends its part in a new order on every pass.
+pub fn blast(
+    image: &DynamicImage,
+    target: SocketAddr,
+    offset: (u16, u16),
+    header: Header,
+    playback: &Playback,
+    pacer: Option<&Pacer>,
+    stats: &Stats,
+) -> Result<()> {
+    match header {
+        Header::Ipv4 if target.is_ipv6() => {
+            bail!("including the IP header is only supported for IPv4 targets")
+        }
+        Header::Ipv6 { .. } if target.is_ipv4() => {
+            bail!("source prefixes are only supported for IPv6 targets")
+        }
+        _ => {}
+    }
+    let shuffle = playback.shuffle;
+    let mut ring = Ring::new(image, target, offset, header, shuffle);
+    if ring.frames.is_empty() {
+        return Ok(());
+    }
+    let address = SockAddr::from(target);
+    let frames_per_thread =
+        (ring.frames.len() / ring.frame_size).div_ceil(rayon::current_num_threads());
+    let (frame_size, icmp_offset) = (ring.frame_size, ring.icmp_offset);
+    let size = packet_size(target.ip(), frame_size - icmp_offset - ICMP_HEADER_SIZE);
+
+    let workers = ring.frames.len().div_ceil(frames_per_thread * frame_size);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = ring
+            .frames
+            .chunks_mut(frames_per_thread * frame_size)
+            .enumerate()
+            .map(|(index, part)| {
+                let address = &address;
+                scope.spawn(move || -> Result<()> {
+                    cores::pin_current_thread(index)?;
+                    if let Some(pacer) = pacer {
+                        thread::sleep(pacer.phase_offset(size, workers));
+                    }
+                    let socket = match header {
+                        Header::None => Icmp::socket_for(target)?,
+                        Header::Ipv4 => {
+                            let socket = Icmp::socket_for(target)?;
+                            socket.set_header_included_v4(true)?;
+                            socket
+                        }
+                        Header::Ipv6 { .. } => Socket::new(
+                            Domain::IPV6,
+                            Type::RAW,
+                            Some(Protocol::from(RAW_PROTOCOL)),
+                        )?,
+                    };
+                    let mut order: Vec<usize> = (0..part.len() / frame_size).collect();
+                    let mut rng = rand::thread_rng();
+                    let mut sequence_number = 0u16;
+                    for _ in 0..playback.passes {
+                        sequence_number = sequence_number.wrapping_add(1);
+                        if shuffle {
+                            order.shuffle(&mut rng);
+                        }
+                        for &index in &order {
+                            let frame = &mut part[index * frame_size..(index + 1) * frame_size];
+                            update_sequence_number(&mut frame[icmp_offset..], sequence_number);
+                            if let Some(pacer) = pacer {
+                                pacer.wait(size);
+                            }
+                            failure::send(stats, size, 1, || {
+                                socket.send_to(frame, address).map(|_| ())
+                            })?;
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().expect("blasting thread panicked")?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,33 @@
+//! The image on the clipboard as the source, to quickly put a screenshot on the canvas.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+use crate::animation::{self, Frame};
+
+/// Loads the image that is currently on the clipboard as a single frame,
+/// scaled to fit into the given size if there is one and cropped to the canvas size.
+pub fn load(
+    scale: Option<(u32, u32)>,
+    filter: FilterType,
+    canvas: (u32, u32),
+) -> Result<Vec<Frame>> {
+    let data = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_image())
+        .context("could not get an image from the clipboard")?;
+    let image = RgbaImage::from_raw(
+        data.width.try_into()?,
+        data.height.try_into()?,
+        data.bytes.into_owned(),
+    )
+    .context("the clipboard image has an invalid size")?;
+    let mut frames = vec![Frame {
+        image,
+        delay: Duration::ZERO,
+    }];
+    animation::fit(&mut frames, scale, filter, canvas);
+    Ok(frames)
+}
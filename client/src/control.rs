@@ -0,0 +1,102 @@
+//! Control over a job while it is sent, so that it can be paused, moved and stopped at runtime.
+//!
+//! Players check the control before every frame, so pausing and stopping take effect between frames.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use anyhow::Result;
+
+/// The error that ends the players of a stopped job.
+#[derive(Debug)]
+pub struct Stopped;
+
+impl fmt::Display for Stopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the job was stopped")
+    }
+}
+
+impl std::error::Error for Stopped {}
+
+/// Shared state of a job, which is changed from outside while the job is sent.
+pub struct Control {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    stopped: AtomicBool,
+    /// Where the source is sent, with x in the upper and y in the lower half.
+    offset: AtomicU32,
+    /// Packets sent for the job.
+    packets: AtomicU64,
+}
+
+impl Control {
+    pub fn new((x, y): (u16, u16)) -> Self {
+        Self {
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            offset: AtomicU32::new(u32::from(x) << 16 | u32::from(y)),
+            packets: AtomicU64::new(0),
+        }
+    }
+
+    pub fn offset(&self) -> (u16, u16) {
+        let offset = self.offset.load(Ordering::Relaxed);
+        ((offset >> 16) as u16, offset as u16)
+    }
+
+    pub fn move_to(&self, (x, y): (u16, u16)) {
+        self.offset
+            .store(u32::from(x) << 16 | u32::from(y), Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Stops the job, which also ends a pause.
+    pub fn stop(&self) {
+        let _paused = self.paused.lock().unwrap();
+        self.stopped.store(true, Ordering::Relaxed);
+        self.resumed.notify_all();
+    }
+
+    /// Waits while the job is paused, and fails with [`Stopped`] once it is stopped.
+    pub fn check(&self) -> Result<()> {
+        drop(
+            self.resumed
+                .wait_while(self.paused.lock().unwrap(), |paused| {
+                    *paused && !self.is_stopped()
+                })
+                .unwrap(),
+        );
+        if self.is_stopped() {
+            return Err(Stopped.into());
+        }
+        Ok(())
+    }
+
+    /// Records a packet that was sent for the job.
+    pub fn sent(&self) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,59 @@
+//! The sending threads, which can be limited in number and pinned to CPU cores, so that every thread
+//! stays on its own core with its own sockets and its own part of the pixels, instead of moving between cores.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Result};
+
+/// Whether sending threads are pinned to cores.
+static PINNED: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the threads that the rayon engine and blasting mode send on: the given number of them, or one per CPU,
+/// optionally pinned to cores.
+pub fn configure(threads: Option<usize>, pin: bool) -> Result<()> {
+    if pin && !cfg!(target_os = "linux") {
+        bail!("pinning threads to cores is only supported on Linux");
+    }
+    PINNED.store(pin, Ordering::Relaxed);
+    let mut pool = rayon::ThreadPoolBuilder::new().start_handler(|index| {
+        if let Err(error) = pin_current_thread(index) {
+            eprintln!("could not pin sending thread {index} to a core: {error}");
+        }
+    });
+    if let Some(threads) = threads {
+        pool = pool.num_threads(threads);
+    }
+    pool.build_global()?;
+    Ok(())
+}
+
+/// Pins the calling thread to the core for the sending thread with the given index, if threads are pinned.
+/// Threads are spread over the cores the client may run on, in order.
+pub fn pin_current_thread(index: usize) -> io::Result<()> {
+    if !PINNED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    // SAFETY: the CPU sets are plain bit sets that live on the stack for the duration of the calls.
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&allowed), &mut allowed) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cores: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&core| libc::CPU_ISSET(core, &allowed))
+            .collect();
+        let Some(&core) = cores.get(index % cores.len().max(1)) else {
+            return Ok(());
+        };
+        let mut only: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut only);
+        if libc::sched_setaffinity(0, std::mem::size_of_val(&only), &only) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = index;
+    Ok(())
+}
@@ -0,0 +1,344 @@
+//! Daemon mode, which keeps running and lets jobs be added, paused, moved and removed through a local HTTP API.
+//!
+//! Jobs are sent with the same JSON keys as the tables of job files, except for the keys that read local files or run
+//! programs. All requests need to carry the token of the daemon as `Authorization: Bearer <token>`.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Path, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use pingxelflut::constant_time_eq;
+use serde::{Deserialize, Serialize};
+
+use crate::jobs::{self, Job, Running};
+use crate::stats::{Stats, Summary};
+use crate::Engine;
+
+/// Keys of job tables that read local files or devices, run programs or fetch URLs. Jobs from the control API can't use
+/// them, since the daemon runs with the rights to send raw packets, often as root.
+const LOCAL_KEYS: [&str; 12] = [
+    "image",
+    "from-clipboard",
+    "sequence",
+    "poll",
+    "slideshow",
+    "video",
+    "capture-screen",
+    "webcam",
+    "layer",
+    "mask",
+    "priority-mask",
+    "ffmpeg",
+];
+
+/// The jobs the daemon runs, by their ID.
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+struct Entry {
+    job: Running,
+    /// Targets that are still being sent to.
+    remaining: usize,
+    /// The first error of the job, which stopped it.
+    error: Option<String>,
+}
+
+impl Jobs {
+    /// Lets the jobs leave out the areas of jobs with a higher priority again, after jobs changed.
+    fn cover(&self) {
+        jobs::cover(
+            &self
+                .entries
+                .values()
+                .map(|entry| &entry.job)
+                .collect::<Vec<_>>(),
+        );
+    }
+}
+
+#[derive(Clone)]
+struct DaemonState {
+    jobs: Arc<Mutex<Jobs>>,
+    stats: Arc<Stats>,
+    token: Arc<str>,
+}
+
+/// Error response of a control API request.
+struct ControlError(StatusCode, String);
+
+impl IntoResponse for ControlError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ControlError {
+    fn from(error: anyhow::Error) -> Self {
+        Self(StatusCode::BAD_REQUEST, format!("{error:#}"))
+    }
+}
+
+/// A job as the control API describes it.
+#[derive(Serialize)]
+struct JobInfo {
+    id: u64,
+    /// One of running, paused, finished and failed.
+    state: &'static str,
+    error: Option<String>,
+    priority: i64,
+    x: u16,
+    y: u16,
+    targets: Vec<IpAddr>,
+    packets: u64,
+}
+
+#[derive(Deserialize)]
+struct Offset {
+    x: u16,
+    y: u16,
+}
+
+/// Starts the jobs, and serves the control API on the given address until an error occurs.
+/// Requests need the token on every address, since the API can send anything anywhere.
+pub fn run(
+    address: SocketAddr,
+    token: String,
+    initial_jobs: Vec<Job>,
+    stats: Arc<Stats>,
+) -> Result<()> {
+    let state = DaemonState {
+        jobs: Arc::default(),
+        stats,
+        token: token.into(),
+    };
+    for job in initial_jobs {
+        add(&state, job)?;
+    }
+    tokio::runtime::Runtime::new()?.block_on(serve(address, state))
+}
+
+async fn serve(address: SocketAddr, state: DaemonState) -> Result<()> {
+    let router = Router::new()
+        .route("/stats", get(stats))
+        .route("/jobs", get(list_jobs).post(add_job))
+        .route("/jobs/:id", get(get_job).delete(remove_job))
+        .route("/jobs/:id/pause", post(pause_job))
+        .route("/jobs/:id/resume", post(resume_job))
+        .route("/jobs/:id/offset", put(move_job))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    eprintln!("control API listening on {address}");
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn authenticate(
+    State(state): State<DaemonState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), state.token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Opens the sources of the job and starts sending it. Returns the ID of the job.
+fn add(state: &DaemonState, job: Job) -> Result<u64> {
+    if job.arguments.blast || job.arguments.engine != Engine::Rayon {
+        bail!("the daemon only runs jobs with the rayon engine, which can be paused and stopped");
+    }
+    let (job, sources) = job.prepare()?;
+    let mut jobs = state.jobs.lock().unwrap();
+    let active: Vec<_> = jobs
+        .entries
+        .values()
+        .filter(|entry| entry.remaining > 0 && !entry.job.control.is_stopped())
+        .map(|entry| &entry.job)
+        .chain([&job])
+        .collect();
+    jobs::check_reservations(&active)?;
+    let id = jobs.next_id;
+    jobs.next_id += 1;
+    state.stats.add_pixels(job.pixels());
+
+    let (shared, stats) = (state.jobs.clone(), state.stats.clone());
+    job.start(sources, &state.stats, move |target, result| {
+        let mut jobs = shared.lock().unwrap();
+        let Some(entry) = jobs.entries.get_mut(&id) else {
+            // The job was removed.
+            return;
+        };
+        entry.remaining -= 1;
+        if entry.remaining == 0 {
+            stats.remove_pixels(entry.job.pixels());
+        }
+        if let Err(error) = result {
+            eprintln!("job {id} could not send to {target}: {error:#}");
+            entry.error.get_or_insert(format!("{target}: {error:#}"));
+            entry.job.control.stop();
+            jobs.cover();
+        }
+    });
+    jobs.entries.insert(
+        id,
+        Entry {
+            remaining: job.lanes.len(),
+            job,
+            error: None,
+        },
+    );
+    jobs.cover();
+    Ok(id)
+}
+
+fn describe(id: u64, entry: &Entry) -> JobInfo {
+    let control = &entry.job.control;
+    let state = if entry.error.is_some() {
+        "failed"
+    } else if entry.remaining == 0 {
+        "finished"
+    } else if control.is_paused() {
+        "paused"
+    } else {
+        "running"
+    };
+    let (x, y) = control.offset();
+    JobInfo {
+        id,
+        state,
+        error: entry.error.clone(),
+        priority: entry.job.priority,
+        x,
+        y,
+        targets: entry
+            .job
+            .lanes
+            .iter()
+            .map(|lane| lane.target.ip())
+            .collect(),
+        packets: control.packets(),
+    }
+}
+
+fn not_found(id: u64) -> ControlError {
+    ControlError(StatusCode::NOT_FOUND, format!("no job {id}"))
+}
+
+async fn stats(State(state): State<DaemonState>) -> Json<Summary> {
+    Json(state.stats.summary())
+}
+
+async fn list_jobs(State(state): State<DaemonState>) -> Json<Vec<JobInfo>> {
+    let jobs = state.jobs.lock().unwrap();
+    Json(
+        jobs.entries
+            .iter()
+            .map(|(&id, entry)| describe(id, entry))
+            .collect(),
+    )
+}
+
+async fn get_job(
+    State(state): State<DaemonState>,
+    Path(id): Path<u64>,
+) -> Result<Json<JobInfo>, ControlError> {
+    let jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.get(&id).ok_or_else(|| not_found(id))?;
+    Ok(Json(describe(id, entry)))
+}
+
+/// Adds a job, given with the keys of a job file table other than the local ones. Opening its sources may take a while.
+async fn add_job(
+    State(state): State<DaemonState>,
+    Json(table): Json<toml::Table>,
+) -> Result<(StatusCode, Json<JobInfo>), ControlError> {
+    if let Some(key) = table
+        .keys()
+        .find(|key| LOCAL_KEYS.contains(&key.replace('_', "-").as_str()))
+    {
+        return Err(ControlError(
+            StatusCode::FORBIDDEN,
+            format!("jobs from the control API can't use {key}, which reads local files or runs programs"),
+        ));
+    }
+    let job = jobs::parse(table)?;
+    let id = {
+        let state = state.clone();
+        tokio::task::spawn_blocking(move || add(&state, job))
+            .await
+            .map_err(anyhow::Error::from)??
+    };
+    let jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.get(&id).ok_or_else(|| not_found(id))?;
+    Ok((StatusCode::CREATED, Json(describe(id, entry))))
+}
+
+/// Stops a job and forgets it. Its pixels stay on the canvas.
+async fn remove_job(
+    State(state): State<DaemonState>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ControlError> {
+    let mut jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.remove(&id).ok_or_else(|| not_found(id))?;
+    entry.job.control.stop();
+    if entry.remaining > 0 {
+        state.stats.remove_pixels(entry.job.pixels());
+    }
+    jobs.cover();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_job(
+    State(state): State<DaemonState>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ControlError> {
+    let jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.get(&id).ok_or_else(|| not_found(id))?;
+    entry.job.control.pause();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn resume_job(
+    State(state): State<DaemonState>,
+    Path(id): Path<u64>,
+) -> Result<StatusCode, ControlError> {
+    let jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.get(&id).ok_or_else(|| not_found(id))?;
+    entry.job.control.resume();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Moves a job to another offset. Its pixels at the old offset stay on the canvas.
+async fn move_job(
+    State(state): State<DaemonState>,
+    Path(id): Path<u64>,
+    Json(offset): Json<Offset>,
+) -> Result<StatusCode, ControlError> {
+    let jobs = state.jobs.lock().unwrap();
+    let entry = jobs.entries.get(&id).ok_or_else(|| not_found(id))?;
+    entry.job.control.move_to((offset.x, offset.y));
+    jobs.cover();
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,195 @@
+//! Procedural demo effects, which are computed frame by frame while they are sent, for testing servers
+//! and as eye candy while the canvas is idle.
+//!
+//! Like moving images, effects are sent in delta mode, so that only the pixels that changed since the previous frame
+//! are sent, and they run until the client or the job is stopped.
+
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use rand::Rng;
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::ParallelSliceMut;
+
+use crate::animation::{Playback, Player, Sink};
+
+/// Where the Mandelbrot zoom dives into, a point on the edge of the set with detail at every depth.
+const ZOOM_CENTER: (f64, f64) = (-0.743_643_887_037_151, 0.131_825_904_205_330);
+/// How many times the Mandelbrot zoom halves the view before starting over, which stays within the precision of f64.
+const ZOOM_DEPTH: f64 = 36.0;
+/// Generations after which the Game of Life starts over with new random cells, in case it settled.
+const LIFE_GENERATIONS: u32 = 2000;
+
+/// Which effect is computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Effect {
+    /// Colorful waves flowing through each other.
+    Plasma,
+    /// An endless zoom into the edge of the Mandelbrot set.
+    Mandelbrot,
+    /// Conway's Game of Life, starting from random cells, on a board whose edges wrap around.
+    Life,
+    /// Diagonal rainbow stripes scrolling across the area.
+    Rainbow,
+}
+
+/// An effect filling an area.
+pub struct Demo {
+    pub effect: Effect,
+    pub area: (u32, u32),
+    /// Time between two frames.
+    pub interval: Duration,
+}
+
+/// A fully saturated color of the given hue, from 0 to 1 around the color wheel.
+fn hue(hue: f64) -> Rgba<u8> {
+    let channel = |offset: f64| {
+        let value = (((hue + offset).rem_euclid(1.0) * 6.0 - 3.0).abs() - 1.0).clamp(0.0, 1.0);
+        (value * 255.0).round() as u8
+    };
+    Rgba([channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0), 255])
+}
+
+/// Computes every pixel of the frame in parallel from its coordinates.
+fn fill(frame: &mut RgbaImage, color: impl Fn(u32, u32) -> Rgba<u8> + Sync) {
+    let width = frame.width();
+    if width == 0 {
+        return;
+    }
+    frame
+        .par_chunks_mut(width as usize * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&color(x as u32, y as u32).0);
+            }
+        });
+}
+
+/// The cells of the Game of Life, row by row.
+struct Life {
+    width: usize,
+    cells: Vec<bool>,
+    generation: u32,
+}
+
+impl Life {
+    fn random((width, height): (u32, u32)) -> Self {
+        let mut rng = rand::thread_rng();
+        Self {
+            width: width as usize,
+            cells: (0..width as usize * height as usize)
+                .map(|_| rng.gen_bool(0.25))
+                .collect(),
+            generation: 0,
+        }
+    }
+
+    fn step(&mut self) {
+        let (width, height) = (self.width, self.cells.len() / self.width.max(1));
+        let alive = |x: usize, y: usize| self.cells[(y % height) * width + x % width];
+        self.cells = (0..self.cells.len())
+            .map(|index| {
+                let (x, y) = (index % width + width, index / width + height);
+                let neighbors = [
+                    (x - 1, y - 1),
+                    (x, y - 1),
+                    (x + 1, y - 1),
+                    (x - 1, y),
+                    (x + 1, y),
+                    (x - 1, y + 1),
+                    (x, y + 1),
+                    (x + 1, y + 1),
+                ]
+                .into_iter()
+                .filter(|&(x, y)| alive(x, y))
+                .count();
+                neighbors == 3 || (neighbors == 2 && self.cells[index])
+            })
+            .collect();
+        self.generation += 1;
+    }
+}
+
+impl Demo {
+    fn render(&self, frame: &mut RgbaImage, seconds: f64, life: &mut Life) {
+        let (width, height) = (f64::from(self.area.0), f64::from(self.area.1));
+        match self.effect {
+            Effect::Plasma => fill(frame, |x, y| {
+                let (x, y) = (f64::from(x) / 16.0, f64::from(y) / 16.0);
+                let value = (x + seconds).sin()
+                    + ((y + seconds) / 2.0).sin()
+                    + ((x + y + seconds) / 2.0).sin()
+                    + ((x * x + y * y).sqrt() - seconds).sin();
+                hue(value / 8.0 + seconds / 10.0)
+            }),
+            Effect::Mandelbrot => {
+                let depth = (seconds / 2.0) % ZOOM_DEPTH;
+                let scale = 3.0 / 2f64.powf(depth) / width.min(height);
+                let iterations = 64 + (depth * 24.0) as u32;
+                fill(frame, |x, y| {
+                    let real = ZOOM_CENTER.0 + (f64::from(x) - width / 2.0) * scale;
+                    let imaginary = ZOOM_CENTER.1 + (f64::from(y) - height / 2.0) * scale;
+                    let (mut a, mut b) = (0.0, 0.0);
+                    for iteration in 0..iterations {
+                        if a * a + b * b > 4.0 {
+                            return hue(f64::from(iteration) / 64.0);
+                        }
+                        (a, b) = (a * a - b * b + real, 2.0 * a * b + imaginary);
+                    }
+                    Rgba([0, 0, 0, 255])
+                });
+            }
+            Effect::Life => {
+                if life.generation >= LIFE_GENERATIONS {
+                    *life = Life::random(self.area);
+                }
+                life.step();
+                for (pixel, &alive) in frame.pixels_mut().zip(&life.cells) {
+                    *pixel = if alive {
+                        Rgba([255, 255, 255, 255])
+                    } else {
+                        Rgba([0, 0, 0, 255])
+                    };
+                }
+            }
+            Effect::Rainbow => fill(frame, |x, y| {
+                hue((f64::from(x) + f64::from(y)) / width.max(1.0) - seconds / 4.0)
+            }),
+        }
+    }
+
+    /// Computes and sends frames until the client or the job is stopped, sending changed pixels in parallel with the
+    /// given function. Frames that can't be sent in time are skipped.
+    pub fn play(&self, playback: &Playback, send: impl Sink) -> Result<()> {
+        let playback = Playback {
+            delta: true,
+            ..playback.clone()
+        };
+        let mut player = Player::new(&playback);
+        let mut life = Life::random(self.area);
+        let mut frame = RgbaImage::new(self.area.0, self.area.1);
+        let mut previous = None;
+        let started = Instant::now();
+        let mut next_step = started;
+        loop {
+            let seconds = next_step.duration_since(started).as_secs_f64();
+            self.render(&mut frame, seconds, &mut life);
+            player.show(&frame, previous.as_ref(), &send)?;
+            // The previous frame is overwritten by the next one.
+            let next = previous.take().unwrap_or_else(|| frame.clone());
+            previous = Some(mem::replace(&mut frame, next));
+
+            next_step += self.interval;
+            let now = Instant::now();
+            while next_step < now {
+                next_step += self.interval;
+            }
+            thread::sleep(next_step - now);
+        }
+    }
+}
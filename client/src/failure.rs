@@ -0,0 +1,111 @@
+//! What senders do when sending a packet fails, depending on the kind of error:
+//! errors that make sending pointless end the job, full send buffers are waited out with a backoff,
+//! and all other failed packets are dropped. Failures are counted in the statistics by their class.
+
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+use crate::stats::Stats;
+
+/// How often a packet is sent again after the send buffers were full.
+pub const MAX_RETRIES: u32 = 5;
+/// The backoff before the first retry, which doubles for every further retry.
+const FIRST_BACKOFF: Duration = Duration::from_micros(100);
+
+/// Kinds of send errors that are handled differently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    /// The client may not send raw packets, or a firewall rejects them.
+    Permission,
+    /// The send buffers are full, since packets are sent faster than the network takes them.
+    NoBuffers,
+    /// There is no route to the target.
+    Unreachable,
+    Other,
+}
+
+/// What happens with a packet that could not be sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// End the job with the error.
+    Abort,
+    /// Wait a little and send the packet again.
+    Backoff,
+    /// Leave the packet out.
+    Drop,
+}
+
+impl Class {
+    pub fn of(error: &io::Error) -> Self {
+        #[cfg(unix)]
+        match error.raw_os_error() {
+            Some(libc::ENOBUFS | libc::EAGAIN) => return Self::NoBuffers,
+            Some(libc::ENETUNREACH | libc::EHOSTUNREACH | libc::ENETDOWN | libc::EHOSTDOWN) => {
+                return Self::Unreachable
+            }
+            _ => {}
+        }
+        match error.kind() {
+            ErrorKind::PermissionDenied => Self::Permission,
+            ErrorKind::WouldBlock => Self::NoBuffers,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn policy(self) -> Policy {
+        match self {
+            Class::Permission => Policy::Abort,
+            Class::NoBuffers => Policy::Backoff,
+            Class::Unreachable | Class::Other => Policy::Drop,
+        }
+    }
+}
+
+/// Describes a send error for the statistics, by its class or otherwise by its kind.
+pub fn describe(error: &io::Error) -> String {
+    match Class::of(error) {
+        Class::Permission => "not permitted".to_string(),
+        Class::NoBuffers => "no buffer space".to_string(),
+        Class::Unreachable => "unreachable".to_string(),
+        Class::Other => format!("{:?}", error.kind()),
+    }
+}
+
+/// How long to wait before the given retry, counting from 0.
+pub fn backoff(retry: u32) -> Duration {
+    FIRST_BACKOFF * 2u32.pow(retry)
+}
+
+/// Sends a packet of the given size and number of pixels with the given function, which is called again after a backoff
+/// while the send buffers are full, and records the outcome.
+///
+/// Returns whether the packet was sent, or the error if the job should end.
+pub fn send(
+    stats: &Stats,
+    size: usize,
+    pixels: u64,
+    mut send: impl FnMut() -> io::Result<()>,
+) -> io::Result<bool> {
+    let mut retry = 0;
+    loop {
+        let Err(error) = send() else {
+            stats.sent(size, pixels);
+            return Ok(true);
+        };
+        match Class::of(&error).policy() {
+            Policy::Backoff if retry < MAX_RETRIES => {
+                thread::sleep(backoff(retry));
+                retry += 1;
+            }
+            Policy::Abort => {
+                stats.failed(&error, pixels);
+                return Err(error);
+            }
+            Policy::Backoff | Policy::Drop => {
+                stats.failed(&error, pixels);
+                return Ok(false);
+            }
+        }
+    }
+}
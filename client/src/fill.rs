@@ -0,0 +1,199 @@
+//! Fills for servers that accept fill rectangle packets, which cover the filled rectangle with a few packets of up to
+//! the largest area the server allows, instead of sending it row by row.
+//!
+//! The areas of jobs with a higher priority are left out by splitting the rectangle around them.
+
+use std::sync::RwLock;
+use std::thread;
+
+use anyhow::Result;
+use pingxelflut::format::{Color, Packet};
+
+use crate::animation::Playback;
+use crate::probe::Resized;
+use crate::Rectangle;
+
+/// A rectangle of one color, at the offset of the job.
+pub struct Fill {
+    pub color: Color,
+    pub size: (u32, u32),
+}
+
+impl Fill {
+    /// Sends the fill the configured number of times in packets of at most `max_area` pixels, leaving out the covered
+    /// areas of the canvas.
+    pub fn play(
+        &self,
+        playback: &Playback,
+        max_area: usize,
+        covered: &RwLock<Vec<Rectangle>>,
+        deliver: impl Fn(Packet<'_>),
+    ) -> Result<()> {
+        for pass in 1..=playback.passes {
+            playback.control.check()?;
+            if playback
+                .prober
+                .as_ref()
+                .is_some_and(|prober| prober.has_changed())
+            {
+                return Err(Resized.into());
+            }
+            let (x, y) = playback.control.offset();
+            let clip = |length: u32, start: u16| length.min(u32::from(u16::MAX - start)) as u16;
+            let area = Rectangle {
+                x,
+                y,
+                width: clip(self.size.0, x),
+                height: clip(self.size.1, y),
+            };
+            let pieces = subtract(area, &covered.read().unwrap());
+            for rectangle in pieces.into_iter().flat_map(|piece| split(piece, max_area)) {
+                if playback.control.is_stopped() {
+                    break;
+                }
+                deliver(Packet::FillRect {
+                    x: rectangle.x,
+                    y: rectangle.y,
+                    width: rectangle.width,
+                    height: rectangle.height,
+                    color: self.color,
+                });
+            }
+            // Like a static image, the fill doesn't change until the next full refresh.
+            if playback.delta && pass < playback.passes {
+                thread::sleep(playback.full_refresh);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits the area into rectangles of at most `max_area` pixels, which are as many whole rows as fit, or parts of a
+/// row if not even one row fits.
+fn split(area: Rectangle, max_area: usize) -> impl Iterator<Item = Rectangle> {
+    let width = area
+        .width
+        .min(max_area.try_into().unwrap_or(u16::MAX))
+        .max(1);
+    let rows = (max_area / usize::from(width)).clamp(1, u16::MAX.into()) as u16;
+    (0..area.height).step_by(rows.into()).flat_map(move |top| {
+        (0..area.width)
+            .step_by(width.into())
+            .map(move |left| Rectangle {
+                x: area.x + left,
+                y: area.y + top,
+                width: width.min(area.width - left),
+                height: rows.min(area.height - top),
+            })
+    })
+}
+
+/// The parts of the area outside of all covered rectangles.
+fn subtract(area: Rectangle, covered: &[Rectangle]) -> Vec<Rectangle> {
+    let mut pieces = vec![area];
+    for cover in covered {
+        pieces = pieces
+            .into_iter()
+            .flat_map(|piece| without(piece, cover))
+            .collect();
+    }
+    pieces
+}
+
+/// The parts of the piece outside of the cover: the rows above and below it, and the parts of its rows left and right
+/// of it.
+fn without(piece: Rectangle, cover: &Rectangle) -> Vec<Rectangle> {
+    let bounds = |rectangle: &Rectangle| {
+        (
+            u32::from(rectangle.x),
+            u32::from(rectangle.y),
+            u32::from(rectangle.x) + u32::from(rectangle.width),
+            u32::from(rectangle.y) + u32::from(rectangle.height),
+        )
+    };
+    let (left, top, right, bottom) = bounds(&piece);
+    let (cover_left, cover_top, cover_right, cover_bottom) = bounds(cover);
+    if cover_left >= right || cover_right <= left || cover_top >= bottom || cover_bottom <= top {
+        return vec![piece];
+    }
+    let (middle_top, middle_bottom) = (cover_top.max(top), cover_bottom.min(bottom));
+    [
+        (left, top, right, middle_top),
+        (left, middle_bottom, right, bottom),
+        (left, middle_top, cover_left.max(left), middle_bottom),
+        (cover_right.min(right), middle_top, right, middle_bottom),
+    ]
+    .into_iter()
+    .filter(|&(left, top, right, bottom)| left < right && top < bottom)
+    .map(|(left, top, right, bottom)| Rectangle {
+        x: left as u16,
+        y: top as u16,
+        width: (right - left) as u16,
+        height: (bottom - top) as u16,
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle(x: u16, y: u16, width: u16, height: u16) -> Rectangle {
+        Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn area(rectangles: &[Rectangle]) -> u32 {
+        rectangles
+            .iter()
+            .map(|rectangle| u32::from(rectangle.width) * u32::from(rectangle.height))
+            .sum()
+    }
+
+    #[test]
+    fn fills_become_few_large_rectangles() {
+        let pieces: Vec<_> = split(rectangle(10, 20, 100, 50), 1000).collect();
+        assert_eq!(pieces.len(), 5);
+        assert!(pieces
+            .iter()
+            .all(|piece| piece.width == 100 && piece.height == 10 && piece.x == 10));
+        assert_eq!(pieces[4].y, 60);
+
+        // Rows wider than the largest area are split as well.
+        let pieces: Vec<_> = split(rectangle(0, 0, 250, 3), 100).collect();
+        assert_eq!(pieces.len(), 9);
+        assert_eq!(pieces[2].width, 50);
+        assert_eq!(area(&pieces), 750);
+    }
+
+    #[test]
+    fn covered_areas_are_left_out() {
+        let area_to_fill = rectangle(0, 0, 100, 100);
+        let pieces = subtract(area_to_fill, &[rectangle(40, 40, 20, 20)]);
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(area(&pieces), 100 * 100 - 20 * 20);
+        for (x, y) in [(50, 50), (40, 59), (59, 40)] {
+            assert!(!pieces.iter().any(|piece| piece.contains(x, y)));
+        }
+        for (x, y) in [(39, 50), (60, 50), (50, 39), (50, 60), (0, 0), (99, 99)] {
+            assert!(pieces.iter().any(|piece| piece.contains(x, y)));
+        }
+
+        assert_eq!(
+            area(&subtract(area_to_fill, &[rectangle(200, 200, 10, 10)])),
+            100 * 100
+        );
+        assert!(subtract(area_to_fill, &[rectangle(0, 0, 200, 200)]).is_empty());
+        assert_eq!(
+            area(&subtract(
+                area_to_fill,
+                &[rectangle(90, 0, 100, 100), rectangle(0, 90, 100, 100)]
+            )),
+            90 * 90
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Job files, which describe several images, videos or patterns that one client sends at the same time.
+//!
+//! A job file is a TOML file with a `[[job]]` table per job, whose keys are the long names of the command line options
+//! (and `x` and `y` for the offset), and an optional `priority`, for example:
+//!
+//! ```toml
+//! [[job]]
+//! target = "10.0.0.1"
+//! image = "background.png"
+//! fit = true
+//! pps = 50000
+//!
+//! [[job]]
+//! target = ["10.0.0.1", "10.0.0.2"]
+//! image = "logo.gif"
+//! x = 100
+//! y = 50
+//! priority = 1
+//! ```
+//!
+//! The values are parsed exactly like their command line counterparts.
+//! Where jobs overlap on a target, jobs with a higher priority are drawn on top:
+//! jobs with a lower priority don't send the pixels in the areas of those jobs.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+
+use crate::address;
+use crate::control::{Control, Stopped};
+use crate::pacing::Pacer;
+use crate::probe::Resized;
+use crate::stats::Stats;
+use crate::{open_source, send, Arguments, Rectangle, Source};
+
+/// One entry of a job file.
+pub struct Job {
+    pub arguments: Arguments,
+    pub priority: i64,
+}
+
+/// A job whose sources are opened for all of its targets.
+pub struct Running {
+    pub arguments: Arc<Arguments>,
+    pub priority: i64,
+    pub control: Arc<Control>,
+    /// The rate limits of a job are shared by its targets.
+    pacer: Option<Arc<Pacer>>,
+    pub lanes: Vec<Lane>,
+}
+
+/// One target of a running job.
+pub struct Lane {
+    pub target: SocketAddr,
+    /// Size of the source sent to the target.
+    pub size: (u32, u32),
+    /// Areas of the canvas that jobs with a higher priority are drawn in.
+    covered: Arc<RwLock<Vec<Rectangle>>>,
+}
+
+impl Job {
+    /// Opens the sources for all targets of the job.
+    pub fn prepare(self) -> Result<(Running, Vec<Source>)> {
+        let arguments = self.arguments;
+        if arguments.webcam.is_some() && arguments.target.len() > 1 {
+            bail!("a webcam can only be sent to one target");
+        }
+        let mut lanes = Vec::new();
+        let mut sources = Vec::new();
+        for target in &arguments.target {
+            let source = address::resolve(target, arguments.resolution)
+                .and_then(|address| Ok((address, open_source(&arguments, address)?)));
+            let (target, source) = source.with_context(|| format!("could not prepare {target}"))?;
+            lanes.push(Lane {
+                target,
+                size: source.size(),
+                covered: Arc::default(),
+            });
+            sources.push(source);
+        }
+        let running = Running {
+            control: Arc::new(Control::new(arguments.offset())),
+            pacer: Pacer::new(arguments.pps, arguments.mbps)
+                .map(|pacer| Arc::new(pacer.with_jitter(arguments.jitter))),
+            arguments: Arc::new(arguments),
+            priority: self.priority,
+            lanes,
+        };
+        Ok((running, sources))
+    }
+}
+
+impl Running {
+    /// Number of pixels that one pass over all targets sends.
+    pub fn pixels(&self) -> u64 {
+        self.lanes
+            .iter()
+            .map(|lane| u64::from(lane.size.0) * u64::from(lane.size.1))
+            .sum()
+    }
+
+    /// Sends the sources to their targets, each from its own thread, and reports how sending to every target ended.
+    /// Stopping the job ends it successfully, and a target whose canvas changed its size gets a source sized for it.
+    pub fn start(
+        &self,
+        sources: Vec<Source>,
+        stats: &Arc<Stats>,
+        finished: impl Fn(IpAddr, Result<()>) + Clone + Send + 'static,
+    ) {
+        for (lane, source) in self.lanes.iter().zip(sources) {
+            let (arguments, control, covered, pacer, stats, finished) = (
+                self.arguments.clone(),
+                self.control.clone(),
+                lane.covered.clone(),
+                self.pacer.clone(),
+                stats.clone(),
+                finished.clone(),
+            );
+            let target = lane.target;
+            thread::spawn(move || {
+                let mut source = source;
+                let result = loop {
+                    match send(
+                        &arguments,
+                        target,
+                        source,
+                        &control,
+                        &covered,
+                        pacer.clone(),
+                        &stats,
+                    ) {
+                        // The source is sized for the new canvas and sent from the start.
+                        Err(error) if error.is::<Resized>() => {
+                            match open_source(&arguments, target) {
+                                Ok(resized) => source = resized,
+                                Err(error) => break Err(error),
+                            }
+                        }
+                        Err(error) if error.is::<Stopped>() => break Ok(()),
+                        result => break result,
+                    }
+                };
+                finished(target.ip(), result);
+            });
+        }
+    }
+}
+
+/// Lets every job leave out the areas of the jobs with a higher priority on the same target, where they are now.
+/// Stopped jobs don't cover anything.
+pub fn cover(jobs: &[&Running]) {
+    for job in jobs {
+        for lane in &job.lanes {
+            let covered = jobs
+                .iter()
+                .filter(|other| other.priority > job.priority && !other.control.is_stopped())
+                .flat_map(|other| {
+                    let (x, y) = other.control.offset();
+                    other
+                        .lanes
+                        .iter()
+                        .filter(|other_lane| other_lane.target == lane.target)
+                        .map(move |other_lane| Rectangle {
+                            x,
+                            y,
+                            width: other_lane.size.0.try_into().unwrap_or(u16::MAX),
+                            height: other_lane.size.1.try_into().unwrap_or(u16::MAX),
+                        })
+                })
+                .collect();
+            *lane.covered.write().unwrap() = covered;
+        }
+    }
+}
+
+/// Fails if jobs reserve their regions on the same server more than once. Servers keep one reservation per source
+/// address, so that such jobs would take over each other's reservation, and the first to end would release it.
+pub fn check_reservations(jobs: &[&Running]) -> Result<()> {
+    let mut servers = HashSet::new();
+    for lane in jobs
+        .iter()
+        .filter(|job| job.arguments.reserve)
+        .flat_map(|job| &job.lanes)
+    {
+        if !servers.insert(lane.target.ip()) {
+            bail!(
+                "only one job can reserve its region on {}, which keeps one reservation per source address",
+                lane.target.ip()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reads the jobs from a job file.
+pub fn load(path: &Path) -> Result<Vec<Job>> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("could not read job file {}", path.display()))?;
+    let mut table: toml::Table =
+        toml::from_str(&text).with_context(|| format!("invalid job file {}", path.display()))?;
+    let Some(toml::Value::Array(jobs)) = table.remove("job") else {
+        bail!("the job file {} has no [[job]] tables", path.display());
+    };
+    if let Some(key) = table.keys().next() {
+        bail!("unknown job file key {key}, options belong into [[job]] tables");
+    }
+    jobs.into_iter()
+        .enumerate()
+        .map(|(index, job)| {
+            let toml::Value::Table(job) = job else {
+                bail!("job {} must be a table", index + 1);
+            };
+            parse(job).with_context(|| format!("invalid job {}", index + 1))
+        })
+        .collect()
+}
+
+/// Turns a job table into the command line it stands for, and parses that.
+pub fn parse(mut job: toml::Table) -> Result<Job> {
+    let priority = match job.remove("priority") {
+        Some(toml::Value::Integer(priority)) => priority,
+        Some(_) => bail!("the priority must be an integer"),
+        None => 0,
+    };
+
+    let mut command_line = vec![OsString::from("client")];
+    for (key, value) in job {
+        // The offset only has short options.
+        let flag = if key.len() == 1 {
+            format!("-{key}")
+        } else {
+            format!("--{key}")
+        };
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            // Values are attached to their flag, so that values starting with a dash aren't taken for flags.
+            match value {
+                toml::Value::Boolean(enabled) => {
+                    if enabled {
+                        command_line.push(flag.clone().into());
+                    }
+                }
+                toml::Value::String(text) => command_line.push(format!("{flag}={text}").into()),
+                toml::Value::Integer(number) => {
+                    command_line.push(format!("{flag}={number}").into())
+                }
+                toml::Value::Float(number) => command_line.push(format!("{flag}={number}").into()),
+                _ => bail!("option {key} must be a string, number, boolean or an array of them"),
+            }
+        }
+    }
+    Ok(Job {
+        arguments: Arguments::try_parse_from(command_line)?,
+        priority,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_starting_with_a_dash_are_not_flags() {
+        let job = parse(toml::toml! {
+            target = ["10.0.0.1", "10.0.0.2"]
+            image = "-logo.png"
+            x = 5
+            once = true
+            priority = 2
+        })
+        .unwrap();
+        assert_eq!(job.arguments.target, ["10.0.0.1", "10.0.0.2"]);
+        assert_eq!(job.arguments.image, Some("-logo.png".into()));
+        assert_eq!(job.arguments.x, 5);
+        assert!(job.arguments.once);
+        assert_eq!(job.priority, 2);
+    }
+
+    #[test]
+    fn servers_are_reserved_once() {
+        let running = |reserve: bool, targets: &[&str]| {
+            let job =
+                parse(toml::toml! { target = "10.0.0.1" fill = "ff0000" reserve = (reserve) })
+                    .unwrap();
+            Running {
+                arguments: Arc::new(job.arguments),
+                priority: 0,
+                control: Arc::new(Control::new((0, 0))),
+                pacer: None,
+                lanes: targets
+                    .iter()
+                    .map(|target| Lane {
+                        target: target.parse().unwrap(),
+                        size: (10, 10),
+                        covered: Default::default(),
+                    })
+                    .collect(),
+            }
+        };
+        let first = running(true, &["10.0.0.1:1337", "10.0.0.2:1337"]);
+        let other = running(true, &["10.0.0.3:1337"]);
+        let unreserved = running(false, &["10.0.0.1:1337"]);
+        assert!(check_reservations(&[&first, &other, &unreserved]).is_ok());
+
+        let same_server = running(true, &["10.0.0.2:1338"]);
+        assert!(check_reservations(&[&first, &same_server]).is_err());
+        let twice = running(true, &["10.0.0.4:1337", "10.0.0.4:1338"]);
+        assert!(check_reservations(&[&twice]).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Layers of images that are composited on top of the source before it is sent, such as a logo on a video,
+//! so that overlays don't need clients of their own that compete for the same pixels.
+//!
+//! Layers are drawn in the order they are given, so later layers are on top. They are placed relative to
+//! the top left corner of the source, and only cover the source, not the canvas around it.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use pingxelflut::format::{color_from_rgba, Color, ColorExt};
+
+/// A layer as it is given on the command line, like `logo.png@100,50,0.8`.
+#[derive(Clone, Debug)]
+pub struct LayerSpec {
+    path: PathBuf,
+    x: u32,
+    y: u32,
+    /// Opacity of the whole layer, from 0 to 1.
+    alpha: f64,
+}
+
+impl FromStr for LayerSpec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("not a layer like logo.png, logo.png@100,50 or logo.png@100,50,0.8: {text}");
+        let Some((path, placement)) = text.rsplit_once('@') else {
+            return Ok(Self {
+                path: text.into(),
+                x: 0,
+                y: 0,
+                alpha: 1.0,
+            });
+        };
+        let mut parts = placement.split(',').map(str::trim);
+        let x = parts
+            .next()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(invalid)?;
+        let y = parts
+            .next()
+            .and_then(|y| y.parse().ok())
+            .ok_or_else(invalid)?;
+        let alpha = match parts.next() {
+            Some(alpha) => alpha
+                .parse()
+                .ok()
+                .filter(|alpha| (0.0..=1.0).contains(alpha))
+                .ok_or_else(invalid)?,
+            None => 1.0,
+        };
+        if path.is_empty() || parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            path: path.into(),
+            x,
+            y,
+            alpha,
+        })
+    }
+}
+
+struct Layer {
+    image: RgbaImage,
+    x: u32,
+    y: u32,
+}
+
+/// The loaded layers, bottom first.
+pub struct Layers(Vec<Layer>);
+
+impl Layers {
+    pub fn load(specs: &[LayerSpec]) -> Result<Self> {
+        specs
+            .iter()
+            .map(|spec| {
+                let mut image = image::open(&spec.path)
+                    .with_context(|| format!("could not open layer {}", spec.path.display()))?
+                    .into_rgba8();
+                for pixel in image.pixels_mut() {
+                    pixel.0[3] = (f64::from(pixel.0[3]) * spec.alpha).round() as u8;
+                }
+                Ok(Layer {
+                    image,
+                    x: spec.x,
+                    y: spec.y,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The color of the source at the given point with the layers on top.
+    pub fn composite(&self, x: u32, y: u32, mut color: Color) -> Color {
+        for layer in &self.0 {
+            let (Some(layer_x), Some(layer_y)) = (x.checked_sub(layer.x), y.checked_sub(layer.y))
+            else {
+                continue;
+            };
+            if let Some(pixel) = layer.image.get_pixel_checked(layer_x, layer_y) {
+                color = color_from_rgba(pixel.0).blend_over(color);
+            }
+        }
+        color
+    }
+
+    /// Draws the layers onto an image of the source.
+    pub fn apply(&self, image: &mut RgbaImage) {
+        if self.is_empty() {
+            return;
+        }
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let color = self.composite(x, y, color_from_rgba(pixel.0));
+            pixel.0 = [color.r, color.g, color.b, color.a];
+        }
+    }
+}
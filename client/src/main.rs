@@ -1,25 +1,292 @@
-use std::net::IpAddr;
+mod address;
+mod adjust;
+mod animation;
+#[cfg(unix)]
+mod async_engine;
+mod blast;
+mod clipboard;
+mod control;
+mod cores;
+mod daemon;
+mod effects;
+mod failure;
+mod fill;
+mod jobs;
+mod layers;
+mod mask;
+mod motion;
+mod pacing;
+mod packing;
+mod palette;
+mod pattern;
+mod pause;
+mod priority;
+mod probe;
+mod queue;
+mod remote;
+mod reserve;
+mod scrape;
+mod sequence;
+mod slideshow;
+mod stats;
+mod verify;
+mod video;
+
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
+use address::{Prefix, Resolution};
+use adjust::Adjustments;
+use animation::{Flushing, Frame, Playback};
+use anyhow::{Context, Result};
+use blast::Header;
+use clap::ArgGroup;
+use clap::Args;
 use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use control::Control;
+use effects::{Demo, Effect};
+use fill::Fill;
+use image::imageops::FilterType;
 use image::DynamicImage;
-use image::GenericImageView;
-use pingxelflut::format::color_from_rgba;
-use pingxelflut::get_size;
-use pingxelflut::set_pixel;
-use rayon::iter::IntoParallelIterator;
-use rayon::iter::ParallelIterator;
+use image::Rgba;
+use jobs::Job;
+use layers::{LayerSpec, Layers};
+use mask::Mask;
+use motion::{Motion, Movement};
+use pacing::{packet_size, Pacer};
+use packing::{Packer, Packing, DEFAULT_PAYLOAD_SIZE, MAX_PAYLOAD_SIZE, MIN_PAYLOAD_SIZE};
+use pattern::Pattern;
+use pingxelflut::format::{color_from_rgba, Color, Packet};
+use pingxelflut::get_size_at;
+use pingxelflut::PingxelflutClient;
+use priority::{Order, Priority};
+use probe::Prober;
+use remote::Polled;
+use reserve::Reservation;
+use scrape::Scrape;
+use sequence::SequencePattern;
+use slideshow::{Slideshow, Transition};
+use stats::Stats;
+use verify::Verifier;
+use video::{ScreenRegion, Video};
+
+/// How packets are sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Engine {
+    /// One thread per CPU, each sending its share of the pixels with blocking sockets.
+    Rayon,
+    /// Tasks on a tokio runtime sending batches of pixels with non-blocking sockets.
+    Tokio,
+}
+
+/// How the source is resampled when it is scaled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Filter {
+    /// Sharp pixels, best for pixel art.
+    Nearest,
+    /// Linear interpolation.
+    Triangle,
+    /// Cubic interpolation.
+    CatmullRom,
+    Gaussian,
+    /// Sharpest for photos, but slowest.
+    Lanczos3,
+}
+
+impl Filter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            Filter::Nearest => FilterType::Nearest,
+            Filter::Triangle => FilterType::Triangle,
+            Filter::CatmullRom => FilterType::CatmullRom,
+            Filter::Gaussian => FilterType::Gaussian,
+            Filter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+
+    /// The closest scaling algorithm of FFmpeg.
+    fn ffmpeg_name(self) -> &'static str {
+        match self {
+            Filter::Nearest => "neighbor",
+            Filter::Triangle => "bilinear",
+            Filter::CatmullRom => "bicubic",
+            Filter::Gaussian => "gauss",
+            Filter::Lanczos3 => "lanczos",
+        }
+    }
+}
 
 /// A simple Pingxelflut client.
 #[derive(Clone, Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(group(ArgGroup::new("moving").args(["video", "capture_screen", "webcam"])))]
+#[command(group(ArgGroup::new("timed").args(["video", "capture_screen", "webcam", "motion", "slideshow", "sequence", "effect"]).multiple(true)))]
+#[command(group(ArgGroup::new("limited").args(["pps", "mbps"]).multiple(true)))]
+#[command(group(ArgGroup::new("backdrop").args(["motion", "slideshow"]).multiple(true)))]
 struct Arguments {
-    /// Target server to send pixels to.
-    #[arg(short, long, value_name = "ADDRESS")]
-    target: IpAddr,
-    /// Source image to send.
-    #[arg(short, long, value_name = "IMAGE")]
-    image: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Target server to send pixels to, as an IP address or hostname. Can be given several times or as a comma-separated
+    /// list, to send the image to all servers at the same time, sized for each canvas.
+    #[arg(
+        short,
+        long,
+        value_name = "ADDRESS",
+        required = true,
+        value_delimiter = ','
+    )]
+    target: Vec<String>,
+    #[command(flatten)]
+    resolution: Resolution,
+    /// Source image to send, as a file or an HTTP(S) URL that is downloaded at startup.
+    /// Animated GIFs are played with their frame delays.
+    #[arg(
+        short,
+        long,
+        value_name = "IMAGE",
+        required_unless_present_any = ["capture_screen", "webcam", "pattern", "slideshow", "from_clipboard", "sequence", "fill", "effect"]
+    )]
+    image: Option<PathBuf>,
+    /// Send the image that is currently on the clipboard instead of an image file.
+    #[arg(long, conflicts_with_all = ["image", "slideshow", "pattern", "video", "capture_screen", "webcam"])]
+    from_clipboard: bool,
+    /// Play a numbered image sequence like frames/%04d.png as an animation, at 25 frames per second by default.
+    /// The sequence starts at frame 0 or 1 and ends before the first missing frame.
+    #[arg(long, value_name = "PATTERN", conflicts_with_all = ["image", "slideshow", "pattern", "video", "capture_screen", "webcam", "from_clipboard", "motion"])]
+    sequence: Option<SequencePattern>,
+    /// Download the image from its URL again at this interval, and send the new image whenever it changed.
+    /// The previous image is kept while downloads fail.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, requires = "image", conflicts_with_all = ["video", "motion", "crop", "verify"])]
+    poll: Option<Duration>,
+    /// Show a slideshow of these images, and the images in these directories sorted by name, instead of an image.
+    /// Directories are read again on every pass, so that images can be added while the slideshow runs.
+    #[arg(long, value_name = "PATH", num_args = 1.., conflicts_with_all = ["image", "video", "capture_screen", "webcam", "pattern", "motion", "crop"])]
+    slideshow: Vec<PathBuf>,
+    /// How long every slide is shown.
+    #[arg(long, value_name = "SECONDS", default_value = "10", value_parser = parse_seconds, requires = "slideshow")]
+    dwell: Duration,
+    /// How one slide changes into the next.
+    #[arg(
+        long,
+        value_name = "TRANSITION",
+        default_value = "cut",
+        requires = "slideshow"
+    )]
+    transition: Transition,
+    /// How long a fade between slides takes.
+    #[arg(long, value_name = "SECONDS", default_value = "1", value_parser = parse_seconds, requires = "slideshow")]
+    transition_time: Duration,
+    /// Send a generated pattern instead of an image, which fills the canvas next to the offset.
+    #[arg(long, value_name = "PATTERN", conflicts_with_all = ["image", "video", "capture_screen", "webcam"])]
+    pattern: Option<Pattern>,
+    /// Send a procedural demo effect instead of an image, which is computed while it is sent and fills the canvas
+    /// next to the offset. Only the pixels that change are sent, at 30 frames per second by default.
+    #[arg(long, value_name = "EFFECT", conflicts_with_all = ["image", "pattern", "fill", "video", "capture_screen", "webcam", "slideshow", "from_clipboard", "sequence", "motion", "crop", "loops", "once"])]
+    effect: Option<Effect>,
+    /// Colors of the pattern as hexadecimal RRGGBB or RRGGBBAA. Can be given several times or as a comma-separated list.
+    #[arg(long, value_name = "COLOR", value_parser = parse_color, value_delimiter = ',', requires = "pattern")]
+    color: Vec<Rgba<u8>>,
+    /// Fill the canvas next to the offset, or the rectangle, with this color as hexadecimal RRGGBB or RRGGBBAA
+    /// instead of sending an image.
+    #[arg(long, value_name = "COLOR", value_parser = parse_color, conflicts_with_all = ["image", "pattern", "video", "capture_screen", "webcam", "slideshow", "from_clipboard", "sequence", "motion"])]
+    fill: Option<Rgba<u8>>,
+    /// Only fill this rectangle of the canvas.
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = parse_rectangle, requires = "fill", conflicts_with_all = ["x", "y", "width", "height", "crop"])]
+    rect: Option<Rectangle>,
+    /// Size of the cells of noise and checkerboard patterns.
+    #[arg(long, value_name = "PIXELS", default_value = "32", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    cell_size: u32,
+    /// Play the source as a video file, which is decoded with FFmpeg and scaled to fit the canvas.
+    #[arg(long, conflicts_with = "blast")]
+    video: bool,
+    /// Stream the screen, or the given region of it such as 1280x720+0+0, instead of an image.
+    /// The screen is captured with FFmpeg and scaled to fit the canvas.
+    #[arg(long, value_name = "REGION", num_args = 0..=1, conflicts_with_all = ["image", "video", "blast"])]
+    capture_screen: Option<Option<ScreenRegion>>,
+    /// Stream the camera with the given device or name instead of an image, by default /dev/video0
+    /// (or the first camera on macOS). The camera is captured with FFmpeg and scaled to fit the canvas.
+    #[arg(long, value_name = "CAMERA", num_args = 0..=1, conflicts_with_all = ["image", "video", "capture_screen", "blast"])]
+    webcam: Option<Option<String>>,
+    /// Flip videos, screen captures and webcams horizontally, like a mirror.
+    #[arg(long, requires = "moving")]
+    mirror: bool,
+    /// Scale images to fit the canvas next to the offset, instead of cropping them.
+    /// Videos, screen captures and webcams are always scaled to fit.
+    #[arg(long)]
+    fit: bool,
+    /// Scale the source to this width, or to fit into it together with the height, keeping the aspect ratio.
+    #[arg(long, value_name = "PIXELS", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    width: Option<u32>,
+    /// Scale the source to this height, or to fit into it together with the width, keeping the aspect ratio.
+    #[arg(long, value_name = "PIXELS", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    height: Option<u32>,
+    /// Scale images up by this integer factor, so that every pixel becomes a sharp block of FACTOR×FACTOR pixels,
+    /// for showing small pixel art large.
+    #[arg(long, value_name = "FACTOR", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..), conflicts_with_all = ["fit", "width", "height", "pattern", "fill", "effect", "moving", "slideshow", "poll"])]
+    scale: Option<u32>,
+    /// How the source is resampled when it is scaled.
+    #[arg(long, value_name = "FILTER", default_value = "lanczos3")]
+    filter: Filter,
+    /// Brighten (or darken) the source by adding this to every color channel, from -1 to 1.
+    #[arg(long, value_name = "AMOUNT", default_value = "0", allow_negative_numbers = true, value_parser = |text: &str| parse_number(text, -1.0..=1.0))]
+    brightness: f64,
+    /// Multiply the contrast of the source by this factor, where 1 leaves it unchanged.
+    #[arg(long, value_name = "FACTOR", default_value = "1", value_parser = |text: &str| parse_number(text, 0.0..=1000.0))]
+    contrast: f64,
+    /// Correct the gamma of the source, where values above 1 brighten dark colors and 1 leaves them unchanged.
+    #[arg(long, value_name = "GAMMA", default_value = "1", value_parser = |text: &str| parse_number(text, 0.1..=10.0))]
+    gamma: f64,
+    /// Draw this image on top of the source before sending it, at an offset and with an opacity from 0 to 1
+    /// relative to the source, like logo.png@100,50,0.8. Can be given several times; later layers are on top.
+    #[arg(long, value_name = "IMAGE[@X,Y[,ALPHA]]", conflicts_with_all = ["crop", "verify"])]
+    layer: Vec<LayerSpec>,
+    /// Only send the pixels of the source where this grayscale image, placed at the top left corner of the source,
+    /// is brighter than the mask threshold.
+    #[arg(long, value_name = "IMAGE")]
+    mask: Option<PathBuf>,
+    /// Brightness from 0 to 255 that mask pixels need to exceed for the source to be sent there.
+    #[arg(long, value_name = "LEVEL", default_value = "127", requires = "mask")]
+    mask_threshold: u8,
+    /// Only send this rectangle of the image, after scaling. It is sent where it would be as part of the whole image,
+    /// so that clients can split an image between them, or repair a damaged area.
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = parse_rectangle, conflicts_with = "moving")]
+    crop: Option<Rectangle>,
+    /// Move the image around the canvas next to the offset, only sending the pixels that change.
+    #[arg(long, value_name = "MOVEMENT", conflicts_with_all = ["moving", "crop", "loops", "once"])]
+    motion: Option<Movement>,
+    /// Points that the top left corner of the moving image passes with `--motion path`, relative to the offset,
+    /// like 0,0 500,0 500,300.
+    #[arg(long, value_name = "X,Y", num_args = 1.., value_parser = parse_point, required_if_eq("motion", "path"))]
+    path: Vec<(u32, u32)>,
+    /// Speed of the moving image in pixels per second.
+    #[arg(long, value_name = "PIXELS", default_value = "100", value_parser = parse_rate, requires = "motion")]
+    speed: f64,
+    /// Color that moving images and slides are drawn on, which erases moving images where they moved away from.
+    #[arg(long, value_name = "COLOR", default_value = "000000", value_parser = parse_color, requires = "backdrop")]
+    background: Rgba<u8>,
+    /// Play the video or image sequence, capture the screen or webcam, move the image or fade between slides
+    /// at this frame rate. Image sequences play at 25 frames per second by default, moving images take 30 steps
+    /// per second, and fades 10.
+    #[arg(long, value_name = "FPS", value_parser = parse_rate, requires = "timed")]
+    fps: Option<f64>,
+    /// How many decoded frames of videos, screen captures and webcams may wait to be sent. When the queue is full,
+    /// the oldest frame is dropped, so that a slow network delays the video by at most this many frames.
+    #[arg(long, value_name = "FRAMES", default_value = "1", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "moving")]
+    frame_queue: usize,
+    /// FFmpeg executable used to decode videos and capture the screen. FFprobe is expected next to it.
+    #[arg(long, value_name = "PATH", default_value = "ffmpeg")]
+    ffmpeg: PathBuf,
     /// X offset to send image at.
     #[arg(short, value_name = "X", default_value = "0")]
     x: u16,
@@ -31,46 +298,868 @@ struct Arguments {
     /// By default, 1920x1080 is used.
     #[arg(long)]
     no_request_size: bool,
+    /// Request the canvas size again at this interval, and size the source for the new canvas when it changed,
+    /// such as after a server restarted with different dimensions.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, conflicts_with = "no_request_size")]
+    reprobe: Option<Duration>,
+    /// Encode the packets for all pixels once and send them over and over, using as little CPU time per packet as possible.
+    #[arg(long)]
+    blast: bool,
+    /// Send every pixel in a packet of its own, even to servers that accept fill rectangle, set pixels and blit packets.
+    /// Only the rayon engine packs pixels, since the other engines send packets of a fixed size.
+    #[arg(long)]
+    no_packing: bool,
+    /// Largest payload of packed packets in bytes, counted from the packet type. The default fits into an MTU of 1500
+    /// bytes even with an IPv6 header; servers that accept less get smaller packets.
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_PAYLOAD_SIZE, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(MIN_PAYLOAD_SIZE as u64..=MAX_PAYLOAD_SIZE as u64), conflicts_with = "no_packing")]
+    payload_size: usize,
+    /// Quantize the image to the palette of servers that have one, with Floyd–Steinberg dithering, and send opaque
+    /// pixels as indices into it, which takes a byte per pixel instead of four. Only images are dithered;
+    /// the frames of videos and other sources take the nearest palette color of every pixel.
+    #[arg(long, conflicts_with = "no_packing")]
+    indexed: bool,
+    /// Reserve the region of the image on servers that support it before sending, so that cooperating participants
+    /// don't draw over it, and renew the reservation until the job ends. Fails if the server refuses it.
+    #[arg(long)]
+    reserve: bool,
+    /// How long in seconds a reservation lasts unless it is renewed, which happens at half of it.
+    #[arg(long, value_name = "SECONDS", default_value = "30", value_parser = clap::value_parser!(u16).range(2..), requires = "reserve")]
+    reserve_lease: u16,
+    /// In blasting mode, also encode the IPv4 header of every packet (IP_HDRINCL), so that the kernel doesn’t build it.
+    #[arg(long, requires = "blast")]
+    header_included: bool,
+    /// In blasting mode, send every packet from a random address of this IPv6 prefix, like 2001:db8:1::/64,
+    /// by encoding its IPv6 header. Only use a prefix that is routed to this host, and only where the rules allow it.
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        requires = "blast",
+        conflicts_with = "header_included"
+    )]
+    source_prefix: Option<Prefix>,
+    /// Send the pixels in a random order, which is shuffled again for every pass over the image.
+    /// Partially delivered images then look evenly dithered instead of showing stripes.
+    #[arg(long)]
+    shuffle: bool,
+    /// Send the most important pixels of every frame first, so that the recognizable parts of the image
+    /// appear early when other clients draw over it.
+    #[arg(long, value_name = "ORDER", conflicts_with = "shuffle")]
+    order: Option<Order>,
+    /// Send the pixels of every frame in the order of the brightness of this grayscale image, brightest first.
+    /// The mask is stretched over the frame.
+    #[arg(long, value_name = "IMAGE", conflicts_with_all = ["shuffle", "order"])]
+    priority_mask: Option<PathBuf>,
+    /// Only send the pixels that changed since the previous frame, which saves most of the traffic for
+    /// mostly static content. All pixels are still sent periodically, to repair lost packets.
+    #[arg(long, conflicts_with = "blast")]
+    delta: bool,
+    /// In delta mode, how often all pixels are sent.
+    #[arg(long, value_name = "SECONDS", default_value = "5", value_parser = parse_seconds, requires = "delta")]
+    full_refresh: Duration,
+    /// Send the image once, then periodically read back random samples of it with get pixel requests,
+    /// and only send the areas with damaged samples again. The server needs to support the get pixel extension.
+    #[arg(long, conflicts_with_all = ["blast", "delta", "moving", "backdrop", "loops", "once"])]
+    verify: bool,
+    /// How often the image is verified.
+    #[arg(long, value_name = "SECONDS", default_value = "5", value_parser = parse_seconds, requires = "verify")]
+    verify_interval: Duration,
+    /// How many pixels are read back per verification. Servers answer 1000 get pixel requests per second by default.
+    #[arg(long, value_name = "N", default_value = "250", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..), requires = "verify")]
+    verify_samples: usize,
+    /// Send the image or video this many times, then exit. By default, it is sent until the client is stopped.
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<u64>::new().range(1..), conflicts_with_all = ["capture_screen", "webcam"])]
+    loops: Option<u64>,
+    /// Send the image or video once, then exit.
+    #[arg(long, conflicts_with_all = ["loops", "capture_screen", "webcam"])]
+    once: bool,
+    /// Send on this many threads with the rayon engine and in blasting mode, instead of one per CPU.
+    /// Every thread sends its own part of the pixels on its own sockets. With job files, the largest number is used.
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    threads: Option<usize>,
+    /// Pin every sending thread to its own CPU core, so that the threads don't get in each other's way. Linux only.
+    #[arg(long)]
+    pin_cores: bool,
+    /// How packets are sent outside of blasting mode.
+    #[arg(long, value_name = "ENGINE", default_value = "rayon")]
+    engine: Engine,
+    /// With the tokio engine, how many batches of pixels are sent at the same time.
+    #[arg(long, value_name = "BATCHES", default_value = "4", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    concurrency: usize,
+    /// Send at most this many packets per second, across all workers.
+    #[arg(long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    pps: Option<u32>,
+    /// Send at most this many megabits per second including IP and ICMP headers, across all workers.
+    #[arg(long, visible_alias = "max-bandwidth", value_name = "MBPS", value_parser = parse_rate)]
+    mbps: Option<f64>,
+    /// Vary the time between packets randomly by up to this fraction of it, from 0 to 1, and start workers
+    /// at random offsets, which spreads out bursts at the same average rate.
+    #[arg(long, value_name = "FRACTION", default_value = "0", value_parser = |text: &str| parse_number(text, 0.0..=1.0), requires = "limited")]
+    jitter: f64,
+}
+
+/// Other things to do than sending.
+#[derive(Clone, Subcommand, Debug)]
+enum Command {
+    /// Read a rectangle of the canvas, or all of it, back from a server with get pixel requests, and save it as an image.
+    Scrape(ScrapeArguments),
+    /// Run the jobs of a job file at the same time, each with the options of a client.
+    Jobs {
+        /// TOML file with a [[job]] table per job, whose keys are the long names of the options, and an optional priority.
+        /// Where jobs overlap on a target, the job with the higher priority is drawn on top.
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Keep running, and add, pause, move and remove jobs at runtime through an HTTP API.
+    /// Jobs are added with the keys of job files as JSON.
+    Daemon {
+        /// Address to serve the control API on.
+        #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:7700")]
+        control_address: SocketAddr,
+        /// Token that requests to the control API need to send as `Authorization: Bearer <token>`.
+        /// Required on every address, since any local user can reach loopback addresses.
+        #[arg(long, value_name = "TOKEN")]
+        control_token: String,
+        /// Job file with the jobs to start with.
+        #[arg(long, value_name = "FILE")]
+        jobs: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Args, Debug)]
+struct ScrapeArguments {
+    /// Server to read the canvas from, as an IP address or hostname. It needs to support the get pixel extension.
+    #[arg(short, long, value_name = "ADDRESS")]
+    target: String,
+    #[command(flatten)]
+    resolution: Resolution,
+    /// Image file to save the canvas to, in the format of its extension, such as PNG.
+    #[arg(short, long, value_name = "IMAGE")]
+    output: PathBuf,
+    /// Only read this rectangle of the canvas. By default, the canvas size is requested and all of it is read.
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT", value_parser = parse_rectangle)]
+    region: Option<Rectangle>,
+    /// Send at most this many requests per second. Servers drop the requests above their limit, 1000 per second by default.
+    #[arg(long, value_name = "N", default_value = "1000", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    rate: u32,
+    /// How long to wait for the last responses before missing pixels are requested again.
+    #[arg(long, value_name = "SECONDS", default_value = "1", value_parser = parse_seconds)]
+    timeout: Duration,
+    /// How often missing pixels are requested again. Pixels that never arrive stay transparent.
+    #[arg(long, value_name = "N", default_value = "3")]
+    retries: u32,
+}
+
+impl Arguments {
+    /// Where the source is sent. A cropped rectangle is sent where it would be in the whole image.
+    fn offset(&self) -> (u16, u16) {
+        match (self.rect, self.crop) {
+            (Some(rect), _) => (rect.x, rect.y),
+            (None, Some(crop)) => (self.x.saturating_add(crop.x), self.y.saturating_add(crop.y)),
+            (None, None) => (self.x, self.y),
+        }
+    }
+
+    fn adjustments(&self) -> Adjustments {
+        Adjustments {
+            brightness: self.brightness,
+            contrast: self.contrast,
+            gamma: self.gamma,
+        }
+    }
 }
 
-fn send_pixel_from_image(
-    image: &DynamicImage,
-    target: IpAddr,
+/// Parse a positive, possibly fractional rate.
+fn parse_rate(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|rate: &f64| rate.is_finite() && *rate > 0.0)
+        .ok_or_else(|| format!("must be a positive number: {text}"))
+}
+
+/// Parse a color like `ff8000` or `ff800080`.
+fn parse_color(text: &str) -> Result<Rgba<u8>, String> {
+    let text = text.strip_prefix('#').unwrap_or(text);
+    let value = u32::from_str_radix(text, 16)
+        .ok()
+        .filter(|_| text.len() == 6 || text.len() == 8)
+        .ok_or_else(|| format!("color must be given as hexadecimal RRGGBB or RRGGBBAA: {text}"))?;
+    Ok(Rgba(if text.len() == 6 {
+        let [_, r, g, b] = value.to_be_bytes();
+        [r, g, b, 0xff]
+    } else {
+        value.to_be_bytes()
+    }))
+}
+
+/// Parse a point like 100,50.
+fn parse_point(text: &str) -> Result<(u32, u32), String> {
+    text.split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| format!("not a point like 100,50: {text}"))
+}
+
+/// A rectangle of the image to send, or of the canvas to read.
+#[derive(Clone, Copy, Debug)]
+struct Rectangle {
     x: u16,
     y: u16,
-    offset_x: u16,
-    offset_y: u16,
-) -> Result<()> {
-    let pixel = image.get_pixel(x.into(), y.into());
-    set_pixel(target, x + offset_x, y + offset_y, color_from_rgba(pixel.0))?;
-    Ok(())
+    width: u16,
+    height: u16,
+}
+
+impl Rectangle {
+    fn contains(&self, x: u16, y: u16) -> bool {
+        (self.x..self.x.saturating_add(self.width)).contains(&x)
+            && (self.y..self.y.saturating_add(self.height)).contains(&y)
+    }
+}
+
+/// Parse a rectangle like 0,0,960,540.
+fn parse_rectangle(text: &str) -> Result<Rectangle, String> {
+    let numbers: Vec<u16> = text
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("not a rectangle like 0,0,960,540: {text}"))?;
+    match numbers[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Rectangle {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(format!("not a rectangle like 0,0,960,540: {text}")),
+    }
+}
+
+/// Parse a number in the given range.
+fn parse_number(text: &str, range: RangeInclusive<f64>) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|number| range.contains(number))
+        .ok_or_else(|| {
+            format!(
+                "must be a number from {} to {}: {text}",
+                range.start(),
+                range.end()
+            )
+        })
+}
+
+/// Parse a positive, possibly fractional number of seconds.
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    parse_rate(text).map(Duration::from_secs_f64)
+}
+
+/// What is sent.
+enum Source {
+    /// An image, or the frames of an animation.
+    Frames(Vec<Frame>),
+    /// A video that is decoded while it is sent.
+    Video(Video),
+    /// An image moving around.
+    Motion(Motion),
+    /// Images shown one after another.
+    Slideshow(Slideshow),
+    /// An image that is downloaded again at an interval.
+    Polled(Polled),
+    /// A procedural effect.
+    Demo(Demo),
+}
+
+impl Source {
+    fn size(&self) -> (u32, u32) {
+        match self {
+            Source::Frames(frames) => frames[0].image.dimensions(),
+            Source::Video(video) => (video.width, video.height),
+            Source::Motion(motion) => motion.area,
+            Source::Slideshow(slideshow) => slideshow.area,
+            Source::Polled(polled) => polled.image.dimensions(),
+            Source::Demo(demo) => demo.area,
+        }
+    }
+}
+
+/// How long stopped jobs have to end after Ctrl+C, before the client exits without waiting for them.
+const STOP_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+thread_local! {
+    /// Every worker thread sends on its own socket per target, which it keeps open for its lifetime.
+    static CLIENTS: RefCell<HashMap<SocketAddr, PingxelflutClient>> = RefCell::new(HashMap::new());
+    /// Every worker thread packs the pixels it sends to a target on its own.
+    static PACKERS: RefCell<HashMap<SocketAddr, Packer>> = RefCell::new(HashMap::new());
+}
+
+fn send_packet(target: SocketAddr, packet: &Packet) -> io::Result<()> {
+    CLIENTS.with_borrow_mut(|clients| {
+        let client = match clients.entry(target) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(PingxelflutClient::new_at(target)?),
+        };
+        client.send(packet)
+    })
+}
+
+/// Adds a pixel to those the current thread packs for the target, handing out the packets that are complete.
+fn pack(
+    target: SocketAddr,
+    packing: &Packing,
+    x: u16,
+    y: u16,
+    color: Color,
+    mut send: impl FnMut(Packet<'_>),
+) {
+    PACKERS.with_borrow_mut(|packers| {
+        packers
+            .entry(target)
+            .or_insert_with(|| Packer::new(packing.clone()))
+            .push(x, y, color, &mut send);
+    });
+}
+
+/// Hands out the packets of all pixels the current thread holds back for the target.
+fn flush_packer(target: SocketAddr, mut send: impl FnMut(Packet<'_>)) {
+    PACKERS.with_borrow_mut(|packers| {
+        if let Some(packer) = packers.get_mut(&target) {
+            packer.flush(&mut send);
+        }
+    });
+}
+
+/// Waits until the user presses Ctrl+C.
+fn wait_for_interrupt() -> io::Result<()> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(tokio::signal::ctrl_c())
 }
 
 fn main() -> Result<()> {
     let arguments: Arguments = Parser::parse();
-    let mut image = image::open(arguments.image)?;
+    if let Some(Command::Scrape(scrape)) = &arguments.command {
+        let target = address::resolve(&scrape.target, scrape.resolution)?;
+        let region = match scrape.region {
+            Some(region) => region,
+            None => {
+                let (width, height) = get_size_at(target)?;
+                Rectangle {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                }
+            }
+        };
+        return Scrape {
+            target,
+            x: region.x,
+            y: region.y,
+            width: region.width,
+            height: region.height,
+            rate: scrape.rate,
+            timeout: scrape.timeout,
+            retries: scrape.retries,
+        }
+        .run(&scrape.output);
+    }
+    if let Some(Command::Daemon {
+        control_address,
+        control_token,
+        jobs,
+    }) = arguments.command
+    {
+        let jobs = match jobs {
+            Some(file) => jobs::load(&file)?,
+            None => Vec::new(),
+        };
+        configure_threads(&jobs)?;
+        let stats = Arc::new(Stats::new(0));
+        report(&stats, Vec::new());
+        return daemon::run(control_address, control_token, jobs, stats);
+    }
+    let jobs = match &arguments.command {
+        Some(Command::Jobs { file }) => jobs::load(file)?,
+        _ => vec![Job {
+            arguments,
+            priority: 0,
+        }],
+    };
+    run(jobs)
+}
+
+/// Prints the statistics every second. When the user presses Ctrl+C, the jobs with the given controls are stopped,
+/// so that the caller can print the summary once they have ended. Jobs that can't be stopped, such as those of the other
+/// engines, get a grace period, after which the summary is printed and the client exits anyway.
+///
+/// Returns whether the user pressed Ctrl+C.
+fn report(stats: &Arc<Stats>, controls: Vec<Arc<Control>>) -> Arc<AtomicBool> {
+    stats.clone().report_periodically();
+    let stats = stats.clone();
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    thread::spawn(move || {
+        if wait_for_interrupt().is_ok() {
+            flag.store(true, Ordering::Relaxed);
+            if !controls.is_empty() {
+                eprintln!("stopping, press Ctrl+C again to quit immediately");
+                for control in &controls {
+                    control.stop();
+                }
+                thread::spawn(|| {
+                    let _ = wait_for_interrupt();
+                    std::process::exit(130);
+                });
+                thread::sleep(STOP_GRACE_PERIOD);
+            }
+            stats.print_summary();
+            std::process::exit(130);
+        }
+    });
+    interrupted
+}
+
+/// Sets up the sending threads for all jobs together.
+fn configure_threads(jobs: &[Job]) -> Result<()> {
+    cores::configure(
+        jobs.iter().filter_map(|job| job.arguments.threads).max(),
+        jobs.iter().any(|job| job.arguments.pin_cores),
+    )
+}
+
+/// Sends all jobs to all of their targets at the same time, until they are done or one of them fails.
+fn run(jobs: Vec<Job>) -> Result<()> {
+    configure_threads(&jobs)?;
+    let jobs = jobs
+        .into_iter()
+        .map(Job::prepare)
+        .collect::<Result<Vec<_>>>()?;
+    let running: Vec<_> = jobs.iter().map(|(job, _)| job).collect();
+    jobs::check_reservations(&running)?;
+    jobs::cover(&running);
+    let stats = Arc::new(Stats::new(jobs.iter().map(|(job, _)| job.pixels()).sum()));
+    let controls: Vec<_> = jobs.iter().map(|(job, _)| job.control.clone()).collect();
+    let interrupted = report(&stats, controls.clone());
+    pause::listen(controls);
+
+    // The first error ends all jobs.
+    let (results, finished) = mpsc::channel();
+    for (job, sources) in jobs {
+        let results = results.clone();
+        job.start(sources, &stats, move |target, result| {
+            let _ = results.send(result.with_context(|| format!("could not send to {target}")));
+        });
+    }
+    drop(results);
+    let result = finished.iter().find(Result::is_err).unwrap_or(Ok(()));
+    stats.print_summary();
+    if interrupted.load(Ordering::Relaxed) {
+        std::process::exit(130);
+    }
+    result
+}
+
+/// Loads the source for the target, sized for its canvas.
+fn open_source(arguments: &Arguments, target: SocketAddr) -> Result<Source> {
     let (width, height) = if arguments.no_request_size {
         (1920u16, 1080u16)
     } else {
-        get_size(arguments.target)?
+        get_size_at(target)?
     };
-
-    image = image.crop_imm(
-        0,
-        0,
-        image.width().min(width.into()),
-        image.height().min(height.into()),
+    // Videos are scaled to fit the canvas next to the offset and the configured size.
+    // Images are only scaled if asked to, and cropped to the canvas.
+    let (x, y) = match arguments.rect {
+        Some(rect) => (rect.x, rect.y),
+        None => (arguments.x, arguments.y),
+    };
+    let canvas_region = (
+        u32::from(width.saturating_sub(x)),
+        u32::from(height.saturating_sub(y)),
     );
-
-    loop {
-        (0..(image.width() as u16)).into_par_iter().for_each(|x| {
-            for y in 0..(image.height() as u16) {
-                let result =
-                    send_pixel_from_image(&image, arguments.target, x, y, arguments.x, arguments.y);
-                if let Err(err) = result {
-                    eprintln!("error while sending pixel: {:?}", err);
+    let (region_width, region_height) = match arguments.rect {
+        Some(rect) => (Some(rect.width.into()), Some(rect.height.into())),
+        None => (arguments.width, arguments.height),
+    };
+    let region = (
+        region_width.unwrap_or(u32::MAX).min(canvas_region.0),
+        region_height.unwrap_or(u32::MAX).min(canvas_region.1),
+    );
+    let image_scale = if arguments.fit {
+        Some(region)
+    } else if arguments.width.is_some() || arguments.height.is_some() {
+        Some((
+            arguments.width.unwrap_or(u32::MAX),
+            arguments.height.unwrap_or(u32::MAX),
+        ))
+    } else {
+        None
+    };
+    let image = || {
+        arguments
+            .image
+            .as_deref()
+            .expect("an image is required without a capture, pattern or clipboard")
+    };
+    if let Some(effect) = arguments.effect {
+        return Ok(Source::Demo(Demo {
+            effect,
+            area: region,
+            interval: Duration::from_secs_f64(1.0 / arguments.fps.unwrap_or(30.0)),
+        }));
+    }
+    if !arguments.slideshow.is_empty() {
+        return Ok(Source::Slideshow(Slideshow {
+            paths: arguments.slideshow.clone(),
+            area: region,
+            dwell: arguments.dwell,
+            transition: arguments.transition,
+            transition_time: arguments.transition_time,
+            interval: Duration::from_secs_f64(1.0 / arguments.fps.unwrap_or(10.0)),
+            background: arguments.background,
+            scale: image_scale,
+            filter: arguments.filter.filter_type(),
+            adjustments: arguments.adjustments(),
+        }));
+    }
+    let video = if let Some(screen) = arguments.capture_screen {
+        Some(Video::capture_screen(
+            screen,
+            &arguments.ffmpeg,
+            arguments.fps,
+            region,
+        )?)
+    } else if let Some(camera) = &arguments.webcam {
+        Some(Video::capture_webcam(
+            camera.as_deref(),
+            &arguments.ffmpeg,
+            arguments.fps,
+            region,
+        )?)
+    } else if arguments.video {
+        Some(Video::open(
+            image(),
+            &arguments.ffmpeg,
+            arguments.fps,
+            region,
+        )?)
+    } else {
+        None
+    };
+    Ok(match video {
+        Some(mut video) => {
+            video.mirror = arguments.mirror;
+            video.scaler = arguments.filter.ffmpeg_name();
+            video.adjustments = arguments.adjustments();
+            video.queue = arguments.frame_queue;
+            Source::Video(video)
+        }
+        None => {
+            let upscale = arguments.scale.unwrap_or(1);
+            // Only the part of the image that ends up on the canvas is scaled up.
+            let canvas = (
+                u32::from(width).div_ceil(upscale),
+                u32::from(height).div_ceil(upscale),
+            );
+            // Fills are sent as a solid pattern to servers that don't accept fill rectangles.
+            let (pattern, colors) = match arguments.fill {
+                Some(fill) => (Some(Pattern::Solid), vec![fill]),
+                None => (arguments.pattern, arguments.color.clone()),
+            };
+            let mut frames = match (pattern, &arguments.sequence) {
+                (Some(pattern), _) => vec![Frame {
+                    image: pattern.generate(region, &colors, arguments.cell_size),
+                    delay: Duration::ZERO,
+                }],
+                (None, Some(sequence)) => sequence.load(
+                    arguments.fps.unwrap_or(25.0),
+                    image_scale,
+                    arguments.filter.filter_type(),
+                    canvas,
+                )?,
+                (None, None) if arguments.from_clipboard => {
+                    clipboard::load(image_scale, arguments.filter.filter_type(), canvas)?
+                }
+                (None, None) => {
+                    animation::load(image(), image_scale, arguments.filter.filter_type(), canvas)?
                 }
+            };
+            if upscale > 1 {
+                animation::upscale(&mut frames, upscale);
+                animation::fit(
+                    &mut frames,
+                    None,
+                    FilterType::Nearest,
+                    (width.into(), height.into()),
+                );
             }
-        });
+            if let Some(crop) = arguments.crop {
+                animation::crop(&mut frames, crop.x, crop.y, crop.width, crop.height);
+                if frames[0].image.is_empty() {
+                    anyhow::bail!("the cropped rectangle is outside of the image");
+                }
+            }
+            for frame in &mut frames {
+                arguments.adjustments().apply(&mut frame.image);
+            }
+            match arguments.motion {
+                Some(movement) => Source::Motion(Motion {
+                    image: frames.swap_remove(0).image,
+                    movement,
+                    area: canvas_region,
+                    path: arguments.path.clone(),
+                    speed: arguments.speed,
+                    interval: Duration::from_secs_f64(1.0 / arguments.fps.unwrap_or(30.0)),
+                    background: arguments.background,
+                }),
+                None if arguments.poll.is_some() => {
+                    let Some(url) = remote::url(image()) else {
+                        anyhow::bail!("only images from URLs can be polled");
+                    };
+                    Source::Polled(Polled {
+                        url: url.to_string(),
+                        interval: arguments.poll.unwrap_or_default(),
+                        scale: image_scale,
+                        filter: arguments.filter.filter_type(),
+                        canvas: (width.into(), height.into()),
+                        adjustments: arguments.adjustments(),
+                        image: frames.swap_remove(0).image,
+                    })
+                }
+                None => {
+                    let layers = Layers::load(&arguments.layer)?;
+                    for frame in &mut frames {
+                        layers.apply(&mut frame.image);
+                    }
+                    Source::Frames(frames)
+                }
+            }
+        }
+    })
+}
+
+/// Sends the image the configured number of times with the configured engine, or until an error.
+/// Pixels in the covered areas of the canvas are left out.
+/// Only the rayon engine plays animations, and can be paused, moved and stopped; the others send the first frame.
+fn send(
+    arguments: &Arguments,
+    target: SocketAddr,
+    source: Source,
+    control: &Arc<Control>,
+    covered: &RwLock<Vec<Rectangle>>,
+    pacer: Option<Arc<Pacer>>,
+    stats: &Arc<Stats>,
+) -> Result<()> {
+    let offset = control.offset();
+    // Released when sending ends, whichever way.
+    let _reservation = if arguments.reserve {
+        Some(Reservation::start(
+            target,
+            control.clone(),
+            source.size(),
+            arguments.reserve_lease,
+        )?)
+    } else {
+        None
+    };
+    let playback = Playback {
+        delta: arguments.delta,
+        full_refresh: arguments.full_refresh,
+        shuffle: arguments.shuffle,
+        priority: match (&arguments.priority_mask, arguments.order) {
+            (Some(mask), _) => Some(Arc::new(Priority::mask(mask)?)),
+            (None, Some(order)) => Some(Arc::new(Priority::Order(order))),
+            (None, None) => None,
+        },
+        passes: if arguments.once {
+            1
+        } else {
+            arguments.loops.unwrap_or(u64::MAX)
+        },
+        control: control.clone(),
+        prober: arguments
+            .reprobe
+            .map(|interval| Arc::new(Prober::start(target, interval))),
+    };
+
+    if arguments.blast || arguments.engine == Engine::Tokio {
+        if arguments.delta {
+            anyhow::bail!("delta mode is only supported by the rayon engine");
+        }
+        if arguments.verify {
+            anyhow::bail!("verification is only supported by the rayon engine");
+        }
+        if playback.priority.is_some() {
+            anyhow::bail!("priority orders are only supported by the rayon engine");
+        }
+        if arguments.mask.is_some() {
+            anyhow::bail!("masks are only supported by the rayon engine");
+        }
+        if playback.prober.is_some() {
+            anyhow::bail!("probing the canvas size again is only supported by the rayon engine");
+        }
+        if !covered.read().unwrap().is_empty() {
+            anyhow::bail!("overlapping jobs are only supported by the rayon engine");
+        }
+        let Source::Frames(mut frames) = source else {
+            anyhow::bail!(
+                "videos, moving images, slideshows, polled images and effects are only supported by the rayon engine"
+            );
+        };
+        if frames.len() > 1 {
+            eprintln!("only the rayon engine plays animations, sending the first frame");
+        }
+        let image = DynamicImage::from(frames.swap_remove(0).image);
+
+        if arguments.blast {
+            return blast::blast(
+                &image,
+                target,
+                offset,
+                match (arguments.header_included, arguments.source_prefix) {
+                    (_, Some(sources)) => Header::Ipv6 { sources },
+                    (true, None) => Header::Ipv4,
+                    (false, None) => Header::None,
+                },
+                &playback,
+                pacer.as_deref(),
+                stats,
+            );
+        }
+
+        #[cfg(unix)]
+        return tokio::runtime::Runtime::new()?.block_on(async_engine::run(
+            Arc::new(image),
+            target,
+            offset,
+            arguments.concurrency,
+            &playback,
+            pacer,
+            stats.clone(),
+        ));
+        #[cfg(not(unix))]
+        anyhow::bail!("the tokio engine is only supported on Unix");
+    }
+
+    let packing = if arguments.no_packing {
+        None
+    } else {
+        Packing::probe(target, arguments.payload_size, arguments.indexed)?
+    };
+    if arguments.indexed && packing.as_ref().and_then(Packing::palette).is_none() {
+        eprintln!("{target} has no palette, sending pixels with their colors");
+    }
+    let source = match (source, packing.as_ref().and_then(Packing::palette)) {
+        (Source::Frames(mut frames), Some(palette)) => {
+            for frame in &mut frames {
+                palette.dither(&mut frame.image);
+            }
+            Source::Frames(frames)
+        }
+        (source, _) => source,
+    };
+    // Images already have their layers, while the other sources get them pixel by pixel.
+    let layers = match source {
+        Source::Frames(_) => Layers::load(&[])?,
+        _ => Layers::load(&arguments.layer)?,
+    };
+    let mask = match &arguments.mask {
+        Some(path) => Some(Mask::load(path, arguments.mask_threshold)?),
+        None => None,
+    };
+    // The first error that ends the job.
+    let fatal = Mutex::new(None);
+    let deliver = |packet: Packet<'_>| {
+        let size = packet_size(target.ip(), packet.to_bytes().len());
+        if let Some(pacer) = &pacer {
+            pacer.wait(size);
+        }
+        match failure::send(stats, size, packet.pixels(), || {
+            send_packet(target, &packet)
+        }) {
+            Ok(true) => control.sent(),
+            Ok(false) => {}
+            Err(error) => {
+                fatal.lock().unwrap().get_or_insert(error);
+                control.stop();
+            }
+        }
+    };
+    // Servers that accept fill rectangles get fills as a few of them, unless pixels need to be masked or layered.
+    if let (Some(color), Some(max_area)) = (
+        arguments.fill,
+        packing.as_ref().and_then(Packing::max_fill_area),
+    ) {
+        if mask.is_none() && arguments.layer.is_empty() && !arguments.verify {
+            let fill = Fill {
+                color: color_from_rgba(color.0),
+                size: source.size(),
+            };
+            let result = fill.play(&playback, max_area, covered, deliver);
+            return match fatal.into_inner().unwrap() {
+                Some(error) => Err(error.into()),
+                None => result,
+            };
+        }
+    }
+    let send_pixel = |x: u16, y: u16, color| {
+        if mask
+            .as_ref()
+            .is_some_and(|mask| !mask.allows(x.into(), y.into()))
+        {
+            return;
+        }
+        let color = layers.composite(x.into(), y.into(), color);
+        let offset = control.offset();
+        let (Some(x), Some(y)) = (x.checked_add(offset.0), y.checked_add(offset.1)) else {
+            return;
+        };
+        if covered
+            .read()
+            .unwrap()
+            .iter()
+            .any(|area| area.contains(x, y))
+        {
+            return;
+        }
+        // Finish the frame quickly once the job is stopped or has to be resized.
+        if control.is_stopped()
+            || playback
+                .prober
+                .as_ref()
+                .is_some_and(|prober| prober.has_changed())
+        {
+            return;
+        }
+        match &packing {
+            Some(packing) => pack(target, packing, x, y, color, deliver),
+            None => deliver(Packet::SetPixel { x, y, color }),
+        }
+    };
+    let send = Flushing(send_pixel, || {
+        if packing.is_some() {
+            flush_packer(target, deliver);
+        }
+    });
+    let result = match source {
+        Source::Frames(frames) if arguments.verify => {
+            if frames.len() > 1 {
+                anyhow::bail!("only static images can be verified");
+            }
+            Verifier {
+                target,
+                samples: arguments.verify_samples,
+                interval: arguments.verify_interval,
+            }
+            .play(&frames[0].image, &playback, send)
+        }
+        Source::Frames(frames) => animation::play(&frames, &playback, send),
+        Source::Video(video) => video.play(&playback, send),
+        Source::Motion(motion) => motion.play(&playback, send),
+        Source::Slideshow(slideshow) => slideshow.play(&playback, send),
+        Source::Polled(polled) => polled.play(&playback, send),
+        Source::Demo(demo) => demo.play(&playback, send),
+    };
+    match fatal.into_inner().unwrap() {
+        Some(error) => Err(error.into()),
+        None => result,
     }
 }
@@ -0,0 +1,32 @@
+//! Masks, which limit the pixels that are sent to an arbitrary shape, so that a stencil can be sprayed onto the canvas
+//! and regions that should stay untouched are left alone.
+//!
+//! Like layers, masks are placed at the top left corner of the source. Pixels of the source outside of the mask
+//! are not sent.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::GrayImage;
+
+/// A grayscale mask, whose pixels above the threshold are sent.
+pub struct Mask {
+    image: GrayImage,
+    threshold: u8,
+}
+
+impl Mask {
+    pub fn load(path: &Path, threshold: u8) -> Result<Self> {
+        let image = image::open(path)
+            .with_context(|| format!("could not open mask {}", path.display()))?
+            .into_luma8();
+        Ok(Self { image, threshold })
+    }
+
+    /// Whether the pixel of the source at the given point is sent.
+    pub fn allows(&self, x: u32, y: u32) -> bool {
+        self.image
+            .get_pixel_checked(x, y)
+            .is_some_and(|pixel| pixel.0[0] > self.threshold)
+    }
+}
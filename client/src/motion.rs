@@ -0,0 +1,140 @@
+//! Moving images, which bounce around, orbit or follow a path within an area of the canvas.
+//!
+//! Every step renders the whole area with the image at its new position on a background color,
+//! and delta sending then only draws the image where it moved to and erases it where it moved away from.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use image::{imageops, Rgba, RgbaImage};
+
+use crate::animation::{Playback, Player, Sink};
+
+/// How the image moves.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Movement {
+    /// Diagonally, bouncing off the edges of the area like a DVD logo.
+    Bounce,
+    /// In the largest circle around the center of the area.
+    Orbit,
+    /// Along a path through the given points, returning to the first one.
+    Path,
+}
+
+/// An image moving within an area.
+pub struct Motion {
+    pub image: RgbaImage,
+    pub movement: Movement,
+    pub area: (u32, u32),
+    /// Points that the top left corner of the image passes with the path movement.
+    pub path: Vec<(u32, u32)>,
+    /// Speed in pixels per second.
+    pub speed: f64,
+    /// Time between two steps.
+    pub interval: Duration,
+    /// Color that the image is drawn on, which erases it where it moved away from.
+    pub background: Rgba<u8>,
+}
+
+/// Position on a line from 0 to `length` that goes back and forth, after moving the given distance.
+fn bounce(distance: f64, length: f64) -> f64 {
+    if length <= 0.0 {
+        return 0.0;
+    }
+    let position = distance % (2.0 * length);
+    if position <= length {
+        position
+    } else {
+        2.0 * length - position
+    }
+}
+
+impl Motion {
+    /// Position of the top left corner of the image after the given time.
+    fn position(&self, seconds: f64) -> (f64, f64) {
+        let distance = self.speed * seconds;
+        // How far the image can move until it touches the edges.
+        let room_x = self.area.0.saturating_sub(self.image.width()) as f64;
+        let room_y = self.area.1.saturating_sub(self.image.height()) as f64;
+        match self.movement {
+            Movement::Bounce => {
+                let distance = distance / std::f64::consts::SQRT_2;
+                (bounce(distance, room_x), bounce(distance, room_y))
+            }
+            Movement::Orbit => {
+                let radius = (room_x / 2.0).min(room_y / 2.0);
+                if radius <= 0.0 {
+                    return (room_x / 2.0, room_y / 2.0);
+                }
+                let angle = distance / radius;
+                (
+                    room_x / 2.0 + radius * angle.cos(),
+                    room_y / 2.0 + radius * angle.sin(),
+                )
+            }
+            Movement::Path => {
+                let points: Vec<(f64, f64)> = self
+                    .path
+                    .iter()
+                    .map(|&(x, y)| (x as f64, y as f64))
+                    .collect();
+                // Every point with the next one, and the last one with the first one.
+                let segments = || points.iter().zip(points.iter().cycle().skip(1));
+                let length =
+                    |(from, to): (&(f64, f64), &(f64, f64))| (to.0 - from.0).hypot(to.1 - from.1);
+                let total_length: f64 = segments().map(length).sum();
+                if total_length <= 0.0 {
+                    return points.first().copied().unwrap_or_default();
+                }
+                let mut distance = distance % total_length;
+                for (from, to) in segments() {
+                    let segment_length = length((from, to));
+                    if distance <= segment_length && segment_length > 0.0 {
+                        let fraction = distance / segment_length;
+                        return (
+                            from.0 + (to.0 - from.0) * fraction,
+                            from.1 + (to.1 - from.1) * fraction,
+                        );
+                    }
+                    distance -= segment_length;
+                }
+                points[0]
+            }
+        }
+    }
+
+    /// The area with the image at its position after the given time.
+    fn render(&self, seconds: f64) -> RgbaImage {
+        let (x, y) = self.position(seconds);
+        let mut frame = RgbaImage::from_pixel(self.area.0, self.area.1, self.background);
+        imageops::overlay(&mut frame, &self.image, x.round() as i64, y.round() as i64);
+        frame
+    }
+
+    /// Moves the image until the client or the job is stopped, sending changed pixels in parallel with the given function.
+    /// Steps that can't be sent in time are skipped.
+    pub fn play(&self, playback: &Playback, send: impl Sink) -> Result<()> {
+        let playback = Playback {
+            delta: true,
+            ..playback.clone()
+        };
+        let mut player = Player::new(&playback);
+        let mut previous: Option<RgbaImage> = None;
+        let started = Instant::now();
+        let mut next_step = started;
+        loop {
+            let frame = self.render(next_step.duration_since(started).as_secs_f64());
+            player.show(&frame, previous.as_ref(), &send)?;
+            previous = Some(frame);
+
+            next_step += self.interval;
+            let now = Instant::now();
+            while next_step < now {
+                next_step += self.interval;
+            }
+            thread::sleep(next_step - now);
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Rate limiting of all sending workers together, with a token bucket in the form of a schedule:
+//! every packet reserves the time it takes at the configured rate, and waits until its reserved time has come.
+//!
+//! With jitter, the time that every packet takes varies randomly around the configured rate, and workers start
+//! at random offsets, which spreads out bursts for switches and for rate limits of servers that count bursts.
+
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use pingxelflut::format::{color_from_rgba, Packet};
+use pingxelflut::icmp::{ICMP_HEADER_SIZE, IPV4_HEADER_SIZE, IPV6_HEADER_SIZE};
+use rand::Rng;
+
+/// How far the schedule may lag behind, which allows short bursts after pauses, such as oversleeping.
+const BURST: Duration = Duration::from_millis(10);
+
+/// Limits the packets and bits per second sent by all workers.
+pub struct Pacer {
+    /// Shortest time between two packets.
+    packet_interval: Duration,
+    /// Seconds that sending one byte takes, or zero without a bandwidth limit.
+    /// Fast links take less than a nanosecond per byte, which a `Duration` can’t represent.
+    seconds_per_byte: f64,
+    /// How much the time of every packet varies, as a fraction of it.
+    jitter: f64,
+    /// When the next packet may be sent.
+    next_send: Mutex<Instant>,
+}
+
+impl Pacer {
+    /// Returns a pacer for the given limits, or `None` if there aren’t any.
+    pub fn new(packets_per_second: Option<u32>, megabits_per_second: Option<f64>) -> Option<Self> {
+        if packets_per_second.is_none() && megabits_per_second.is_none() {
+            return None;
+        }
+        Some(Self {
+            packet_interval: packets_per_second
+                .map(|rate| Duration::from_secs(1) / rate)
+                .unwrap_or_default(),
+            seconds_per_byte: megabits_per_second
+                .map(|rate| 8.0 / (rate * 1_000_000.0))
+                .unwrap_or_default(),
+            jitter: 0.0,
+            next_send: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Varies the time of every packet randomly by up to this fraction of it, from 0 to 1.
+    /// The average rate stays the same.
+    pub fn with_jitter(self, jitter: f64) -> Self {
+        Self { jitter, ..self }
+    }
+
+    /// The time a packet of the given size takes at the configured rate.
+    fn cost(&self, size: usize) -> Duration {
+        self.packet_interval
+            .max(Duration::from_secs_f64(self.seconds_per_byte * size as f64))
+    }
+
+    /// A random offset for a worker to start at, so that workers don't send their first packets at the same time.
+    /// Offsets are within the jitter of the time between two packets of the same worker.
+    pub fn phase_offset(&self, size: usize, workers: usize) -> Duration {
+        if self.jitter == 0.0 {
+            return Duration::ZERO;
+        }
+        let spacing = self.cost(size) * u32::try_from(workers).unwrap_or(u32::MAX);
+        spacing.mul_f64(self.jitter * rand::thread_rng().gen::<f64>())
+    }
+
+    /// Reserves the time to send a packet of the given size, including IP and ICMP headers.
+    /// Returns how long to wait before sending it.
+    pub fn reserve(&self, size: usize) -> Duration {
+        let mut cost = self.cost(size);
+        if self.jitter > 0.0 {
+            cost = cost.mul_f64(1.0 + self.jitter * rand::thread_rng().gen_range(-1.0..=1.0));
+        }
+        let now = Instant::now();
+        let mut next_send = self.next_send.lock().unwrap();
+        let start = (*next_send).max(now.checked_sub(BURST).unwrap_or(now));
+        *next_send = start + cost;
+        start.saturating_duration_since(now)
+    }
+
+    /// Blocks until a packet of the given size may be sent.
+    pub fn wait(&self, size: usize) {
+        let delay = self.reserve(size);
+        if !delay.is_zero() {
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+/// Size of a ping to the target with the given payload, including IP and ICMP headers.
+pub fn packet_size(target: IpAddr, payload_size: usize) -> usize {
+    let ip_header_size = if target.is_ipv4() {
+        IPV4_HEADER_SIZE
+    } else {
+        IPV6_HEADER_SIZE
+    };
+    ip_header_size + ICMP_HEADER_SIZE + payload_size
+}
+
+/// Size of a ping to the target that sets a pixel.
+pub fn set_pixel_size(target: IpAddr) -> usize {
+    let packet = Packet::SetPixel {
+        x: 0,
+        y: 0,
+        color: color_from_rgba([0; 4]),
+    };
+    packet_size(target, packet.to_bytes().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reserves the given number of packets at once and returns how long the last one waits.
+    fn last_delay(pacer: &Pacer, packets: u32, size: usize) -> Duration {
+        (0..packets).map(|_| pacer.reserve(size)).last().unwrap()
+    }
+
+    #[test]
+    fn without_limits_there_is_no_pacer() {
+        assert!(Pacer::new(None, None).is_none());
+    }
+
+    #[test]
+    fn packets_are_spaced_at_the_rate() {
+        let pacer = Pacer::new(Some(1000), None).unwrap();
+        assert_eq!(pacer.reserve(100), Duration::ZERO);
+        let delay = last_delay(&pacer, 100, 100);
+        assert!(
+            (Duration::from_millis(95)..=Duration::from_millis(100)).contains(&delay),
+            "{delay:?}"
+        );
+    }
+
+    #[test]
+    fn large_packets_are_limited_by_the_bandwidth() {
+        // One microsecond per byte, so 10 kB take 10 ms, longer than the packet interval.
+        let pacer = Pacer::new(Some(1000), Some(8.0)).unwrap();
+        pacer.reserve(10_000);
+        let delay = pacer.reserve(10_000);
+        assert!(
+            (Duration::from_millis(9)..=Duration::from_millis(10)).contains(&delay),
+            "{delay:?}"
+        );
+    }
+
+    #[test]
+    fn pauses_allow_only_short_bursts() {
+        let pacer = Pacer::new(Some(1000), None).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        // The schedule catches up by at most the burst, so the 30th packet still waits about 20 ms.
+        let delay = last_delay(&pacer, 30, 100);
+        assert!(
+            (Duration::from_millis(15)..=Duration::from_millis(20)).contains(&delay),
+            "{delay:?}"
+        );
+    }
+
+    #[test]
+    fn jitter_keeps_the_average_rate() {
+        let pacer = Pacer::new(Some(1000), None).unwrap().with_jitter(0.5);
+        let delay = last_delay(&pacer, 1001, 100);
+        assert!(
+            (Duration::from_millis(950)..=Duration::from_millis(1050)).contains(&delay),
+            "{delay:?}"
+        );
+    }
+}
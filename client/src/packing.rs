@@ -0,0 +1,519 @@
+//! Packing of pixels into the larger packets of optional protocol extensions, for servers that support them.
+//!
+//! Pixels next to each other in a row become blit packets, or fill rectangle packets where they have the same color,
+//! and the remaining pixels are collected into set pixels packets. With `--indexed`, opaque pixels are sent to servers
+//! with a palette as indexed blits instead, quantized to the nearest palette color. Servers without these extensions
+//! get single set pixel packets, as do all servers with `--no-packing`.
+//!
+//! Every sending thread packs the pixels it sends on its own, in the order it sends them,
+//! so that rows are only packed as far as they are sent by one thread in one go.
+
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use pingxelflut::format::{
+    Capabilities, Color, Colors, Extensions, Packet, PixelEntries, COLOR_SIZE, PIXEL_ENTRY_SIZE,
+};
+use pingxelflut::icmp::{ICMP_HEADER_SIZE, IPV4_HEADER_SIZE, IPV6_HEADER_SIZE};
+use pingxelflut::{get_capabilities_at, get_palette_at};
+
+use crate::palette::Palette;
+
+/// How long to wait for the capabilities or the palette of the server, which servers without the extension never send.
+const CAPABILITIES_TIMEOUT: Duration = Duration::from_secs(1);
+/// Largest payload by default, so that packets fit into the Ethernet MTU of 1500 bytes even with an IPv6 header.
+pub const DEFAULT_PAYLOAD_SIZE: usize = 1500 - IPV6_HEADER_SIZE - ICMP_HEADER_SIZE;
+/// Smallest payload that fits two pixels into a set pixels packet.
+pub const MIN_PAYLOAD_SIZE: usize = 1 + 2 * PIXEL_ENTRY_SIZE;
+/// Largest payload of an IPv4 ping.
+pub const MAX_PAYLOAD_SIZE: usize = u16::MAX as usize - IPV4_HEADER_SIZE - ICMP_HEADER_SIZE;
+/// Same-colored pixels in a row become a fill rectangle from this many on, since shorter fills save little over a blit.
+const MIN_FILL_LENGTH: usize = 4;
+/// Pixels of the same palette color become a fill rectangle from this many on,
+/// since a fill rectangle takes as many bytes as that many indices.
+const MIN_INDEXED_FILL_LENGTH: usize = 12;
+
+/// Which of the larger packets a server accepts, and how large they may be.
+#[derive(Clone)]
+pub struct Packing {
+    extensions: Extensions,
+    /// Largest payload, in bytes from the packet type on.
+    payload_size: usize,
+    max_fill_area: usize,
+    /// Palette of the server, if opaque pixels are sent as indices into it.
+    palette: Option<Arc<Palette>>,
+}
+
+impl Packing {
+    /// Asks the server for its capabilities, and packs payloads of up to the given size if it accepts any larger packets.
+    /// If indexed pixels are wanted and the server has a palette, it is asked for the palette as well.
+    /// Returns `None` for servers that only accept single pixels.
+    pub fn probe(
+        target: SocketAddr,
+        payload_size: usize,
+        indexed: bool,
+    ) -> io::Result<Option<Self>> {
+        let Some(capabilities) = get_capabilities_at(target, CAPABILITIES_TIMEOUT)? else {
+            return Ok(None);
+        };
+        let palette = if indexed && capabilities.extensions.contains(Extensions::PALETTE) {
+            get_palette_at(target, CAPABILITIES_TIMEOUT)?
+                .filter(|colors| !colors.is_empty())
+                .map(|colors| Arc::new(Palette::new(colors)))
+        } else {
+            None
+        };
+        Ok(Self::new(capabilities, payload_size, palette))
+    }
+
+    fn new(
+        capabilities: Capabilities,
+        payload_size: usize,
+        palette: Option<Arc<Palette>>,
+    ) -> Option<Self> {
+        let extensions = capabilities.extensions;
+        let packs = [
+            Extensions::FILL_RECT,
+            Extensions::SET_PIXELS,
+            Extensions::BLIT,
+        ]
+        .into_iter()
+        .any(|extension| extensions.contains(extension));
+        (packs || palette.is_some()).then(|| Self {
+            extensions,
+            payload_size: payload_size.min(capabilities.max_packet_size.into()),
+            max_fill_area: capabilities.max_fill_area.try_into().unwrap_or(usize::MAX),
+            palette,
+        })
+    }
+
+    /// Most pixels a fill rectangle packet may cover, if the server accepts them.
+    pub fn max_fill_area(&self) -> Option<usize> {
+        Some(self.max_fill_area)
+            .filter(|&area| area > 0 && self.extensions.contains(Extensions::FILL_RECT))
+    }
+
+    /// The palette that opaque pixels are quantized to, if they are sent as indices.
+    pub fn palette(&self) -> Option<&Palette> {
+        self.palette.as_deref()
+    }
+
+    /// Most colors in one blit packet.
+    fn blit_capacity(&self) -> usize {
+        (self.payload_size.saturating_sub(Packet::BLIT_HEADER_SIZE) / COLOR_SIZE).max(1)
+    }
+
+    /// Most pixels in a row that are packed together.
+    fn max_run(&self) -> usize {
+        if self.palette.is_some() {
+            self.payload_size
+                .saturating_sub(Packet::BLIT_HEADER_SIZE)
+                .max(1)
+        } else if self.extensions.contains(Extensions::BLIT) {
+            self.blit_capacity()
+        } else if self.extensions.contains(Extensions::FILL_RECT) {
+            self.max_fill_area.clamp(1, u16::MAX.into())
+        } else {
+            1
+        }
+    }
+
+    /// Whether the pixel is sent as an index into the palette.
+    fn is_indexed(&self, color: Color) -> bool {
+        self.palette.is_some() && color.a == 0xff
+    }
+}
+
+/// Collects the pixels that one thread sends to one target, and hands out the packets they are packed into.
+pub struct Packer {
+    packing: Packing,
+    /// Position of the first pixel of the current row of pixels next to each other, which the next pixel may continue.
+    run_start: Option<(u16, u16)>,
+    /// Colors of the pixels of the current row.
+    run: Vec<Color>,
+    /// Encoded pixels of the next set pixels packet.
+    entries: Vec<u8>,
+    /// Palette indices of the colors seen so far, since finding the nearest palette color takes a while.
+    indices: HashMap<[u8; 3], u8>,
+}
+
+impl Packer {
+    pub fn new(packing: Packing) -> Self {
+        Self {
+            packing,
+            run_start: None,
+            run: Vec::new(),
+            entries: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Adds a pixel, and hands the packets that are complete to the function.
+    pub fn push(&mut self, x: u16, y: u16, color: Color, send: &mut impl FnMut(Packet<'_>)) {
+        if let Some((run_x, run_y)) = self.run_start {
+            let continues = y == run_y && usize::from(x) == usize::from(run_x) + self.run.len();
+            if continues && self.run.len() < self.packing.max_run() {
+                self.run.push(color);
+                return;
+            }
+            self.finish_run(send);
+        }
+        self.run_start = Some((x, y));
+        self.run.push(color);
+    }
+
+    /// Hands out the packets of all pixels that were added so far.
+    pub fn flush(&mut self, send: &mut impl FnMut(Packet<'_>)) {
+        self.finish_run(send);
+        self.send_entries(send);
+    }
+
+    /// Sends the current row, split into the parts that are sent as palette indices and those sent with their colors.
+    fn finish_run(&mut self, send: &mut impl FnMut(Packet<'_>)) {
+        let Some((x, y)) = self.run_start.take() else {
+            return;
+        };
+        let run = mem::take(&mut self.run);
+        let mut start = 0;
+        while start < run.len() {
+            let indexed = self.packing.is_indexed(run[start]);
+            let length = run[start..]
+                .iter()
+                .take_while(|&&color| self.packing.is_indexed(color) == indexed)
+                .count();
+            let part = &run[start..start + length];
+            if indexed {
+                self.send_indexed(x + start as u16, y, part, send);
+            } else {
+                self.send_colors(x + start as u16, y, part, send);
+            }
+            start += length;
+        }
+        // Keep the allocation for the next row.
+        self.run = run;
+        self.run.clear();
+    }
+
+    /// Sends pixels with their colors, with fill rectangles for long enough parts of the same color.
+    fn send_colors(&mut self, x: u16, y: u16, colors: &[Color], send: &mut impl FnMut(Packet<'_>)) {
+        let min_fill = self.min_fill(MIN_FILL_LENGTH);
+        for (range, fill) in split_fills(colors, min_fill, self.packing.max_fill_area) {
+            let start = x + range.start as u16;
+            if fill {
+                send(Packet::FillRect {
+                    x: start,
+                    y,
+                    width: range.len() as u16,
+                    height: 1,
+                    color: colors[range.start],
+                });
+            } else {
+                self.send_row(start, y, &colors[range], send);
+            }
+        }
+    }
+
+    /// Sends opaque pixels as indices of the nearest palette colors,
+    /// with fill rectangles for long enough parts of the same palette color.
+    fn send_indexed(
+        &mut self,
+        x: u16,
+        y: u16,
+        colors: &[Color],
+        send: &mut impl FnMut(Packet<'_>),
+    ) {
+        let palette = self
+            .packing
+            .palette
+            .clone()
+            .expect("only pixels for a palette are indexed");
+        let indices: Vec<u8> = colors
+            .iter()
+            .map(|&color| {
+                *self
+                    .indices
+                    .entry([color.r, color.g, color.b])
+                    .or_insert_with(|| palette.nearest(color))
+            })
+            .collect();
+        let min_fill = self.min_fill(MIN_INDEXED_FILL_LENGTH);
+        for (range, fill) in split_fills(&indices, min_fill, self.packing.max_fill_area) {
+            let start = x + range.start as u16;
+            if fill {
+                send(Packet::FillRect {
+                    x: start,
+                    y,
+                    width: range.len() as u16,
+                    height: 1,
+                    color: palette.color(indices[range.start]),
+                });
+            } else {
+                send(Packet::IndexedBlit {
+                    x: start,
+                    y,
+                    indices: &indices[range],
+                });
+            }
+        }
+    }
+
+    /// Fewest pixels of the same color in a row that are sent as a fill rectangle, if the server accepts them.
+    fn min_fill(&self, length: usize) -> usize {
+        if self.packing.extensions.contains(Extensions::FILL_RECT) {
+            length
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Sends pixels next to each other in a row as blits if the server accepts them,
+    /// and adds them to the next set pixels packet otherwise.
+    fn send_row(&mut self, x: u16, y: u16, colors: &[Color], send: &mut impl FnMut(Packet<'_>)) {
+        if colors.len() > 1 && self.packing.extensions.contains(Extensions::BLIT) {
+            let capacity = self.packing.blit_capacity();
+            for (chunk_index, chunk) in colors.chunks(capacity).enumerate() {
+                let x = x + (chunk_index * capacity) as u16;
+                if let [color] = chunk {
+                    self.push_entry(x, y, *color, send);
+                    continue;
+                }
+                let data: Vec<u8> = chunk
+                    .iter()
+                    .flat_map(|color| [color.r, color.g, color.b, color.a])
+                    .collect();
+                let colors = Colors::new(&data).expect("every color has four bytes");
+                send(Packet::Blit { x, y, colors });
+            }
+            return;
+        }
+        for (offset, &color) in colors.iter().enumerate() {
+            self.push_entry(x + offset as u16, y, color, send);
+        }
+    }
+
+    fn push_entry(&mut self, x: u16, y: u16, color: Color, send: &mut impl FnMut(Packet<'_>)) {
+        if !self.packing.extensions.contains(Extensions::SET_PIXELS) {
+            send(Packet::SetPixel { x, y, color });
+            return;
+        }
+        if 1 + self.entries.len() + PIXEL_ENTRY_SIZE > self.packing.payload_size {
+            self.send_entries(send);
+        }
+        self.entries
+            .extend_from_slice(&PixelEntries::encode(x, y, color));
+    }
+
+    fn send_entries(&mut self, send: &mut impl FnMut(Packet<'_>)) {
+        let entries = PixelEntries::new(&self.entries).expect("entries are whole");
+        match entries.len() {
+            0 => {}
+            // A single pixel is smaller on its own.
+            1 => {
+                let (x, y, color) = entries.iter().next().expect("there is one entry");
+                send(Packet::SetPixel { x, y, color });
+            }
+            _ => send(Packet::SetPixels(entries)),
+        }
+        self.entries.clear();
+    }
+}
+
+/// Splits a row into runs of at least `min_fill` and at most `max_fill` equal values, which are marked as fills,
+/// and the parts between them.
+fn split_fills<T: PartialEq>(
+    values: &[T],
+    min_fill: usize,
+    max_fill: usize,
+) -> Vec<(Range<usize>, bool)> {
+    let mut parts = Vec::new();
+    // Start of the values that aren't in a part yet.
+    let mut pending = 0;
+    let mut index = 0;
+    while index < values.len() {
+        let same = values[index..]
+            .iter()
+            .take_while(|&value| *value == values[index])
+            .count();
+        if same >= min_fill && same <= max_fill {
+            if pending < index {
+                parts.push((pending..index, false));
+            }
+            parts.push((index..index + same, true));
+            pending = index + same;
+        }
+        index += same;
+    }
+    if pending < values.len() {
+        parts.push((pending..values.len(), false));
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use pingxelflut::format::color_from_rgba;
+
+    use super::*;
+
+    const RED: Color = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+    const BLUE: Color = Color {
+        r: 0,
+        g: 0,
+        b: 255,
+        a: 255,
+    };
+
+    fn packing(extensions: Extensions) -> Packing {
+        Packing {
+            extensions,
+            payload_size: DEFAULT_PAYLOAD_SIZE,
+            max_fill_area: 16384,
+            palette: None,
+        }
+    }
+
+    /// Packs the pixels and returns the encoded packets.
+    fn pack(packing: Packing, pixels: &[(u16, u16, Color)]) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        let mut send = |packet: Packet<'_>| packets.push(packet.to_bytes());
+        let mut packer = Packer::new(packing);
+        for &(x, y, color) in pixels {
+            packer.push(x, y, color, &mut send);
+        }
+        packer.flush(&mut send);
+        packets
+    }
+
+    fn decode(packets: &[Vec<u8>]) -> Vec<Packet<'_>> {
+        packets
+            .iter()
+            .map(|packet| Packet::from_bytes(packet).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rows_become_blits_and_fills() {
+        let row: Vec<_> = [RED, BLUE, RED, RED, RED, RED, BLUE]
+            .into_iter()
+            .enumerate()
+            .map(|(x, color)| (x as u16 + 10, 5, color))
+            .collect();
+        let packets = pack(packing(Extensions::FILL_RECT | Extensions::BLIT), &row);
+        let packets = decode(&packets);
+        assert!(matches!(packets[0], Packet::Blit { x: 10, y: 5, colors } if colors.len() == 2));
+        assert_eq!(
+            packets[1],
+            Packet::FillRect {
+                x: 12,
+                y: 5,
+                width: 4,
+                height: 1,
+                color: RED,
+            }
+        );
+        assert_eq!(
+            packets[2],
+            Packet::SetPixel {
+                x: 16,
+                y: 5,
+                color: BLUE,
+            }
+        );
+        assert_eq!(packets.len(), 3);
+    }
+
+    #[test]
+    fn scattered_pixels_are_batched() {
+        let pixels: Vec<_> = (0..400)
+            .map(|index| (index * 2, index, color_from_rgba([index as u8, 0, 0, 255])))
+            .collect();
+        let packets = pack(packing(Extensions::SET_PIXELS | Extensions::BLIT), &pixels);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.len() <= DEFAULT_PAYLOAD_SIZE));
+        let unpacked: Vec<_> = decode(&packets)
+            .into_iter()
+            .flat_map(|packet| match packet {
+                Packet::SetPixels(entries) => entries.iter().collect::<Vec<_>>(),
+                packet => panic!("unexpected {packet:?}"),
+            })
+            .collect();
+        assert_eq!(unpacked, pixels);
+    }
+
+    #[test]
+    fn packets_fit_the_payload_size() {
+        let packing = Packing {
+            payload_size: 100,
+            ..packing(Extensions::SET_PIXELS | Extensions::BLIT)
+        };
+        let row: Vec<_> = (0..1000)
+            .map(|x| (x, 0, color_from_rgba([x as u8, 0, 0, 255])))
+            .collect();
+        let packets = pack(packing, &row);
+        assert!(packets.iter().all(|packet| packet.len() <= 100));
+        let pixels: u64 = decode(&packets).iter().map(Packet::pixels).sum();
+        assert_eq!(pixels, 1000);
+        assert_eq!(packets.len(), 1000usize.div_ceil((100 - 5) / 4));
+    }
+
+    #[test]
+    fn opaque_pixels_become_indices() {
+        let packing = Packing {
+            palette: Some(Arc::new(Palette::new(vec![RED, BLUE]))),
+            ..packing(Extensions::FILL_RECT | Extensions::BLIT | Extensions::PALETTE)
+        };
+        let translucent = color_from_rgba([0, 0, 255, 128]);
+        let mut row = vec![RED, color_from_rgba([200, 0, 50, 255]), BLUE, translucent];
+        row.extend([BLUE; 12]);
+        let pixels: Vec<_> = row
+            .into_iter()
+            .enumerate()
+            .map(|(x, color)| (x as u16, 0, color))
+            .collect();
+        let packets = pack(packing, &pixels);
+        assert_eq!(
+            decode(&packets),
+            [
+                Packet::IndexedBlit {
+                    x: 0,
+                    y: 0,
+                    indices: &[0, 0, 1],
+                },
+                Packet::SetPixel {
+                    x: 3,
+                    y: 0,
+                    color: translucent,
+                },
+                Packet::FillRect {
+                    x: 4,
+                    y: 0,
+                    width: 12,
+                    height: 1,
+                    color: BLUE,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn without_extensions_pixels_are_single() {
+        let pixels = [(0, 0, RED), (1, 0, RED), (2, 0, BLUE)];
+        let packets = pack(packing(Extensions::GET_PIXEL), &pixels);
+        let expected: Vec<_> = pixels
+            .iter()
+            .map(|&(x, y, color)| Packet::SetPixel { x, y, color })
+            .collect();
+        assert_eq!(decode(&packets), expected);
+    }
+}
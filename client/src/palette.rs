@@ -0,0 +1,120 @@
+//! Quantization of images to the palette of a server, so that pixels can be sent as indices into it.
+
+use std::mem;
+
+use image::{Rgba, RgbaImage};
+use pingxelflut::format::Color;
+
+/// The colors of a server’s palette.
+pub struct Palette {
+    colors: Vec<Color>,
+}
+
+impl Palette {
+    /// Wraps the colors of a palette response, which has at least one and at most 256 colors.
+    pub fn new(colors: Vec<Color>) -> Self {
+        assert!(
+            (1..=256).contains(&colors.len()),
+            "palettes have 1 to 256 colors"
+        );
+        Self { colors }
+    }
+
+    /// Index of the palette color closest to the color, ignoring its alpha value.
+    pub fn nearest(&self, color: Color) -> u8 {
+        self.nearest_rgb([color.r, color.g, color.b].map(i32::from))
+    }
+
+    fn nearest_rgb(&self, [r, g, b]: [i32; 3]) -> u8 {
+        let distance = |color: &Color| {
+            let (dr, dg, db) = (
+                r - i32::from(color.r),
+                g - i32::from(color.g),
+                b - i32::from(color.b),
+            );
+            dr * dr + dg * dg + db * db
+        };
+        (0..self.colors.len())
+            .min_by_key(|&index| distance(&self.colors[index]))
+            .expect("palettes aren't empty") as u8
+    }
+
+    pub fn color(&self, index: u8) -> Color {
+        self.colors[usize::from(index)]
+    }
+
+    /// Replaces the opaque pixels of the image with palette colors, spreading the difference of every pixel to its
+    /// color onto the pixels right and below with Floyd–Steinberg dithering. Translucent pixels are left as they are.
+    pub fn dither(&self, image: &mut RgbaImage) {
+        let width = image.width() as usize;
+        // Differences carried over to the current and the next row, in sixteenths and with a pixel of margin on either side.
+        let mut current = vec![[0i32; 3]; width + 2];
+        let mut next = vec![[0i32; 3]; width + 2];
+        for y in 0..image.height() {
+            for x in 0..width {
+                let pixel = image.get_pixel_mut(x as u32, y);
+                if pixel[3] != 0xff {
+                    continue;
+                }
+                let carried = current[x + 1];
+                let wanted: [i32; 3] = [0, 1, 2].map(|channel| {
+                    (i32::from(pixel[channel]) + carried[channel] / 16).clamp(0, 255)
+                });
+                let color = self.color(self.nearest_rgb(wanted));
+                *pixel = Rgba([color.r, color.g, color.b, 0xff]);
+                let difference = [
+                    wanted[0] - i32::from(color.r),
+                    wanted[1] - i32::from(color.g),
+                    wanted[2] - i32::from(color.b),
+                ];
+                for channel in 0..3 {
+                    current[x + 2][channel] += difference[channel] * 7;
+                    next[x][channel] += difference[channel] * 3;
+                    next[x + 1][channel] += difference[channel] * 5;
+                    next[x + 2][channel] += difference[channel];
+                }
+            }
+            current = mem::replace(&mut next, vec![[0; 3]; width + 2]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pingxelflut::format::color_from_rgb;
+
+    use super::*;
+
+    fn black_and_white() -> Palette {
+        Palette::new(vec![
+            color_from_rgb([0, 0, 0]),
+            color_from_rgb([255, 255, 255]),
+        ])
+    }
+
+    #[test]
+    fn nearest_color() {
+        let palette = black_and_white();
+        assert_eq!(palette.nearest(color_from_rgb([30, 40, 50])), 0);
+        assert_eq!(palette.nearest(color_from_rgb([200, 100, 250])), 1);
+    }
+
+    #[test]
+    fn dithering_keeps_the_average() {
+        let palette = black_and_white();
+        let mut image = RgbaImage::from_pixel(64, 64, Rgba([128, 128, 128, 255]));
+        image.put_pixel(0, 0, Rgba([128, 128, 128, 100]));
+        palette.dither(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([128, 128, 128, 100]));
+        let opaque: Vec<_> = image.pixels().filter(|pixel| pixel[3] == 0xff).collect();
+        assert!(opaque
+            .iter()
+            .all(|pixel| pixel.0 == [0, 0, 0, 255] || pixel.0 == [255, 255, 255, 255]));
+        let white = opaque.iter().filter(|pixel| pixel[0] == 255).count();
+        let share = white as f64 / opaque.len() as f64;
+        assert!(
+            (share - 0.5).abs() < 0.05,
+            "{share} of the pixels are white"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Generated patterns, which exercise the canvas and the network without any input file.
+
+use clap::ValueEnum;
+use image::{Rgba, RgbaImage};
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 0xff]);
+const WHITE: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+
+/// The bars of a test card at 75 % brightness: white, yellow, cyan, green, magenta, red and blue.
+const COLOR_BARS: [Rgba<u8>; 7] = [
+    Rgba([0xbf, 0xbf, 0xbf, 0xff]),
+    Rgba([0xbf, 0xbf, 0x00, 0xff]),
+    Rgba([0x00, 0xbf, 0xbf, 0xff]),
+    Rgba([0x00, 0xbf, 0x00, 0xff]),
+    Rgba([0xbf, 0x00, 0xbf, 0xff]),
+    Rgba([0xbf, 0x00, 0x00, 0xff]),
+    Rgba([0x00, 0x00, 0xbf, 0xff]),
+];
+
+/// A pattern to send instead of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Pattern {
+    /// A horizontal gradient through the colors, black to white by default.
+    Gradient,
+    /// Random colors, or a random one of the colors for every cell.
+    Noise,
+    /// Cells alternating between the colors, black and white by default.
+    Checkerboard,
+    /// Vertical bars of the colors, the bars of a test card by default.
+    Colorbars,
+    /// The first color, white by default.
+    Solid,
+}
+
+impl Pattern {
+    /// Generates the pattern in the given size, with square cells of the given size for noise and checkerboards.
+    pub fn generate(
+        self,
+        (width, height): (u32, u32),
+        colors: &[Rgba<u8>],
+        cell_size: u32,
+    ) -> RgbaImage {
+        let colors = match (self, colors) {
+            (_, [_, ..]) => colors,
+            (Pattern::Colorbars, []) => &COLOR_BARS,
+            (Pattern::Noise, []) => &[],
+            (Pattern::Solid, []) => &[WHITE],
+            (Pattern::Gradient | Pattern::Checkerboard, []) => &[BLACK, WHITE],
+        };
+        let cell_size = cell_size.max(1);
+        match self {
+            Pattern::Gradient => RgbaImage::from_fn(width, height, |x, _| {
+                if colors.len() == 1 || width <= 1 {
+                    return colors[0];
+                }
+                // Position between the first and the last color.
+                let position = x as f64 / (width - 1) as f64 * (colors.len() - 1) as f64;
+                let index = (position as usize).min(colors.len() - 2);
+                let fraction = position - index as f64;
+                let (from, to) = (colors[index].0, colors[index + 1].0);
+                Rgba(std::array::from_fn(|channel| {
+                    (from[channel] as f64 + (to[channel] as f64 - from[channel] as f64) * fraction)
+                        .round() as u8
+                }))
+            }),
+            Pattern::Noise => {
+                let mut rng = rand::thread_rng();
+                let columns = width.div_ceil(cell_size) as usize;
+                let cells: Vec<Rgba<u8>> = (0..columns * height.div_ceil(cell_size) as usize)
+                    .map(|_| match colors.choose(&mut rng) {
+                        Some(&color) => color,
+                        None => Rgba([rng.gen(), rng.gen(), rng.gen(), 0xff]),
+                    })
+                    .collect();
+                RgbaImage::from_fn(width, height, |x, y| {
+                    cells[(y / cell_size) as usize * columns + (x / cell_size) as usize]
+                })
+            }
+            Pattern::Checkerboard => RgbaImage::from_fn(width, height, |x, y| {
+                colors[(x / cell_size + y / cell_size) as usize % colors.len()]
+            }),
+            Pattern::Colorbars => RgbaImage::from_fn(width, height, |x, _| {
+                colors[(x as usize * colors.len() / width as usize).min(colors.len() - 1)]
+            }),
+            Pattern::Solid => RgbaImage::from_pixel(width, height, colors[0]),
+        }
+    }
+}
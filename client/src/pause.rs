@@ -0,0 +1,62 @@
+//! Pausing and resuming all jobs from outside, so that operators can yield the canvas for a moment:
+//! SIGUSR1 pauses and SIGUSR2 resumes, and in a terminal, pressing Enter toggles the pause.
+//!
+//! Like pausing through the control API, this only affects jobs sent with the rayon engine,
+//! which continue where they were paused.
+
+use std::io::{self, BufRead, IsTerminal};
+use std::sync::Arc;
+use std::thread;
+
+use crate::control::Control;
+
+/// Pauses or resumes the jobs, and tells the user.
+fn set_paused(controls: &[Arc<Control>], paused: bool) {
+    for control in controls {
+        if paused {
+            control.pause();
+        } else {
+            control.resume();
+        }
+    }
+    if paused {
+        eprintln!("paused");
+    } else {
+        eprintln!("resumed");
+    }
+}
+
+/// Listens for pause and resume requests for the jobs in the background.
+pub fn listen(controls: Vec<Arc<Control>>) {
+    let controls: Arc<[Arc<Control>]> = controls.into();
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        for (kind, paused) in [
+            (SignalKind::user_defined1(), true),
+            (SignalKind::user_defined2(), false),
+        ] {
+            let controls = controls.clone();
+            thread::spawn(move || -> io::Result<()> {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                runtime.block_on(async {
+                    let mut signals = signal(kind)?;
+                    while signals.recv().await.is_some() {
+                        set_paused(&controls, paused);
+                    }
+                    Ok(())
+                })
+            });
+        }
+    }
+    if io::stdin().is_terminal() {
+        thread::spawn(move || {
+            for _ in io::stdin().lock().lines().map_while(Result::ok) {
+                let paused = controls.first().is_some_and(|control| control.is_paused());
+                set_paused(&controls, !paused);
+            }
+        });
+    }
+}
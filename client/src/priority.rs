@@ -0,0 +1,91 @@
+//! Priority orders, which send the most recognizable pixels of a frame first,
+//! so that they appear on the canvas early when other clients draw over it.
+
+use std::cmp::Reverse;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::{GrayImage, RgbaImage};
+
+/// Which pixels are the most important.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    /// The pixels in the middle of the frame, then outwards.
+    Center,
+    /// The pixels with the most contrast to their neighbors, such as edges and text.
+    Detail,
+    /// The seven passes of Adam7 interlacing, as in PNG: a coarse grid of the whole frame first,
+    /// which is refined by the following passes.
+    Interlaced,
+}
+
+/// The Adam7 pass of every pixel within a block of 8×8 pixels.
+const ADAM7: [[u8; 8]; 8] = [
+    [1, 6, 4, 6, 2, 6, 4, 6],
+    [7, 7, 7, 7, 7, 7, 7, 7],
+    [5, 6, 5, 6, 5, 6, 5, 6],
+    [7, 7, 7, 7, 7, 7, 7, 7],
+    [3, 6, 4, 6, 3, 6, 4, 6],
+    [7, 7, 7, 7, 7, 7, 7, 7],
+    [5, 6, 5, 6, 5, 6, 5, 6],
+    [7, 7, 7, 7, 7, 7, 7, 7],
+];
+
+/// How the pixels of a frame are ranked.
+pub enum Priority {
+    Order(Order),
+    /// Brighter pixels of the mask first. The mask is stretched over the frame.
+    Mask(GrayImage),
+}
+
+impl Priority {
+    /// Loads a priority mask image.
+    pub fn mask(path: &Path) -> Result<Self> {
+        let mask = image::open(path)
+            .with_context(|| format!("could not open priority mask {}", path.display()))?;
+        Ok(Self::Mask(mask.into_luma8()))
+    }
+
+    /// Sorts the pixels of the frame, most important first.
+    pub fn sort(&self, frame: &RgbaImage, pixels: &mut [(u16, u16)]) {
+        let (width, height) = frame.dimensions();
+        match self {
+            Priority::Order(Order::Center) => {
+                // Doubled coordinates, so that the center of even sizes is exact.
+                let distance = |(x, y): (u16, u16)| {
+                    let dx = i64::from(x) * 2 + 1 - i64::from(width);
+                    let dy = i64::from(y) * 2 + 1 - i64::from(height);
+                    dx * dx + dy * dy
+                };
+                pixels.sort_by_cached_key(|&pixel| distance(pixel));
+            }
+            Priority::Order(Order::Detail) => {
+                let luma = |x: u32, y: u32| {
+                    let [r, g, b, a] = frame.get_pixel(x, y).0;
+                    (u32::from(r) * 2 + u32::from(g) * 5 + u32::from(b)) * u32::from(a) / 255
+                };
+                let detail = |(x, y): (u16, u16)| {
+                    let (x, y) = (u32::from(x), u32::from(y));
+                    let center = luma(x, y);
+                    let right = luma((x + 1).min(width - 1), y);
+                    let below = luma(x, (y + 1).min(height - 1));
+                    center.abs_diff(right) + center.abs_diff(below)
+                };
+                pixels.sort_by_cached_key(|&pixel| Reverse(detail(pixel)));
+            }
+            Priority::Order(Order::Interlaced) => {
+                pixels.sort_by_key(|&(x, y)| ADAM7[usize::from(y % 8)][usize::from(x % 8)]);
+            }
+            Priority::Mask(mask) => {
+                let (mask_width, mask_height) = mask.dimensions();
+                let brightness = |(x, y): (u16, u16)| {
+                    let mask_x = u64::from(x) * u64::from(mask_width) / u64::from(width);
+                    let mask_y = u64::from(y) * u64::from(mask_height) / u64::from(height);
+                    mask.get_pixel(mask_x as u32, mask_y as u32).0[0]
+                };
+                pixels.sort_by_cached_key(|&pixel| Reverse(brightness(pixel)));
+            }
+        }
+    }
+}
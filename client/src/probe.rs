@@ -0,0 +1,74 @@
+//! Probing the canvas size again while a job is sent, so that a job is sized for the new canvas
+//! when a server restarts with different dimensions, instead of sending pixels outside of it.
+//!
+//! Only the rayon engine notices the change, at the next frame, and then opens its source again.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use pingxelflut::get_size_at;
+
+/// The error that ends the players of a job whose canvas changed its size.
+#[derive(Debug)]
+pub struct Resized;
+
+impl fmt::Display for Resized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the canvas size changed")
+    }
+}
+
+impl std::error::Error for Resized {}
+
+/// Requests the canvas size of a target in the background at an interval, until it is dropped.
+pub struct Prober {
+    changed: Arc<AtomicBool>,
+    /// Dropping the sender ends the background thread.
+    _running: mpsc::Sender<()>,
+}
+
+impl Prober {
+    /// Starts probing the target. The first size it answers with is the one that later sizes are compared to.
+    pub fn start(target: SocketAddr, interval: Duration) -> Self {
+        let changed = Arc::new(AtomicBool::new(false));
+        let (running, stopped) = mpsc::channel();
+        let flag = changed.clone();
+        // Size requests wait for an answer as long as it takes, so the thread is not joined.
+        thread::spawn(move || {
+            let mut size = get_size_at(target).ok();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let Ok(new_size) = get_size_at(target) else {
+                    continue;
+                };
+                match size {
+                    Some((width, height)) if (width, height) != new_size => {
+                        eprintln!(
+                            "the canvas of {} changed from {width}x{height} to {}x{}, resizing the job",
+                            target.ip(),
+                            new_size.0,
+                            new_size.1
+                        );
+                        flag.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                    Some(_) => {}
+                    None => size = Some(new_size),
+                }
+            }
+        });
+        Self {
+            changed,
+            _running: running,
+        }
+    }
+
+    /// Whether the canvas size differs from the first one.
+    pub fn has_changed(&self) -> bool {
+        self.changed.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,71 @@
+//! A bounded queue of frames between a decoding thread and the sender, so that slow decoding never stalls
+//! the sender and a slow network never lets decoded frames pile up in memory.
+//!
+//! When the queue is full, the oldest frame is dropped for the new one, which keeps the playback in time.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+
+struct State<T> {
+    frames: VecDeque<T>,
+    finished: bool,
+    pushed: u64,
+    dropped: u64,
+}
+
+pub struct FrameQueue<T> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+impl<T> FrameQueue<T> {
+    /// Creates a queue for the given number of frames, at least one.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Mutex::new(State {
+                frames: VecDeque::new(),
+                finished: false,
+                pushed: 0,
+                dropped: 0,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    /// Adds a frame, dropping the oldest one if the queue is full.
+    pub fn push(&self, frame: T) {
+        let mut state = self.state.lock().unwrap();
+        state.pushed += 1;
+        if state.frames.len() >= self.capacity {
+            state.frames.pop_front();
+            state.dropped += 1;
+        }
+        state.frames.push_back(frame);
+        self.ready.notify_one();
+    }
+
+    /// Tells the receiver that no more frames follow.
+    pub fn finish(&self) {
+        self.state.lock().unwrap().finished = true;
+        self.ready.notify_one();
+    }
+
+    /// Waits for the oldest frame, or returns nothing once the queue is finished and empty.
+    pub fn pop(&self) -> Option<T> {
+        self.ready
+            .wait_while(self.state.lock().unwrap(), |state| {
+                state.frames.is_empty() && !state.finished
+            })
+            .unwrap()
+            .frames
+            .pop_front()
+    }
+
+    /// How many frames were pushed, and how many of them were dropped.
+    pub fn counts(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.pushed, state.dropped)
+    }
+}
@@ -0,0 +1,106 @@
+//! Images from HTTP(S) URLs, such as dashboards that render a PNG, which can be downloaded again periodically
+//! to pick up changes.
+
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::RgbaImage;
+
+use crate::adjust::Adjustments;
+use crate::animation::{self, Playback, Player, Sink};
+
+/// How long a download may take.
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest image that is downloaded.
+const MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The URL that an image path stands for, if it is one.
+pub fn url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Downloads the file at the URL.
+pub fn download(url: &str) -> Result<Vec<u8>> {
+    let response = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .get(url)
+        .call()
+        .with_context(|| format!("could not download {url}"))?;
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_SIZE)
+        .read_to_end(&mut data)
+        .with_context(|| format!("could not download {url}"))?;
+    Ok(data)
+}
+
+/// An image at a URL that is downloaded again at an interval.
+pub struct Polled {
+    pub url: String,
+    pub interval: Duration,
+    pub scale: Option<(u32, u32)>,
+    pub filter: FilterType,
+    pub canvas: (u32, u32),
+    pub adjustments: Adjustments,
+    /// The latest download.
+    pub image: RgbaImage,
+}
+
+impl Polled {
+    /// Downloads the image, scaled, cropped and adjusted like other images.
+    pub fn fetch(&self) -> Result<RgbaImage> {
+        let mut frames =
+            animation::load(Path::new(&self.url), self.scale, self.filter, self.canvas)?;
+        let mut image = frames.swap_remove(0).image;
+        self.adjustments.apply(&mut image);
+        Ok(image)
+    }
+
+    /// Sends the image the configured number of times, sending pixels in parallel with the given function,
+    /// and downloads it again whenever the interval has passed. In delta mode, only the changes are sent.
+    /// Failed downloads keep the previous image.
+    pub fn play(mut self, playback: &Playback, send: impl Sink) -> Result<()> {
+        let mut player = Player::new(playback);
+        // The image before the latest download, which delta mode compares against once.
+        let mut replaced: Option<RgbaImage> = None;
+        let mut next_poll = Instant::now() + self.interval;
+        for pass in 0..playback.passes {
+            if Instant::now() >= next_poll {
+                match self.fetch() {
+                    Ok(image) if image != self.image => {
+                        replaced = Some(mem::replace(&mut self.image, image));
+                    }
+                    Ok(_) => {}
+                    Err(error) => eprintln!("could not refresh the image: {error:#}"),
+                }
+                next_poll = Instant::now() + self.interval;
+            }
+            let previous = match replaced.take() {
+                Some(replaced) if replaced.dimensions() == self.image.dimensions() => {
+                    Some(replaced)
+                }
+                Some(_) => None,
+                None if pass == 0 => None,
+                None => Some(self.image.clone()),
+            };
+            player.show(&self.image, previous.as_ref(), &send)?;
+
+            // An unchanged image doesn't need to be sent again in delta mode until the next full refresh.
+            if playback.delta {
+                let delay = player
+                    .until_full_refresh()
+                    .min(next_poll.saturating_duration_since(Instant::now()));
+                thread::sleep(delay);
+            }
+        }
+        Ok(())
+    }
+}
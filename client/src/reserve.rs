@@ -0,0 +1,85 @@
+//! Reserving the region of a job on servers that support it, so that cooperating participants don’t draw over each
+//! other.
+//!
+//! The reservation is renewed in the background at half its lease, for the region at the current offset of the job,
+//! and released once the job ends.
+
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use pingxelflut::{lock_region_at, unlock_region_at};
+
+use crate::control::Control;
+
+/// How long to wait for the server to answer a lock request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Keeps the region of a job reserved until it is dropped.
+pub struct Reservation {
+    /// Dropping the sender ends the background thread, which then releases the reservation.
+    running: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reservation {
+    /// Reserves the region of the given size at the offset of the job for the lease in seconds,
+    /// or fails if the server doesn’t support reservations or refused this one.
+    pub fn start(
+        target: SocketAddr,
+        control: Arc<Control>,
+        size: (u32, u32),
+        lease: u16,
+    ) -> Result<Self> {
+        let region = move || {
+            let (x, y) = control.offset();
+            let clip = |length: u32, start: u16| length.min(u32::from(u16::MAX - start)) as u16;
+            (x, y, clip(size.0, x), clip(size.1, y))
+        };
+        let granted = match lock_region_at(target, region(), lease, RESPONSE_TIMEOUT)? {
+            None => bail!("{} doesn't support reserving regions", target.ip()),
+            Some(0) => bail!(
+                "{} refused to reserve the region, which is too large or overlaps the reservation of someone else",
+                target.ip()
+            ),
+            Some(granted) => granted,
+        };
+        let (running, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut granted = granted;
+            while let Err(RecvTimeoutError::Timeout) =
+                stopped.recv_timeout(Duration::from_secs(u64::from(granted / 2).max(1)))
+            {
+                match lock_region_at(target, region(), lease, RESPONSE_TIMEOUT) {
+                    Ok(Some(0)) => eprintln!("{} refused to renew the reservation", target.ip()),
+                    Ok(Some(renewed)) => granted = renewed,
+                    Ok(None) => eprintln!(
+                        "{} didn't answer the renewal of the reservation",
+                        target.ip()
+                    ),
+                    Err(error) => eprintln!(
+                        "could not renew the reservation on {}: {error}",
+                        target.ip()
+                    ),
+                }
+            }
+            let _ = unlock_region_at(target);
+        });
+        Ok(Self {
+            running: Some(running),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.running.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
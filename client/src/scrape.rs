@@ -0,0 +1,131 @@
+//! Scraping, which reads a rectangle of a server's canvas back with get pixel requests and saves it as an image.
+//!
+//! Requests are sent at a fixed rate, since servers drop the requests above their limit, while the responses
+//! are collected on another thread. Pixels whose responses were lost are requested again in later rounds,
+//! and pixels that never arrive stay transparent.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use image::{Rgba, RgbaImage};
+use pingxelflut::format::Packet;
+use pingxelflut::{PingxelflutClient, PixelReceiver};
+
+use crate::pacing::{packet_size, Pacer};
+
+/// How often the receiving thread checks whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A rectangle of the canvas to scrape, and how.
+pub struct Scrape {
+    pub target: SocketAddr,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Requests per second.
+    pub rate: u32,
+    /// How long to wait for the last responses of a round.
+    pub timeout: Duration,
+    /// How many more rounds request the missing pixels again.
+    pub retries: u32,
+}
+
+/// The pixels received so far.
+struct Received {
+    image: RgbaImage,
+    received: Vec<bool>,
+    count: u64,
+}
+
+impl Scrape {
+    /// Reads the rectangle from the server and saves it to the given file, in the format of its extension.
+    pub fn run(&self, output: &Path) -> Result<()> {
+        let (width, height) = (u32::from(self.width), u32::from(self.height));
+        let total = u64::from(width) * u64::from(height);
+        let received = Mutex::new(Received {
+            image: RgbaImage::new(width, height),
+            received: vec![false; total as usize],
+            count: 0,
+        });
+        let mut client = PingxelflutClient::new_at(self.target)?;
+        let mut receiver = PixelReceiver::new_at(self.target)?;
+        let pacer = Pacer::new(Some(self.rate), None).expect("the rate is limited");
+        let request_size = packet_size(
+            self.target.ip(),
+            Packet::GetPixel { x: 0, y: 0 }.to_bytes().len(),
+        );
+        let finished = AtomicBool::new(false);
+
+        thread::scope(|scope| -> Result<()> {
+            let receiving = scope.spawn(|| -> Result<()> {
+                while !finished.load(Ordering::Relaxed) {
+                    let Some((x, y, color)) = receiver.receive(POLL_INTERVAL)? else {
+                        continue;
+                    };
+                    // Responses to other clients' requests can arrive on the same socket.
+                    let (Some(x), Some(y)) = (x.checked_sub(self.x), y.checked_sub(self.y)) else {
+                        continue;
+                    };
+                    let (x, y) = (u32::from(x), u32::from(y));
+                    if x >= width || y >= height {
+                        continue;
+                    }
+                    let mut received = received.lock().unwrap();
+                    let index = (y * width + x) as usize;
+                    if !received.received[index] {
+                        received.received[index] = true;
+                        received.count += 1;
+                    }
+                    received
+                        .image
+                        .put_pixel(x, y, Rgba([color.r, color.g, color.b, color.a]));
+                }
+                Ok(())
+            });
+
+            let sending = (|| -> Result<()> {
+                for round in 0..=self.retries {
+                    let missing: Vec<(u32, u32)> = {
+                        let received = received.lock().unwrap();
+                        (0..height)
+                            .flat_map(|y| (0..width).map(move |x| (x, y)))
+                            .filter(|&(x, y)| !received.received[(y * width + x) as usize])
+                            .collect()
+                    };
+                    if missing.is_empty() {
+                        break;
+                    }
+                    for (x, y) in missing {
+                        pacer.wait(request_size);
+                        client.request_pixel(self.x + x as u16, self.y + y as u16)?;
+                    }
+                    thread::sleep(self.timeout);
+                    let count = received.lock().unwrap().count;
+                    eprintln!("round {}: received {count} of {total} pixels", round + 1);
+                }
+                Ok(())
+            })();
+            finished.store(true, Ordering::Relaxed);
+            receiving
+                .join()
+                .unwrap()
+                .context("could not receive pixels")?;
+            sending.context("could not request pixels")
+        })?;
+
+        let received = received.into_inner().unwrap();
+        if received.count == 0 {
+            anyhow::bail!("the server didn't answer any get pixel requests");
+        }
+        received
+            .image
+            .save(output)
+            .with_context(|| format!("could not save {}", output.display()))
+    }
+}
@@ -0,0 +1,85 @@
+//! Numbered image sequences like `frames/%04d.png`, the usual output of rendering tools,
+//! which are played as an animation at a fixed frame rate.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+
+use crate::animation::{self, Frame};
+
+/// The path of the frames with a printf-style placeholder for the frame number, like `frames/%04d.png`.
+#[derive(Clone, Debug)]
+pub struct SequencePattern {
+    prefix: String,
+    /// Number of digits that frame numbers are padded to with zeros.
+    width: usize,
+    suffix: String,
+}
+
+impl FromStr for SequencePattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || format!("not a frame pattern like frames/%04d.png or frames/%d.png: {text}");
+        let (prefix, rest) = text.split_once('%').ok_or_else(invalid)?;
+        let (width, suffix) = rest.split_once('d').ok_or_else(invalid)?;
+        let width = match width {
+            "" => 0,
+            width if width.starts_with('0') => width.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        };
+        if suffix.contains('%') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            prefix: prefix.to_string(),
+            width,
+            suffix: suffix.to_string(),
+        })
+    }
+}
+
+impl SequencePattern {
+    fn path(&self, number: u32) -> PathBuf {
+        format!(
+            "{}{number:0width$}{}",
+            self.prefix,
+            self.suffix,
+            width = self.width
+        )
+        .into()
+    }
+
+    /// Loads the frames from the first number that exists, 0 or 1, up to the last consecutive one.
+    /// Frames are scaled to fit into the given size if there is one, keeping the aspect ratio,
+    /// and cropped to the canvas size.
+    pub fn load(
+        &self,
+        fps: f64,
+        scale: Option<(u32, u32)>,
+        filter: FilterType,
+        canvas: (u32, u32),
+    ) -> Result<Vec<Frame>> {
+        let Some(first) = (0..=1).find(|&number| self.path(number).exists()) else {
+            bail!("there is no first frame {}", self.path(1).display());
+        };
+        let mut frames = Vec::new();
+        for path in (first..)
+            .map(|number| self.path(number))
+            .take_while(|path| path.exists())
+        {
+            let image = image::open(&path)
+                .with_context(|| format!("could not open frame {}", path.display()))?;
+            frames.push(Frame {
+                image: image.into_rgba8(),
+                delay: Duration::from_secs_f64(1.0 / fps),
+            });
+        }
+        animation::fit(&mut frames, scale, filter, canvas);
+        Ok(frames)
+    }
+}
@@ -0,0 +1,159 @@
+//! Slideshows, which cycle through images with a cut or a fade between them.
+//!
+//! Directories are read again on every pass, so that images can be added to a running exhibit.
+//! Slides are loaded when they are shown, and centered on a background color in an area of a fixed size.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{ImageFormat, Rgba, RgbaImage};
+
+use crate::adjust::Adjustments;
+use crate::animation::{self, Playback, Player, Sink};
+
+/// How one slide changes into the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Transition {
+    /// Show the next slide at once.
+    Cut,
+    /// Blend the slides into each other.
+    Fade,
+}
+
+/// A slideshow of image files and the images in directories.
+pub struct Slideshow {
+    pub paths: Vec<PathBuf>,
+    pub area: (u32, u32),
+    /// How long every slide is shown.
+    pub dwell: Duration,
+    pub transition: Transition,
+    pub transition_time: Duration,
+    /// Time between two steps of a fade.
+    pub interval: Duration,
+    pub background: Rgba<u8>,
+    /// Size that images are scaled to fit into, if they are.
+    pub scale: Option<(u32, u32)>,
+    pub filter: FilterType,
+    pub adjustments: Adjustments,
+}
+
+impl Slideshow {
+    /// The image files, with the images in directories sorted by name.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            if !path.is_dir() {
+                files.push(path.clone());
+                continue;
+            }
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    let mut images: Vec<PathBuf> = entries
+                        .filter_map(|entry| Some(entry.ok()?.path()))
+                        .filter(|path| path.is_file() && ImageFormat::from_path(path).is_ok())
+                        .collect();
+                    images.sort();
+                    files.extend(images);
+                }
+                Err(error) => eprintln!("could not read {}: {error}", path.display()),
+            }
+        }
+        files
+    }
+
+    /// Loads an image as a slide, centered in the area.
+    fn load(&self, path: &Path) -> Result<RgbaImage> {
+        let mut frames = animation::load(path, self.scale, self.filter, self.area)?;
+        let mut image = frames.swap_remove(0).image;
+        self.adjustments.apply(&mut image);
+        let mut slide = RgbaImage::from_pixel(self.area.0, self.area.1, self.background);
+        let x = (self.area.0 - image.width()) / 2;
+        let y = (self.area.1 - image.height()) / 2;
+        imageops::overlay(&mut slide, &image, x.into(), y.into());
+        Ok(slide)
+    }
+
+    /// Shows every slide for the dwell time, the configured number of times,
+    /// sending pixels in parallel with the given function. Slides that can't be loaded are skipped.
+    pub fn play(&self, playback: &Playback, send: impl Sink) -> Result<()> {
+        let mut player = Player::new(playback);
+        let mut previous: Option<RgbaImage> = None;
+        for _ in 0..playback.passes {
+            let mut shown = 0;
+            for path in self.files() {
+                let slide = match self.load(path.as_path()) {
+                    Ok(slide) => slide,
+                    Err(error) => {
+                        eprintln!("could not load {}: {error:#}", path.display());
+                        continue;
+                    }
+                };
+                shown += 1;
+                if let (Transition::Fade, Some(from)) = (self.transition, &previous) {
+                    self.fade(&mut player, from, &slide, &send)?;
+                }
+                player.show(&slide, previous.as_ref(), &send)?;
+                self.dwell(&mut player, playback, &slide, &send)?;
+                previous = Some(slide);
+            }
+            if shown == 0 {
+                bail!("no images to show");
+            }
+        }
+        Ok(())
+    }
+
+    /// Blends from one slide into the other, in steps of the configured interval.
+    fn fade(
+        &self,
+        player: &mut Player,
+        from: &RgbaImage,
+        to: &RgbaImage,
+        send: &impl Sink,
+    ) -> Result<()> {
+        let steps = (self.transition_time.as_secs_f64() / self.interval.as_secs_f64()) as u32;
+        let mut shown = from.clone();
+        for step in 1..steps {
+            let started = Instant::now();
+            let fraction = step as f64 / steps as f64;
+            let blended = RgbaImage::from_fn(self.area.0, self.area.1, |x, y| {
+                let (from, to) = (from.get_pixel(x, y).0, to.get_pixel(x, y).0);
+                Rgba(std::array::from_fn(|channel| {
+                    (from[channel] as f64 + (to[channel] as f64 - from[channel] as f64) * fraction)
+                        .round() as u8
+                }))
+            });
+            player.show(&blended, Some(&shown), send)?;
+            shown = blended;
+            thread::sleep(self.interval.saturating_sub(started.elapsed()));
+        }
+        Ok(())
+    }
+
+    /// Keeps showing the slide until the dwell time is over: over and over,
+    /// or in delta mode only when a full refresh is due.
+    fn dwell(
+        &self,
+        player: &mut Player,
+        playback: &Playback,
+        slide: &RgbaImage,
+        send: &impl Sink,
+    ) -> Result<()> {
+        let until = Instant::now() + self.dwell;
+        loop {
+            let remaining = until.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(());
+            }
+            if playback.delta {
+                thread::sleep(remaining.min(player.until_full_refresh()));
+            }
+            player.show(slide, Some(slide), send)?;
+        }
+    }
+}
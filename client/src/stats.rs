@@ -0,0 +1,181 @@
+//! Throughput and error statistics, which are shown as a status line every second and summarized on exit.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::failure;
+
+/// How often the status line is printed.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counters shared by all workers.
+pub struct Stats {
+    started: Instant,
+    /// Number of pixels in the image, which one loop over the image sends.
+    pixels: AtomicU64,
+    packets: AtomicU64,
+    /// Pixels in the packets that were sent, which is more than the packets when pixels are packed together.
+    sent_pixels: AtomicU64,
+    /// Pixels in the packets that could not be sent.
+    failed_pixels: AtomicU64,
+    /// Bytes sent including IP and ICMP headers.
+    bytes: AtomicU64,
+    /// Failed sends by error class, ordered by name.
+    errors: Mutex<BTreeMap<String, u64>>,
+    /// Ctrl+C and the jobs ending can both print the summary, but only the first one does.
+    summary: Once,
+}
+
+/// The counters since the start, for reporting them elsewhere.
+#[derive(Serialize)]
+pub struct Summary {
+    seconds: f64,
+    packets: u64,
+    pixels: u64,
+    bytes: u64,
+    /// Failed sends by error kind.
+    errors: BTreeMap<String, u64>,
+}
+
+/// A snapshot of the counters.
+struct Totals {
+    time: Instant,
+    packets: u64,
+    pixels: u64,
+    failed_pixels: u64,
+    bytes: u64,
+}
+
+impl Stats {
+    pub fn new(pixels: u64) -> Self {
+        Self {
+            started: Instant::now(),
+            pixels: AtomicU64::new(pixels),
+            packets: AtomicU64::default(),
+            sent_pixels: AtomicU64::default(),
+            failed_pixels: AtomicU64::default(),
+            bytes: AtomicU64::default(),
+            errors: Mutex::default(),
+            summary: Once::new(),
+        }
+    }
+
+    /// Changes the number of pixels that one loop sends, when jobs are added or removed.
+    pub fn add_pixels(&self, pixels: u64) {
+        self.pixels.fetch_add(pixels, Ordering::Relaxed);
+    }
+
+    pub fn remove_pixels(&self, pixels: u64) {
+        self.pixels.fetch_sub(pixels, Ordering::Relaxed);
+    }
+
+    /// Record a packet of the given size with the given number of pixels that was sent.
+    pub fn sent(&self, size: usize, pixels: u64) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+        self.sent_pixels.fetch_add(pixels, Ordering::Relaxed);
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// Record a packet with the given number of pixels that could not be sent.
+    pub fn failed(&self, error: &io::Error, pixels: u64) {
+        self.failed_pixels.fetch_add(pixels, Ordering::Relaxed);
+        *self
+            .errors
+            .lock()
+            .unwrap()
+            .entry(failure::describe(error))
+            .or_default() += 1;
+    }
+
+    fn totals(&self) -> Totals {
+        Totals {
+            time: Instant::now(),
+            packets: self.packets.load(Ordering::Relaxed),
+            pixels: self.sent_pixels.load(Ordering::Relaxed),
+            failed_pixels: self.failed_pixels.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Describes the rates between two snapshots, the completed loops and the errors so far.
+    fn describe(&self, start: &Totals, end: &Totals) -> String {
+        let seconds = end
+            .time
+            .duration_since(start.time)
+            .as_secs_f64()
+            .max(f64::EPSILON);
+        let loops = (end.pixels + end.failed_pixels)
+            .checked_div(self.pixels.load(Ordering::Relaxed))
+            .unwrap_or(0);
+        let packets = end.packets - start.packets;
+        let mut line = format!(
+            "{:.0} packets/s, {:.1} pixels/packet, {:.2} MB/s, {} loops",
+            packets as f64 / seconds,
+            (end.pixels - start.pixels) as f64 / packets.max(1) as f64,
+            (end.bytes - start.bytes) as f64 / seconds / 1_000_000.0,
+            loops
+        );
+        let errors = self.errors.lock().unwrap();
+        if !errors.is_empty() {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|(kind, count)| format!("{count} {kind}"))
+                .collect();
+            line.push_str(&format!(", errors: {}", errors.join(", ")));
+        }
+        line
+    }
+
+    /// Prints a status line every second, forever.
+    pub fn report_periodically(self: Arc<Self>) {
+        thread::spawn(move || {
+            let mut last = self.totals();
+            loop {
+                thread::sleep(REPORT_INTERVAL);
+                let now = self.totals();
+                eprintln!("{}", self.describe(&last, &now));
+                last = now;
+            }
+        });
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            seconds: self.started.elapsed().as_secs_f64(),
+            packets: self.packets.load(Ordering::Relaxed),
+            pixels: self.sent_pixels.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().clone(),
+        }
+    }
+
+    /// Prints the totals and average rates since the start, unless they were already printed.
+    pub fn print_summary(&self) {
+        self.summary.call_once(|| self.print_totals());
+    }
+
+    fn print_totals(&self) {
+        let start = Totals {
+            time: self.started,
+            packets: 0,
+            pixels: 0,
+            failed_pixels: 0,
+            bytes: 0,
+        };
+        let end = self.totals();
+        eprintln!(
+            "sent {} pixels in {} packets ({:.2} MB) in {:.1} seconds: {}",
+            end.pixels,
+            end.packets,
+            end.bytes as f64 / 1_000_000.0,
+            end.time.duration_since(self.started).as_secs_f64(),
+            self.describe(&start, &end)
+        );
+    }
+}
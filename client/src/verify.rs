@@ -0,0 +1,160 @@
+//! Verification, which reads random samples of the sent image back with get pixel requests to find out how much of it is intact,
+//! and only sends the areas with damaged samples again instead of the whole image.
+//!
+//! Only opaque pixels are sampled, since the server blends translucent ones with what was there before.
+//! Samples whose responses were lost are left out, so lost responses aren't mistaken for damage.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use image::RgbaImage;
+use pingxelflut::format::{color_from_rgba, Color, Packet};
+use pingxelflut::{PingxelflutClient, PixelReceiver};
+use rand::seq::SliceRandom;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::animation::{Playback, Player, Sink};
+use crate::pacing::{packet_size, Pacer};
+
+/// Get pixel requests per second, the default limit of servers.
+const REQUEST_RATE: u32 = 1000;
+/// How long to wait for the last responses.
+const TIMEOUT: Duration = Duration::from_secs(1);
+/// Size of the squares that the image is divided into, which are sent again when a sample in them is damaged.
+const AREA_SIZE: u32 = 32;
+/// Above this fraction of damaged samples, the whole image is sent again.
+const MAX_DAMAGE: f64 = 0.25;
+
+/// Reads back samples of an image sent to a target, at the offset of the playback's control.
+pub struct Verifier {
+    pub target: SocketAddr,
+    /// How many pixels are read back per check.
+    pub samples: usize,
+    /// Time between two checks.
+    pub interval: Duration,
+}
+
+impl Verifier {
+    /// Sends the image, then checks it periodically until the client or the job is stopped,
+    /// sending pixels in parallel with the given function.
+    pub fn play(&self, image: &RgbaImage, playback: &Playback, send: impl Sink) -> Result<()> {
+        let opaque: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] == 0xff)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        if opaque.is_empty() {
+            bail!("only opaque pixels can be verified, and the image has none");
+        }
+        let mut client = PingxelflutClient::new_at(self.target)?;
+        let mut receiver = PixelReceiver::new_at(self.target)?;
+        let mut player = Player::new(playback);
+        let mut rng = rand::thread_rng();
+        player.show(image, None, &send)?;
+        loop {
+            thread::sleep(self.interval);
+            playback.control.check()?;
+            let samples: Vec<(u32, u32)> = opaque
+                .choose_multiple(&mut rng, self.samples)
+                .copied()
+                .collect();
+            let offset = playback.control.offset();
+            let colors = self.read(&mut client, &mut receiver, offset, &samples)?;
+            if colors.is_empty() {
+                eprintln!("no samples were answered, sending everything again");
+                player.show(image, None, &send)?;
+                continue;
+            }
+            let damaged: Vec<(u32, u32)> = colors
+                .iter()
+                .filter(|&(&(x, y), color)| *color != color_from_rgba(image.get_pixel(x, y).0))
+                .map(|(&position, _)| position)
+                .collect();
+            let damage = damaged.len() as f64 / colors.len() as f64;
+            eprintln!(
+                "verified {} of {} samples: {:.1} % intact",
+                colors.len(),
+                samples.len(),
+                (1.0 - damage) * 100.0
+            );
+            if damage > MAX_DAMAGE {
+                player.show(image, None, &send)?;
+            } else if !damaged.is_empty() {
+                send_areas(image, &damaged, &send);
+            }
+        }
+    }
+
+    /// Requests the samples of the image at the given offset,
+    /// and returns the colors that arrived within the timeout by their position in the image.
+    fn read(
+        &self,
+        client: &mut PingxelflutClient,
+        receiver: &mut PixelReceiver,
+        offset: (u16, u16),
+        samples: &[(u32, u32)],
+    ) -> Result<HashMap<(u32, u32), Color>> {
+        let pacer = Pacer::new(Some(REQUEST_RATE), None).expect("the rate is limited");
+        let request_size = packet_size(
+            self.target.ip(),
+            Packet::GetPixel { x: 0, y: 0 }.to_bytes().len(),
+        );
+        let canvas_position = |(x, y): (u32, u32)| (offset.0 + x as u16, offset.1 + y as u16);
+        let mut pending: HashMap<(u16, u16), (u32, u32)> = samples
+            .iter()
+            .map(|&position| (canvas_position(position), position))
+            .collect();
+        // Responses are read while the requests are sent, so that they don't overflow the receive buffer.
+        let until =
+            Instant::now() + Duration::from_secs(1) * samples.len() as u32 / REQUEST_RATE + TIMEOUT;
+        thread::scope(|scope| {
+            let requesting = scope.spawn(|| -> io::Result<()> {
+                for &position in samples {
+                    pacer.wait(request_size);
+                    let (x, y) = canvas_position(position);
+                    client.request_pixel(x, y)?;
+                }
+                Ok(())
+            });
+
+            let mut colors = HashMap::new();
+            while !pending.is_empty() {
+                let remaining = until.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                // Responses to earlier checks and other clients' requests are skipped.
+                if let Some((x, y, color)) = receiver.receive(remaining)? {
+                    if let Some(position) = pending.remove(&(x, y)) {
+                        colors.insert(position, color);
+                    }
+                }
+            }
+            requesting.join().unwrap()?;
+            Ok(colors)
+        })
+    }
+}
+
+/// Sends the areas with damaged samples again, every pixel only once.
+fn send_areas(image: &RgbaImage, damaged: &[(u32, u32)], send: &impl Sink) {
+    let mut areas: Vec<(u32, u32)> = damaged
+        .iter()
+        .map(|&(x, y)| (x / AREA_SIZE, y / AREA_SIZE))
+        .collect();
+    areas.sort_unstable();
+    areas.dedup();
+    areas.into_par_iter().for_each(|(column, row)| {
+        let (left, top) = (column * AREA_SIZE, row * AREA_SIZE);
+        for y in top..(top + AREA_SIZE).min(image.height()) {
+            for x in left..(left + AREA_SIZE).min(image.width()) {
+                send.send(x as u16, y as u16, color_from_rgba(image.get_pixel(x, y).0));
+            }
+        }
+        send.flush();
+    });
+}
@@ -0,0 +1,293 @@
+//! Playback of video files, screen captures and webcams, which FFmpeg decodes and scales to raw RGBA frames while they are sent.
+//!
+//! FFmpeg decodes in real time on its own.
+//! Screens are captured with FFmpeg's x11grab device, or gdigrab on Windows.
+//! Webcams are opened with v4l2, dshow on Windows, or avfoundation on macOS. Decoded frames wait in a bounded queue
+//! for the sender. If sending takes longer than the frames are shown, the oldest frames in the queue are dropped,
+//! so that the video stays in time.
+
+use std::ffi::OsString;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+
+use crate::adjust::Adjustments;
+use crate::animation::{Playback, Player, Sink};
+use crate::queue::FrameQueue;
+
+/// A video file, screen capture or webcam, and the size it is played at.
+pub struct Video {
+    /// FFmpeg options that open the input, which FFprobe understands as well.
+    input: Vec<OsString>,
+    /// Whether the input is a file, which is read at its frame rate instead of as fast as possible.
+    is_file: bool,
+    ffmpeg: PathBuf,
+    /// Frame rate to convert the video to, instead of its own.
+    fps: Option<f64>,
+    pub width: u32,
+    pub height: u32,
+    /// Flip the video horizontally, like a mirror.
+    pub mirror: bool,
+    /// FFmpeg's algorithm for scaling the video.
+    pub scaler: &'static str,
+    pub adjustments: Adjustments,
+    /// How many decoded frames may wait to be sent.
+    pub queue: usize,
+}
+
+/// A rectangle of the screen, in the X geometry format `WIDTHxHEIGHT+X+Y`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenRegion {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl FromStr for ScreenRegion {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (size, offset) = text.split_once('+').unwrap_or((text, "0+0"));
+            let (width, height) = size.split_once('x')?;
+            let (x, y) = offset.split_once('+')?;
+            Some(Self {
+                width: width.parse().ok().filter(|&width| width > 0)?,
+                height: height.parse().ok().filter(|&height| height > 0)?,
+                x: x.parse().ok()?,
+                y: y.parse().ok()?,
+            })
+        };
+        parse().ok_or_else(|| format!("not a region like 1280x720+0+0: {text}"))
+    }
+}
+
+impl Video {
+    /// Opens a video file, which is scaled to fit into the given region.
+    pub fn open(path: &Path, ffmpeg: &Path, fps: Option<f64>, region: (u32, u32)) -> Result<Self> {
+        let input = vec!["-i".into(), path.into()];
+        Self::new(input, true, None, ffmpeg, fps, region)
+            .with_context(|| format!("could not open {}", path.display()))
+    }
+
+    /// Captures the given region of the screen, or all of it, which is scaled to fit into the given region.
+    /// The capture runs at the given frame rate, or at FFmpeg's default of about 30 frames per second.
+    pub fn capture_screen(
+        screen: Option<ScreenRegion>,
+        ffmpeg: &Path,
+        fps: Option<f64>,
+        region: (u32, u32),
+    ) -> Result<Self> {
+        let mut input: Vec<OsString> = Vec::new();
+        if let Some(fps) = fps {
+            input.extend(["-framerate".into(), fps.to_string().into()]);
+        }
+        if let Some(screen) = screen {
+            input.extend([
+                "-video_size".into(),
+                format!("{}x{}", screen.width, screen.height).into(),
+            ]);
+        }
+        if cfg!(windows) {
+            if let Some(screen) = screen {
+                input.extend([
+                    "-offset_x".into(),
+                    screen.x.to_string().into(),
+                    "-offset_y".into(),
+                    screen.y.to_string().into(),
+                ]);
+            }
+            input.extend(["-f".into(), "gdigrab".into(), "-i".into(), "desktop".into()]);
+        } else {
+            let mut display = std::env::var("DISPLAY").unwrap_or_else(|_| ":0".to_owned());
+            if let Some(screen) = screen {
+                display.push_str(&format!("+{},{}", screen.x, screen.y));
+            }
+            input.extend(["-f".into(), "x11grab".into(), "-i".into(), display.into()]);
+        }
+        let size = screen.map(|screen| (screen.width, screen.height));
+        Self::new(input, false, size, ffmpeg, fps, region).context("could not capture the screen")
+    }
+
+    /// Captures the given camera, or the default one, which is scaled to fit into the given region.
+    /// On Windows, the camera has to be named; `ffmpeg -list_devices true -f dshow -i dummy` lists them.
+    pub fn capture_webcam(
+        camera: Option<&str>,
+        ffmpeg: &Path,
+        fps: Option<f64>,
+        region: (u32, u32),
+    ) -> Result<Self> {
+        let mut input: Vec<OsString> = Vec::new();
+        if let Some(fps) = fps {
+            input.extend(["-framerate".into(), fps.to_string().into()]);
+        }
+        let (format, camera) = if cfg!(windows) {
+            let Some(camera) = camera else {
+                bail!("the camera needs to be named on Windows");
+            };
+            ("dshow", format!("video={camera}"))
+        } else if cfg!(target_os = "macos") {
+            ("avfoundation", camera.unwrap_or("0").to_owned())
+        } else {
+            ("v4l2", camera.unwrap_or("/dev/video0").to_owned())
+        };
+        input.extend(["-f".into(), format.into(), "-i".into(), camera.into()]);
+        Self::new(input, false, None, ffmpeg, fps, region).context("could not open the camera")
+    }
+
+    /// Probes the size of the input with FFprobe unless it is known, and scales it to fit into the given region,
+    /// keeping its aspect ratio. FFprobe is expected next to FFmpeg.
+    fn new(
+        input: Vec<OsString>,
+        is_file: bool,
+        size: Option<(u32, u32)>,
+        ffmpeg: &Path,
+        fps: Option<f64>,
+        (max_width, max_height): (u32, u32),
+    ) -> Result<Self> {
+        let (width, height) = match size {
+            Some(size) => size,
+            None => probe_size(&input, &ffmpeg.with_file_name("ffprobe"))?,
+        };
+        let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+        Ok(Self {
+            input,
+            is_file,
+            ffmpeg: ffmpeg.to_owned(),
+            fps,
+            mirror: false,
+            scaler: "bicubic",
+            adjustments: Adjustments::default(),
+            queue: 1,
+            width: ((width as f64 * scale).round() as u32).clamp(1, max_width.max(1)),
+            height: ((height as f64 * scale).round() as u32).clamp(1, max_height.max(1)),
+        })
+    }
+
+    /// The FFmpeg filters that scale and adjust the video.
+    fn filters(&self) -> String {
+        let mut filters = vec![format!(
+            "scale={}:{}:flags={}",
+            self.width, self.height, self.scaler
+        )];
+        if self.mirror {
+            filters.push("hflip".to_owned());
+        }
+        if !self.adjustments.is_identity() {
+            filters.push(self.adjustments.ffmpeg_filter());
+        }
+        filters.join(",")
+    }
+
+    /// Plays the video the configured number of times, or the screen capture until it fails or the job is stopped,
+    /// sending pixels in parallel with the given function.
+    pub fn play(&self, playback: &Playback, send: impl Sink) -> Result<()> {
+        // FFmpeg repeats the input this many times after playing it once, or forever for -1.
+        let repeats = match playback.passes {
+            u64::MAX => "-1".to_owned(),
+            passes => (passes - 1).to_string(),
+        };
+        let mut ffmpeg = Command::new(&self.ffmpeg)
+            .args(["-loglevel", "error", "-nostdin"])
+            // Decode files at the frame rate of the video instead of as fast as possible.
+            .args(
+                self.is_file
+                    .then_some(["-re", "-stream_loop", &repeats])
+                    .into_iter()
+                    .flatten(),
+            )
+            .args(&self.input)
+            .args(["-an", "-vf", &self.filters()])
+            .args(
+                self.fps
+                    .iter()
+                    .flat_map(|fps| ["-r".to_owned(), fps.to_string()]),
+            )
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("could not start {}", self.ffmpeg.display()))?;
+        let mut output = ffmpeg.stdout.take().expect("standard output is piped");
+
+        let frames = FrameQueue::new(self.queue);
+        let (width, height) = (self.width, self.height);
+        let decoding = thread::scope(|scope| {
+            let decoder = scope.spawn(|| {
+                let result = read_frames(&mut output, width, height, |frame| frames.push(frame));
+                frames.finish();
+                result
+            });
+
+            let mut player = Player::new(playback);
+            let mut previous = None;
+            let mut shown = Ok(());
+            while let Some(frame) = frames.pop() {
+                shown = player.show(&frame, previous.as_ref(), &send);
+                if shown.is_err() {
+                    // Ends FFmpeg's output, so that the decoder finishes.
+                    let _ = ffmpeg.kill();
+                    break;
+                }
+                previous = Some(frame);
+            }
+            (shown, decoder.join().unwrap())
+        });
+
+        let status = ffmpeg.wait()?;
+        let (decoded, dropped) = frames.counts();
+        eprintln!("played {decoded} frames, dropped {dropped}");
+        let (shown, decoding) = decoding;
+        shown?;
+        decoding.context("could not read frames from FFmpeg")?;
+        if !status.success() {
+            bail!("FFmpeg failed: {status}");
+        }
+        Ok(())
+    }
+}
+
+/// Asks FFprobe for the size of the first video stream of the input.
+fn probe_size(input: &[OsString], ffprobe: &Path) -> Result<(u32, u32)> {
+    let output = Command::new(ffprobe)
+        .args(["-loglevel", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height", "-of", "csv=p=0:s=x"])
+        .args(input)
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("could not start {}", ffprobe.display()))?;
+    if !output.status.success() {
+        bail!("FFprobe failed: {}", output.status);
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    output
+        .trim()
+        .split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .context("no video stream")
+}
+
+/// Reads raw RGBA frames of the given size until the end of the output.
+fn read_frames(
+    output: &mut impl Read,
+    width: u32,
+    height: u32,
+    mut frame: impl FnMut(RgbaImage),
+) -> io::Result<()> {
+    loop {
+        let mut buffer = vec![0; width as usize * height as usize * 4];
+        match output.read_exact(&mut buffer) {
+            Ok(()) => {
+                frame(RgbaImage::from_raw(width, height, buffer).expect("buffer fits the frame"))
+            }
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(error) => return Err(error),
+        }
+    }
+}
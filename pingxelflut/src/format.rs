@@ -9,18 +9,230 @@ use rgb::RGB8;
 use rgb::RGBA8;
 
 /// A Pingxelflut packet.
-#[derive(Debug, Clone, Copy)]
-pub enum Packet {
+///
+/// Packets with a variable amount of data borrow it from the buffer they were parsed from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
     /// A size request, type `aa`.
     SizeRequest,
     /// A size response, type `bb`.
     SizeResponse { width: u16, height: u16 },
     /// A pixel set request, type `cc`
     SetPixel { x: u16, y: u16, color: Color },
+    /// A pixel read request, type `dd`.
+    GetPixel { x: u16, y: u16 },
+    /// A pixel read response, type `ee`.
+    PixelResponse { x: u16, y: u16, color: Color },
+    /// A request for the server’s capabilities, type `a1`.
+    CapabilitiesRequest,
+    /// A capabilities response, type `b1`.
+    CapabilitiesResponse(Capabilities),
+    /// A rectangle filled with one color, type `c1`.
+    FillRect {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        color: Color,
+    },
+    /// Many pixels at once, type `c2`.
+    SetPixels(PixelEntries<'a>),
+    /// A row of pixels from a position to the right, type `c3`.
+    Blit { x: u16, y: u16, colors: Colors<'a> },
+    /// A request for the server’s palette, type `a2`.
+    PaletteRequest,
+    /// The colors of the server’s palette, type `b2`.
+    PaletteResponse(Palette<'a>),
+    /// A row of pixels from a position to the right, given as indices into the server’s palette, type `c4`.
+    IndexedBlit { x: u16, y: u16, indices: &'a [u8] },
+    /// A request to reserve a region for the sender for a lease in seconds, or to renew the reservation, type `a3`.
+    LockRegion {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        lease: u16,
+    },
+    /// The answer to a lock region request with the granted lease in seconds, which is 0 if it was refused, type `b3`.
+    LockResponse {
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        lease: u16,
+    },
+    /// A request to release the sender’s reservation, type `a4`.
+    UnlockRegion,
+}
+
+/// The optional extensions and limits of a server, as told by a capabilities response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    pub extensions: Extensions,
+    /// Largest packet the server accepts, in bytes from the packet type on.
+    pub max_packet_size: u16,
+    pub canvases: u16,
+    /// Get pixel requests the server answers per second and source, if it supports them.
+    pub get_pixel_rate: u32,
+    /// Most pixels a fill rectangle packet may cover, if the server supports them.
+    pub max_fill_area: u32,
+}
+
+/// The pixels of a set pixels packet in their encoded form: position and RGBA color, [`PIXEL_ENTRY_SIZE`] bytes each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelEntries<'a>(&'a [u8]);
+
+impl<'a> PixelEntries<'a> {
+    /// Wraps encoded entries, or returns None if the bytes don’t consist of whole entries.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        (bytes.len() % PIXEL_ENTRY_SIZE == 0).then_some(Self(bytes))
+    }
+
+    /// Encodes one entry, for building the data of a set pixels packet.
+    pub fn encode(x: u16, y: u16, color: Color) -> [u8; PIXEL_ENTRY_SIZE] {
+        let [x_high, x_low] = x.to_be_bytes();
+        let [y_high, y_low] = y.to_be_bytes();
+        [
+            x_high, x_low, y_high, y_low, color.r, color.g, color.b, color.a,
+        ]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / PIXEL_ENTRY_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The position and color of every pixel.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, u16, Color)> + 'a {
+        self.0.chunks_exact(PIXEL_ENTRY_SIZE).map(|entry| {
+            (
+                u16::from_be_bytes([entry[0], entry[1]]),
+                u16::from_be_bytes([entry[2], entry[3]]),
+                color_from_rgba([entry[4], entry[5], entry[6], entry[7]]),
+            )
+        })
+    }
+}
+
+/// The colors of a blit packet in their encoded form, [`COLOR_SIZE`] bytes of RGBA each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colors<'a>(&'a [u8]);
+
+impl<'a> Colors<'a> {
+    /// Wraps encoded colors, or returns None if the bytes don’t consist of whole colors.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        (bytes.len() % COLOR_SIZE == 0).then_some(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / COLOR_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Color> + 'a {
+        self.0
+            .chunks_exact(COLOR_SIZE)
+            .map(|color| color_from_rgba([color[0], color[1], color[2], color[3]]))
+    }
+}
+
+/// The colors of a palette response in their encoded form, [`PALETTE_COLOR_SIZE`] bytes of RGB each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette<'a>(&'a [u8]);
+
+impl<'a> Palette<'a> {
+    /// Most colors in a palette, since indices are single bytes.
+    pub const MAX_COLORS: usize = 256;
+
+    /// Wraps encoded colors, or returns None if the bytes don’t consist of whole colors or are too many.
+    pub fn new(bytes: &'a [u8]) -> Option<Self> {
+        (bytes.len() % PALETTE_COLOR_SIZE == 0
+            && bytes.len() <= Self::MAX_COLORS * PALETTE_COLOR_SIZE)
+            .then_some(Self(bytes))
+    }
+
+    /// Encodes one color, for building the data of a palette response.
+    pub fn encode(color: Color) -> [u8; PALETTE_COLOR_SIZE] {
+        [color.r, color.g, color.b]
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len() / PALETTE_COLOR_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The color at the index, if the palette is large enough.
+    pub fn get(&self, index: u8) -> Option<Color> {
+        let start = usize::from(index) * PALETTE_COLOR_SIZE;
+        let color = self.0.get(start..start + PALETTE_COLOR_SIZE)?;
+        Some(color_from_rgb([color[0], color[1], color[2]]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Color> + 'a {
+        self.0
+            .chunks_exact(PALETTE_COLOR_SIZE)
+            .map(|color| color_from_rgb([color[0], color[1], color[2]]))
+    }
+}
+
+/// Set of optional protocol extensions that a server supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Extensions(pub u16);
+
+impl Extensions {
+    /// The server answers get pixel requests.
+    pub const GET_PIXEL: Self = Self(1 << 0);
+    /// The server accepts fill rectangle packets.
+    pub const FILL_RECT: Self = Self(1 << 1);
+    /// The server accepts set pixels packets.
+    pub const SET_PIXELS: Self = Self(1 << 2);
+    /// The server accepts blit packets.
+    pub const BLIT: Self = Self(1 << 3);
+    /// Palette request and response, and indexed blit packets.
+    pub const PALETTE: Self = Self(1 << 4);
+    /// Lock region, lock response and unlock region packets.
+    pub const LOCK_REGION: Self = Self(1 << 5);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for Extensions {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
 }
 
 pub type Color = RGBA8;
 pub const COLOR_SIZE: usize = 4;
+/// Size of a pixel in a set pixels packet: its position and RGBA color.
+pub const PIXEL_ENTRY_SIZE: usize = 8;
+/// Size of an encoded palette color.
+pub const PALETTE_COLOR_SIZE: usize = 3;
 
 pub fn color_from_rgb(vec: [u8; 3]) -> Color {
     cast::<_, RGB8>(vec).alpha(0xff)
@@ -29,13 +241,82 @@ pub fn color_from_rgba(vec: [u8; 4]) -> Color {
     cast::<_, RGBA8>(vec)
 }
 
-impl Packet {
+/// Color operations that the `rgb` crate doesn’t provide.
+pub trait ColorExt {
+    /// Composite this color on top of `below` with the source-over operator.
+    fn blend_over(self, below: Color) -> Color;
+}
+
+impl ColorExt for Color {
+    fn blend_over(self, below: Color) -> Color {
+        let src_alpha = u32::from(self.a);
+        let below_alpha = u32::from(below.a) * (0xff - src_alpha) / 0xff;
+        let alpha_out = src_alpha + below_alpha;
+        if alpha_out == 0 {
+            return Color::new(0, 0, 0, 0);
+        }
+        let channel = |src: u8, below: u8| {
+            ((u32::from(src) * src_alpha + u32::from(below) * below_alpha) / alpha_out) as u8
+        };
+        Color::new(
+            channel(self.r, below.r),
+            channel(self.g, below.g),
+            channel(self.b, below.b),
+            alpha_out as u8,
+        )
+    }
+}
+
+/// Get an RGB or RGBA color, depending on how large the slice is.
+fn color_from_bytes(color_slice: &[u8]) -> Option<Color> {
+    <[u8; 4]>::try_from(color_slice)
+        .ok()
+        .and_then(|color| try_cast::<_, RGBA8>(color).ok())
+        .or_else(|| {
+            <[u8; 3]>::try_from(color_slice)
+                .ok()
+                .and_then(|color| try_cast::<_, RGB8>(color).ok())
+                .map(|color| color.alpha(0xff))
+        })
+}
+
+/// Write a color to the start of the buffer, omitting the alpha value if the color is opaque.
+/// Returns the number of written bytes, or None if the buffer wasn’t large enough.
+fn write_color(color: &Color, buffer: &mut [u8]) -> Option<usize> {
+    if color.a != 0xff {
+        buffer.get_mut(0..4)?.copy_from_slice(color.as_slice());
+        Some(4)
+    } else {
+        buffer
+            .get_mut(0..3)?
+            .copy_from_slice(color.rgb().as_slice());
+        Some(3)
+    }
+}
+
+impl<'a> Packet<'a> {
     pub const SIZE_REQUEST_ID: u8 = 0xaa;
     pub const SIZE_RESPONSE_ID: u8 = 0xbb;
     pub const SET_PIXEL_ID: u8 = 0xcc;
+    pub const GET_PIXEL_ID: u8 = 0xdd;
+    pub const PIXEL_RESPONSE_ID: u8 = 0xee;
+    pub const CAPABILITIES_REQUEST_ID: u8 = 0xa1;
+    pub const CAPABILITIES_RESPONSE_ID: u8 = 0xb1;
+    pub const FILL_RECT_ID: u8 = 0xc1;
+    pub const SET_PIXELS_ID: u8 = 0xc2;
+    pub const BLIT_ID: u8 = 0xc3;
+    pub const PALETTE_REQUEST_ID: u8 = 0xa2;
+    pub const PALETTE_RESPONSE_ID: u8 = 0xb2;
+    pub const INDEXED_BLIT_ID: u8 = 0xc4;
+    pub const LOCK_REGION_ID: u8 = 0xa3;
+    pub const LOCK_RESPONSE_ID: u8 = 0xb3;
+    pub const UNLOCK_REGION_ID: u8 = 0xa4;
+    /// Size of the fields of a blit or indexed blit packet before its colors, including the packet type.
+    pub const BLIT_HEADER_SIZE: usize = 5;
 
     /// Parse a packet from the start of the provided binary representation.
-    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    /// Packets with a variable amount of data take all remaining bytes as their data.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
         let kind = bytes.first()?;
         match kind {
             0xaa => Some(Self::SizeRequest),
@@ -47,19 +328,89 @@ impl Packet {
             0xcc => {
                 let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
                 let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
-                let color_slice = bytes.get(5..)?;
-                // get an rgb or rgba color, depending on how large the remaining slice is
-                let color = <[u8; 4]>::try_from(color_slice)
-                    .ok()
-                    .and_then(|color| try_cast::<_, RGBA8>(color).ok())
-                    .or_else(|| {
-                        <[u8; 3]>::try_from(color_slice)
-                            .ok()
-                            .and_then(|color| try_cast::<_, RGB8>(color).ok())
-                            .map(|color| color.alpha(0xff))
-                    })?;
+                let color = color_from_bytes(bytes.get(5..)?)?;
                 Some(Self::SetPixel { x, y, color })
             }
+            0xdd => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                Some(Self::GetPixel { x, y })
+            }
+            0xee => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let color = color_from_bytes(bytes.get(5..)?)?;
+                Some(Self::PixelResponse { x, y, color })
+            }
+            0xa1 => Some(Self::CapabilitiesRequest),
+            0xb1 => {
+                let extensions = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let max_packet_size = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let canvases = u16::from_be_bytes(bytes.get(5..=6)?.try_into().unwrap());
+                let get_pixel_rate = u32::from_be_bytes(bytes.get(7..=10)?.try_into().unwrap());
+                let max_fill_area = u32::from_be_bytes(bytes.get(11..=14)?.try_into().unwrap());
+                Some(Self::CapabilitiesResponse(Capabilities {
+                    extensions: Extensions(extensions),
+                    max_packet_size,
+                    canvases,
+                    get_pixel_rate,
+                    max_fill_area,
+                }))
+            }
+            0xc1 => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let width = u16::from_be_bytes(bytes.get(5..=6)?.try_into().unwrap());
+                let height = u16::from_be_bytes(bytes.get(7..=8)?.try_into().unwrap());
+                let color = color_from_bytes(bytes.get(9..)?)?;
+                Some(Self::FillRect {
+                    x,
+                    y,
+                    width,
+                    height,
+                    color,
+                })
+            }
+            0xc2 => Some(Self::SetPixels(PixelEntries::new(bytes.get(1..)?)?)),
+            0xc3 => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let colors = Colors::new(bytes.get(Self::BLIT_HEADER_SIZE..)?)?;
+                Some(Self::Blit { x, y, colors })
+            }
+            0xa2 => Some(Self::PaletteRequest),
+            0xb2 => Some(Self::PaletteResponse(Palette::new(bytes.get(1..)?)?)),
+            0xc4 => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let indices = bytes.get(Self::BLIT_HEADER_SIZE..)?;
+                Some(Self::IndexedBlit { x, y, indices })
+            }
+            0xa3 | 0xb3 => {
+                let x = u16::from_be_bytes(bytes.get(1..=2)?.try_into().unwrap());
+                let y = u16::from_be_bytes(bytes.get(3..=4)?.try_into().unwrap());
+                let width = u16::from_be_bytes(bytes.get(5..=6)?.try_into().unwrap());
+                let height = u16::from_be_bytes(bytes.get(7..=8)?.try_into().unwrap());
+                let lease = u16::from_be_bytes(bytes.get(9..=10)?.try_into().unwrap());
+                Some(if *kind == 0xa3 {
+                    Self::LockRegion {
+                        x,
+                        y,
+                        width,
+                        height,
+                        lease,
+                    }
+                } else {
+                    Self::LockResponse {
+                        x,
+                        y,
+                        width,
+                        height,
+                        lease,
+                    }
+                })
+            }
+            0xa4 => Some(Self::UnlockRegion),
             _ => None,
         }
     }
@@ -82,33 +433,423 @@ impl Packet {
                     .map(|x| x.copy_from_slice(&height.to_be_bytes()))?;
                 5
             }
-            Packet::SetPixel { x, y, color } => {
-                buffer.get_mut(0).map(|x| *x = Self::SET_PIXEL_ID)?;
+            Packet::SetPixel { x, y, color } | Packet::PixelResponse { x, y, color } => {
+                let id = if matches!(self, Packet::SetPixel { .. }) {
+                    Self::SET_PIXEL_ID
+                } else {
+                    Self::PIXEL_RESPONSE_ID
+                };
+                buffer.get_mut(0).map(|x| *x = id)?;
+                buffer
+                    .get_mut(1..=2)
+                    .map(|val| val.copy_from_slice(&x.to_be_bytes()))?;
+                buffer
+                    .get_mut(3..=4)
+                    .map(|x| x.copy_from_slice(&y.to_be_bytes()))?;
+                5 + write_color(color, buffer.get_mut(5..)?)?
+            }
+            Packet::GetPixel { x, y } => {
+                buffer.get_mut(0).map(|x| *x = Self::GET_PIXEL_ID)?;
+                buffer
+                    .get_mut(1..=2)
+                    .map(|val| val.copy_from_slice(&x.to_be_bytes()))?;
+                buffer
+                    .get_mut(3..=4)
+                    .map(|x| x.copy_from_slice(&y.to_be_bytes()))?;
+                5
+            }
+            Packet::CapabilitiesRequest => {
+                buffer
+                    .get_mut(0)
+                    .map(|x| *x = Self::CAPABILITIES_REQUEST_ID)?;
+                1
+            }
+            Packet::CapabilitiesResponse(Capabilities {
+                extensions,
+                max_packet_size,
+                canvases,
+                get_pixel_rate,
+                max_fill_area,
+            }) => {
+                buffer
+                    .get_mut(0)
+                    .map(|x| *x = Self::CAPABILITIES_RESPONSE_ID)?;
+                buffer
+                    .get_mut(1..=2)
+                    .map(|x| x.copy_from_slice(&extensions.0.to_be_bytes()))?;
+                buffer
+                    .get_mut(3..=4)
+                    .map(|x| x.copy_from_slice(&max_packet_size.to_be_bytes()))?;
+                buffer
+                    .get_mut(5..=6)
+                    .map(|x| x.copy_from_slice(&canvases.to_be_bytes()))?;
+                buffer
+                    .get_mut(7..=10)
+                    .map(|x| x.copy_from_slice(&get_pixel_rate.to_be_bytes()))?;
+                buffer
+                    .get_mut(11..=14)
+                    .map(|x| x.copy_from_slice(&max_fill_area.to_be_bytes()))?;
+                15
+            }
+            Packet::FillRect {
+                x,
+                y,
+                width,
+                height,
+                color,
+            } => {
+                buffer.get_mut(0).map(|x| *x = Self::FILL_RECT_ID)?;
+                buffer
+                    .get_mut(1..=2)
+                    .map(|val| val.copy_from_slice(&x.to_be_bytes()))?;
+                buffer
+                    .get_mut(3..=4)
+                    .map(|x| x.copy_from_slice(&y.to_be_bytes()))?;
+                buffer
+                    .get_mut(5..=6)
+                    .map(|x| x.copy_from_slice(&width.to_be_bytes()))?;
+                buffer
+                    .get_mut(7..=8)
+                    .map(|x| x.copy_from_slice(&height.to_be_bytes()))?;
+                9 + write_color(color, buffer.get_mut(9..)?)?
+            }
+            Packet::SetPixels(pixels) => {
+                buffer.get_mut(0).map(|x| *x = Self::SET_PIXELS_ID)?;
+                let data = pixels.as_bytes();
+                buffer
+                    .get_mut(1..1 + data.len())
+                    .map(|x| x.copy_from_slice(data))?;
+                1 + data.len()
+            }
+            Packet::Blit { x, y, colors } => {
+                buffer.get_mut(0).map(|x| *x = Self::BLIT_ID)?;
                 buffer
                     .get_mut(1..=2)
                     .map(|val| val.copy_from_slice(&x.to_be_bytes()))?;
                 buffer
                     .get_mut(3..=4)
                     .map(|x| x.copy_from_slice(&y.to_be_bytes()))?;
-                5 + if color.a != 0xff {
-                    buffer.get_mut(5..9)?.copy_from_slice(color.as_slice());
-                    4
+                let data = colors.as_bytes();
+                buffer
+                    .get_mut(Self::BLIT_HEADER_SIZE..Self::BLIT_HEADER_SIZE + data.len())
+                    .map(|x| x.copy_from_slice(data))?;
+                Self::BLIT_HEADER_SIZE + data.len()
+            }
+            Packet::PaletteRequest => {
+                buffer.get_mut(0).map(|x| *x = Self::PALETTE_REQUEST_ID)?;
+                1
+            }
+            Packet::PaletteResponse(palette) => {
+                buffer.get_mut(0).map(|x| *x = Self::PALETTE_RESPONSE_ID)?;
+                let data = palette.as_bytes();
+                buffer
+                    .get_mut(1..1 + data.len())
+                    .map(|x| x.copy_from_slice(data))?;
+                1 + data.len()
+            }
+            Packet::IndexedBlit { x, y, indices } => {
+                buffer.get_mut(0).map(|x| *x = Self::INDEXED_BLIT_ID)?;
+                buffer
+                    .get_mut(1..=2)
+                    .map(|val| val.copy_from_slice(&x.to_be_bytes()))?;
+                buffer
+                    .get_mut(3..=4)
+                    .map(|x| x.copy_from_slice(&y.to_be_bytes()))?;
+                buffer
+                    .get_mut(Self::BLIT_HEADER_SIZE..Self::BLIT_HEADER_SIZE + indices.len())
+                    .map(|x| x.copy_from_slice(indices))?;
+                Self::BLIT_HEADER_SIZE + indices.len()
+            }
+            Packet::LockRegion {
+                x,
+                y,
+                width,
+                height,
+                lease,
+            }
+            | Packet::LockResponse {
+                x,
+                y,
+                width,
+                height,
+                lease,
+            } => {
+                let id = if matches!(self, Packet::LockRegion { .. }) {
+                    Self::LOCK_REGION_ID
                 } else {
+                    Self::LOCK_RESPONSE_ID
+                };
+                buffer.get_mut(0).map(|x| *x = id)?;
+                for (index, value) in [x, y, width, height, lease].into_iter().enumerate() {
                     buffer
-                        .get_mut(5..8)?
-                        .copy_from_slice(color.rgb().as_slice());
-                    3
+                        .get_mut(1 + 2 * index..3 + 2 * index)
+                        .map(|x| x.copy_from_slice(&value.to_be_bytes()))?;
                 }
+                11
+            }
+            Packet::UnlockRegion => {
+                buffer.get_mut(0).map(|x| *x = Self::UNLOCK_REGION_ID)?;
+                1
             }
         })
     }
 
+    /// Number of pixels that the packet sets.
+    pub fn pixels(&self) -> u64 {
+        match self {
+            Packet::SetPixel { .. } => 1,
+            Packet::SetPixels(pixels) => pixels.len() as u64,
+            Packet::Blit { colors, .. } => colors.len() as u64,
+            Packet::FillRect { width, height, .. } => u64::from(*width) * u64::from(*height),
+            Packet::IndexedBlit { indices, .. } => indices.len() as u64,
+            _ => 0,
+        }
+    }
+
+    /// The variable amount of data that the packet carries after its fixed fields.
+    fn data(&self) -> &'a [u8] {
+        match self {
+            Packet::SetPixels(pixels) => pixels.as_bytes(),
+            Packet::Blit { colors, .. } => colors.as_bytes(),
+            Packet::PaletteResponse(palette) => palette.as_bytes(),
+            Packet::IndexedBlit { indices, .. } => indices,
+            _ => &[],
+        }
+    }
+
     /// Convert the packet to its byte representation.
     #[cfg(feature = "std")]
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = vec![0; 9];
+        // Large enough for the fixed fields of the largest packet type, plus the data.
+        let mut buffer = vec![0; 15 + self.data().len()];
         let length = self.write_to(&mut buffer).unwrap();
         buffer.truncate(length);
         buffer
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(packet: Packet) {
+        let mut buffer = [0; 64];
+        let length = packet.write_to(&mut buffer).unwrap();
+        assert_eq!(Packet::from_bytes(&buffer[..length]), Some(packet));
+    }
+
+    #[test]
+    fn get_pixel() {
+        let bytes = [0xdd, 0, 1, 0, 2];
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Some(Packet::GetPixel { x: 1, y: 2 })
+        );
+        assert_eq!(Packet::from_bytes(&bytes[..4]), None);
+        round_trip(Packet::GetPixel { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn pixel_response() {
+        let bytes = [0xee, 0, 1, 0, 2, 10, 20, 30, 40];
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Some(Packet::PixelResponse {
+                x: 1,
+                y: 2,
+                color: color_from_rgba([10, 20, 30, 40]),
+            })
+        );
+        // Opaque colors may omit their alpha value.
+        assert_eq!(
+            Packet::from_bytes(&bytes[..8]),
+            Some(Packet::PixelResponse {
+                x: 1,
+                y: 2,
+                color: color_from_rgb([10, 20, 30]),
+            })
+        );
+        assert_eq!(Packet::from_bytes(&bytes[..7]), None);
+        round_trip(Packet::PixelResponse {
+            x: 1,
+            y: 2,
+            color: color_from_rgba([10, 20, 30, 40]),
+        });
+    }
+
+    #[test]
+    fn capabilities() {
+        assert_eq!(
+            Packet::from_bytes(&[0xa1]),
+            Some(Packet::CapabilitiesRequest)
+        );
+        let bytes = [0xb1, 0, 0b101, 5, 0xdc, 0, 2, 0, 0, 3, 0xe8, 0, 1, 0, 0];
+        let capabilities = Capabilities {
+            extensions: Extensions::GET_PIXEL | Extensions::SET_PIXELS,
+            max_packet_size: 1500,
+            canvases: 2,
+            get_pixel_rate: 1000,
+            max_fill_area: 65536,
+        };
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Some(Packet::CapabilitiesResponse(capabilities))
+        );
+        assert!(capabilities.extensions.contains(Extensions::SET_PIXELS));
+        assert!(!capabilities.extensions.contains(Extensions::FILL_RECT));
+        assert_eq!(Packet::from_bytes(&bytes[..14]), None);
+        round_trip(Packet::CapabilitiesRequest);
+        round_trip(Packet::CapabilitiesResponse(capabilities));
+    }
+
+    #[test]
+    fn fill_rect() {
+        let bytes = [0xc1, 0, 1, 0, 2, 0, 3, 0, 4, 10, 20, 30];
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Some(Packet::FillRect {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+                color: color_from_rgb([10, 20, 30]),
+            })
+        );
+        assert_eq!(Packet::from_bytes(&bytes[..9]), None);
+        round_trip(Packet::FillRect {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            color: color_from_rgba([10, 20, 30, 40]),
+        });
+    }
+
+    #[test]
+    fn set_pixels() {
+        let entries = [
+            PixelEntries::encode(1, 2, color_from_rgba([10, 20, 30, 40])),
+            PixelEntries::encode(3, 4, color_from_rgba([50, 60, 70, 80])),
+        ]
+        .concat();
+        let bytes = [&[0xc2][..], &entries].concat();
+        let Some(Packet::SetPixels(pixels)) = Packet::from_bytes(&bytes) else {
+            panic!("set pixels packet wasn't parsed");
+        };
+        assert_eq!(
+            pixels.iter().collect::<Vec<_>>(),
+            [
+                (1, 2, color_from_rgba([10, 20, 30, 40])),
+                (3, 4, color_from_rgba([50, 60, 70, 80])),
+            ]
+        );
+        assert_eq!(
+            Packet::from_bytes(&[&[0xc2][..], &entries[..7]].concat()),
+            None
+        );
+        round_trip(Packet::SetPixels(PixelEntries::new(&entries).unwrap()));
+    }
+
+    #[test]
+    fn blit() {
+        let bytes = [0xc3, 0, 1, 0, 2, 10, 20, 30, 40, 50, 60, 70, 80];
+        let Some(Packet::Blit { x: 1, y: 2, colors }) = Packet::from_bytes(&bytes) else {
+            panic!("blit packet wasn't parsed");
+        };
+        assert_eq!(
+            colors.iter().collect::<Vec<_>>(),
+            [
+                color_from_rgba([10, 20, 30, 40]),
+                color_from_rgba([50, 60, 70, 80]),
+            ]
+        );
+        assert_eq!(Packet::from_bytes(&bytes[..12]), None);
+        assert_eq!(Packet::from_bytes(&bytes[..4]), None);
+        round_trip(Packet::Blit {
+            x: 1,
+            y: 2,
+            colors: Colors::new(&bytes[5..]).unwrap(),
+        });
+    }
+
+    #[test]
+    fn palette() {
+        assert_eq!(Packet::from_bytes(&[0xa2]), Some(Packet::PaletteRequest));
+        let bytes = [0xb2, 0, 0, 0, 255, 128, 0];
+        let Some(Packet::PaletteResponse(palette)) = Packet::from_bytes(&bytes) else {
+            panic!("palette response wasn't parsed");
+        };
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette.get(1), Some(color_from_rgb([255, 128, 0])));
+        assert_eq!(palette.get(2), None);
+        assert_eq!(Packet::from_bytes(&bytes[..6]), None);
+        let too_many = [0; 1 + 257 * PALETTE_COLOR_SIZE];
+        assert_eq!(Packet::from_bytes(&[&[0xb2][..], &too_many].concat()), None);
+        round_trip(Packet::PaletteResponse(palette));
+    }
+
+    #[test]
+    fn indexed_blit() {
+        let bytes = [0xc4, 0, 1, 0, 2, 5, 0, 7];
+        assert_eq!(
+            Packet::from_bytes(&bytes),
+            Some(Packet::IndexedBlit {
+                x: 1,
+                y: 2,
+                indices: &[5, 0, 7],
+            })
+        );
+        assert_eq!(Packet::from_bytes(&bytes[..4]), None);
+        round_trip(Packet::IndexedBlit {
+            x: 1,
+            y: 2,
+            indices: &[5, 0, 7],
+        });
+    }
+
+    #[test]
+    fn lock_region() {
+        let bytes = [0xa3, 0, 1, 0, 2, 0, 3, 0, 4, 0, 30];
+        let lock = Packet::LockRegion {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            lease: 30,
+        };
+        assert_eq!(Packet::from_bytes(&bytes), Some(lock));
+        assert_eq!(Packet::from_bytes(&bytes[..10]), None);
+        round_trip(lock);
+        round_trip(Packet::LockResponse {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            lease: 0,
+        });
+        round_trip(Packet::UnlockRegion);
+    }
+
+    #[test]
+    fn blend_over() {
+        let below = Color::new(0, 0, 255, 128);
+        let red = Color::new(255, 0, 0, 255);
+        assert_eq!(red.blend_over(below), red);
+        assert_eq!(red.blend_over(Color::new(0, 0, 0, 0)), red);
+
+        let transparent = Color::new(255, 0, 0, 0);
+        assert_eq!(transparent.blend_over(below), below);
+        assert_eq!(
+            transparent.blend_over(Color::new(0, 0, 255, 0)),
+            Color::new(0, 0, 0, 0)
+        );
+
+        let half_red = Color::new(255, 0, 0, 128);
+        assert_eq!(
+            half_red.blend_over(Color::new(0, 0, 255, 255)),
+            Color::new(128, 0, 127, 255)
+        );
+        // The result is more opaque than either color, and mostly the color on top.
+        assert_eq!(half_red.blend_over(below), Color::new(170, 0, 84, 191));
+    }
+}
@@ -6,12 +6,20 @@ use etherparse::{Icmpv6Slice, SlicedPacket, TransportSlice};
 use socket2::{Domain, Protocol, Socket, Type};
 use std::{
     io::{self, ErrorKind, Read},
-    net::SocketAddr,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
 /// Includes both the real header (4 bytes) as well as the echo standard data (4 bytes).
 pub const ICMP_HEADER_SIZE: usize = 8;
 pub const IPV4_HEADER_SIZE: usize = 20;
+pub const IPV6_HEADER_SIZE: usize = 40;
+/// Protocol number of ICMPv6, as the next header of IPv6 packets.
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
 pub const ECHO_REQUEST_V4: u8 = 8;
 pub const ECHO_REQUEST_V6: u8 = 128;
 pub const ECHO_REPLY_V4: u8 = 0;
@@ -65,31 +73,49 @@ impl Icmp {
     }
 
     /// lowest priority DSCP
-    const DSCP_LOW_PRIORITY: u32 = 8 << 2;
+    pub const DSCP_LOW_PRIORITY: u32 = 8 << 2;
 
     /// Send this ICMP packet.
     /// Apart from the send action this has the additional effect of incrementing the sequence number of this packet.
     ///
     /// Returns the socket used for sending so that responses can be received.
     pub fn send(&mut self) -> Result<Socket, io::Error> {
-        self.encode();
-        let socket = if self.target.is_ipv4() {
+        let socket = Self::socket_for(self.target)?;
+        self.send_on(&socket)?;
+        Ok(socket)
+    }
+
+    /// Opens a low-priority raw socket that can send ICMP packets to the target’s address family.
+    pub fn socket_for(target: SocketAddr) -> Result<Socket, io::Error> {
+        let socket = if target.is_ipv4() {
             Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?
         } else {
             Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))?
         };
-        if self.target.is_ipv4() {
+        if target.is_ipv4() {
             socket.set_tos(Self::DSCP_LOW_PRIORITY)?;
         } else {
             socket.set_tclass_v6(Self::DSCP_LOW_PRIORITY)?;
         }
+        Ok(socket)
+    }
 
+    /// Send this ICMP packet on an existing socket from [`Icmp::socket_for`], for sending many packets without opening a socket for each.
+    /// Like [`Icmp::send`], this increments the sequence number of this packet.
+    pub fn send_on(&mut self, socket: &Socket) -> Result<(), io::Error> {
+        self.encode();
         socket.send_to(&self.packet, &self.target.into())?;
 
         self.current_sequence_number = self.current_sequence_number.wrapping_add(1);
         self.update_seq(self.current_sequence_number);
+        Ok(())
+    }
 
-        Ok(socket)
+    /// Encode this packet without sending it, for example to send it many times without encoding it again.
+    /// Use [`update_sequence_number`] to change the sequence number of the encoded packet.
+    pub fn encoded(&mut self) -> &[u8] {
+        self.encode();
+        &self.packet
     }
 
     /// Encode this packet’s data.
@@ -139,6 +165,67 @@ impl Icmp {
     }
 }
 
+/// Change the sequence number of an encoded ICMP packet, updating its checksum incrementally (RFC 1624)
+/// instead of computing it over the whole packet again.
+pub fn update_sequence_number(packet: &mut [u8], sequence_number: u16) {
+    let old_sequence_number = u16::from_be_bytes([packet[6], packet[7]]);
+    let old_checksum = u16::from_be_bytes([packet[2], packet[3]]);
+    let mut sum =
+        u32::from(!old_checksum) + u32::from(!old_sequence_number) + u32::from(sequence_number);
+    while (sum >> 16) > 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    packet[2..4].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+    packet[6..8].copy_from_slice(&sequence_number.to_be_bytes());
+}
+
+/// Add the IPv6 pseudo header to the checksum of an encoded ICMPv6 packet.
+/// The kernel does this for normal ICMPv6 sockets, but not for packets whose IP header is included.
+pub fn add_pseudo_header_v6(packet: &mut [u8], source: Ipv6Addr, destination: Ipv6Addr) {
+    let length = packet.len() as u32;
+    let mut sum = u32::from(!u16::from_be_bytes([packet[2], packet[3]]));
+    for segment in source.segments().into_iter().chain(destination.segments()) {
+        sum += u32::from(segment);
+    }
+    sum += (length >> 16) + (length & 0xffff) + u32::from(NEXT_HEADER_ICMPV6);
+    while (sum >> 16) > 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    packet[2..4].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+}
+
+/// Sends many echo packets to one target over a single socket,
+/// instead of opening a new socket for every packet like [`Icmp::send`] does.
+pub struct IcmpSender {
+    socket: Socket,
+    icmp: Icmp,
+}
+
+impl IcmpSender {
+    /// Open a sender for packets to the target.
+    pub fn new(
+        target: SocketAddr,
+        identifier: u16,
+        direction: EchoDirection,
+    ) -> Result<Self, io::Error> {
+        Ok(Self {
+            socket: Icmp::socket_for(target)?,
+            icmp: Icmp::new(target, identifier, direction),
+        })
+    }
+
+    /// Send a packet with the given payload. Subsequent packets have increasing sequence numbers.
+    pub fn send(&mut self, payload: Vec<u8>) -> Result<(), io::Error> {
+        self.icmp.set_payload(payload);
+        self.icmp.send_on(&self.socket)
+    }
+
+    /// The socket packets are sent on, which also receives responses.
+    pub fn socket(&mut self) -> &mut Socket {
+        &mut self.socket
+    }
+}
+
 /// Read ICMP packets from the specified socket, and return the first payload that matches a certain condition.
 pub(crate) fn read_icmp_packets_until(
     socket: &mut Socket,
@@ -193,15 +280,38 @@ pub(crate) fn read_first_icmp_packet_with_type(
     })
 }
 
+/// Tells listeners to stop. Clones share the same state, so one handle can stop many listeners.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle(Arc<AtomicBool>);
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks all listeners using this handle to stop.
+    pub fn shutdown(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 /// There’s no working async raw socket implementation for Rust at the moment, and I don’t want to implement a “real” one just for this.
 /// Instead, run blocking reads on an additional thread and forward data through an async channel to the async workers.
 pub struct IcmpListener {
     socket: Socket,
     send_queue: async_channel::Sender<(Vec<u8>, SocketAddr)>,
     pub receive_queue: async_channel::Receiver<(Vec<u8>, SocketAddr)>,
+    shutdown: ShutdownHandle,
 }
 
 impl IcmpListener {
+    /// How often a blocked listener checks whether it should stop.
+    pub const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
     pub fn new(is_ipv4: bool) -> Result<IcmpListener, io::Error> {
         let socket = if is_ipv4 {
             Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?
@@ -218,29 +328,64 @@ impl IcmpListener {
         Ok(Self::new_from_socket(socket))
     }
 
+    /// Create a listener that only receives packets sent to the given address.
+    pub fn bound_to_address(address: IpAddr) -> Result<IcmpListener, io::Error> {
+        let listener = Self::new(address.is_ipv4())?;
+        listener.socket.bind(&SocketAddr::new(address, 0).into())?;
+        Ok(listener)
+    }
+
+    /// Create a listener that only receives packets arriving on the given network interface, such as `eth0`.
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn bound_to_interface(is_ipv4: bool, interface: &str) -> Result<IcmpListener, io::Error> {
+        let listener = Self::new(is_ipv4)?;
+        listener.socket.bind_device(Some(interface.as_bytes()))?;
+        Ok(listener)
+    }
+
     pub fn new_from_socket(socket: Socket) -> Self {
         let (send_queue, receive_queue) = async_channel::unbounded();
         Self {
             socket,
             send_queue,
             receive_queue,
+            shutdown: ShutdownHandle::new(),
         }
     }
 
-    /// Reads data from the socket in an infinite loop.
-    pub fn run(&mut self) {
+    /// Use an existing shutdown handle, for example one that is shared with other listeners.
+    pub fn with_shutdown_handle(mut self, shutdown: ShutdownHandle) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Reads data from the socket in a loop, until the listener is shut down, the socket is closed or reading fails.
+    /// Afterwards, the receive queue is closed once the listener is dropped.
+    pub fn run(&mut self) -> Result<(), io::Error> {
         let mut buffer = [0; 2048];
-        loop {
-            let result = self
-                .socket
-                .recv_from(unsafe { std::mem::transmute(buffer.as_mut_slice()) });
+        // Wake up regularly, so that shutdown requests are noticed even without traffic.
+        let _ = self
+            .socket
+            .set_read_timeout(Some(Self::SHUTDOWN_POLL_INTERVAL));
+        while !self.shutdown.is_shut_down() {
+            let result = self.socket.recv_from(unsafe {
+                std::mem::transmute::<&mut [u8], &mut [std::mem::MaybeUninit<u8>]>(
+                    buffer.as_mut_slice(),
+                )
+            });
             match result {
                 Err(why) => match why.kind() {
+                    // read timeout, check for shutdown
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => {}
                     // socket closed, time to stop
                     ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe => {
-                        return;
+                        return Ok(());
                     }
-                    _ => {}
+                    _ => return Err(why),
                 },
                 Ok((size, address)) => {
                     let received_data = buffer[..size].to_owned();
@@ -249,10 +394,36 @@ impl IcmpListener {
                         address.as_socket().expect("only ip sockets are supported"),
                     ));
                     if send_result.is_err() {
-                        return;
+                        return Ok(());
                     }
                 }
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incremental_checksums_match_full_ones() {
+        for (target, payload) in [
+            (
+                "192.0.2.1:0",
+                vec![0xaa, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde],
+            ),
+            ("[2001:db8::1]:0", vec![0xff; 7]),
+        ] {
+            let mut icmp = Icmp::new(target.parse().unwrap(), 0x1234, EchoDirection::Request);
+            icmp.set_payload(payload);
+            let mut packet = icmp.encoded().to_vec();
+            for sequence_number in [1, 0xff, 0x100, 0x7fff, 0xfffe, 0xffff, 0] {
+                update_sequence_number(&mut packet, sequence_number);
+                icmp.update_seq(sequence_number);
+                assert_eq!(packet, icmp.packet, "sequence number {sequence_number}");
+            }
+        }
     }
 }
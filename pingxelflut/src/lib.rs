@@ -1,4 +1,7 @@
 //! Common backend of the Pingxelflut client and server.
+//!
+//! Targets are IP addresses, or with the `_at` variants socket addresses whose port is ignored,
+//! so that link-local IPv6 addresses keep their scope ID.
 #![cfg_attr(not(feature = "std"), no_std)]
 
 pub mod format;
@@ -8,22 +11,35 @@ pub mod icmp;
 #[cfg(feature = "std")]
 mod std_functions {
     use std::io;
+    use std::io::ErrorKind;
+    use std::mem::MaybeUninit;
     use std::net::IpAddr;
     use std::net::SocketAddr;
+    use std::time::Duration;
+    use std::time::Instant;
 
+    use etherparse::Icmpv6Slice;
+    use etherparse::SlicedPacket;
+    use etherparse::TransportSlice;
+    use socket2::Socket;
+
+    use crate::format::Capabilities;
     use crate::format::Color;
     use crate::format::Packet;
     use crate::icmp::read_first_icmp_packet_with_type;
+    use crate::icmp::read_icmp_packets_until;
     use crate::icmp::EchoDirection;
     use crate::icmp::Icmp;
+    use crate::icmp::IcmpSender;
 
     /// Query and return the size of the provided Pingxelflut server.
     pub fn get_size(target: IpAddr) -> Result<(u16, u16), io::Error> {
-        let mut size_request = Icmp::new(
-            SocketAddr::new(target, 0).to_owned(),
-            0,
-            EchoDirection::Request,
-        );
+        get_size_at(SocketAddr::new(target, 0))
+    }
+
+    /// Query and return the size of the Pingxelflut server at the socket address, whose port is ignored.
+    pub fn get_size_at(target: SocketAddr) -> Result<(u16, u16), io::Error> {
+        let mut size_request = Icmp::new(target, 0, EchoDirection::Request);
         size_request.set_payload(Packet::SizeRequest.to_bytes());
         let mut socket = size_request.send()?;
         let raw_response = read_first_icmp_packet_with_type(&mut socket, Packet::SIZE_RESPONSE_ID)?;
@@ -35,13 +51,234 @@ mod std_functions {
         }
     }
 
+    /// Read a single pixel from a target Pingxelflut server.
+    /// The server needs to support the get pixel extension.
+    pub fn get_pixel(target: IpAddr, x: u16, y: u16) -> Result<Color, io::Error> {
+        get_pixel_at(SocketAddr::new(target, 0), x, y)
+    }
+
+    /// Read a single pixel from the Pingxelflut server at the socket address, whose port is ignored.
+    pub fn get_pixel_at(target: SocketAddr, x: u16, y: u16) -> Result<Color, io::Error> {
+        let mut get_request = Icmp::new(target, 0, EchoDirection::Request);
+        get_request.set_payload(Packet::GetPixel { x, y }.to_bytes());
+        let mut socket = get_request.send()?;
+        let raw_response = read_icmp_packets_until(&mut socket, |buffer| {
+            matches!(
+                Packet::from_bytes(buffer),
+                Some(Packet::PixelResponse { x: response_x, y: response_y, .. })
+                    if response_x == x && response_y == y
+            )
+        })?;
+        match Packet::from_bytes(&raw_response) {
+            Some(Packet::PixelResponse { color, .. }) => Ok(color),
+            _ => Err(io::Error::other("invalid packet")),
+        }
+    }
+
+    /// A connection to a Pingxelflut server that keeps its socket open, for sending many packets.
+    /// This is much faster than the free functions, which open a new socket for every packet.
+    pub struct PingxelflutClient {
+        sender: IcmpSender,
+    }
+
+    impl PingxelflutClient {
+        pub fn new(target: IpAddr) -> Result<Self, io::Error> {
+            Self::new_at(SocketAddr::new(target, 0))
+        }
+
+        /// Connects to the server at the socket address, whose port is ignored.
+        pub fn new_at(target: SocketAddr) -> Result<Self, io::Error> {
+            Ok(Self {
+                sender: IcmpSender::new(target, 0, EchoDirection::Request)?,
+            })
+        }
+
+        /// Set a single pixel on the server.
+        pub fn set_pixel(&mut self, x: u16, y: u16, color: Color) -> Result<(), io::Error> {
+            self.sender
+                .send(Packet::SetPixel { x, y, color }.to_bytes())
+        }
+
+        /// Send any packet to the server, such as one of the larger packets of optional extensions.
+        pub fn send(&mut self, packet: &Packet) -> Result<(), io::Error> {
+            self.sender.send(packet.to_bytes())
+        }
+
+        /// Ask the server for a single pixel without waiting for the response, so that many requests can be in flight.
+        /// The responses are read with a [`PixelReceiver`].
+        pub fn request_pixel(&mut self, x: u16, y: u16) -> Result<(), io::Error> {
+            self.sender.send(Packet::GetPixel { x, y }.to_bytes())
+        }
+    }
+
+    /// Receives pixel responses from a Pingxelflut server on a socket of its own,
+    /// so that requests can be sent at the same time.
+    pub struct PixelReceiver {
+        socket: Socket,
+        target: SocketAddr,
+    }
+
+    impl PixelReceiver {
+        pub fn new(target: IpAddr) -> Result<Self, io::Error> {
+            Self::new_at(SocketAddr::new(target, 0))
+        }
+
+        /// Receives from the server at the socket address, whose port is ignored.
+        pub fn new_at(target: SocketAddr) -> Result<Self, io::Error> {
+            Ok(Self {
+                socket: Icmp::socket_for(target)?,
+                target,
+            })
+        }
+
+        /// Wait for the next pixel response from the server, returning its position and color,
+        /// or `None` if none arrived within the timeout. Other packets are skipped.
+        pub fn receive(
+            &mut self,
+            timeout: Duration,
+        ) -> Result<Option<(u16, u16, Color)>, io::Error> {
+            receive_matching(&self.socket, self.target, timeout, |packet| match packet {
+                Packet::PixelResponse { x, y, color } => Some((x, y, color)),
+                _ => None,
+            })
+        }
+    }
+
+    /// Wait for the first packet from the target that the function picks something from,
+    /// or return `None` if none arrived within the timeout.
+    fn receive_matching<T>(
+        socket: &Socket,
+        target: SocketAddr,
+        timeout: Duration,
+        mut pick: impl FnMut(Packet) -> Option<T>,
+    ) -> Result<Option<T>, io::Error> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [MaybeUninit::<u8>::uninit(); 2048];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let (size, address) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(why) => match why.kind() {
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted => {
+                        continue
+                    }
+                    _ => return Err(why),
+                },
+            };
+            if address.as_socket().map(|address| address.ip()) != Some(target.ip()) {
+                continue;
+            }
+            // SAFETY: the socket initialized the first `size` bytes.
+            let packet = unsafe { std::slice::from_raw_parts(buffer.as_ptr().cast::<u8>(), size) };
+            let payload = if target.is_ipv4() {
+                match SlicedPacket::from_ip(packet).map(|packet| packet.transport) {
+                    Ok(Some(TransportSlice::Icmpv4(icmp))) => icmp.payload(),
+                    _ => continue,
+                }
+            } else {
+                match Icmpv6Slice::from_slice(packet) {
+                    Ok(icmp) => icmp.payload(),
+                    _ => continue,
+                }
+            };
+            if let Some(picked) = Packet::from_bytes(payload).and_then(&mut pick) {
+                return Ok(Some(picked));
+            }
+        }
+    }
+
+    /// Ask the server at the socket address, whose port is ignored, for its optional extensions and limits.
+    /// Returns `None` if the server didn’t answer within the timeout, which servers without the capabilities
+    /// extension never do.
+    pub fn get_capabilities_at(
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Option<Capabilities>, io::Error> {
+        let socket = Icmp::socket_for(target)?;
+        let mut request = Icmp::new(target, 0, EchoDirection::Request);
+        request.set_payload(Packet::CapabilitiesRequest.to_bytes());
+        request.send_on(&socket)?;
+        receive_matching(&socket, target, timeout, |packet| match packet {
+            Packet::CapabilitiesResponse(capabilities) => Some(capabilities),
+            _ => None,
+        })
+    }
+
+    /// Ask the server at the socket address, whose port is ignored, for the colors of its palette.
+    /// Returns `None` if the server didn’t answer within the timeout, which servers without the palette extension
+    /// never do.
+    pub fn get_palette_at(
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Option<Vec<Color>>, io::Error> {
+        let socket = Icmp::socket_for(target)?;
+        let mut request = Icmp::new(target, 0, EchoDirection::Request);
+        request.set_payload(Packet::PaletteRequest.to_bytes());
+        request.send_on(&socket)?;
+        receive_matching(&socket, target, timeout, |packet| match packet {
+            Packet::PaletteResponse(palette) => Some(palette.iter().collect()),
+            _ => None,
+        })
+    }
+
+    /// Ask the server at the socket address, whose port is ignored, to reserve a region for this host for the lease
+    /// in seconds, or to renew the reservation. Returns the lease the server granted, which is 0 if it refused,
+    /// or `None` if it didn’t answer within the timeout, which servers without the lock region extension never do.
+    pub fn lock_region_at(
+        target: SocketAddr,
+        (x, y, width, height): (u16, u16, u16, u16),
+        lease: u16,
+        timeout: Duration,
+    ) -> Result<Option<u16>, io::Error> {
+        let socket = Icmp::socket_for(target)?;
+        let mut request = Icmp::new(target, 0, EchoDirection::Request);
+        request.set_payload(
+            Packet::LockRegion {
+                x,
+                y,
+                width,
+                height,
+                lease,
+            }
+            .to_bytes(),
+        );
+        request.send_on(&socket)?;
+        receive_matching(&socket, target, timeout, |packet| match packet {
+            Packet::LockResponse {
+                x: response_x,
+                y: response_y,
+                width: response_width,
+                height: response_height,
+                lease,
+            } if (response_x, response_y, response_width, response_height)
+                == (x, y, width, height) =>
+            {
+                Some(lease)
+            }
+            _ => None,
+        })
+    }
+
+    /// Release the reservation of this host on the server at the socket address, whose port is ignored.
+    pub fn unlock_region_at(target: SocketAddr) -> Result<(), io::Error> {
+        let mut request = Icmp::new(target, 0, EchoDirection::Request);
+        request.set_payload(Packet::UnlockRegion.to_bytes());
+        request.send()?;
+        Ok(())
+    }
+
     /// Set a single pixel on a target Pingxelflut server.
     pub fn set_pixel(target: IpAddr, x: u16, y: u16, color: Color) -> Result<(), io::Error> {
-        let mut set_request = Icmp::new(
-            SocketAddr::new(target, 0).to_owned(),
-            1,
-            EchoDirection::Request,
-        );
+        set_pixel_at(SocketAddr::new(target, 0), x, y, color)
+    }
+
+    /// Set a single pixel on the Pingxelflut server at the socket address, whose port is ignored.
+    pub fn set_pixel_at(target: SocketAddr, x: u16, y: u16, color: Color) -> Result<(), io::Error> {
+        let mut set_request = Icmp::new(target, 0, EchoDirection::Request);
         set_request.set_payload(Packet::SetPixel { x, y, color }.to_bytes());
         set_request.send()?;
         Ok(())
@@ -50,3 +287,9 @@ mod std_functions {
 
 #[cfg(feature = "std")]
 pub use std_functions::*;
+
+/// Compare two byte strings without leaking the position of the first difference through timing,
+/// for checking the access tokens of control APIs.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
@@ -0,0 +1,90 @@
+// Applies queued pixels and fade steps to the canvas on the GPU, and copies it into the texture that is rendered.
+// Colors are packed as little-endian RGBA, the same byte order as the frame buffer on the CPU.
+
+struct Params {
+    width: u32,
+    height: u32,
+    write_count: u32,
+    // Nonzero ignores alpha instead of blending, like `BlendMode::Overwrite`.
+    overwrite: u32,
+    fade_steps: u32,
+    // Fraction of the distance to the background that every fade step keeps, in 1/65536.
+    fade_retain: u32,
+    // Area covered by overlays, which is left untouched in the texture. Empty if start and end are equal.
+    overlay_start: vec2<u32>,
+    overlay_end: vec2<u32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> canvas: array<u32>;
+// Pixel index and packed color of every queued write.
+@group(0) @binding(2) var<storage, read> writes: array<vec2<u32>>;
+@group(0) @binding(3) var output: texture_storage_2d<rgba8unorm, write>;
+@group(1) @binding(0) var<storage, read> background: array<u32>;
+// Nonzero for every pixel that was set since the last fade step.
+@group(1) @binding(1) var<storage, read_write> fresh: array<u32>;
+
+fn unpack(color: u32) -> vec4<u32> {
+    return vec4<u32>(color & 0xffu, (color >> 8u) & 0xffu, (color >> 16u) & 0xffu, color >> 24u);
+}
+
+fn pack(color: vec4<u32>) -> u32 {
+    return color.r | (color.g << 8u) | (color.b << 16u) | (color.a << 24u);
+}
+
+// Source-over compositing, with the same integer arithmetic as `ColorExt::blend_over`.
+fn blend_over(src: vec4<u32>, below: vec4<u32>) -> vec4<u32> {
+    let below_alpha = below.a * (255u - src.a) / 255u;
+    let alpha_out = src.a + below_alpha;
+    if alpha_out == 0u {
+        return vec4<u32>(0u);
+    }
+    let rgb = (src.rgb * src.a + below.rgb * below_alpha) / alpha_out;
+    return vec4<u32>(rgb, alpha_out);
+}
+
+@compute @workgroup_size(64)
+fn apply(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.write_count {
+        return;
+    }
+    let write = writes[id.x];
+    let color = unpack(write.y);
+    fresh[write.x] = 1u;
+    if params.overwrite != 0u || color.a == 255u {
+        canvas[write.x] = pack(vec4<u32>(color.rgb, 255u));
+    } else {
+        canvas[write.x] = pack(blend_over(color, unpack(canvas[write.x])));
+    }
+}
+
+@compute @workgroup_size(8, 8)
+fn fade(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    let index = id.x + id.y * params.width;
+    // Pixels set since the last step skip one step, like on the CPU.
+    let skipped = min(fresh[index], 1u);
+    fresh[index] = 0u;
+    var color = vec4<i32>(unpack(canvas[index]));
+    let goal = vec4<i32>(unpack(background[index])).rgb;
+    // Same arithmetic as `fade_channel`: keep the retained fraction of the distance, rounded down.
+    for (var step = skipped; step < params.fade_steps; step += 1u) {
+        let distance = vec3<i32>((vec3<u32>(abs(color.rgb - goal)) * params.fade_retain) >> vec3<u32>(16u));
+        color = vec4<i32>(select(goal - distance, goal + distance, color.rgb > goal), color.a);
+    }
+    canvas[index] = pack(vec4<u32>(color));
+}
+
+@compute @workgroup_size(8, 8)
+fn present(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.width || id.y >= params.height {
+        return;
+    }
+    if all(id.xy >= params.overlay_start) && all(id.xy < params.overlay_end) {
+        return;
+    }
+    let color = unpack(canvas[id.x + id.y * params.width]);
+    textureStore(output, vec2<i32>(id.xy), vec4<f32>(color) / 255.0);
+}
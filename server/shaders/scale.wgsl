@@ -0,0 +1,34 @@
+// Scales the canvas texture onto the window surface.
+// Based on the scaling shader of the `pixels` crate.
+
+struct VertexOutput {
+    @location(0) tex_coord: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+}
+
+struct Locals {
+    transform: mat4x4<f32>,
+    // Gain of the red, green and blue channels, and the gamma.
+    correction: vec4<f32>,
+}
+@group(0) @binding(2) var<uniform> r_locals: Locals;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec2<f32>,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coord = fma(position, vec2<f32>(0.5, -0.5), vec2<f32>(0.5, 0.5));
+    out.position = r_locals.transform * vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+@group(0) @binding(0) var r_tex_color: texture_2d<f32>;
+@group(0) @binding(1) var r_tex_sampler: sampler;
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    let color = textureSample(r_tex_color, r_tex_sampler, tex_coord);
+    let gained = clamp(color.rgb * r_locals.correction.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    return vec4<f32>(pow(gained, vec3<f32>(1.0 / r_locals.correction.w)), color.a);
+}
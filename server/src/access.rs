@@ -0,0 +1,144 @@
+use ipnet::IpNet;
+use parking_lot::RwLock;
+use std::net::IpAddr;
+
+/// Decides which source addresses may interact with the server, based on allowed and banned prefixes.
+#[derive(Debug, Default)]
+pub struct AccessList {
+    /// If not empty, only sources within these prefixes are allowed.
+    allowed: RwLock<Vec<IpNet>>,
+    /// Sources within these prefixes are never allowed, even if they are within an allowed prefix.
+    banned: RwLock<Vec<IpNet>>,
+}
+
+impl AccessList {
+    pub fn new(allowed: Vec<IpNet>, banned: Vec<IpNet>) -> Self {
+        Self {
+            allowed: RwLock::new(allowed),
+            banned: RwLock::new(banned),
+        }
+    }
+
+    /// Replaces both lists, for example with the ones from a reloaded configuration.
+    pub fn replace(&self, allowed: Vec<IpNet>, banned: Vec<IpNet>) {
+        *self.allowed.write() = allowed;
+        *self.banned.write() = banned;
+    }
+
+    /// Returns whether packets from the source should be handled.
+    pub fn is_allowed(&self, source: IpAddr) -> bool {
+        let allowed = self.allowed.read();
+        (allowed.is_empty() || allowed.iter().any(|prefix| prefix.contains(&source)))
+            && !self
+                .banned
+                .read()
+                .iter()
+                .any(|prefix| prefix.contains(&source))
+    }
+
+    /// Ban a prefix. Returns whether the prefix wasn’t banned before.
+    pub fn ban(&self, prefix: IpNet) -> bool {
+        insert_prefix(&self.banned, prefix)
+    }
+
+    /// Unban a prefix. Returns whether the prefix was banned before.
+    pub fn unban(&self, prefix: IpNet) -> bool {
+        remove_prefix(&self.banned, prefix)
+    }
+
+    /// Returns all currently banned prefixes.
+    pub fn banned(&self) -> Vec<IpNet> {
+        self.banned.read().clone()
+    }
+
+    /// Add a prefix to the allowlist. Returns whether the prefix wasn’t allowed before.
+    ///
+    /// Note that adding the first prefix forbids all sources outside of it.
+    pub fn allow(&self, prefix: IpNet) -> bool {
+        insert_prefix(&self.allowed, prefix)
+    }
+
+    /// Remove a prefix from the allowlist. Returns whether the prefix was allowed before.
+    ///
+    /// Note that removing the last prefix allows all sources again.
+    pub fn disallow(&self, prefix: IpNet) -> bool {
+        remove_prefix(&self.allowed, prefix)
+    }
+
+    /// Returns all prefixes on the allowlist.
+    pub fn allowed(&self) -> Vec<IpNet> {
+        self.allowed.read().clone()
+    }
+}
+
+fn insert_prefix(list: &RwLock<Vec<IpNet>>, prefix: IpNet) -> bool {
+    let mut list = list.write();
+    if list.contains(&prefix) {
+        return false;
+    }
+    list.push(prefix);
+    true
+}
+
+fn remove_prefix(list: &RwLock<Vec<IpNet>>, prefix: IpNet) -> bool {
+    let mut list = list.write();
+    let length_before = list.len();
+    list.retain(|existing| *existing != prefix);
+    list.len() != length_before
+}
+
+/// Parse a CIDR prefix like `10.0.0.0/8`. A plain address is treated as a prefix containing only that address.
+pub fn parse_prefix(text: &str) -> Result<IpNet, String> {
+    text.parse::<IpNet>()
+        .map(|prefix| prefix.trunc())
+        .or_else(|_| text.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("invalid address or CIDR prefix: {text}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(text: &str) -> IpNet {
+        parse_prefix(text).unwrap()
+    }
+
+    #[test]
+    fn prefixes_are_parsed_and_truncated() {
+        assert_eq!(prefix("10.1.2.3/8"), "10.0.0.0/8".parse().unwrap());
+        assert_eq!(prefix("192.0.2.1"), "192.0.2.1/32".parse().unwrap());
+        assert_eq!(prefix("2001:db8::1"), "2001:db8::1/128".parse().unwrap());
+        assert_eq!(prefix("2001:db8::1/32"), "2001:db8::/32".parse().unwrap());
+        assert!(parse_prefix("10.0.0.0/33").is_err());
+        assert!(parse_prefix("example.com").is_err());
+    }
+
+    #[test]
+    fn bans_override_the_allowlist() {
+        let access = AccessList::default();
+        let (inside, outside) = ([10, 1, 2, 3].into(), [192, 0, 2, 1].into());
+        assert!(access.is_allowed(inside) && access.is_allowed(outside));
+
+        assert!(access.allow(prefix("10.0.0.0/8")));
+        assert!(!access.allow(prefix("10.0.0.0/8")));
+        assert!(access.is_allowed(inside));
+        assert!(!access.is_allowed(outside));
+
+        assert!(access.ban(prefix("10.1.0.0/16")));
+        assert!(!access.is_allowed(inside));
+        assert!(access.is_allowed([10, 2, 0, 1].into()));
+
+        assert!(access.unban(prefix("10.1.0.0/16")));
+        assert!(!access.unban(prefix("10.1.0.0/16")));
+        assert!(access.disallow(prefix("10.0.0.0/8")));
+        assert!(access.is_allowed(outside));
+    }
+
+    #[test]
+    fn ipv6_sources_match_ipv6_prefixes() {
+        let access = AccessList::new(Vec::new(), vec![prefix("2001:db8::/32")]);
+        assert!(!access.is_allowed("2001:db8::1".parse().unwrap()));
+        assert!(access.is_allowed("2001:db9::1".parse().unwrap()));
+        assert!(access.is_allowed([10, 0, 0, 1].into()));
+    }
+}
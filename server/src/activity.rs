@@ -0,0 +1,173 @@
+//! Per-source activity records for analysis after an event.
+//!
+//! The log is a JSON-lines file. Every interval, one record is appended for each source that sent packets since the last interval,
+//! with the totals since the server started.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::info;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::SharedState;
+
+/// How many rotated log files are kept besides the current one.
+const ROTATED_FILES: usize = 5;
+
+/// What a source has done so far. Times are in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Serialize)]
+struct SourceActivity {
+    packets: u64,
+    bytes: u64,
+    dropped_pixels: u64,
+    first_seen: u64,
+    last_seen: u64,
+    /// Whether anything happened since the last record was written.
+    #[serde(skip)]
+    changed: bool,
+}
+
+/// Collects activity of all sources.
+#[derive(Debug, Default)]
+pub struct Activity {
+    sources: Mutex<HashMap<IpAddr, SourceActivity>>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+impl Activity {
+    /// Record that the source sent a packet of the given size.
+    pub fn record_packet(&self, source: IpAddr, bytes: usize) {
+        let now = unix_time();
+        let mut sources = self.sources.lock();
+        let activity = sources.entry(source).or_insert(SourceActivity {
+            packets: 0,
+            bytes: 0,
+            dropped_pixels: 0,
+            first_seen: now,
+            last_seen: now,
+            changed: true,
+        });
+        activity.packets += 1;
+        activity.bytes += bytes as u64;
+        activity.last_seen = now;
+        activity.changed = true;
+    }
+
+    /// Record that a pixel of the source was dropped because the pixel queue was full.
+    pub fn record_dropped(&self, source: IpAddr) {
+        if let Some(activity) = self.sources.lock().get_mut(&source) {
+            activity.dropped_pixels += 1;
+            activity.changed = true;
+        }
+    }
+
+    /// Returns the activity of all sources that changed since the last call.
+    fn take_changed(&self) -> Vec<(IpAddr, SourceActivity)> {
+        self.sources
+            .lock()
+            .iter_mut()
+            .filter(|(_, activity)| activity.changed)
+            .map(|(source, activity)| {
+                activity.changed = false;
+                (*source, *activity)
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct Record {
+    time: u64,
+    source: IpAddr,
+    applied_pixels: u64,
+    #[serde(flatten)]
+    activity: SourceActivity,
+}
+
+/// Appends activity records to the log file every interval, rotating the file once it grows beyond the maximum size.
+pub async fn write_periodically(
+    state: SharedState,
+    activity: Arc<Activity>,
+    path: PathBuf,
+    interval: Duration,
+    max_size: u64,
+) -> Result<()> {
+    info!("writing activity log to {}", path.display());
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let state = state.clone();
+        let activity = activity.clone();
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || write_records(&state, &activity, &path, max_size))
+            .await??;
+    }
+}
+
+fn write_records(
+    state: &SharedState,
+    activity: &Activity,
+    path: &Path,
+    max_size: u64,
+) -> Result<()> {
+    let changed = activity.take_changed();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() >= max_size) {
+        rotate(path)?;
+    }
+
+    let time = unix_time();
+    let mut writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?);
+    for (source, activity) in changed {
+        let applied_pixels = state
+            .canvases
+            .iter()
+            .map(|canvas| canvas.leaderboard.lock().pixels(source))
+            .sum();
+        let record = Record {
+            time,
+            source,
+            applied_pixels,
+            activity,
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Moves `log` to `log.1`, `log.1` to `log.2` and so on, deleting the oldest file.
+fn rotate(path: &Path) -> Result<()> {
+    let rotated = |index: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{index}"));
+        PathBuf::from(name)
+    };
+    let _ = fs::remove_file(rotated(ROTATED_FILES));
+    for index in (1..ROTATED_FILES).rev() {
+        let _ = fs::rename(rotated(index), rotated(index + 1));
+    }
+    fs::rename(path, rotated(1))?;
+    // Start the new file right away, so that it exists even if no records follow.
+    File::create(path)?;
+    Ok(())
+}
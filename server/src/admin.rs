@@ -0,0 +1,493 @@
+//! Authenticated HTTP API for operating the server at runtime.
+//!
+//! All requests need to carry the configured token as `Authorization: Bearer <token>`.
+
+use std::{
+    io::Cursor,
+    net::SocketAddr,
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderName, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use image::{ImageFormat, RgbaImage};
+use ipnet::IpNet;
+use log::info;
+use pingxelflut::constant_time_eq;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    access::parse_prefix,
+    canvas::{Canvas, Region},
+    clients::ClientRecord,
+    preview,
+    snapshot::save_snapshot,
+    SharedState,
+};
+
+#[derive(Debug, Clone)]
+struct AdminState {
+    shared: SharedState,
+    token: Arc<str>,
+}
+
+/// Error response of an admin API request.
+struct AdminError(StatusCode, String);
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, self.1).into_response()
+    }
+}
+
+impl From<anyhow::Error> for AdminError {
+    fn from(error: anyhow::Error) -> Self {
+        Self(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+    }
+}
+
+/// Serve the admin API on the given address until an error occurs.
+pub async fn serve(address: SocketAddr, token: String, shared: SharedState) -> Result<()> {
+    let state = AdminState {
+        shared,
+        token: token.into(),
+    };
+    let router = Router::new()
+        .route("/stats", get(stats))
+        .route("/clients", get(clients))
+        .route("/canvas.png", get(canvas_png))
+        .route("/canvas.raw", get(canvas_raw))
+        .route("/canvas/clear", post(clear_canvas))
+        .route("/canvas/image", post(load_image))
+        .route("/canvas/protected", get(list_protected))
+        .route("/canvas/protected/:region", put(protect).delete(unprotect))
+        .route("/snapshot", post(snapshot))
+        .route("/bans", get(list_bans))
+        .route("/bans/*prefix", put(ban).delete(unban))
+        .route("/allowed", get(list_allowed))
+        .route("/allowed/*prefix", put(allow).delete(disallow))
+        .route("/rate-limits", get(rate_limits).put(set_rate_limits))
+        .route("/reload", post(reload))
+        .layer(middleware::from_fn_with_state(state.clone(), authenticate))
+        .with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!("admin API listening on {}", address);
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn authenticate(
+    State(state): State<AdminState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), state.token.as_bytes()) => {
+            Ok(next.run(request).await)
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Selects which canvas an operation applies to, by index.
+#[derive(Deserialize)]
+struct CanvasSelection {
+    #[serde(default)]
+    canvas: usize,
+}
+
+impl AdminState {
+    fn canvas(&self, selection: &CanvasSelection) -> Result<&Canvas, AdminError> {
+        self.shared
+            .canvases
+            .get(selection.canvas)
+            .ok_or_else(|| AdminError(StatusCode::NOT_FOUND, "no such canvas".to_string()))
+    }
+}
+
+#[derive(Serialize)]
+struct CanvasStats {
+    width: u16,
+    height: u16,
+    queued_pixels: usize,
+    dropped_pixels: u64,
+    applied_pixels: u64,
+    sources: usize,
+    /// Pixels owned by each team, if there are teams.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    teams: Vec<TeamScore>,
+}
+
+#[derive(Serialize)]
+struct TeamScore {
+    name: String,
+    pixels: u64,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    canvases: Vec<CanvasStats>,
+    banned_prefixes: usize,
+}
+
+async fn stats(State(state): State<AdminState>) -> Json<Stats> {
+    let canvases = state
+        .shared
+        .canvases
+        .iter()
+        .map(|canvas| {
+            let leaderboard = canvas.leaderboard.lock();
+            CanvasStats {
+                width: canvas.width,
+                height: canvas.height,
+                queued_pixels: canvas.pixel_queue_out.len(),
+                dropped_pixels: canvas.dropped_pixels.load(Ordering::Relaxed),
+                applied_pixels: leaderboard.total(),
+                sources: leaderboard.source_count().unwrap_or_default(),
+                teams: canvas
+                    .territory
+                    .iter()
+                    .flat_map(|territory| territory.scores())
+                    .map(|(name, pixels)| TeamScore {
+                        name: name.to_string(),
+                        pixels,
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+    Json(Stats {
+        canvases,
+        banned_prefixes: state.shared.access.banned().len(),
+    })
+}
+
+/// How many clients to list.
+#[derive(Deserialize)]
+struct ClientLimit {
+    #[serde(default = "default_client_limit")]
+    limit: usize,
+}
+
+fn default_client_limit() -> usize {
+    100
+}
+
+/// Lists the clients that sent the most packets, by source address and echo identifier.
+async fn clients(
+    State(state): State<AdminState>,
+    Query(limit): Query<ClientLimit>,
+) -> Result<Json<Vec<ClientRecord>>, AdminError> {
+    let client_stats = state.shared.client_stats.as_ref().ok_or_else(|| {
+        AdminError(
+            StatusCode::NOT_FOUND,
+            "client statistics are disabled".to_string(),
+        )
+    })?;
+    Ok(Json(client_stats.top(limit.limit)))
+}
+
+/// Selects a downscaled preview of the canvas instead of the full canvas.
+#[derive(Deserialize)]
+struct PreviewSelection {
+    /// The canvas is scaled down by 2^level; 0 is the full canvas.
+    #[serde(default)]
+    level: usize,
+}
+
+impl PreviewSelection {
+    fn check(&self) -> Result<(), AdminError> {
+        if self.level > preview::LEVELS {
+            return Err(AdminError(
+                StatusCode::BAD_REQUEST,
+                format!("preview levels go up to {}", preview::LEVELS),
+            ));
+        }
+        Ok(())
+    }
+
+    fn image(&self, canvas: &Canvas) -> RgbaImage {
+        match self.level {
+            0 => canvas.to_image(),
+            level => canvas.preview(level),
+        }
+    }
+}
+
+/// Encodes the current canvas contents as a PNG.
+async fn canvas_png(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+    Query(preview): Query<PreviewSelection>,
+) -> Result<impl IntoResponse, AdminError> {
+    let canvas = state.canvas(&selection)?.clone();
+    preview.check()?;
+    let png = tokio::task::spawn_blocking(move || {
+        let mut png = Vec::new();
+        preview
+            .image(&canvas)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map(|()| png)
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+    .map_err(anyhow::Error::from)?;
+    Ok(([(CONTENT_TYPE, "image/png")], png))
+}
+
+/// Returns the current canvas contents as RGBA bytes, row by row, with the size in headers.
+async fn canvas_raw(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+    Query(preview): Query<PreviewSelection>,
+) -> Result<impl IntoResponse, AdminError> {
+    let canvas = state.canvas(&selection)?.clone();
+    preview.check()?;
+    let image = tokio::task::spawn_blocking(move || preview.image(&canvas))
+        .await
+        .map_err(anyhow::Error::from)?;
+    Ok((
+        [
+            (CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                HeaderName::from_static("x-canvas-width"),
+                image.width().to_string(),
+            ),
+            (
+                HeaderName::from_static("x-canvas-height"),
+                image.height().to_string(),
+            ),
+        ],
+        image.into_raw(),
+    ))
+}
+
+async fn clear_canvas(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+) -> Result<StatusCode, AdminError> {
+    state.canvas(&selection)?.clear();
+    info!("canvas {} cleared via admin API", selection.canvas);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+struct ImagePosition {
+    #[serde(default)]
+    x: u16,
+    #[serde(default)]
+    y: u16,
+}
+
+/// Draws the image in the request body onto the canvas.
+async fn load_image(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+    Query(position): Query<ImagePosition>,
+    body: Bytes,
+) -> Result<StatusCode, AdminError> {
+    let canvas = state.canvas(&selection)?.clone();
+    // Decoding and drawing large images takes a while, which would hold up the runtime.
+    tokio::task::spawn_blocking(move || {
+        let image = image::load_from_memory(&body)
+            .map_err(|why| AdminError(StatusCode::BAD_REQUEST, why.to_string()))?
+            .into_rgba8();
+        canvas.draw_image(&image, position.x, position.y);
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+}
+
+async fn list_protected(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+) -> Result<Json<Vec<Region>>, AdminError> {
+    Ok(Json(state.canvas(&selection)?.protected_regions()))
+}
+
+/// Parse the region path parameter, like `0,0,1920x100`.
+fn region_from_path(region: &str) -> Result<Region, AdminError> {
+    region
+        .parse()
+        .map_err(|why| AdminError(StatusCode::BAD_REQUEST, why))
+}
+
+async fn protect(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+    Path(region): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let region = region_from_path(&region)?;
+    if state.canvas(&selection)?.protect(region) {
+        info!(
+            "protected {} on canvas {} via admin API",
+            region, selection.canvas
+        );
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+async fn unprotect(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+    Path(region): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let region = region_from_path(&region)?;
+    if state.canvas(&selection)?.unprotect(region) {
+        info!(
+            "unprotected {} on canvas {} via admin API",
+            region, selection.canvas
+        );
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn snapshot(
+    State(state): State<AdminState>,
+    Query(selection): Query<CanvasSelection>,
+) -> Result<String, AdminError> {
+    let canvas = state.canvas(&selection)?.clone();
+    let directory = state.shared.settings.read().snapshot_directory.clone();
+    let path =
+        tokio::task::spawn_blocking(move || save_snapshot(&canvas, selection.canvas, &directory))
+            .await
+            .map_err(anyhow::Error::from)??;
+    state
+        .shared
+        .webhooks
+        .snapshot_saved(selection.canvas, &path);
+    Ok(path.display().to_string())
+}
+
+/// Parse the prefix path parameter, which may contain a slash.
+fn prefix_from_path(prefix: &str) -> Result<IpNet, AdminError> {
+    parse_prefix(prefix).map_err(|why| AdminError(StatusCode::BAD_REQUEST, why))
+}
+
+async fn list_bans(State(state): State<AdminState>) -> Json<Vec<IpNet>> {
+    Json(state.shared.access.banned())
+}
+
+async fn ban(
+    State(state): State<AdminState>,
+    Path(prefix): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let prefix = prefix_from_path(&prefix)?;
+    if state.shared.access.ban(prefix) {
+        info!("banned {} via admin API", prefix);
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+async fn unban(
+    State(state): State<AdminState>,
+    Path(prefix): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let prefix = prefix_from_path(&prefix)?;
+    if state.shared.access.unban(prefix) {
+        info!("unbanned {} via admin API", prefix);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+async fn list_allowed(State(state): State<AdminState>) -> Json<Vec<IpNet>> {
+    Json(state.shared.access.allowed())
+}
+
+async fn allow(
+    State(state): State<AdminState>,
+    Path(prefix): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let prefix = prefix_from_path(&prefix)?;
+    if state.shared.access.allow(prefix) {
+        info!("allowed {} via admin API", prefix);
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+async fn disallow(
+    State(state): State<AdminState>,
+    Path(prefix): Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let prefix = prefix_from_path(&prefix)?;
+    if state.shared.access.disallow(prefix) {
+        info!("removed {} from the allowlist via admin API", prefix);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+#[derive(Serialize)]
+struct RateLimits {
+    get_pixel_rate: u32,
+    size_request_rate: u32,
+}
+
+/// Rate limits to change, leaving out the ones that stay as they are.
+#[derive(Deserialize)]
+struct RateLimitChanges {
+    get_pixel_rate: Option<u32>,
+    size_request_rate: Option<u32>,
+}
+
+async fn rate_limits(State(state): State<AdminState>) -> Json<RateLimits> {
+    Json(RateLimits {
+        get_pixel_rate: state.shared.get_pixel_limiter.limit(),
+        size_request_rate: state.shared.size_request_limiter.limit(),
+    })
+}
+
+async fn set_rate_limits(
+    State(state): State<AdminState>,
+    Json(changes): Json<RateLimitChanges>,
+) -> Json<RateLimits> {
+    if let Some(get_pixel_rate) = changes.get_pixel_rate {
+        state.shared.get_pixel_limiter.set_limit(get_pixel_rate);
+    }
+    if let Some(size_request_rate) = changes.size_request_rate {
+        state
+            .shared
+            .size_request_limiter
+            .set_limit(size_request_rate);
+    }
+    info!("rate limits changed via admin API");
+    rate_limits(State(state)).await
+}
+
+/// Reloads the configuration, like SIGHUP does.
+async fn reload(State(state): State<AdminState>) -> Result<StatusCode, AdminError> {
+    tokio::task::spawn_blocking(move || state.shared.reload())
+        .await
+        .map_err(anyhow::Error::from)?
+        .map_err(|why| AdminError(StatusCode::BAD_REQUEST, why.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,111 @@
+//! Attract mode, an animation that invites people to participate while nobody draws on the canvas.
+//!
+//! The canvas is dimmed, a logo bounces across it and instructions scroll by at the bottom.
+
+use std::{
+    net::{IpAddr, UdpSocket},
+    time::Instant,
+};
+
+use embedded_graphics::prelude::*;
+use image::RgbaImage;
+
+use crate::{
+    overlay::{self, FrameTarget},
+    window::bounce,
+};
+
+/// Speed of the logo in frame pixels per second, along both axes.
+const LOGO_SPEED: f32 = 120.0;
+/// Speed of the scrolling instructions in frame pixels per second.
+const TICKER_SPEED: f32 = 100.0;
+
+/// What the attract animation shows.
+#[derive(Debug)]
+pub struct AttractContent {
+    /// Image that bounces across the canvas. Without one, the name of the server bounces instead.
+    pub logo: Option<RgbaImage>,
+    pub text: String,
+}
+
+/// The address that other hosts most likely reach this server on, which is the source address of the default route.
+fn local_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    // Connecting a UDP socket only selects a route, no packet is sent.
+    socket.connect("192.0.2.1:9").ok()?;
+    Some(socket.local_addr().ok()?.ip())
+}
+
+/// Instructions for participants, mentioning the server’s address if it can be determined.
+pub fn default_text() -> String {
+    match local_address() {
+        Some(address) => format!(
+            "Draw on this canvas! Send Pingxelflut pixels as pings to {address}, one ping per pixel."
+        ),
+        None => "Draw on this canvas! Send Pingxelflut pixels as pings to this server, one ping per pixel.".to_string(),
+    }
+}
+
+/// State of a running attract animation.
+#[derive(Debug)]
+pub struct Attract {
+    logo_position: (f32, f32),
+    logo_velocity: (f32, f32),
+    ticker_offset: f32,
+    last_update: Instant,
+}
+
+impl Attract {
+    pub fn new() -> Self {
+        Self {
+            logo_position: (0.0, 0.0),
+            logo_velocity: (LOGO_SPEED, LOGO_SPEED),
+            ticker_offset: 0.0,
+            last_update: Instant::now(),
+        }
+    }
+
+    /// Advances the animation and draws it over the whole frame.
+    pub fn draw(&mut self, target: &mut FrameTarget, content: &AttractContent) {
+        let elapsed = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+        let frame = target.bounding_box();
+        target.shade(frame);
+
+        let name = ["Pingxelflut".to_string()];
+        let logo_size = match &content.logo {
+            Some(logo) => Size::new(logo.width(), logo.height()),
+            None => overlay::text_box_size(&name),
+        };
+        let max_x = frame.size.width as f32 - logo_size.width as f32;
+        let max_y = frame.size.height as f32 - logo_size.height as f32;
+        (self.logo_position.0, self.logo_velocity.0) = bounce(
+            self.logo_position.0 + self.logo_velocity.0 * elapsed,
+            self.logo_velocity.0,
+            max_x,
+        );
+        (self.logo_position.1, self.logo_velocity.1) = bounce(
+            self.logo_position.1 + self.logo_velocity.1 * elapsed,
+            self.logo_velocity.1,
+            max_y,
+        );
+        let logo_origin = Point::new(self.logo_position.0 as i32, self.logo_position.1 as i32);
+        match &content.logo {
+            Some(logo) => {
+                overlay::draw_image_at(target, logo, logo_origin);
+            }
+            None => {
+                overlay::draw_text_box_at(target, &name, logo_origin);
+            }
+        }
+
+        // The text enters on the right and starts over once it left on the left.
+        let cycle = (frame.size.width + overlay::text_width(&content.text)) as f32;
+        self.ticker_offset = (self.ticker_offset + TICKER_SPEED * elapsed) % cycle;
+        overlay::draw_ticker(
+            target,
+            &content.text,
+            frame.size.width as i32 - self.ticker_offset as i32,
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Headless ingest benchmark, which measures how fast the listeners and canvases handle packets without any output,
+//! as a reproducible way of comparing the performance of server changes.
+//!
+//! Synthetic traffic is sent as pings to the loopback address. Traffic from other hosts is measured as well,
+//! so that the benchmark can also be driven by real clients.
+
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::fd::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::error;
+use parking_lot::RwLock;
+use pingxelflut::{
+    format::{color_from_rgb, Packet},
+    icmp::{EchoDirection, Icmp, ShutdownHandle},
+};
+use rand::Rng;
+use socket2::Socket;
+
+use crate::{
+    canvas::{Background, Canvas},
+    ping_handler,
+    stats_window::raw_socket_drops,
+    webhook::Webhooks,
+    window::create_canvas,
+    Arguments,
+};
+
+/// How often queued pixels are applied, like a display with 60 frames per second would.
+const FRAME_INTERVAL: Duration = Duration::from_micros(16_667);
+/// Where synthetic traffic is sent to.
+const TRAFFIC_TARGET: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+/// How often progress is reported.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// Clock ticks per second of the CPU times in `/proc`, which is fixed on Linux.
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// Counters that the benchmark results are computed from.
+#[derive(Debug, Clone, Copy)]
+struct Measurement {
+    time: Instant,
+    received_packets: u64,
+    applied_pixels: u64,
+    queue_drops: u64,
+    kernel_drops: u64,
+    /// CPU time of the whole process in seconds, if available.
+    cpu_time: Option<f64>,
+}
+
+impl Measurement {
+    /// Measures the current counters. Drops on the sockets of the synthetic senders are left out.
+    fn take(received_packets: &AtomicU64, canvases: &[Canvas], sender_sockets: &[u64]) -> Self {
+        Self {
+            time: Instant::now(),
+            received_packets: received_packets.load(Ordering::Relaxed),
+            applied_pixels: canvases
+                .iter()
+                .map(|canvas| canvas.leaderboard.lock().total())
+                .sum(),
+            queue_drops: canvases
+                .iter()
+                .map(|canvas| canvas.dropped_pixels.load(Ordering::Relaxed))
+                .sum(),
+            kernel_drops: raw_socket_drops()
+                .into_iter()
+                .filter(|(inode, _)| !sender_sockets.contains(inode))
+                .map(|(_, drops)| drops)
+                .sum(),
+            cpu_time: cpu_time(),
+        }
+    }
+}
+
+/// User and system CPU time of this process in seconds. Only available on Linux.
+fn cpu_time() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The process name may contain spaces, but it is the only field in parentheses.
+    let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+    let user: u64 = fields.next()?.parse().ok()?;
+    let system: u64 = fields.next()?.parse().ok()?;
+    Some((user + system) as f64 / CLOCK_TICKS_PER_SECOND)
+}
+
+/// The inode of a socket, which identifies it in `/proc/net`.
+fn socket_inode(socket: &Socket) -> Option<u64> {
+    let link = fs::read_link(format!("/proc/self/fd/{}", socket.as_raw_fd())).ok()?;
+    link.to_str()?
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .parse()
+        .ok()
+}
+
+/// Sends pings that set random pixels of the first canvas to the loopback address until stopped.
+/// Payloads start with the magic prefix that the server requires, if any.
+fn send_traffic(
+    socket: Socket,
+    (width, height): (u16, u16),
+    magic: &[u8],
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut rng = rand::thread_rng();
+    while !stop.load(Ordering::Relaxed) {
+        let mut icmp = Icmp::new(TRAFFIC_TARGET, 0, EchoDirection::Request);
+        let packet = Packet::SetPixel {
+            x: rng.gen_range(0..width),
+            y: rng.gen_range(0..height),
+            color: color_from_rgb(rng.gen()),
+        };
+        icmp.set_payload([magic, &packet.to_bytes()].concat());
+        icmp.send_on(&socket)?;
+    }
+    Ok(())
+}
+
+/// Prints the rates between two measurements.
+fn report(label: &str, start: &Measurement, end: &Measurement) {
+    let seconds = end
+        .time
+        .duration_since(start.time)
+        .as_secs_f64()
+        .max(f64::EPSILON);
+    let rate = |start: u64, end: u64| end.saturating_sub(start) as f64 / seconds;
+    let cpu = match (start.cpu_time, end.cpu_time) {
+        (Some(start), Some(end)) => format!("{:.0}% CPU", (end - start) / seconds * 100.0),
+        _ => "CPU usage unavailable".to_string(),
+    };
+    println!(
+        "{label}: {:.0} packets/s, {:.0} applied pixels/s, {} dropped by the queue, {} dropped by the kernel, {cpu}",
+        rate(start.received_packets, end.received_packets),
+        rate(start.applied_pixels, end.applied_pixels),
+        end.queue_drops.saturating_sub(start.queue_drops),
+        end.kernel_drops.saturating_sub(start.kernel_drops),
+    );
+}
+
+/// Runs the listeners and canvases without windows for the configured duration, and prints the achieved rates.
+pub async fn run(arguments: Arguments, background: Background) -> Result<()> {
+    let canvases: Vec<Canvas> = (0..arguments.canvases.len())
+        .map(|index| create_canvas(&arguments, index, &background))
+        .collect();
+    let shutdown = ShutdownHandle::new();
+    let received_packets = Arc::<AtomicU64>::default();
+    let stop = Arc::new(AtomicBool::new(false));
+    let duration = arguments.bench_duration;
+    let senders = arguments.bench_senders;
+    let budget = Some(arguments.max_pixels_per_frame).filter(|&budget| budget > 0);
+
+    // Webhooks would announce the benchmark as a real event.
+    tokio::spawn(ping_handler(
+        canvases.clone(),
+        Arc::new(RwLock::new(arguments.clone())),
+        shutdown.clone(),
+        Arc::default(),
+        received_packets.clone(),
+        Arc::new(Webhooks::new(Vec::new())),
+    ));
+
+    {
+        let canvases = canvases.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                for canvas in &canvases {
+                    canvas.set_queue_pixels(budget);
+                    canvas.take_dirty();
+                }
+                thread::sleep(FRAME_INTERVAL);
+            }
+        });
+    }
+    // Sender sockets receive a copy of every ping too, whose drops don’t say anything about the server.
+    let mut sender_sockets = Vec::new();
+    for _ in 0..senders {
+        let socket = Icmp::socket_for(TRAFFIC_TARGET)?;
+        sender_sockets.extend(socket_inode(&socket));
+        let size = arguments.canvases[0];
+        let magic = arguments.magic.clone().unwrap_or_default();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(why) = send_traffic(socket, size, &magic, &stop) {
+                error!("could not send benchmark traffic: {}", why);
+            }
+        });
+    }
+
+    println!(
+        "benchmarking ingest for {:.1} seconds with {} synthetic senders",
+        duration.as_secs_f64(),
+        senders
+    );
+    let start = Measurement::take(&received_packets, &canvases, &sender_sockets);
+    let mut last = start;
+    while last.time.duration_since(start.time) < duration {
+        let remaining = duration.saturating_sub(last.time.duration_since(start.time));
+        tokio::time::sleep(REPORT_INTERVAL.min(remaining)).await;
+        let now = Measurement::take(&received_packets, &canvases, &sender_sockets);
+        report(
+            &format!(
+                "{:>5.1}s",
+                now.time.duration_since(start.time).as_secs_f64()
+            ),
+            &last,
+            &now,
+        );
+        last = now;
+    }
+    stop.store(true, Ordering::Relaxed);
+    shutdown.shutdown();
+    report("total", &start, &last);
+    Ok(())
+}
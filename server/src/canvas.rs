@@ -1,70 +1,974 @@
 use async_channel::{Receiver, Sender};
-use parking_lot::RwLock;
-use pingxelflut::format::{Color, COLOR_SIZE};
+use clap::ValueEnum;
+use image::RgbaImage;
+use parking_lot::{Mutex, RwLock};
+use pingxelflut::format::{Color, ColorExt, COLOR_SIZE};
 use rgb::ComponentSlice;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    ops::Range,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use pixels::Pixels;
+use crate::{
+    leaderboard::{Leaderboard, Tally},
+    parse_pair, parse_size,
+    preview::Pyramid,
+    priority::Prioritization,
+    teams::{Team, Territory},
+};
+
+/// How pixels that are not fully opaque are applied to the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BlendMode {
+    /// Composite semi-transparent pixels onto the existing canvas (source-over).
+    #[default]
+    Blend,
+    /// Ignore alpha and overwrite the existing pixel. Fully transparent pixels are still dropped.
+    Overwrite,
+}
+
+/// What happens to new pixels when the pixel queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued pixel, favoring recent activity.
+    #[default]
+    DropOldest,
+    /// Discard the new pixel, keeping the queue as it is.
+    DropNewest,
+}
+
+/// How received pixels get onto the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Ingest {
+    /// Queue pixels and apply them once per frame, which allows limiting how many are applied per frame.
+    #[default]
+    Queued,
+    /// Write pixels into the canvas as soon as they are received, without queueing or locking.
+    /// The queue capacity, overflow policy and per-frame limit don’t apply, and the whole canvas is redrawn every frame.
+    Direct,
+}
+
+/// What the canvas shows before anybody draws on it, and after it is cleared.
+#[derive(Debug, Clone)]
+pub struct Background {
+    pub(crate) color: Color,
+    pub(crate) image: Option<RgbaImage>,
+}
+
+impl Background {
+    /// Renders the background for a canvas of the given size.
+    /// The image is centered on the background color and cut off if it doesn’t fit.
+    fn render(&self, width: u16, height: u16) -> Vec<u8> {
+        let color = Color {
+            a: 0xff,
+            ..self.color
+        };
+        let mut frame = color.as_slice().repeat(width as usize * height as usize);
+        if let Some(image) = &self.image {
+            let offset_x = (i64::from(width) - i64::from(image.width())) / 2;
+            let offset_y = (i64::from(height) - i64::from(image.height())) / 2;
+            for (image_x, image_y, pixel) in image.enumerate_pixels() {
+                let x = i64::from(image_x) + offset_x;
+                let y = i64::from(image_y) + offset_y;
+                if !(0..i64::from(width)).contains(&x) || !(0..i64::from(height)).contains(&y) {
+                    continue;
+                }
+                let pixel_pos = (x as usize + y as usize * width as usize) * COLOR_SIZE;
+                let new_color = Color::from(pixel.0).blend_over(color);
+                frame[pixel_pos..pixel_pos + COLOR_SIZE].copy_from_slice(new_color.as_slice());
+            }
+        }
+        frame
+    }
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Self {
+            color: Color::new(0, 0, 0, 0xff),
+            image: None,
+        }
+    }
+}
+
+/// A rectangular area of the canvas, from the inclusive start to the exclusive end coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub(crate) start_x: usize,
+    pub(crate) start_y: usize,
+    pub(crate) end_x: usize,
+    pub(crate) end_y: usize,
+}
+
+impl DirtyRect {
+    /// The area covered by a single pixel.
+    pub fn pixel(x: usize, y: usize) -> Self {
+        Self {
+            start_x: x,
+            start_y: y,
+            end_x: x + 1,
+            end_y: y + 1,
+        }
+    }
+
+    /// The area covered by an entire canvas.
+    pub fn full(width: u16, height: u16) -> Self {
+        Self {
+            start_x: 0,
+            start_y: 0,
+            end_x: width.into(),
+            end_y: height.into(),
+        }
+    }
+
+    /// The bounding box of both areas.
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            start_x: self.start_x.min(other.start_x),
+            start_y: self.start_y.min(other.start_y),
+            end_x: self.end_x.max(other.end_x),
+            end_y: self.end_y.max(other.end_y),
+        }
+    }
+
+    /// Extends an optional area to also cover this area.
+    pub fn add_to(self, area: &mut Option<Self>) {
+        *area = Some(match *area {
+            Some(area) => area.union(self),
+            None => self,
+        });
+    }
+
+    pub fn width(&self) -> usize {
+        self.end_x - self.start_x
+    }
+
+    pub fn height(&self) -> usize {
+        self.end_y - self.start_y
+    }
+}
+
+/// The pixels of a canvas, which can be read and written from any thread without locking.
+///
+/// Every pixel is an RGBA color packed into an atomic integer, in the same byte order as a frame buffer.
+/// Reading a whole area is not atomic, which at worst shows a pixel that is being drawn a frame late.
+#[derive(Debug)]
+pub struct Frame(Box<[AtomicU32]>);
+
+impl Frame {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self(
+            bytes
+                .chunks_exact(COLOR_SIZE)
+                .map(|color| AtomicU32::new(u32::from_le_bytes(color.try_into().unwrap())))
+                .collect(),
+        )
+    }
+
+    fn load(&self, index: usize) -> Color {
+        Color::from(self.0[index].load(Ordering::Relaxed).to_le_bytes())
+    }
+
+    fn store(&self, index: usize, color: Color) {
+        self.0[index].store(pack(color), Ordering::Relaxed);
+    }
+
+    /// Replaces a pixel with a color computed from its current color,
+    /// retrying if another thread changed the pixel in the meantime.
+    fn update(&self, index: usize, mut f: impl FnMut(Color) -> Color) {
+        let _ = self.0[index].fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+            Some(pack(f(Color::from(current.to_le_bytes()))))
+        });
+    }
+
+    /// Overwrites pixels from the given index on with the colors in the buffer.
+    pub fn copy_from(&self, start: usize, bytes: &[u8]) {
+        for (pixel, color) in self.0[start..].iter().zip(bytes.chunks_exact(COLOR_SIZE)) {
+            pixel.store(
+                u32::from_le_bytes(color.try_into().unwrap()),
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    /// Copies a range of pixels into a buffer of the same length.
+    fn copy_to(&self, pixels: Range<usize>, target: &mut [u8]) {
+        for (pixel, color) in self.0[pixels]
+            .iter()
+            .zip(target.chunks_exact_mut(COLOR_SIZE))
+        {
+            color.copy_from_slice(&pixel.load(Ordering::Relaxed).to_le_bytes());
+        }
+    }
+
+    /// Returns a range of pixels as RGBA bytes.
+    pub fn bytes(&self, pixels: Range<usize>) -> Vec<u8> {
+        let mut bytes = vec![0; pixels.len() * COLOR_SIZE];
+        self.copy_to(pixels, &mut bytes);
+        bytes
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+fn pack(color: Color) -> u32 {
+    u32::from_le_bytes([color.r, color.g, color.b, color.a])
+}
+
+/// A rectangle of the canvas that participants can’t draw on, such as a rules banner or a sponsor area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Region {
+    fn contains(&self, x: u16, y: u16) -> bool {
+        (u32::from(self.x)..u32::from(self.x) + u32::from(self.width)).contains(&u32::from(x))
+            && (u32::from(self.y)..u32::from(self.y) + u32::from(self.height))
+                .contains(&u32::from(y))
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    /// Parse a region like `0,0,1920x100`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (position, size) = text
+            .rsplit_once(',')
+            .ok_or_else(|| format!("region must be given as X,Y,WIDTHxHEIGHT: {text}"))?;
+        let (x, y) = parse_pair(position)?;
+        let (width, height) = parse_size(size)?;
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// A protected region from the configuration, for one canvas or all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectedRegion {
+    pub canvas: Option<usize>,
+    pub region: Region,
+}
+
+impl ProtectedRegion {
+    /// The configured regions that protect the canvas with the given index.
+    pub fn for_canvas(regions: &[Self], index: usize) -> Vec<Region> {
+        regions
+            .iter()
+            .filter(|protected| protected.canvas.map_or(true, |canvas| canvas == index))
+            .map(|protected| protected.region)
+            .collect()
+    }
+}
+
+/// Parse a protected region like `0,0,1920x100`, optionally prefixed by a canvas index like `1:0,0,1920x100`.
+pub fn parse_protected_region(text: &str) -> Result<ProtectedRegion, String> {
+    let (canvas, region) = match text.split_once(':') {
+        Some((canvas, region)) => (
+            Some(
+                canvas
+                    .parse()
+                    .map_err(|_| format!("invalid canvas index: {canvas}"))?,
+            ),
+            region,
+        ),
+        None => (None, text),
+    };
+    Ok(ProtectedRegion {
+        canvas,
+        region: region.parse()?,
+    })
+}
+
+/// Edge length of the square tiles that changes by participants are tracked in.
+pub const TILE_SIZE: u16 = 128;
+/// Pixels applied per frame with prioritization when no budget is configured, since pixels can only be deferred
+/// while more are queued than the budget allows.
+const PRIORITIZED_BUDGET: usize = 1 << 20;
 
 /// Canvas handling datastructures.
 /// This is a lightweight, easily clonable datastructure that contains reference-counted references to the underlying shared data, such as the frame buffer and pixel queue.
+///
+/// The canvas keeps its own RGBA frame buffer, separate from the window’s, so that overlays drawn for display never end up on the canvas itself.
 #[derive(Debug, Clone)]
 pub struct Canvas {
-    pub(crate) pixels: Arc<RwLock<Pixels>>,
-    pub(crate) pixel_queue_in: Sender<(usize, Color)>,
-    pub(crate) pixel_queue_out: Receiver<(usize, Color)>,
+    pub(crate) frame: Arc<Frame>,
+    background: Arc<[u8]>,
+    pub(crate) pixel_queue_in: Sender<(usize, Color, IpAddr)>,
+    pub(crate) pixel_queue_out: Receiver<(usize, Color, IpAddr)>,
     pub(crate) width: u16,
     pub(crate) height: u16,
+    pub(crate) blend_mode: BlendMode,
+    pub(crate) ingest: Ingest,
+    overflow_policy: OverflowPolicy,
+    /// Number of pixels discarded because the queue was full.
+    pub(crate) dropped_pixels: Arc<AtomicU64>,
+    pub(crate) leaderboard: Arc<Mutex<Leaderboard>>,
+    /// Bounding box of everything that changed since the last frame.
+    /// Changes made outside of the pixel queue, like clearing, hold this lock while they change the frame.
+    dirty: Arc<Mutex<Option<DirtyRect>>>,
+    /// While paused, queued pixels are counted but discarded, so that the canvas stays as it is.
+    paused: Arc<AtomicBool>,
+    /// Regions that participants can’t draw on.
+    protected: Arc<RwLock<Vec<Region>>>,
+    /// Whether there are any protected regions, so that pixels don’t need to take the lock otherwise.
+    has_protected: Arc<AtomicBool>,
+    /// Which tiles of [`TILE_SIZE`] participants drew on since the tiles were last taken.
+    changed_tiles: Arc<[AtomicBool]>,
+    /// Whether participants sent any pixels since the flag was last taken, even while paused.
+    pixel_arrived: Arc<AtomicBool>,
+    /// Downscaled previews, updated from the areas that changed.
+    preview: Arc<Pyramid>,
+    /// Which team owns each pixel, in team competitions.
+    pub(crate) territory: Option<Arc<Territory>>,
+    /// Decides which sources yield to others while the queue has a backlog.
+    prioritization: Option<Arc<dyn Prioritization>>,
+    /// One bit per pixel that was set since the last fade step, while the canvas fades.
+    fresh: Option<Arc<[AtomicU64]>>,
 }
 
 impl Canvas {
-    pub fn new(pixels: Arc<RwLock<Pixels>>, width: u16, height: u16) -> Self {
-        let (pixel_queue_in, pixel_queue_out) = async_channel::unbounded();
+    pub fn new(
+        width: u16,
+        height: u16,
+        blend_mode: BlendMode,
+        background: &Background,
+        ingest: Ingest,
+        queue_capacity: usize,
+        overflow_policy: OverflowPolicy,
+    ) -> Self {
+        // The background is opaque, so that blending onto untouched pixels blends onto it.
+        let background: Arc<[u8]> = background.render(width, height).into();
+        let (pixel_queue_in, pixel_queue_out) = async_channel::bounded(queue_capacity);
+        let tile_count =
+            usize::from(width.div_ceil(TILE_SIZE)) * usize::from(height.div_ceil(TILE_SIZE));
         Self {
-            pixels,
+            frame: Arc::new(Frame::from_bytes(&background)),
+            background,
             pixel_queue_in,
             pixel_queue_out,
             width,
             height,
+            blend_mode,
+            ingest,
+            overflow_policy,
+            dropped_pixels: Arc::default(),
+            leaderboard: Arc::default(),
+            // The first frame needs to show the background.
+            dirty: Arc::new(Mutex::new(Some(DirtyRect::full(width, height)))),
+            paused: Arc::default(),
+            protected: Arc::default(),
+            has_protected: Arc::default(),
+            changed_tiles: (0..tile_count).map(|_| AtomicBool::new(false)).collect(),
+            pixel_arrived: Arc::default(),
+            preview: Arc::default(),
+            territory: None,
+            prioritization: None,
+            fresh: None,
         }
     }
 
-    pub fn set_pixel(&mut self, x: u16, y: u16, color: Color) {
-        if color.a == 0 {
-            return;
+    /// Tracks which pixels were set since the last fade step, so that fading skips them.
+    pub fn with_fading(mut self, fading: bool) -> Self {
+        if fading {
+            let pixel_count = usize::from(self.width) * usize::from(self.height);
+            self.fresh = Some(
+                (0..pixel_count.div_ceil(64))
+                    .map(|_| AtomicU64::new(0))
+                    .collect(),
+            );
+        }
+        self
+    }
+
+    /// Lets the policy decide which sources yield to others while the queue has a backlog.
+    pub fn with_prioritization(mut self, prioritization: Option<Arc<dyn Prioritization>>) -> Self {
+        self.prioritization = prioritization;
+        self
+    }
+
+    pub fn with_leaderboard(mut self, leaderboard: Leaderboard) -> Self {
+        self.leaderboard = Arc::new(Mutex::new(leaderboard));
+        self
+    }
+
+    /// Tracks which of the teams owns each pixel. Without teams, no territory is tracked.
+    pub fn with_teams(mut self, teams: Vec<Team>) -> Self {
+        if !teams.is_empty() {
+            let pixel_count = usize::from(self.width) * usize::from(self.height);
+            self.territory = Some(Arc::new(Territory::new(teams, pixel_count)));
+        }
+        self
+    }
+
+    /// Records that a participant drew on the pixel at the given byte position.
+    fn mark_changed(&self, pixel_pos: usize) {
+        let pixel_index = pixel_pos / COLOR_SIZE;
+        let width = usize::from(self.width);
+        let tile_size = usize::from(TILE_SIZE);
+        let tile_index = pixel_index / width / tile_size
+            * usize::from(self.width.div_ceil(TILE_SIZE))
+            + pixel_index % width / tile_size;
+        let tile = &self.changed_tiles[tile_index];
+        // Avoid writing the shared flag for every pixel, which would bounce its cache line between threads.
+        if !tile.load(Ordering::Relaxed) {
+            tile.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether participants sent any pixels since the last call.
+    pub fn take_pixel_arrived(&self) -> bool {
+        self.pixel_arrived.swap(false, Ordering::Relaxed)
+    }
+
+    /// Returns the tiles that participants drew on since the last call, clipped to the canvas.
+    pub fn take_changed_tiles(&self) -> Vec<Region> {
+        let tiles_per_row = usize::from(self.width.div_ceil(TILE_SIZE));
+        self.changed_tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, changed)| changed.swap(false, Ordering::Relaxed))
+            .map(|(tile_index, _)| {
+                let x = (tile_index % tiles_per_row) as u16 * TILE_SIZE;
+                let y = (tile_index / tiles_per_row) as u16 * TILE_SIZE;
+                Region {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(self.width - x),
+                    height: TILE_SIZE.min(self.height - y),
+                }
+            })
+            .collect()
+    }
+
+    /// Regions that participants can’t draw on.
+    pub fn protected_regions(&self) -> Vec<Region> {
+        self.protected.read().clone()
+    }
+
+    /// Replaces all protected regions.
+    pub fn set_protected_regions(&self, regions: Vec<Region>) {
+        let mut protected = self.protected.write();
+        *protected = regions;
+        self.has_protected
+            .store(!protected.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Protects a region, returning whether it wasn’t protected before.
+    pub fn protect(&self, region: Region) -> bool {
+        let mut protected = self.protected.write();
+        if protected.contains(&region) {
+            return false;
+        }
+        protected.push(region);
+        self.has_protected.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Stops protecting a region, returning whether it was protected.
+    pub fn unprotect(&self, region: Region) -> bool {
+        let mut protected = self.protected.write();
+        let count = protected.len();
+        protected.retain(|protected_region| *protected_region != region);
+        self.has_protected
+            .store(!protected.is_empty(), Ordering::Relaxed);
+        protected.len() != count
+    }
+
+    fn is_protected(&self, x: u16, y: u16) -> bool {
+        self.has_protected.load(Ordering::Relaxed)
+            && self
+                .protected
+                .read()
+                .iter()
+                .any(|region| region.contains(x, y))
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Marks an area as changed, so that the next frame shows it.
+    fn mark_dirty(&self, area: DirtyRect) {
+        area.add_to(&mut self.dirty.lock());
+    }
+
+    /// Replaces the whole frame, unless something changed since the dirty area was last taken.
+    pub fn replace_unless_dirty(&self, bytes: &[u8]) {
+        let dirty = self.dirty.lock();
+        if dirty.is_none() {
+            self.frame.copy_from(0, bytes);
+        }
+    }
+
+    /// Returns the area that changed since the last call, if anything changed.
+    /// With direct ingest, changes are not tracked and the whole canvas counts as changed.
+    pub fn take_dirty(&self) -> Option<DirtyRect> {
+        let dirty = self.dirty.lock().take();
+        let dirty = match self.ingest {
+            Ingest::Queued => dirty,
+            Ingest::Direct => Some(DirtyRect::full(self.width, self.height)),
+        };
+        if let Some(dirty) = dirty {
+            self.preview.mark(dirty);
+        }
+        dirty
+    }
+
+    /// Returns a copy of the canvas scaled down by 2^level, with levels from 1 to [`LEVELS`](crate::preview::LEVELS).
+    /// Only the areas that were drawn since the last call are scaled down again.
+    pub fn preview(&self, level: usize) -> RgbaImage {
+        self.preview
+            .level(&self.frame, self.width, self.height, level)
+    }
+
+    /// Whether participants can set the pixel: it has to be visible, on the canvas and outside of protected regions.
+    pub fn accepts_pixel(&self, x: u16, y: u16, color: Color) -> bool {
+        color.a != 0 && x < self.width && y < self.height && !self.is_protected(x, y)
+    }
+
+    /// Queues a pixel to be set, or sets it right away with direct ingest. Pixels in protected regions are ignored.
+    /// The source address is counted for the leaderboard, through the tally with direct ingest.
+    /// If the queue is full, returns the source of the pixel that was dropped.
+    pub fn set_pixel(
+        &self,
+        x: u16,
+        y: u16,
+        color: Color,
+        source: IpAddr,
+        tally: &mut Tally,
+    ) -> Option<IpAddr> {
+        if !self.accepts_pixel(x, y, color) {
+            return None;
+        }
+        if !self.pixel_arrived.load(Ordering::Relaxed) {
+            self.pixel_arrived.store(true, Ordering::Relaxed);
         }
+        let x = x as usize;
+        let y = y as usize;
+        let pixel_pos = (x + y * self.width as usize) * COLOR_SIZE;
+        if self.ingest == Ingest::Direct {
+            tally.add(&self.leaderboard, source);
+            if !self.is_paused() {
+                self.mark_changed(pixel_pos);
+                if let Some(territory) = &self.territory {
+                    territory.claim(pixel_pos / COLOR_SIZE, source);
+                }
+                self.apply_pixel(pixel_pos, color);
+            }
+            return None;
+        }
+
+        let pixel = (pixel_pos, color, source);
+        let dropped = match self.overflow_policy {
+            OverflowPolicy::DropOldest => match self.pixel_queue_in.force_send(pixel) {
+                Ok(Some((_, _, dropped_source))) => Some(dropped_source),
+                _ => None,
+            },
+            OverflowPolicy::DropNewest => self.pixel_queue_in.try_send(pixel).err().map(|_| source),
+        };
+        if dropped.is_some() {
+            self.dropped_pixels.fetch_add(1, Ordering::Relaxed);
+        }
+        dropped
+    }
+
+    /// Reads the current color of a pixel, or None if it lies outside the canvas.
+    pub fn get_pixel(&self, x: u16, y: u16) -> Option<Color> {
         let x = x as usize;
         let y = y as usize;
         if x >= self.width as usize || y >= self.height as usize {
-            return;
+            return None;
         }
         let pixel_pos = (x + y * self.width as usize) * COLOR_SIZE;
 
-        let _ = self.pixel_queue_in.force_send((pixel_pos, color));
-    }
-
-    /// Sets all the pixels from the queue.
-    pub fn set_queue_pixels(&self) {
-        let mut pixels = self.pixels.write();
-        let frame = pixels.frame_mut();
-        while let Ok((pixel_pos, color)) = self.pixel_queue_out.try_recv() {
-            let pixel_end_pos = pixel_pos + COLOR_SIZE;
-            let new_color = if color.a == 0xff {
-                color
-            } else {
-                let current_color_data: [u8; 4] =
-                    frame[pixel_pos..pixel_end_pos].try_into().unwrap();
-                let current_color: Color = Color::from(current_color_data);
-                alpha_multiply(color, current_color)
+        Some(self.frame.load(pixel_pos / COLOR_SIZE))
+    }
+
+    /// Sets pixels from the queue, at most `budget` many if given.
+    /// Pixels over budget stay queued for the next frame. Returns the number of pixels still queued.
+    pub fn set_queue_pixels(&self, budget: Option<usize>) -> usize {
+        let mut dirty = None;
+        let backlog = self.drain_queue(budget, |pixel_pos, color| {
+            self.apply_pixel(pixel_pos, color);
+            let pixel_index = pixel_pos / COLOR_SIZE;
+            DirtyRect::pixel(
+                pixel_index % self.width as usize,
+                pixel_index / self.width as usize,
+            )
+            .add_to(&mut dirty);
+        });
+        if let Some(dirty) = dirty {
+            self.mark_dirty(dirty);
+        }
+        backlog
+    }
+
+    /// Takes pixels from the queue, at most `budget` many if given, and hands their byte position and color to `apply`.
+    /// Pixels are counted on the leaderboard, but not handed over while the canvas is paused.
+    /// If more pixels are queued than the budget allows, pixels of deprioritized sources go back to the end of the queue.
+    /// With prioritization, the budget is [`PRIORITIZED_BUDGET`] unless one is given.
+    /// Returns the number of pixels still queued.
+    pub fn drain_queue(&self, budget: Option<usize>, mut apply: impl FnMut(usize, Color)) -> usize {
+        let paused = self.is_paused();
+        let mut tally = Tally::default();
+        let mut remaining_budget = budget
+            .or(self.prioritization.as_ref().map(|_| PRIORITIZED_BUDGET))
+            .unwrap_or(usize::MAX);
+        // Deferred pixels come around again after the pixels queued before them, and are applied then.
+        let mut deferrable = self.pixel_queue_out.len();
+        while remaining_budget > 0 {
+            let Ok((pixel_pos, color, source)) = self.pixel_queue_out.try_recv() else {
+                break;
             };
-            frame[pixel_pos..pixel_end_pos].copy_from_slice(new_color.as_slice());
+            if let Some(prioritization) = &self.prioritization {
+                if deferrable > 0
+                    && self.pixel_queue_out.len() >= remaining_budget
+                    && prioritization.is_deprioritized(source)
+                {
+                    deferrable -= 1;
+                    if self
+                        .pixel_queue_in
+                        .try_send((pixel_pos, color, source))
+                        .is_err()
+                    {
+                        self.dropped_pixels.fetch_add(1, Ordering::Relaxed);
+                    }
+                    continue;
+                }
+                deferrable = deferrable.saturating_sub(1);
+                prioritization.record_applied(source);
+            }
+            remaining_budget -= 1;
+            tally.add(&self.leaderboard, source);
+            if !paused {
+                self.mark_changed(pixel_pos);
+                if let Some(territory) = &self.territory {
+                    territory.claim(pixel_pos / COLOR_SIZE, source);
+                }
+                apply(pixel_pos, color);
+            }
+        }
+        self.pixel_queue_out.len()
+    }
+
+    /// Writes a color to the frame at the given byte position, according to the blend mode.
+    fn apply_pixel(&self, pixel_pos: usize, color: Color) {
+        let pixel_index = pixel_pos / COLOR_SIZE;
+        if let Some(fresh) = &self.fresh {
+            let bit = 1 << (pixel_index % 64);
+            let word = &fresh[pixel_index / 64];
+            if word.load(Ordering::Relaxed) & bit == 0 {
+                word.fetch_or(bit, Ordering::Relaxed);
+            }
+        }
+        if color.a == 0xff || self.blend_mode == BlendMode::Overwrite {
+            self.frame.store(pixel_index, Color { a: 0xff, ..color });
+        } else {
+            self.frame
+                .update(pixel_index, |current_color| color.blend_over(current_color));
+        }
+    }
+
+    /// Resets the canvas to its background and discards all queued pixels.
+    pub fn clear(&self) {
+        let mut dirty = self.dirty.lock();
+        while self.pixel_queue_out.try_recv().is_ok() {}
+        self.frame.copy_from(0, &self.background);
+        if let Some(territory) = &self.territory {
+            territory.release_all();
         }
+        DirtyRect::full(self.width, self.height).add_to(&mut dirty);
+    }
+
+    /// Resets a region of the canvas to the background, for example to remove offending content.
+    pub fn restore_background(&self, region: Region) {
+        let mut dirty = self.dirty.lock();
+        let width = usize::from(self.width);
+        let start_x = usize::from(region.x).min(width);
+        let end_x = (usize::from(region.x) + usize::from(region.width)).min(width);
+        let start_y = usize::from(region.y).min(usize::from(self.height));
+        let end_y =
+            (usize::from(region.y) + usize::from(region.height)).min(usize::from(self.height));
+        if start_x == end_x || start_y == end_y {
+            return;
+        }
+        for y in start_y..end_y {
+            let row = y * width + start_x..y * width + end_x;
+            self.frame.copy_from(
+                row.start,
+                &self.background[row.start * COLOR_SIZE..row.end * COLOR_SIZE],
+            );
+            if let Some(territory) = &self.territory {
+                row.for_each(|pixel_index| territory.release(pixel_index));
+            }
+        }
+        DirtyRect {
+            start_x,
+            start_y,
+            end_x,
+            end_y,
+        }
+        .add_to(&mut dirty);
+    }
+
+    /// Draws an image onto the canvas with its top left corner at the given position.
+    /// Parts of the image outside the canvas are cut off.
+    pub fn draw_image(&self, image: &RgbaImage, x: u16, y: u16) {
+        let mut dirty = self.dirty.lock();
+        for (image_x, image_y, pixel) in image.enumerate_pixels() {
+            let canvas_x = image_x as usize + x as usize;
+            let canvas_y = image_y as usize + y as usize;
+            if canvas_x >= self.width as usize || canvas_y >= self.height as usize || pixel[3] == 0
+            {
+                continue;
+            }
+            let pixel_pos = (canvas_x + canvas_y * self.width as usize) * COLOR_SIZE;
+            if let Some(territory) = &self.territory {
+                territory.release(pixel_pos / COLOR_SIZE);
+            }
+            self.apply_pixel(pixel_pos, Color::from(pixel.0));
+            DirtyRect::pixel(canvas_x, canvas_y).add_to(&mut dirty);
+        }
+    }
+
+    /// Returns a copy of the current canvas contents.
+    pub fn to_image(&self) -> RgbaImage {
+        RgbaImage::from_raw(
+            self.width.into(),
+            self.height.into(),
+            self.frame.bytes(0..self.frame.len()),
+        )
+        .expect("frame has the canvas size")
+    }
+
+    /// Moves every pixel that wasn’t set since the last step one fade step closer to the background,
+    /// keeping `retain` / [`Fade::RETAIN_ONE`] of the distance.
+    pub fn fade_step(&self, retain: u32) {
+        let mut dirty = self.dirty.lock();
+        let mut fresh = 0;
+        for (pixel_index, background) in self.background.chunks_exact(COLOR_SIZE).enumerate() {
+            if pixel_index % 64 == 0 {
+                fresh = self.fresh.as_ref().map_or(0, |fresh| {
+                    fresh[pixel_index / 64].swap(0, Ordering::Relaxed)
+                });
+            }
+            if fresh & (1 << (pixel_index % 64)) != 0 {
+                continue;
+            }
+            self.frame.update(pixel_index, |color| Color {
+                r: fade_channel(color.r, background[0], retain),
+                g: fade_channel(color.g, background[1], retain),
+                b: fade_channel(color.b, background[2], retain),
+                a: color.a,
+            });
+        }
+        DirtyRect::full(self.width, self.height).add_to(&mut dirty);
+    }
+
+    /// The background the canvas was created with, as RGBA bytes.
+    pub(crate) fn background(&self) -> &[u8] {
+        &self.background
+    }
+
+    /// Copies an area of the canvas into a frame buffer of the same size.
+    pub fn draw_to(&self, target: &mut [u8], area: DirtyRect) {
+        let width = self.width as usize;
+        for y in area.start_y..area.end_y {
+            let row = y * width + area.start_x..y * width + area.end_x;
+            self.frame.copy_to(
+                row.clone(),
+                &mut target[row.start * COLOR_SIZE..row.end * COLOR_SIZE],
+            );
+        }
+    }
+}
+
+/// Moves a color channel towards the background, keeping `retain` / [`Fade::RETAIN_ONE`] of the distance.
+/// The distance is rounded down, so that every step makes progress and the channel reaches the background eventually.
+/// The compositor shader fades with the same arithmetic.
+fn fade_channel(channel: u8, background: u8, retain: u32) -> u8 {
+    let distance = ((u32::from(channel.abs_diff(background)) * retain) >> 16) as u8;
+    if channel > background {
+        background + distance
+    } else {
+        background - distance
+    }
+}
+
+/// Slowly fades the canvas towards its background, so that pixels nobody refreshes eventually disappear.
+///
+/// Fading happens in discrete steps, since small per-frame changes would get lost to rounding in the 8-bit color channels.
+#[derive(Debug)]
+pub struct Fade {
+    step_interval: Duration,
+    /// Fraction of the distance to the background that every step keeps, in units of 1 / [`Self::RETAIN_ONE`].
+    retain: u32,
+    last_step: Instant,
+}
+
+impl Fade {
+    /// Fixed-point representation of keeping the entire distance.
+    pub const RETAIN_ONE: u32 = 1 << 16;
+    /// Long half-lives are divided into this many steps.
+    const STEPS_PER_HALF_LIFE: u32 = 16;
+    /// Short half-lives take larger steps instead of stepping more often than this.
+    const MIN_STEP_INTERVAL: Duration = Duration::from_millis(20);
+
+    pub fn new(half_life: Duration) -> Self {
+        let step_interval = (half_life / Self::STEPS_PER_HALF_LIFE).max(Self::MIN_STEP_INTERVAL);
+        let retain = 0.5f64.powf(step_interval.as_secs_f64() / half_life.as_secs_f64());
+        Self {
+            step_interval,
+            retain: (retain * f64::from(Self::RETAIN_ONE)).round() as u32,
+            last_step: Instant::now(),
+        }
+    }
+
+    /// Fraction of the distance to the background that every step keeps, in units of 1 / [`Self::RETAIN_ONE`].
+    pub fn retain(&self) -> u32 {
+        self.retain
+    }
+
+    /// Performs all fade steps that are due.
+    pub fn update(&mut self, canvas: &Canvas) {
+        for _ in 0..self.due_steps() {
+            canvas.fade_step(self.retain);
+        }
+    }
+
+    /// Returns how many fade steps are due, and considers them done.
+    pub fn due_steps(&mut self) -> u32 {
+        let mut steps = 0;
+        while self.last_step.elapsed() >= self.step_interval {
+            steps += 1;
+            self.last_step += self.step_interval;
+        }
+        steps
+    }
+
+    /// Skips all fade steps that are due, so that the canvas doesn’t fade while it is paused.
+    pub fn hold(&mut self) {
+        self.last_step = Instant::now();
     }
 }
 
-pub fn alpha_multiply(a: Color, b: Color) -> Color {
-    let alpha_out = a.a + b.a * (0xff - a.a);
-    let mut color_out = (a * a.a + b * b.a * (0xff - a.a)) / alpha_out;
-    color_out.a = alpha_out;
-    color_out
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: Color = Color {
+        r: 100,
+        g: 100,
+        b: 100,
+        a: 0xff,
+    };
+    const WHITE: Color = Color {
+        r: 0xff,
+        g: 0xff,
+        b: 0xff,
+        a: 0xff,
+    };
+
+    fn fading_canvas() -> Canvas {
+        let background = Background {
+            color: BACKGROUND,
+            image: None,
+        };
+        Canvas::new(
+            2,
+            1,
+            BlendMode::default(),
+            &background,
+            Ingest::Direct,
+            16,
+            OverflowPolicy::default(),
+        )
+        .with_fading(true)
+    }
+
+    #[test]
+    fn channels_fade_towards_the_background_from_both_sides() {
+        let half = Fade::RETAIN_ONE / 2;
+        assert_eq!(fade_channel(200, 100, half), 150);
+        assert_eq!(fade_channel(0, 100, half), 50);
+        assert_eq!(fade_channel(100, 100, half), 100);
+        // The rounded-down distance shrinks with every step, even when little of it is left.
+        assert_eq!(fade_channel(101, 100, Fade::RETAIN_ONE - 1), 100);
+        assert_eq!(fade_channel(99, 100, Fade::RETAIN_ONE - 1), 100);
+    }
+
+    #[test]
+    fn fade_steps_follow_the_half_life() {
+        let fade = Fade::new(Duration::from_secs(16));
+        assert_eq!(fade.step_interval, Duration::from_secs(1));
+        let retained = (0..Fade::STEPS_PER_HALF_LIFE)
+            .fold(f64::from(Fade::RETAIN_ONE), |left, _| {
+                left * f64::from(fade.retain()) / f64::from(Fade::RETAIN_ONE)
+            });
+        assert!((retained / f64::from(Fade::RETAIN_ONE) - 0.5).abs() < 0.001);
+
+        // Short half-lives take larger steps instead.
+        let fade = Fade::new(Duration::from_millis(20));
+        assert_eq!(fade.retain(), Fade::RETAIN_ONE / 2);
+    }
+
+    #[test]
+    fn pixels_set_since_the_last_step_skip_it() {
+        let canvas = fading_canvas();
+        canvas.apply_pixel(0, WHITE);
+        canvas.apply_pixel(COLOR_SIZE, WHITE);
+        canvas.fade_step(Fade::RETAIN_ONE / 2);
+        // Only the second pixel was set again after the first step.
+        canvas.apply_pixel(COLOR_SIZE, WHITE);
+        canvas.fade_step(Fade::RETAIN_ONE / 2);
+        assert_eq!(
+            canvas.frame.load(0),
+            Color {
+                r: 177,
+                g: 177,
+                b: 177,
+                a: 0xff
+            }
+        );
+        assert_eq!(canvas.frame.load(1), WHITE);
+        canvas.fade_step(Fade::RETAIN_ONE / 2);
+        assert_eq!(
+            canvas.frame.load(1),
+            Color {
+                r: 177,
+                g: 177,
+                b: 177,
+                a: 0xff
+            }
+        );
+    }
 }
@@ -0,0 +1,122 @@
+//! Chaos mode, which drops and delays incoming packets on purpose,
+//! so that client authors can test how their clients cope with a lossy network.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+/// Work that runs once a packet’s delay is over.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// What happens to a received packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Deliver,
+    Drop,
+    /// The packet is handled after this delay.
+    Delay(Duration),
+}
+
+/// A job waiting for its delay to be over, ordered by when it is due.
+struct Delayed {
+    due: Instant,
+    /// Keeps jobs that are due at the same time in the order they were delayed.
+    sequence: u64,
+    job: Job,
+}
+
+impl PartialEq for Delayed {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.sequence) == (other.due, other.sequence)
+    }
+}
+
+impl Eq for Delayed {}
+
+impl PartialOrd for Delayed {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Delayed {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.due, self.sequence).cmp(&(other.due, other.sequence))
+    }
+}
+
+/// Decides the fate of packets, and runs delayed packets on a background thread.
+#[derive(Debug)]
+pub struct Chaos {
+    /// Probabilities from 0 to 1.
+    drop_probability: f64,
+    delay_probability: f64,
+    max_delay: Duration,
+    delayed: Sender<(Instant, Job)>,
+}
+
+impl Chaos {
+    /// Drops and delays the given percentages of packets. Delays are uniformly distributed up to the maximum delay.
+    pub fn start(drop_percentage: f64, delay_percentage: f64, max_delay: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel::<(Instant, Job)>();
+        thread::spawn(move || {
+            let mut waiting = BinaryHeap::new();
+            let mut sequence = 0;
+            loop {
+                let next_due = waiting
+                    .peek()
+                    .map(|Reverse(delayed): &Reverse<Delayed>| delayed.due);
+                let received = match next_due {
+                    Some(due) => {
+                        receiver.recv_timeout(due.saturating_duration_since(Instant::now()))
+                    }
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok((due, job)) => {
+                        waiting.push(Reverse(Delayed { due, sequence, job }));
+                        sequence += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                while waiting
+                    .peek()
+                    .is_some_and(|Reverse(delayed)| delayed.due <= Instant::now())
+                {
+                    let Reverse(delayed) = waiting.pop().expect("a job is waiting");
+                    (delayed.job)();
+                }
+            }
+        });
+        Self {
+            drop_probability: (drop_percentage / 100.0).clamp(0.0, 1.0),
+            delay_probability: (delay_percentage / 100.0).clamp(0.0, 1.0),
+            max_delay,
+            delayed: sender,
+        }
+    }
+
+    /// Decides what happens to a received packet.
+    pub fn fate(&self) -> Fate {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.drop_probability) {
+            Fate::Drop
+        } else if rng.gen_bool(self.delay_probability) {
+            Fate::Delay(self.max_delay.mul_f64(rng.gen()))
+        } else {
+            Fate::Deliver
+        }
+    }
+
+    /// Runs the job once the delay is over.
+    pub fn delay(&self, delay: Duration, job: impl FnOnce() + Send + 'static) {
+        let _ = self.delayed.send((Instant::now() + delay, Box::new(job)));
+    }
+}
@@ -0,0 +1,216 @@
+//! Per-client statistics, which tell apart multiple clients behind one address by their echo identifier.
+//!
+//! Clients pick their echo identifier freely, so it serves as a lightweight tag.
+//! Over UDP, which has no echo identifier, all clients of a source count as identifier 0.
+//!
+//! Workers count activity in a [`ClientTally`] and record it once per batch of packets. At most [`MAX_CLIENTS`]
+//! clients are tracked: once there is no more room, clients that were idle for a while are forgotten, and new clients
+//! aren’t counted until there is room again. Otherwise, every spoofed source or identifier would take memory forever.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use pingxelflut::format::Packet;
+use serde::Serialize;
+
+/// Most clients that are tracked at the same time.
+const MAX_CLIENTS: usize = 65_536;
+/// Clients that didn’t send anything for this long are forgotten once there is no more room.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Shortest time between two searches for idle clients, which go through all of them.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What one client has sent so far.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ClientActivity {
+    pub packets: u64,
+    pub bytes: u64,
+    pub set_pixels: u64,
+    pub get_pixels: u64,
+}
+
+impl ClientActivity {
+    fn add(&mut self, other: &ClientActivity) {
+        self.packets += other.packets;
+        self.bytes += other.bytes;
+        self.set_pixels += other.set_pixels;
+        self.get_pixels += other.get_pixels;
+    }
+}
+
+/// A client and its activity.
+#[derive(Debug, Serialize)]
+pub struct ClientRecord {
+    pub source: IpAddr,
+    pub identifier: u16,
+    #[serde(flatten)]
+    pub activity: ClientActivity,
+}
+
+/// Collects activity per source address and echo identifier.
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    clients: Mutex<Clients>,
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    /// Activity and when the client last sent something, per source address and echo identifier.
+    entries: HashMap<(IpAddr, u16), (ClientActivity, Instant)>,
+    last_sweep: Option<Instant>,
+}
+
+impl Clients {
+    /// Forgets idle clients, unless that was done recently.
+    fn sweep(&mut self, now: Instant) {
+        if self
+            .last_sweep
+            .is_some_and(|last_sweep| now.duration_since(last_sweep) < SWEEP_INTERVAL)
+        {
+            return;
+        }
+        self.last_sweep = Some(now);
+        self.entries
+            .retain(|_, (_, last_seen)| now.duration_since(*last_seen) < IDLE_TIMEOUT);
+    }
+}
+
+impl ClientStats {
+    /// Records the activity that a worker counted.
+    fn record(&self, pending: impl Iterator<Item = ((IpAddr, u16), ClientActivity)>, now: Instant) {
+        let mut clients = self.clients.lock();
+        for (client, activity) in pending {
+            if clients.entries.len() >= MAX_CLIENTS && !clients.entries.contains_key(&client) {
+                clients.sweep(now);
+                if clients.entries.len() >= MAX_CLIENTS {
+                    continue;
+                }
+            }
+            let (total, last_seen) = clients
+                .entries
+                .entry(client)
+                .or_insert((ClientActivity::default(), now));
+            total.add(&activity);
+            *last_seen = now;
+        }
+    }
+
+    /// Returns the clients that sent the most packets, in descending order.
+    pub fn top(&self, count: usize) -> Vec<ClientRecord> {
+        let mut records: Vec<_> = self
+            .clients
+            .lock()
+            .entries
+            .iter()
+            .map(|(&(source, identifier), &(activity, _))| ClientRecord {
+                source,
+                identifier,
+                activity,
+            })
+            .collect();
+        records.sort_unstable_by_key(|record| Reverse(record.activity.packets));
+        records.truncate(count);
+        records
+    }
+}
+
+/// Counts the activity of clients until it is flushed, so that the statistics only need to be locked once per batch
+/// of packets.
+#[derive(Debug)]
+pub struct ClientTally {
+    stats: Arc<ClientStats>,
+    pending: HashMap<(IpAddr, u16), ClientActivity>,
+}
+
+impl ClientTally {
+    pub fn new(stats: Arc<ClientStats>) -> Self {
+        Self {
+            stats,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Counts that the client sent the packet, whose raw size is given separately.
+    pub fn add(&mut self, source: IpAddr, identifier: u16, packet: &Packet, bytes: usize) {
+        let activity = self.pending.entry((source, identifier)).or_default();
+        activity.packets += 1;
+        activity.bytes += bytes as u64;
+        match packet {
+            Packet::GetPixel { .. } => activity.get_pixels += 1,
+            packet => activity.set_pixels += packet.pixels(),
+        }
+    }
+
+    /// Records all counted activity in the statistics.
+    pub fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.stats.record(self.pending.drain(), Instant::now());
+        }
+    }
+}
+
+impl Drop for ClientTally {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(index: u32) -> IpAddr {
+        IpAddr::V4(index.into())
+    }
+
+    #[test]
+    fn activity_is_recorded_when_flushed() {
+        let stats = Arc::new(ClientStats::default());
+        let mut tally = ClientTally::new(stats.clone());
+        let set = Packet::SetPixel {
+            x: 0,
+            y: 0,
+            color: Default::default(),
+        };
+        tally.add(source(1), 7, &set, 10);
+        tally.add(source(1), 7, &Packet::GetPixel { x: 0, y: 0 }, 5);
+        tally.add(source(1), 8, &set, 10);
+        assert!(stats.top(10).is_empty());
+
+        tally.flush();
+        tally.add(source(1), 7, &set, 10);
+        drop(tally);
+        let top = stats.top(10);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].source, top[0].identifier), (source(1), 7));
+        assert_eq!(top[0].activity.packets, 3);
+        assert_eq!(top[0].activity.bytes, 25);
+        assert_eq!(top[0].activity.set_pixels, 2);
+        assert_eq!(top[0].activity.get_pixels, 1);
+        assert_eq!(top[1].activity.packets, 1);
+    }
+
+    #[test]
+    fn clients_are_bounded() {
+        let stats = Arc::new(ClientStats::default());
+        let mut tally = ClientTally::new(stats.clone());
+        for index in 0..MAX_CLIENTS as u32 + 10 {
+            tally.add(source(index), 0, &Packet::SizeRequest, 1);
+        }
+        tally.flush();
+        assert_eq!(stats.top(usize::MAX).len(), MAX_CLIENTS);
+
+        // Idle clients make room for new ones.
+        let client = ((source(u32::MAX), 0), ClientActivity::default());
+        stats.record([client].into_iter(), Instant::now() + IDLE_TIMEOUT);
+        let top = stats.top(usize::MAX);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].source, source(u32::MAX));
+    }
+}
@@ -0,0 +1,480 @@
+//! Applies queued pixels on the GPU instead of the CPU.
+//!
+//! At very high pixel rates, writing pixels into the frame buffer is limited by CPU memory bandwidth,
+//! since every pixel lands on a random cache line. With GPU compositing, the CPU only packs queued pixels
+//! into a buffer that is uploaded every frame, and a compute shader applies them (including blending and fading)
+//! to a copy of the canvas in GPU memory, which is then written into the texture that is rendered.
+//!
+//! The frame buffer of the canvas on the CPU becomes a mirror that is read back from the GPU every [`READBACK_INTERVAL`].
+//! Everything that reads the canvas, like get pixel requests and snapshots, sees it that much delayed.
+//! Changes made on the CPU, like clearing the canvas or drawing images via the admin API, are uploaded to the GPU.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use log::warn;
+use parking_lot::Mutex;
+use pingxelflut::format::{Color, COLOR_SIZE};
+use pixels::{
+    wgpu::{self, util::DeviceExt},
+    Pixels,
+};
+
+use crate::canvas::{BlendMode, Canvas, DirtyRect};
+
+/// How often the canvas is read back from the GPU.
+pub const READBACK_INTERVAL: Duration = Duration::from_secs(1);
+/// Most pixels applied in one frame, limited by how many workgroups a single dispatch can run.
+const MAX_WRITES_PER_FRAME: usize = 65535 * APPLY_WORKGROUP_SIZE;
+const APPLY_WORKGROUP_SIZE: usize = 64;
+const IMAGE_WORKGROUP_SIZE: u32 = 8;
+/// Queued writes the write buffer initially has room for. It grows as needed.
+const INITIAL_WRITE_CAPACITY: usize = 65536;
+/// Size of one queued write: the pixel index and the packed color.
+const WRITE_SIZE: usize = 2 * std::mem::size_of::<u32>();
+
+/// Where queued pixels are applied to the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Compositing {
+    /// Write pixels into the frame buffer on the CPU.
+    #[default]
+    Cpu,
+    /// Apply pixels with a compute shader, which scales to higher pixel rates.
+    /// Get pixel requests and snapshots see the canvas up to a second late.
+    Gpu,
+}
+
+/// Layout of the `Params` uniform in the shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    width: u32,
+    height: u32,
+    write_count: u32,
+    overwrite: u32,
+    fade_steps: u32,
+    fade_retain: u32,
+    overlay_start: [u32; 2],
+    overlay_end: [u32; 2],
+    _end_padding: [u32; 2],
+}
+
+#[derive(Debug)]
+pub struct GpuCompositor {
+    width: u32,
+    height: u32,
+    params: Params,
+    /// Queued writes of the current frame, as pixel index and packed color.
+    writes: Vec<[u32; 2]>,
+    /// The canvas in GPU memory, with one packed color per pixel.
+    canvas_buffer: wgpu::Buffer,
+    write_buffer: wgpu::Buffer,
+    write_capacity: usize,
+    params_buffer: wgpu::Buffer,
+    output_view: wgpu::TextureView,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// The background that fading moves pixels towards, and which pixels were set since the last fade step.
+    fade_bind_group: wgpu::BindGroup,
+    apply_pipeline: wgpu::ComputePipeline,
+    fade_pipeline: wgpu::ComputePipeline,
+    present_pipeline: wgpu::ComputePipeline,
+    readback_buffer: wgpu::Buffer,
+    last_readback: Instant,
+    readback: Readback,
+}
+
+/// Progress of reading the canvas back from the GPU.
+#[derive(Debug)]
+enum Readback {
+    Idle,
+    /// The copy into the readback buffer is recorded in this frame.
+    Copying,
+    /// The readback buffer is being mapped, and the result is stored once the GPU is done.
+    Mapping(Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>),
+}
+
+impl GpuCompositor {
+    /// Sets up GPU compositing into the texture, which needs to be a storage texture of the canvas size.
+    /// Fails if the canvas is too large for a storage buffer on this GPU.
+    pub fn new(pixels: &Pixels, texture: &wgpu::Texture, canvas: &Canvas) -> Result<Self, String> {
+        let device = &pixels.context().device;
+        let width = u32::from(canvas.width);
+        let height = u32::from(canvas.height);
+        let canvas_size = u64::from(width) * u64::from(height) * COLOR_SIZE as u64;
+        let limits = device.limits();
+        if canvas_size > u64::from(limits.max_storage_buffer_binding_size)
+            || canvas_size > limits.max_buffer_size
+        {
+            return Err(format!(
+                "the canvas needs {} bytes, but this GPU supports storage buffers of at most {} bytes",
+                canvas_size,
+                limits.max_storage_buffer_binding_size
+            ));
+        }
+
+        let module = device.create_shader_module(wgpu::include_wgsl!("../shaders/composite.wgsl"));
+        let canvas_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_compositor_canvas_buffer"),
+            size: canvas_size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let background_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("gpu_compositor_background_buffer"),
+            contents: canvas.background(),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let fresh_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_compositor_fresh_buffer"),
+            size: u64::from(width) * u64::from(height) * std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let write_buffer = create_write_buffer(device, INITIAL_WRITE_CAPACITY);
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_compositor_params_buffer"),
+            size: std::mem::size_of::<Params>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("gpu_compositor_readback_buffer"),
+            size: canvas_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let output_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let storage_buffer = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("gpu_compositor_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<Params>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                storage_buffer(1, false),
+                storage_buffer(2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba8Unorm,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let fade_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("gpu_compositor_fade_bind_group_layout"),
+                entries: &[storage_buffer(0, true), storage_buffer(1, false)],
+            });
+        let fade_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("gpu_compositor_fade_bind_group"),
+            layout: &fade_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: background_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: fresh_buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("gpu_compositor_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout, &fade_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point,
+            })
+        };
+        let apply_pipeline = pipeline("apply");
+        let fade_pipeline = pipeline("fade");
+        let present_pipeline = pipeline("present");
+
+        // The canvas starts out dirty, so its background is uploaded with the first frame.
+        let compositor = Self {
+            width,
+            height,
+            params: Params {
+                width,
+                height,
+                overwrite: u32::from(canvas.blend_mode == BlendMode::Overwrite),
+                ..Params::default()
+            },
+            writes: Vec::new(),
+            bind_group: create_bind_group(
+                device,
+                &bind_group_layout,
+                &params_buffer,
+                &canvas_buffer,
+                &write_buffer,
+                &output_view,
+            ),
+            fade_bind_group,
+            canvas_buffer,
+            write_buffer,
+            write_capacity: INITIAL_WRITE_CAPACITY,
+            params_buffer,
+            output_view,
+            bind_group_layout,
+            apply_pipeline,
+            fade_pipeline,
+            present_pipeline,
+            readback_buffer,
+            last_readback: Instant::now(),
+            readback: Readback::Idle,
+        };
+        Ok(compositor)
+    }
+
+    /// Most pixels that can be applied in one frame.
+    pub fn budget(&self, budget: Option<usize>) -> usize {
+        budget.map_or(MAX_WRITES_PER_FRAME, |budget| {
+            budget.min(MAX_WRITES_PER_FRAME)
+        })
+    }
+
+    /// Queues a pixel at the given byte position to be applied in the next frame.
+    /// Pixels written to the same position in the same frame are applied in no particular order.
+    pub fn queue_pixel(&mut self, pixel_pos: usize, color: Color) {
+        self.writes.push([
+            (pixel_pos / COLOR_SIZE) as u32,
+            u32::from_le_bytes([color.r, color.g, color.b, color.a]),
+        ]);
+    }
+
+    /// Applies the given number of fade steps in the next frame,
+    /// each keeping `retain` / [`Fade::RETAIN_ONE`](crate::canvas::Fade::RETAIN_ONE) of the distance to the background.
+    pub fn fade(&mut self, steps: u32, retain: u32) {
+        self.params.fade_steps += steps;
+        self.params.fade_retain = retain;
+    }
+
+    /// Uploads an area of the canvas that was changed on the CPU, replacing what the GPU has there.
+    pub fn upload(&mut self, pixels: &Pixels, canvas: &Canvas, area: DirtyRect) {
+        let queue = &pixels.context().queue;
+        let width = self.width as usize;
+        for y in area.start_y..area.end_y {
+            let row = y * width + area.start_x..y * width + area.end_x;
+            queue.write_buffer(
+                &self.canvas_buffer,
+                (row.start * COLOR_SIZE) as u64,
+                &canvas.frame.bytes(row),
+            );
+        }
+        // A readback in progress predates this change and would undo it on the CPU.
+        if let Readback::Mapping(_) = self.readback {
+            self.readback_buffer.unmap();
+        }
+        self.readback = Readback::Idle;
+    }
+
+    /// Uploads the queued writes of this frame, leaving the overlay area of the texture untouched.
+    pub fn prepare(&mut self, pixels: &Pixels, overlay_area: Option<DirtyRect>) {
+        let context = pixels.context();
+        if self.writes.len() > self.write_capacity {
+            self.write_capacity = self.writes.len().next_power_of_two();
+            self.write_buffer = create_write_buffer(&context.device, self.write_capacity);
+            self.bind_group = create_bind_group(
+                &context.device,
+                &self.bind_group_layout,
+                &self.params_buffer,
+                &self.canvas_buffer,
+                &self.write_buffer,
+                &self.output_view,
+            );
+        }
+        if !self.writes.is_empty() {
+            context
+                .queue
+                .write_buffer(&self.write_buffer, 0, bytemuck::cast_slice(&self.writes));
+        }
+        self.params.write_count = self.writes.len() as u32;
+        self.writes.clear();
+        (self.params.overlay_start, self.params.overlay_end) = match overlay_area {
+            Some(area) => (
+                [area.start_x as u32, area.start_y as u32],
+                [area.end_x as u32, area.end_y as u32],
+            ),
+            None => ([0, 0], [0, 0]),
+        };
+        context
+            .queue
+            .write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    /// Records the compute passes of this frame, and the copy for reading back the canvas if one is due.
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let image_workgroups = (
+            self.width.div_ceil(IMAGE_WORKGROUP_SIZE),
+            self.height.div_ceil(IMAGE_WORKGROUP_SIZE),
+        );
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("gpu_compositor_compute_pass"),
+        });
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_bind_group(1, &self.fade_bind_group, &[]);
+        // Fade before applying new pixels, which then skip the next fade step.
+        if self.params.fade_steps > 0 {
+            pass.set_pipeline(&self.fade_pipeline);
+            pass.dispatch_workgroups(image_workgroups.0, image_workgroups.1, 1);
+        }
+        if self.params.write_count > 0 {
+            pass.set_pipeline(&self.apply_pipeline);
+            pass.dispatch_workgroups(
+                (self.params.write_count as usize).div_ceil(APPLY_WORKGROUP_SIZE) as u32,
+                1,
+                1,
+            );
+        }
+        pass.set_pipeline(&self.present_pipeline);
+        pass.dispatch_workgroups(image_workgroups.0, image_workgroups.1, 1);
+        drop(pass);
+        self.params.fade_steps = 0;
+        self.params.write_count = 0;
+
+        if matches!(self.readback, Readback::Idle)
+            && self.last_readback.elapsed() >= READBACK_INTERVAL
+        {
+            self.encode_readback(encoder);
+        }
+    }
+
+    fn encode_readback(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            &self.canvas_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            self.canvas_buffer.size(),
+        );
+        self.readback = Readback::Copying;
+    }
+
+    /// Continues reading back the canvas after the frame was submitted,
+    /// and updates the frame buffer of the canvas once the GPU delivered it.
+    pub fn continue_readback(&mut self, pixels: &Pixels, canvas: &Canvas) {
+        match &self.readback {
+            Readback::Idle => {}
+            Readback::Copying => {
+                let result: Arc<Mutex<_>> = Arc::default();
+                let callback_result = Arc::clone(&result);
+                self.readback_buffer
+                    .slice(..)
+                    .map_async(wgpu::MapMode::Read, move |map_result| {
+                        *callback_result.lock() = Some(map_result);
+                    });
+                self.readback = Readback::Mapping(result);
+                self.last_readback = Instant::now();
+            }
+            Readback::Mapping(result) => {
+                pixels.context().device.poll(wgpu::Maintain::Poll);
+                let Some(map_result) = result.lock().take() else {
+                    return;
+                };
+                self.readback = Readback::Idle;
+                if let Err(why) = map_result {
+                    warn!("could not read back the canvas from the GPU: {}", why);
+                    return;
+                }
+                // Changes made on the CPU in the meantime still need to be uploaded, and the readback would undo them.
+                canvas.replace_unless_dirty(&self.readback_buffer.slice(..).get_mapped_range());
+                self.readback_buffer.unmap();
+            }
+        }
+    }
+
+    /// Reads back the canvas right away, blocking until the GPU is done.
+    pub fn read_back(&mut self, pixels: &Pixels, canvas: &Canvas) {
+        if let Readback::Mapping(_) = self.readback {
+            self.readback_buffer.unmap();
+        }
+        let context = pixels.context();
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("gpu_compositor_readback_encoder"),
+            });
+        self.encode_readback(&mut encoder);
+        context.queue.submit(Some(encoder.finish()));
+        self.continue_readback(pixels, canvas);
+        context.device.poll(wgpu::Maintain::Wait);
+        self.continue_readback(pixels, canvas);
+    }
+}
+
+fn create_write_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("gpu_compositor_write_buffer"),
+        size: (capacity * WRITE_SIZE) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    params_buffer: &wgpu::Buffer,
+    canvas_buffer: &wgpu::Buffer,
+    write_buffer: &wgpu::Buffer,
+    output_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("gpu_compositor_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: canvas_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: write_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(output_view),
+            },
+        ],
+    })
+}
@@ -0,0 +1,167 @@
+//! Configuration files, which provide defaults for the command line options.
+//!
+//! A configuration file is a TOML table whose keys are the long names of the command line options, for example:
+//!
+//! ```toml
+//! canvas = ["1920x1080", "1280x720"]
+//! blend-mode = "overwrite"
+//! get-pixel-rate = 500
+//! admin-address = "127.0.0.1:8080"
+//! ```
+//!
+//! The values are parsed exactly like their command line counterparts. Options given on the command line take precedence.
+
+use std::{ffi::OsString, fs, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{parser::ValueSource, ArgMatches, Command, CommandFactory, FromArgMatches, Parser};
+
+use crate::Arguments;
+
+/// Parses the command line arguments, filling in options that weren’t given from the configuration file, if there is one.
+///
+/// Invalid options are returned as a [`clap::Error`] instead of exiting, so that reloading a broken configuration
+/// keeps the server running.
+pub fn parse_arguments() -> Result<Arguments> {
+    parse_from(std::env::args_os().collect())
+}
+
+fn parse_from(command_line: Vec<OsString>) -> Result<Arguments> {
+    let command = Arguments::command();
+    let matches = command.clone().try_get_matches_from(&command_line)?;
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(Arguments::from_arg_matches(&matches)?);
+    };
+
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("could not read configuration file {}", path.display()))?;
+    let table: toml::Table = toml::from_str(&text)
+        .with_context(|| format!("invalid configuration file {}", path.display()))?;
+    let combined = merge(&command, &matches, &command_line, table)?;
+    Ok(Arguments::try_parse_from(combined)?)
+}
+
+/// Turns the options of a configuration file into command line arguments, leaving out those given on the command line.
+fn merge(
+    command: &Command,
+    matches: &ArgMatches,
+    command_line: &[OsString],
+    table: toml::Table,
+) -> Result<Vec<OsString>> {
+    let mut file_arguments = Vec::new();
+    for (key, value) in table {
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(key.as_str()) && key != "config")
+        else {
+            bail!("unknown configuration option {key}");
+        };
+        if matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        let flag = OsString::from(format!("--{key}"));
+        let values = match value {
+            toml::Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(enabled) if !arg.get_action().takes_values() => {
+                    if enabled {
+                        file_arguments.push(flag.clone());
+                    }
+                }
+                value => {
+                    file_arguments.push(flag.clone());
+                    file_arguments.push(option_value(&key, value)?.into());
+                }
+            }
+        }
+    }
+
+    // File options go before the command line, so that the program name stays first.
+    let mut combined = command_line[..1].to_vec();
+    combined.extend(file_arguments);
+    combined.extend_from_slice(&command_line[1..]);
+    Ok(combined)
+}
+
+/// Converts a TOML value to the text that would be given on the command line.
+fn option_value(key: &str, value: toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(text) => text,
+        toml::Value::Integer(number) => number.to_string(),
+        toml::Value::Float(number) => number.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        _ => bail!("configuration option {key} must be a string, number or boolean"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The command line that the configuration file turns the given command line into.
+    fn merged(command_line: &[&str], file: &str) -> Result<Vec<String>> {
+        let command = Arguments::command();
+        let command_line: Vec<OsString> = command_line.iter().map(OsString::from).collect();
+        let matches = command.clone().try_get_matches_from(&command_line)?;
+        let combined = merge(&command, &matches, &command_line, toml::from_str(file)?)?;
+        Ok(combined
+            .into_iter()
+            .map(|argument| argument.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn file_options_go_before_the_command_line() {
+        let merged = merged(
+            &["server", "--get-pixel-rate", "5"],
+            r#"canvas = ["640x480", "320x240"]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            merged,
+            [
+                "server",
+                "--canvas",
+                "640x480",
+                "--canvas",
+                "320x240",
+                "--get-pixel-rate",
+                "5"
+            ]
+        );
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let merged = merged(&["server", "--get-pixel-rate", "5"], "get-pixel-rate = 100").unwrap();
+        assert_eq!(merged, ["server", "--get-pixel-rate", "5"]);
+    }
+
+    #[test]
+    fn flags_are_only_given_when_enabled() {
+        let merged = merged(&["server"], "no-vsync = true\ntest-card = false").unwrap();
+        assert_eq!(merged, ["server", "--no-vsync"]);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(merged(&["server"], "no-such-option = 1").is_err());
+        assert!(merged(&["server"], "config = \"other.toml\"").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_errors_instead_of_exiting() {
+        let merged = merged(&["server"], r#"get-pixel-rate = "lots""#).unwrap();
+        let error = Arguments::try_parse_from(merged).unwrap_err();
+        assert_eq!(error.kind(), clap::error::ErrorKind::ValueValidation);
+        assert!(
+            parse_from(vec!["server".into(), "--get-pixel-rate".into(), "x".into()])
+                .unwrap_err()
+                .is::<clap::Error>()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Canvas history, which records keyframes of every canvas during the event so that past moments can be viewed again.
+//!
+//! Keyframes are PNG images named `canvasN-TIMESTAMP.png` in the history directory, with the time in milliseconds since the Unix epoch.
+//! Windows can switch to a read-only history view that steps through the keyframes while the canvas stays live underneath.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use image::RgbaImage;
+use log::{info, warn};
+use parking_lot::Mutex;
+
+use crate::SharedState;
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Saves a keyframe of every canvas into the directory every interval.
+pub async fn record_periodically(
+    state: SharedState,
+    directory: PathBuf,
+    interval: Duration,
+) -> Result<()> {
+    fs::create_dir_all(&directory)?;
+    info!("recording canvas history to {}", directory.display());
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let state = state.clone();
+        let directory = directory.clone();
+        tokio::task::spawn_blocking(move || record_keyframes(&state, &directory)).await??;
+    }
+}
+
+fn record_keyframes(state: &SharedState, directory: &Path) -> Result<()> {
+    let timestamp = unix_millis();
+    for (index, canvas) in state.canvases.iter().enumerate() {
+        canvas
+            .to_image()
+            .save(directory.join(format!("canvas{index}-{timestamp}.png")))?;
+    }
+    Ok(())
+}
+
+/// Lists the keyframes of a canvas in the directory, oldest first, with their timestamps.
+fn keyframes(directory: &Path, canvas: usize) -> Vec<(u64, PathBuf)> {
+    let prefix = format!("canvas{canvas}-");
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+    let mut keyframes: Vec<_> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let timestamp = path
+                .file_name()?
+                .to_str()?
+                .strip_prefix(&prefix)?
+                .strip_suffix(".png")?
+                .parse()
+                .ok()?;
+            Some((timestamp, path))
+        })
+        .collect();
+    keyframes.sort_unstable_by_key(|(timestamp, _)| *timestamp);
+    keyframes
+}
+
+/// The position of the keyframe to load, and the keyframe once it finished loading in the background.
+/// Keyframes that finish loading after the position moved on are discarded.
+type LoadedKeyframe = Arc<Mutex<(usize, Option<RgbaImage>)>>;
+
+/// The history view of one window, which shows a past keyframe instead of the live canvas.
+pub struct HistoryView {
+    keyframes: Vec<(u64, PathBuf)>,
+    position: usize,
+    size: (u32, u32),
+    loaded: LoadedKeyframe,
+    shown: Option<RgbaImage>,
+}
+
+impl HistoryView {
+    /// Opens the history of a canvas at its latest keyframe. Returns None if there are no keyframes yet.
+    pub fn open(directory: &Path, canvas: usize, size: (u32, u32)) -> Option<Self> {
+        let keyframes = keyframes(directory, canvas);
+        let position = keyframes.len().checked_sub(1)?;
+        let view = Self {
+            position,
+            keyframes,
+            size,
+            loaded: Arc::new(Mutex::new((position, None))),
+            shown: None,
+        };
+        view.load();
+        Some(view)
+    }
+
+    /// Moves through the history by the given number of keyframes, stopping at either end.
+    pub fn step(&mut self, keyframes: isize) {
+        let position = self
+            .position
+            .saturating_add_signed(keyframes)
+            .min(self.keyframes.len() - 1);
+        if position != self.position {
+            self.position = position;
+            self.load();
+        }
+    }
+
+    /// Loads the keyframe at the current position in the background.
+    fn load(&self) {
+        let position = self.position;
+        let path = self.keyframes[position].1.clone();
+        let size = self.size;
+        let loaded = self.loaded.clone();
+        *loaded.lock() = (position, None);
+        tokio::task::spawn_blocking(move || match image::open(&path) {
+            // Keyframes of a differently sized canvas from an earlier event can’t be shown.
+            Ok(image) if image.width() == size.0 && image.height() == size.1 => {
+                let mut loaded = loaded.lock();
+                if loaded.0 == position {
+                    loaded.1 = Some(image.into_rgba8());
+                }
+            }
+            Ok(_) => warn!("keyframe {} has a different size", path.display()),
+            Err(why) => warn!("could not load keyframe {}: {}", path.display(), why),
+        });
+    }
+
+    /// The keyframe to show, which is the last loaded one until the current one finished loading.
+    pub fn shown(&mut self) -> Option<&RgbaImage> {
+        if let Some(image) = self.loaded.lock().1.take() {
+            self.shown = Some(image);
+        }
+        self.shown.as_ref()
+    }
+
+    /// How far through the history the current keyframe is, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.keyframes.len() < 2 {
+            1.0
+        } else {
+            self.position as f32 / (self.keyframes.len() - 1) as f32
+        }
+    }
+
+    /// Describes the time of the current keyframe relative to now, like `-01:02:03`.
+    pub fn label(&self) -> String {
+        let age = unix_millis().saturating_sub(self.keyframes[self.position].0) / 1000;
+        format!(
+            "History -{:02}:{:02}:{:02} ({}/{})",
+            age / 3600,
+            age / 60 % 60,
+            age % 60,
+            self.position + 1,
+            self.keyframes.len()
+        )
+    }
+}
@@ -0,0 +1,381 @@
+//! Counting of applied pixels, in total and per source address, and the leaderboard of the sources that applied the
+//! most.
+//!
+//! Pixels are only counted per source where something needs them, and the counts are bounded: once
+//! [`MAX_ENTRIES`] addresses are tracked, addresses that were idle for a while are forgotten, and new addresses only
+//! count towards the total until there is room again. Otherwise, every spoofed or scanning source would take memory
+//! forever. The leaderboard counts truncated addresses per network, so that hosts of the same network share an entry.
+
+use clap::ValueEnum;
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    hash::{BuildHasher, RandomState},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Most addresses that are counted at the same time, both per source and on the leaderboard.
+const MAX_ENTRIES: usize = 65_536;
+/// Addresses that didn’t apply pixels for this long are forgotten once there is no more room.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Shortest time between two searches for idle addresses, which go through all of them.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How source addresses are shown on the leaderboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Anonymization {
+    /// Show the full address.
+    Full,
+    /// Only show the network part of the address (/24 for IPv4, /64 for IPv6), counting the pixels of the whole
+    /// network together.
+    #[default]
+    Truncate,
+    /// Show a short hash of the address that is stable for the runtime of the server.
+    Hash,
+}
+
+impl Anonymization {
+    /// The leaderboard entry that the source counts towards.
+    fn entry(self, source: IpAddr) -> IpAddr {
+        match (self, source) {
+            (Anonymization::Truncate, IpAddr::V4(address)) => {
+                IpAddr::V4(Ipv4Addr::from(u32::from(address) & !0xff))
+            }
+            (Anonymization::Truncate, IpAddr::V6(address)) => {
+                IpAddr::V6(Ipv6Addr::from(u128::from(address) & !u128::from(u64::MAX)))
+            }
+            _ => source,
+        }
+    }
+}
+
+/// Applied pixels per address, bounded to [`MAX_ENTRIES`] addresses.
+#[derive(Debug, Default)]
+struct Counts {
+    /// Applied pixels and when the last of them was applied, per address.
+    entries: HashMap<IpAddr, (u64, Instant)>,
+    last_sweep: Option<Instant>,
+}
+
+impl Counts {
+    /// Counts pixels of the address. Returns its new count, or `None` if there is no room for the address.
+    /// When looking for room, the kept addresses aren’t forgotten even if they are idle.
+    fn add(
+        &mut self,
+        address: IpAddr,
+        pixels: u64,
+        now: Instant,
+        kept: &[(IpAddr, u64)],
+    ) -> Option<u64> {
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&address) {
+            self.sweep(now, kept);
+        }
+        if self.entries.len() >= MAX_ENTRIES && !self.entries.contains_key(&address) {
+            return None;
+        }
+        let (count, last_applied) = self.entries.entry(address).or_insert((0, now));
+        *count += pixels;
+        *last_applied = now;
+        Some(*count)
+    }
+
+    /// Forgets idle addresses, unless that was done recently.
+    fn sweep(&mut self, now: Instant, kept: &[(IpAddr, u64)]) {
+        if self
+            .last_sweep
+            .is_some_and(|last_sweep| now.duration_since(last_sweep) < SWEEP_INTERVAL)
+        {
+            return;
+        }
+        self.last_sweep = Some(now);
+        self.entries.retain(|address, (_, last_applied)| {
+            now.duration_since(*last_applied) < IDLE_TIMEOUT
+                || kept.iter().any(|(kept, _)| kept == address)
+        });
+    }
+}
+
+/// The entries with the most applied pixels.
+#[derive(Debug)]
+struct Ranking {
+    anonymization: Anonymization,
+    /// How many entries are ranked.
+    size: usize,
+    entries: Counts,
+    /// The ranked entries with their pixels, in descending order. Since counts only grow, an entry can only enter
+    /// the ranking when it is counted, so that it is kept up to date without looking at the other entries.
+    top: Vec<(IpAddr, u64)>,
+}
+
+impl Ranking {
+    fn add(&mut self, source: IpAddr, pixels: u64, now: Instant) {
+        let entry = self.anonymization.entry(source);
+        let Some(count) = self.entries.add(entry, pixels, now, &self.top) else {
+            return;
+        };
+        if let Some(ranked) = self.top.iter_mut().find(|(ranked, _)| *ranked == entry) {
+            ranked.1 = count;
+        } else if self.top.len() < self.size {
+            self.top.push((entry, count));
+        } else if self.top.last().is_some_and(|&(_, last)| last < count) {
+            *self.top.last_mut().unwrap() = (entry, count);
+        } else {
+            return;
+        }
+        self.top.sort_by(|(_, a), (_, b)| b.cmp(a));
+    }
+}
+
+/// Tracks how many pixels were applied to a canvas, and optionally how many every source address applied.
+#[derive(Debug, Default)]
+pub struct Leaderboard {
+    total: u64,
+    /// Applied pixels per source, if anything needs them.
+    sources: Option<Counts>,
+    /// The leaderboard, if it is shown.
+    ranking: Option<Ranking>,
+    /// Salt for hashed source addresses, so that the hashes can’t be correlated across server runs.
+    salt: RandomState,
+}
+
+/// Counts pixels until their source or canvas changes, so that the leaderboard only needs to be locked once per run of pixels.
+/// Consecutive pixels mostly come from the same source.
+#[derive(Debug, Default)]
+pub struct Tally {
+    pending: Option<(IpAddr, Arc<Mutex<Leaderboard>>, u64)>,
+}
+
+impl Tally {
+    /// Counts one pixel of the source for the leaderboard.
+    pub fn add(&mut self, leaderboard: &Arc<Mutex<Leaderboard>>, source: IpAddr) {
+        match &mut self.pending {
+            Some((pending_source, pending_leaderboard, count))
+                if *pending_source == source && Arc::ptr_eq(pending_leaderboard, leaderboard) =>
+            {
+                *count += 1;
+            }
+            _ => {
+                self.flush();
+                self.pending = Some((source, leaderboard.clone(), 1));
+            }
+        }
+    }
+
+    /// Records all counted pixels on their leaderboard.
+    pub fn flush(&mut self) {
+        if let Some((source, leaderboard, count)) = self.pending.take() {
+            leaderboard.lock().record(source, count);
+        }
+    }
+}
+
+impl Drop for Tally {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl Leaderboard {
+    /// Also counts the applied pixels of every source, for the statistics and logs that show them.
+    pub fn with_sources(mut self, tracked: bool) -> Self {
+        self.sources = tracked.then(Counts::default);
+        self
+    }
+
+    /// Ranks the given number of sources, or networks if addresses are truncated. 0 doesn’t rank any.
+    pub fn with_ranking(mut self, size: usize, anonymization: Anonymization) -> Self {
+        self.ranking = (size > 0).then(|| Ranking {
+            anonymization,
+            size,
+            entries: Counts::default(),
+            top: Vec::with_capacity(size + 1),
+        });
+        self
+    }
+
+    /// Record that the source applied some pixels.
+    pub fn record(&mut self, source: IpAddr, pixel_count: u64) {
+        self.total += pixel_count;
+        if self.sources.is_none() && self.ranking.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(sources) = &mut self.sources {
+            sources.add(source, pixel_count, now, &[]);
+        }
+        if let Some(ranking) = &mut self.ranking {
+            ranking.add(source, pixel_count, now);
+        }
+    }
+
+    /// Number of pixels the source applied, or 0 if sources aren’t tracked.
+    pub fn pixels(&self, source: IpAddr) -> u64 {
+        self.sources
+            .as_ref()
+            .and_then(|sources| sources.entries.get(&source))
+            .map_or(0, |&(pixels, _)| pixels)
+    }
+
+    /// Total number of applied pixels across all sources.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Number of tracked sources that applied at least one pixel, if sources are tracked.
+    pub fn source_count(&self) -> Option<usize> {
+        self.sources.as_ref().map(|sources| sources.entries.len())
+    }
+
+    /// All tracked sources that applied at least one pixel.
+    pub fn sources(&self) -> Vec<IpAddr> {
+        self.sources
+            .iter()
+            .flat_map(|sources| sources.entries.keys().copied())
+            .collect()
+    }
+
+    /// Returns up to the given number of entries with the most applied pixels, in descending order.
+    pub fn top(&self, count: usize) -> Vec<(IpAddr, u64)> {
+        self.ranking.as_ref().map_or_else(Vec::new, |ranking| {
+            ranking.top.iter().take(count).copied().collect()
+        })
+    }
+
+    /// Formats an entry of [`Leaderboard::top`] for display.
+    pub fn display_entry(&self, entry: IpAddr) -> String {
+        let anonymization = self
+            .ranking
+            .as_ref()
+            .map_or(Anonymization::Full, |ranking| ranking.anonymization);
+        match (anonymization, entry) {
+            (Anonymization::Full, _) => entry.to_string(),
+            (Anonymization::Truncate, IpAddr::V4(address)) => format!("{address}/24"),
+            (Anonymization::Truncate, IpAddr::V6(address)) => format!("{address}/64"),
+            (Anonymization::Hash, _) => {
+                format!("#{:06x}", self.salt.hash_one(entry) & 0xff_ffff)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn top_entries_are_kept_in_order() {
+        let mut leaderboard = Leaderboard::default().with_ranking(2, Anonymization::Full);
+        leaderboard.record(address("10.0.0.1"), 5);
+        leaderboard.record(address("10.0.0.2"), 3);
+        leaderboard.record(address("10.0.0.3"), 4);
+        assert_eq!(
+            leaderboard.top(10),
+            [(address("10.0.0.1"), 5), (address("10.0.0.3"), 4)]
+        );
+        leaderboard.record(address("10.0.0.2"), 3);
+        leaderboard.record(address("10.0.0.3"), 2);
+        assert_eq!(
+            leaderboard.top(10),
+            [(address("10.0.0.2"), 6), (address("10.0.0.3"), 6)]
+        );
+        assert_eq!(leaderboard.top(1).len(), 1);
+        assert_eq!(leaderboard.total(), 17);
+    }
+
+    #[test]
+    fn truncated_addresses_are_counted_per_network() {
+        let mut leaderboard = Leaderboard::default().with_ranking(5, Anonymization::Truncate);
+        leaderboard.record(address("192.0.2.1"), 2);
+        leaderboard.record(address("192.0.2.200"), 3);
+        leaderboard.record(address("198.51.100.1"), 4);
+        leaderboard.record(address("2001:db8::1"), 1);
+        leaderboard.record(address("2001:db8::ffff:1"), 1);
+        let top = leaderboard.top(5);
+        assert_eq!(
+            top,
+            [
+                (address("192.0.2.0"), 5),
+                (address("198.51.100.0"), 4),
+                (address("2001:db8::"), 2),
+            ]
+        );
+        let shown: Vec<_> = top
+            .iter()
+            .map(|&(entry, _)| leaderboard.display_entry(entry))
+            .collect();
+        assert_eq!(shown, ["192.0.2.0/24", "198.51.100.0/24", "2001:db8::/64"]);
+    }
+
+    #[test]
+    fn addresses_can_be_shown_in_full_or_hashed() {
+        let source = address("192.0.2.1");
+        let full = Leaderboard::default().with_ranking(1, Anonymization::Full);
+        assert_eq!(full.display_entry(source), "192.0.2.1");
+        let hashed = Leaderboard::default().with_ranking(1, Anonymization::Hash);
+        let shown = hashed.display_entry(source);
+        assert!(shown.starts_with('#') && shown.len() == 7, "{shown}");
+        assert_eq!(hashed.display_entry(source), shown);
+    }
+
+    #[test]
+    fn sources_are_only_tracked_when_needed() {
+        let source = address("192.0.2.1");
+        let mut untracked = Leaderboard::default();
+        untracked.record(source, 3);
+        assert_eq!(untracked.total(), 3);
+        assert_eq!(untracked.source_count(), None);
+        assert_eq!(untracked.pixels(source), 0);
+        assert!(untracked.top(1).is_empty());
+
+        let mut tracked = Leaderboard::default().with_sources(true);
+        tracked.record(source, 3);
+        tracked.record(source, 2);
+        assert_eq!(tracked.source_count(), Some(1));
+        assert_eq!(tracked.pixels(source), 5);
+        assert_eq!(tracked.sources(), [source]);
+    }
+
+    #[test]
+    fn counts_are_bounded() {
+        let mut counts = Counts::default();
+        let now = Instant::now();
+        for index in 0..MAX_ENTRIES as u32 {
+            counts.add(IpAddr::V4(index.into()), 1, now, &[]);
+        }
+        let newcomer = IpAddr::V4(Ipv4Addr::BROADCAST);
+        assert_eq!(counts.add(newcomer, 1, now, &[]), None);
+        assert_eq!(counts.add(IpAddr::V4(0.into()), 1, now, &[]), Some(2));
+
+        // Idle addresses make room, except for the kept ones.
+        let kept = [(IpAddr::V4(1.into()), 1)];
+        assert_eq!(counts.add(newcomer, 1, now + IDLE_TIMEOUT, &kept), Some(1));
+        assert_eq!(counts.entries.len(), 2);
+        assert!(counts.entries.contains_key(&kept[0].0));
+    }
+
+    #[test]
+    fn tallies_record_runs_of_pixels() {
+        let (first, second) = (
+            Arc::new(Mutex::new(Leaderboard::default().with_sources(true))),
+            Arc::new(Mutex::new(Leaderboard::default())),
+        );
+        let (source, other) = (address("192.0.2.1"), address("192.0.2.2"));
+        let mut tally = Tally::default();
+        tally.add(&first, source);
+        tally.add(&first, source);
+        assert_eq!(first.lock().total(), 0);
+        tally.add(&first, other);
+        assert_eq!(first.lock().pixels(source), 2);
+        tally.add(&second, other);
+        assert_eq!(first.lock().pixels(other), 1);
+        drop(tally);
+        assert_eq!(second.lock().total(), 1);
+        assert_eq!(first.lock().total(), 3);
+    }
+}
@@ -0,0 +1,129 @@
+//! Listen bindings, which restrict ICMP listeners to an address or a network interface,
+//! for hosts with multiple networks facing the event.
+
+use std::{fmt::Display, io, net::IpAddr, str::FromStr};
+
+use anyhow::{Context, Result};
+use log::info;
+use pingxelflut::icmp::{IcmpListener, ShutdownHandle};
+
+/// Longest network interface name that Linux accepts, without the terminating null byte.
+const MAX_INTERFACE_NAME_LENGTH: usize = 15;
+
+/// Where ICMP listeners receive packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// Only packets sent to this address, which also selects the IP version.
+    Address(IpAddr),
+    /// Only packets arriving on this network interface, over both IPv4 and IPv6.
+    Interface(String),
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    /// Parse an address like `192.0.2.1` or `2001:db8::1`, or an interface name like `eth0`.
+    fn from_str(text: &str) -> Result<Self, String> {
+        if let Ok(address) = text.parse() {
+            return Ok(Self::Address(address));
+        }
+        let is_interface_name = !text.is_empty()
+            && text.len() <= MAX_INTERFACE_NAME_LENGTH
+            && !text.contains(|c: char| c.is_whitespace() || c == '/' || c == ':');
+        if is_interface_name {
+            Ok(Self::Interface(text.to_string()))
+        } else {
+            Err(format!(
+                "must be an IP address or a network interface name: {text}"
+            ))
+        }
+    }
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Address(address) => address.fmt(f),
+            Self::Interface(interface) => interface.fmt(f),
+        }
+    }
+}
+
+impl Binding {
+    /// The listeners for this binding. An address only selects one IP version, an interface receives both.
+    fn listeners(&self) -> Vec<Listener> {
+        let versions: &[bool] = match self {
+            Self::Address(address) => &[address.is_ipv4()][..],
+            Self::Interface(_) => &[true, false],
+        };
+        versions
+            .iter()
+            .map(|&is_ipv4| Listener {
+                binding: Some(self.clone()),
+                is_ipv4,
+            })
+            .collect()
+    }
+}
+
+/// One ICMP listener, which can be opened again after it failed.
+#[derive(Debug, Clone)]
+pub struct Listener {
+    /// Where the listener receives packets, or `None` for all addresses.
+    binding: Option<Binding>,
+    pub is_ipv4: bool,
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let version = if self.is_ipv4 { "IPv4" } else { "IPv6" };
+        match &self.binding {
+            Some(binding) => write!(f, "{version} ICMP listener on {binding}"),
+            None => write!(f, "{version} ICMP listener"),
+        }
+    }
+}
+
+impl Listener {
+    /// The listeners for all bindings. Without any bindings, one IPv4 and one IPv6 listener receive packets on all addresses.
+    pub fn all(bindings: &[Binding]) -> Vec<Self> {
+        if bindings.is_empty() {
+            [true, false]
+                .into_iter()
+                .map(|is_ipv4| Self {
+                    binding: None,
+                    is_ipv4,
+                })
+                .collect()
+        } else {
+            bindings.iter().flat_map(Binding::listeners).collect()
+        }
+    }
+
+    /// Opens the listener’s socket.
+    pub fn open(&self, shutdown: &ShutdownHandle) -> Result<IcmpListener> {
+        let listener = match &self.binding {
+            None => IcmpListener::new(self.is_ipv4),
+            Some(Binding::Address(address)) => IcmpListener::bound_to_address(*address),
+            Some(Binding::Interface(interface)) => interface_listener(self.is_ipv4, interface),
+        }
+        .with_context(|| format!("could not open {self}"))?;
+        if self.binding.is_some() {
+            info!("{} opened", self);
+        }
+        Ok(listener.with_shutdown_handle(shutdown.clone()))
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+fn interface_listener(is_ipv4: bool, interface: &str) -> io::Result<IcmpListener> {
+    IcmpListener::bound_to_interface(is_ipv4, interface)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+fn interface_listener(_is_ipv4: bool, _interface: &str) -> io::Result<IcmpListener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "listening on a network interface is only supported on Linux",
+    ))
+}
@@ -0,0 +1,187 @@
+//! Reservations of canvas regions by participants, for cooperative events where clients agree not to draw over
+//! each other.
+//!
+//! Every source address holds at most one reservation at a time, which it renews by locking again before the lease
+//! runs out. While a reservation lasts, pixels from other sources inside its region are ignored.
+
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::RwLock;
+
+/// A rectangle on a canvas, as position and size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Region {
+    fn contains(&self, x: u16, y: u16) -> bool {
+        (self.x..self.x.saturating_add(self.width)).contains(&x)
+            && (self.y..self.y.saturating_add(self.height)).contains(&y)
+    }
+
+    fn overlaps(&self, other: &Region) -> bool {
+        u32::from(self.x) < u32::from(other.x) + u32::from(other.width)
+            && u32::from(other.x) < u32::from(self.x) + u32::from(self.width)
+            && u32::from(self.y) < u32::from(other.y) + u32::from(other.height)
+            && u32::from(other.y) < u32::from(self.y) + u32::from(self.height)
+    }
+
+    fn area(&self) -> u32 {
+        u32::from(self.width) * u32::from(self.height)
+    }
+}
+
+#[derive(Debug)]
+struct Reservation {
+    source: IpAddr,
+    canvas: usize,
+    region: Region,
+    expires: Instant,
+}
+
+/// The reservations of all sources.
+#[derive(Debug)]
+pub struct Locks {
+    /// Longest lease that is granted, which is zero if no reservations are accepted.
+    max_lease: Duration,
+    /// Largest region in pixels that can be reserved.
+    max_area: u32,
+    /// Reservations, including expired ones until the next reservation is made.
+    reservations: RwLock<Vec<Reservation>>,
+    /// Whether there are any reservations, so that pixels don’t need to look at them while there are none.
+    any: AtomicBool,
+}
+
+impl Locks {
+    pub fn new(max_lease: Duration, max_area: u32) -> Self {
+        Self {
+            max_lease,
+            max_area,
+            reservations: RwLock::default(),
+            any: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether reservations are accepted at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.max_lease.is_zero()
+    }
+
+    /// Reserves the region for the source, replacing its previous reservation, for at most the longest lease.
+    /// Returns the granted lease, which is zero if the region is too large or overlaps the reservation of another source.
+    /// The previous reservation is released even then.
+    pub fn lock(&self, source: IpAddr, canvas: usize, region: Region, lease: Duration) -> Duration {
+        let lease = lease.min(self.max_lease);
+        let now = Instant::now();
+        let mut reservations = self.reservations.write();
+        reservations
+            .retain(|reservation| reservation.expires > now && reservation.source != source);
+        let refused = lease.is_zero()
+            || region.area() > self.max_area
+            || reservations.iter().any(|reservation| {
+                reservation.canvas == canvas && reservation.region.overlaps(&region)
+            });
+        if !refused {
+            reservations.push(Reservation {
+                source,
+                canvas,
+                region,
+                expires: now + lease,
+            });
+        }
+        self.any.store(!reservations.is_empty(), Ordering::Relaxed);
+        if refused {
+            Duration::ZERO
+        } else {
+            lease
+        }
+    }
+
+    /// Releases the reservation of the source, if it has one.
+    pub fn unlock(&self, source: IpAddr) {
+        let mut reservations = self.reservations.write();
+        reservations.retain(|reservation| reservation.source != source);
+        self.any.store(!reservations.is_empty(), Ordering::Relaxed);
+    }
+
+    /// Returns whether the source may set the pixel, which it may unless another source reserved it.
+    pub fn allows(&self, source: IpAddr, canvas: usize, x: u16, y: u16) -> bool {
+        if !self.any.load(Ordering::Relaxed) {
+            return true;
+        }
+        let now = Instant::now();
+        !self.reservations.read().iter().any(|reservation| {
+            reservation.source != source
+                && reservation.canvas == canvas
+                && reservation.expires > now
+                && reservation.region.contains(x, y)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEASE: Duration = Duration::from_secs(30);
+
+    fn region(x: u16, y: u16, width: u16, height: u16) -> Region {
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn reserved_pixels_are_only_allowed_for_their_source() {
+        let locks = Locks::new(LEASE, 10_000);
+        let (owner, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert_eq!(locks.lock(owner, 0, region(10, 10, 20, 20), LEASE), LEASE);
+        assert!(locks.allows(owner, 0, 15, 15));
+        assert!(!locks.allows(other, 0, 15, 15));
+        assert!(locks.allows(other, 0, 30, 15));
+        assert!(locks.allows(other, 1, 15, 15));
+        locks.unlock(owner);
+        assert!(locks.allows(other, 0, 15, 15));
+    }
+
+    #[test]
+    fn overlapping_and_large_regions_are_refused() {
+        let locks = Locks::new(LEASE, 1000);
+        let (owner, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert_eq!(locks.lock(owner, 0, region(0, 0, 20, 20), LEASE), LEASE);
+        assert_eq!(
+            locks.lock(other, 0, region(19, 19, 5, 5), LEASE),
+            Duration::ZERO
+        );
+        assert_eq!(locks.lock(other, 0, region(20, 0, 5, 5), LEASE), LEASE);
+        assert_eq!(
+            locks.lock(other, 0, region(100, 100, 40, 40), LEASE),
+            Duration::ZERO
+        );
+        // Locking again moves the reservation of the source.
+        assert_eq!(locks.lock(owner, 0, region(50, 50, 5, 5), LEASE), LEASE);
+        assert!(locks.allows(other, 0, 0, 0));
+    }
+
+    #[test]
+    fn leases_are_capped_and_expire() {
+        let locks = Locks::new(Duration::from_millis(1), 1000);
+        let (owner, other) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert_eq!(
+            locks.lock(owner, 0, region(0, 0, 10, 10), LEASE),
+            Duration::from_millis(1)
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(locks.allows(other, 0, 5, 5));
+        assert!(!Locks::new(Duration::ZERO, 1000).is_enabled());
+    }
+}
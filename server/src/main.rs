@@ -2,46 +2,640 @@
 #![forbid(unsafe_code)]
 #![allow(clippy::single_match)]
 
+mod access;
+mod activity;
+mod admin;
+mod attract;
+#[cfg(target_os = "linux")]
+mod bench;
 mod canvas;
+mod chaos;
+mod clients;
+mod compositor;
+mod config;
+mod history;
+mod leaderboard;
+mod listen;
+mod locks;
+mod mirror;
+mod moderation;
+mod overlay;
+mod preview;
+mod priority;
+mod ratelimit;
+mod renderer;
+#[cfg(target_os = "linux")]
+mod shared_memory;
+mod snapshot;
+mod stats_window;
+mod stream;
+mod supervisor;
+mod systemd;
+mod teams;
+mod testcard;
+mod tui;
+mod webhook;
 mod window;
 
 use std::{
-    net::{IpAddr, SocketAddr},
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr, UdpSocket},
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
+    time::Duration,
 };
 
-use anyhow::Result;
-use canvas::Canvas;
+use access::{parse_prefix, AccessList};
+use activity::Activity;
+use anyhow::{bail, Context, Result};
+use attract::AttractContent;
+use canvas::{
+    parse_protected_region, Background, BlendMode, Canvas, Ingest, OverflowPolicy, ProtectedRegion,
+};
+use chaos::{Chaos, Fate};
+use clap::Parser;
+use clients::{ClientStats, ClientTally};
+use compositor::Compositing;
 use etherparse::{Icmpv4Type, Icmpv6Slice, Icmpv6Type, SlicedPacket, TransportSlice};
-use futures::{Future, StreamExt};
-use log::{error, warn};
+use futures::Future;
+use ipnet::IpNet;
+use leaderboard::{Anonymization, Tally};
+use listen::{Binding, Listener};
+use locks::{Locks, Region};
+use log::{error, info, warn};
+use mirror::Mirror;
+use moderation::CommandModerator;
+use overlay::Placement;
+use parking_lot::RwLock;
 use pingxelflut::{
-    format::Packet,
-    icmp::{EchoDirection, Icmp, IcmpListener},
+    format::{Capabilities, Color, Extensions, Packet, Palette},
+    icmp::{EchoDirection, Icmp, IcmpListener, ShutdownHandle},
 };
-use window::App;
+use ratelimit::RateLimiter;
+use renderer::ScalingFilter;
+use stream::{Encoder, StreamSettings};
+use systemd::Liveness;
+use teams::Team;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use webhook::{Event, Webhook, Webhooks};
+use window::{App, AppEvent, DisplayMode};
 use winit::event_loop::EventLoop;
 
-const WIDTH: u16 = 1920;
-const HEIGHT: u16 = 1080;
+/// A reasonably performant Pingxelflut server.
+#[derive(Clone, Parser, Debug)]
+struct Arguments {
+    /// TOML file providing defaults for these options, keyed by their long names.
+    /// Options given on the command line take precedence.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Size of the canvas. If given multiple times, one canvas is created for each size,
+    /// and the echo identifier of each packet selects the canvas, starting from 0.
+    /// Packets with identifiers beyond the last canvas draw on the first one.
+    #[arg(long = "canvas", value_name = "WIDTHxHEIGHT", value_parser = parse_size, default_value = "1920x1080")]
+    canvases: Vec<(u16, u16)>,
+    /// Only show this much of each canvas, for canvases larger than the display.
+    /// Defaults to the entire canvas.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size)]
+    viewport: Option<(u16, u16)>,
+    /// Position of the top left corner of the viewport on the canvas.
+    #[arg(long, value_name = "X,Y", value_parser = parse_pair::<u16>, default_value = "0,0")]
+    viewport_position: (u16, u16),
+    /// Scroll the viewport across the canvas with this speed in pixels per second, bouncing off the canvas edges.
+    #[arg(long, value_name = "X,Y", value_parser = parse_pair::<f32>, default_value = "0,0", allow_hyphen_values = true)]
+    viewport_scroll: (f32, f32),
+    /// How the canvas windows are shown. F11 toggles borderless fullscreen at runtime.
+    #[arg(long, value_enum, default_value_t = DisplayMode::default())]
+    display_mode: DisplayMode,
+    /// Index of the monitor to show the canvas on. Give this once per canvas to place multiple canvases.
+    /// Canvases without a monitor are shown on the primary monitor.
+    #[arg(long = "monitor", value_name = "INDEX")]
+    monitors: Vec<usize>,
+    /// Redraw the canvas windows at most this many times per second, such as 30 for projectors.
+    /// By default, windows are redrawn as often as the display allows.
+    #[arg(long, value_name = "FPS")]
+    max_fps: Option<NonZeroU32>,
+    /// Present frames as soon as they are rendered instead of waiting for the display refresh, which may cause tearing.
+    #[arg(long)]
+    no_vsync: bool,
+    /// How the canvas is filtered when it is scaled to the window size.
+    /// The aspect ratio is always preserved, with the background color filling the remaining space.
+    #[arg(long, value_enum, default_value_t = ScalingFilter::default())]
+    scaling_filter: ScalingFilter,
+    /// Gamma applied when the canvas is shown. Values above 1 brighten dark colors, for example on projectors that wash them out.
+    #[arg(long, value_name = "GAMMA", value_parser = parse_gamma, default_value = "1")]
+    gamma: f32,
+    /// Factors for the red, green and blue channels when the canvas is shown, to compensate for displays with a color cast.
+    #[arg(long, value_name = "R,G,B", value_parser = parse_gain, default_value = "1,1,1")]
+    color_gain: [f32; 3],
+    /// How pixels with an alpha value below 255 are applied to the canvas.
+    #[arg(long, value_enum, default_value_t = BlendMode::default())]
+    blend_mode: BlendMode,
+    /// Whether queued pixels are applied on the CPU or with a compute shader on the GPU.
+    /// GPU compositing handles higher pixel rates, but get pixel requests and snapshots see the canvas up to a second late.
+    #[arg(long, value_enum, default_value_t = Compositing::default())]
+    compositing: Compositing,
+    /// How received pixels get onto the canvas. Direct ingest writes them without queueing or locking,
+    /// for the highest pixel rates, but the options limiting the queue don’t apply.
+    #[arg(long, value_enum, default_value_t = Ingest::default())]
+    ingest: Ingest,
+    /// Maximum number of pixels applied to each canvas per frame, so that floods don’t stall rendering.
+    /// Further pixels are applied in later frames. 0 applies all queued pixels every frame,
+    /// unless `--low-priority` or `--share-threshold` is given, which apply up to 1048576 pixels per frame then.
+    #[arg(long, value_name = "PIXELS", default_value = "0")]
+    max_pixels_per_frame: usize,
+    /// Maximum number of pixels queued for each canvas, which bounds memory use when pixels arrive faster than they are rendered.
+    #[arg(long, value_name = "PIXELS", default_value = "4194304", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    queue_capacity: usize,
+    /// Which pixels are discarded when the queue is full.
+    #[arg(long, value_enum, default_value_t = OverflowPolicy::default())]
+    overflow_policy: OverflowPolicy,
+    /// Number of threads decoding packets per IP version. Defaults to the number of CPU cores.
+    #[arg(long, value_name = "THREADS", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    decode_workers: Option<usize>,
+    /// Largest rectangle in pixels that one fill rectangle packet may cover. Larger rectangles are ignored,
+    /// and 0 doesn't accept fill rectangles at all.
+    #[arg(long, value_name = "PIXELS", default_value = "16384")]
+    max_fill_area: u32,
+    /// Colors of the palette that indexed blit packets refer to, as comma-separated hexadecimal RRGGBB,
+    /// with at most 256 colors. Without a palette, indexed blits aren't accepted.
+    #[arg(long, value_name = "RRGGBB,...", value_parser = parse_color, value_delimiter = ',')]
+    palette: Vec<Color>,
+    /// Longest time in seconds for which a participant may reserve a region with lock region packets, during which
+    /// pixels from others inside it are ignored. Participants renew their reservation before it runs out.
+    /// 0 doesn't accept reservations.
+    #[arg(long, value_name = "SECONDS", default_value = "0")]
+    max_lock_lease: u16,
+    /// Largest region in pixels that a participant may reserve.
+    #[arg(long, value_name = "PIXELS", default_value = "65536")]
+    max_lock_area: u32,
+    /// Maximum number of pixel read requests answered per second and source address.
+    #[arg(long, value_name = "REQUESTS", default_value = "1000")]
+    get_pixel_rate: u32,
+    /// Maximum number of size and capabilities requests answered per second, source address and canvas.
+    /// Further requests within the same second are dropped, since the client already got the answer.
+    #[arg(long, value_name = "REQUESTS", default_value = "5")]
+    size_request_rate: u32,
+    /// Fade the canvas towards its background, halving the distance of untouched pixels to it in this many seconds.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    fade_half_life: Option<Duration>,
+    /// Color of the empty canvas, as hexadecimal RRGGBB.
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_color, default_value = "000000")]
+    background_color: Color,
+    /// Image shown centered on the empty canvas, such as a logo or instructions for participants.
+    /// It is shown again whenever the canvas is cleared.
+    #[arg(long, value_name = "PATH")]
+    background_image: Option<PathBuf>,
+    /// PNG image, such as an event logo or rules, shown on top of the canvas.
+    /// Participants can’t draw over it, and it doesn’t appear in snapshots.
+    #[arg(long, value_name = "PATH")]
+    watermark: Option<PathBuf>,
+    /// Where the watermark is shown.
+    #[arg(long, value_enum, default_value_t = Placement::default())]
+    watermark_position: Placement,
+    /// Show an attract animation once participants haven’t sent pixels for this many seconds.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds)]
+    attract_after: Option<Duration>,
+    /// Image that bounces across the canvas in the attract animation.
+    #[arg(long, value_name = "PATH")]
+    attract_logo: Option<PathBuf>,
+    /// Instructions that scroll by in the attract animation. By default, they mention the server’s address.
+    #[arg(long, value_name = "TEXT")]
+    attract_text: Option<String>,
+    /// Draw a test card with color bars and a labeled coordinate grid onto every canvas at startup.
+    /// Pressing T draws it again.
+    #[arg(long)]
+    test_card: bool,
+    /// Open a window that plots packets and applied pixels per second, the queue depth and kernel drops over time.
+    #[arg(long)]
+    stats_window: bool,
+    /// Show a leaderboard of the N sources that applied the most pixels. 0 disables the leaderboard.
+    #[arg(long, value_name = "N", default_value = "0")]
+    leaderboard: usize,
+    /// How source addresses are shown on the leaderboard.
+    #[arg(long, value_enum, default_value_t = Anonymization::default())]
+    leaderboard_anonymization: Anonymization,
+    /// While pixels queue up, apply the pixels of sources within this prefix last, for example to favor remote participants over LAN hosts.
+    /// May be given multiple times.
+    #[arg(long, value_name = "PREFIX", value_parser = parse_prefix)]
+    low_priority: Vec<IpNet>,
+    /// While pixels queue up, apply the pixels of sources last that had more than this percentage of the applied pixels in the last second.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    share_threshold: Option<f64>,
+    /// Play a territory-control game between teams, given as NAME=PREFIX[,PREFIX…]. May be given multiple times.
+    /// Every pixel belongs to the team of the source that set it last, and a scoreboard shows how much of the canvas each team owns.
+    #[arg(long, value_name = "TEAM")]
+    team: Vec<Team>,
+    /// Where the team scoreboard is shown.
+    #[arg(long, value_enum, default_value_t = Placement::TopLeft)]
+    scoreboard_position: Placement,
+    /// For testing clients: drop this percentage of incoming packets.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    chaos_drop: Option<f64>,
+    /// For testing clients: delay this percentage of incoming packets, by up to `--chaos-max-delay`.
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    chaos_delay: Option<f64>,
+    /// Longest delay of packets delayed by `--chaos-delay`. Delays are spread evenly up to this.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "0.5")]
+    chaos_max_delay: Duration,
+    /// Only handle packets from sources within this prefix (CIDR notation or a single address).
+    /// May be given multiple times. If not given, all sources are allowed.
+    #[arg(long, value_name = "PREFIX", value_parser = parse_prefix)]
+    allow: Vec<IpNet>,
+    /// Ignore packets from sources within this prefix (CIDR notation or a single address).
+    /// May be given multiple times. Takes precedence over `--allow`.
+    #[arg(long, value_name = "PREFIX", value_parser = parse_prefix)]
+    deny: Vec<IpNet>,
+    /// Rectangle that participants can’t draw on, such as a rules banner, as X,Y,WIDTHxHEIGHT.
+    /// Prefix it with a canvas index like 1:X,Y,WIDTHxHEIGHT to only protect one canvas. May be given multiple times.
+    #[arg(long, value_name = "REGION", value_parser = parse_protected_region)]
+    protect: Vec<ProtectedRegion>,
+    /// Only listen for ICMP packets on this IP address or network interface, such as 192.0.2.1 or eth0.
+    /// An interface receives both IPv4 and IPv6 packets. May be given multiple times, all bindings feed the same canvases.
+    /// Listens on all addresses if no binding is given.
+    #[arg(long, value_name = "BINDING")]
+    listen: Vec<Binding>,
+    /// Also accept packets as UDP datagrams on this address, for networks where ICMP is filtered.
+    /// UDP packets always draw on the first canvas. May be given multiple times.
+    #[arg(long, value_name = "ADDRESS")]
+    udp_address: Vec<SocketAddr>,
+    /// Only accept packets whose payload starts with these bytes, given in hexadecimal like 50584c46.
+    /// The prefix is removed before decoding, and responses are sent without it.
+    /// This ignores stray pings from scanners when the server runs on a public address.
+    #[arg(long, value_name = "HEX", value_parser = parse_magic)]
+    magic: Option<Box<[u8]>>,
+    /// Address to serve the admin HTTP API on. The admin API is disabled if no address is given.
+    #[arg(long, value_name = "ADDRESS", requires = "admin_token")]
+    admin_address: Option<SocketAddr>,
+    /// Token that admin API requests have to provide as a bearer token.
+    #[arg(long, value_name = "TOKEN")]
+    admin_token: Option<String>,
+    /// Periodically append per-source activity records to this JSON-lines file.
+    #[arg(long, value_name = "PATH")]
+    activity_log: Option<PathBuf>,
+    /// How often activity records are written.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "60")]
+    activity_log_interval: Duration,
+    /// Size in bytes after which the activity log is rotated. Five rotated files are kept.
+    #[arg(long, value_name = "BYTES", default_value = "104857600")]
+    activity_log_max_size: u64,
+    /// Collect statistics per source address and echo identifier, to tell apart clients behind one address.
+    /// They are available from the admin API.
+    #[arg(long)]
+    client_stats: bool,
+    /// Command that reviews the parts of the canvas that participants drew on, such as an external classifier.
+    /// It receives the canvas index and the tile’s X, Y, width and height as arguments and a downsampled PNG image
+    /// of the tile on standard input, and prints `keep`, `blank` or `freeze`.
+    #[arg(long, value_name = "PATH")]
+    moderation_command: Option<PathBuf>,
+    /// How often changed parts of the canvas are reviewed by the moderation command.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    moderation_interval: Duration,
+    /// Forward the pixels that participants set to the Pingxelflut server at this address, so that it mirrors the canvases.
+    /// May be given multiple times.
+    #[arg(long, value_name = "ADDRESS")]
+    mirror: Vec<IpAddr>,
+    /// How many pixels per second are forwarded to each mirror at most.
+    #[arg(long, value_name = "PIXELS", default_value = "10000")]
+    mirror_rate: u32,
+    /// Send notifications about the server starting, pixel and source milestones, and saved snapshots to this URL.
+    /// Prefix it with `discord=` or `slack=` to send chat messages to such webhooks, otherwise JSON objects are sent.
+    /// May be given multiple times.
+    #[arg(long, value_name = "URL")]
+    webhook: Vec<Webhook>,
+    /// Notify webhooks every time this many more pixels have been applied. 0 disables pixel milestones.
+    #[arg(long, value_name = "PIXELS", default_value = "1000000")]
+    pixel_milestone: u64,
+    /// Record keyframes of the canvases into this directory, which can be viewed again in the history view.
+    #[arg(long, value_name = "DIRECTORY")]
+    history_directory: Option<PathBuf>,
+    /// How often keyframes are recorded for the history.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "30")]
+    history_interval: Duration,
+    /// Stream the canvas live to this RTMP or SRT URL, such as `rtmp://live.twitch.tv/app/KEY`. Needs FFmpeg.
+    #[arg(long, value_name = "URL")]
+    stream_url: Option<String>,
+    /// Index of the canvas that is streamed.
+    #[arg(long, value_name = "INDEX", default_value = "0")]
+    stream_canvas: usize,
+    /// Frame rate of the live stream.
+    #[arg(long, value_name = "FPS", default_value = "30", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    stream_fps: u32,
+    /// Video bitrate of the live stream in kilobits per second.
+    #[arg(long, value_name = "KBPS", default_value = "6000")]
+    stream_bitrate: u32,
+    /// H.264 encoder for the live stream.
+    #[arg(long, value_enum, default_value_t = Encoder::default())]
+    stream_encoder: Encoder,
+    /// FFmpeg executable used for the live stream.
+    #[arg(long, value_name = "PATH", default_value = "ffmpeg")]
+    ffmpeg: PathBuf,
+    /// Export every canvas into a shared memory segment at `/dev/shm/NAME-INDEX`, which other processes on this host can map.
+    /// Only available on Linux.
+    #[arg(long, value_name = "NAME")]
+    shared_memory: Option<String>,
+    /// How often the shared memory segments are updated.
+    #[arg(long, value_name = "FPS", default_value = "30", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    shared_memory_fps: u32,
+    /// Render the first canvas to the terminal with colored half-block characters instead of opening windows,
+    /// for machines without a display or GPU.
+    #[arg(long, conflicts_with = "bench_ingest")]
+    tui: bool,
+    /// How many frames per second are rendered to the terminal.
+    #[arg(long, value_name = "FPS", default_value = "10", value_parser = clap::builder::RangedU64ValueParser::<u32>::new().range(1..))]
+    tui_fps: u32,
+    /// Measure how many packets the server handles, without opening windows, and print the results.
+    /// The benchmark sends its own pings to the loopback address, and also counts pings from other hosts.
+    /// Only available on Linux.
+    #[arg(long)]
+    bench_ingest: bool,
+    /// How long the ingest benchmark runs.
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, default_value = "10")]
+    bench_duration: Duration,
+    /// Number of threads sending pings during the ingest benchmark. With 0, only pings from other hosts are measured.
+    #[arg(long, value_name = "N", default_value = "1")]
+    bench_senders: usize,
+    /// Directory that canvas snapshots are saved to.
+    #[arg(long, value_name = "DIRECTORY", default_value = ".")]
+    snapshot_directory: PathBuf,
+}
+
+/// Parse a size like `1920x1080`.
+fn parse_size(text: &str) -> Result<(u16, u16), String> {
+    let (width, height) = text
+        .split_once('x')
+        .ok_or_else(|| format!("size must be given as WIDTHxHEIGHT: {text}"))?;
+    let width = width
+        .parse()
+        .map_err(|_| format!("invalid width: {width}"))?;
+    let height = height
+        .parse()
+        .map_err(|_| format!("invalid height: {height}"))?;
+    if width == 0 || height == 0 {
+        return Err(format!("size must not be empty: {text}"));
+    }
+    Ok((width, height))
+}
+
+/// Parse a pair of numbers like `10,20`.
+fn parse_pair<T: FromStr>(text: &str) -> Result<(T, T), String> {
+    let (first, second) = text
+        .split_once(',')
+        .ok_or_else(|| format!("must be given as X,Y: {text}"))?;
+    let parse = |number: &str| {
+        number
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid number: {number}"))
+    };
+    Ok((parse(first)?, parse(second)?))
+}
+
+/// Parse a positive gamma value.
+fn parse_gamma(text: &str) -> Result<f32, String> {
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|&gamma: &f32| gamma > 0.0 && gamma.is_finite())
+        .ok_or_else(|| format!("gamma must be a positive number: {text}"))
+}
+
+/// Parse channel gains like `1.0,0.9,1.1`.
+fn parse_gain(text: &str) -> Result<[f32; 3], String> {
+    let gains: Vec<f32> = text
+        .split(',')
+        .map(|gain| {
+            gain.trim()
+                .parse()
+                .ok()
+                .filter(|&gain: &f32| gain >= 0.0 && gain.is_finite())
+                .ok_or_else(|| format!("invalid gain: {gain}"))
+        })
+        .collect::<Result<_, _>>()?;
+    gains
+        .try_into()
+        .map_err(|_| format!("gains must be given as R,G,B: {text}"))
+}
+
+/// Parse an opaque color like `ff8000`.
+fn parse_color(text: &str) -> Result<Color, String> {
+    let text = text.strip_prefix('#').unwrap_or(text);
+    let value = u32::from_str_radix(text, 16)
+        .ok()
+        .filter(|_| text.len() == 6)
+        .ok_or_else(|| format!("color must be given as hexadecimal RRGGBB: {text}"))?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok(Color::new(r, g, b, 0xff))
+}
+
+/// Parse a positive, possibly fractional number of seconds.
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    let seconds: f64 = text
+        .parse()
+        .map_err(|_| format!("invalid number: {text}"))?;
+    if !(seconds.is_finite() && seconds > 0.) {
+        return Err(format!("must be a positive number of seconds: {text}"));
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parse a non-empty sequence of bytes given in hexadecimal, like `50584c46`.
+fn parse_magic(text: &str) -> Result<Box<[u8]>, String> {
+    let error = || format!("must be a non-empty sequence of hexadecimal bytes: {text}");
+    if text.is_empty() || text.len() % 2 != 0 || !text.is_ascii() {
+        return Err(error());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).map_err(|_| error()))
+        .collect()
+}
+
+/// Parse a percentage from 0 to 100.
+fn parse_percentage(text: &str) -> Result<f64, String> {
+    text.parse()
+        .ok()
+        .filter(|percentage| (0.0..=100.0).contains(percentage))
+        .ok_or_else(|| format!("must be a percentage from 0 to 100: {text}"))
+}
+
+/// Server state shared between the packet handlers and the admin API.
+#[derive(Debug, Clone)]
+struct SharedState {
+    /// The current configuration, which is replaced when reloading.
+    settings: Arc<RwLock<Arguments>>,
+    /// Stops all packet listeners.
+    shutdown: ShutdownHandle,
+    liveness: Arc<Liveness>,
+    /// Number of packets received from allowed sources.
+    received_packets: Arc<AtomicU64>,
+    /// Per-source activity, only collected if the activity log is enabled.
+    activity: Option<Arc<Activity>>,
+    /// Per-client statistics, only collected if enabled.
+    client_stats: Option<Arc<ClientStats>>,
+    canvases: Arc<[Canvas]>,
+    access: Arc<AccessList>,
+    get_pixel_limiter: Arc<RateLimiter>,
+    /// Limits size responses per source and canvas, since every response is a packet sent on the request of a possibly spoofed address.
+    size_request_limiter: Arc<RateLimiter<(IpAddr, usize)>>,
+    webhooks: Arc<Webhooks>,
+    /// Forwards set pixels to other servers, if any are configured.
+    mirror: Option<Arc<Mirror>>,
+    /// Drops and delays packets in chaos mode.
+    chaos: Option<Arc<Chaos>>,
+    /// Prefix that payloads must start with to be decoded, which is empty if any payload is accepted.
+    magic: Arc<[u8]>,
+    /// Most pixels a fill rectangle packet may cover, or 0 if they aren't accepted.
+    max_fill_area: u32,
+    /// Encoded colors of the palette for indexed blits, which is empty if they aren't accepted.
+    palette: Arc<[u8]>,
+    /// Regions reserved by participants.
+    locks: Arc<Locks>,
+}
+
+impl SharedState {
+    /// Returns the canvas that a packet with the given echo identifier is meant for.
+    fn canvas_for(&self, identifier: u16) -> &Canvas {
+        &self.canvases[self.canvas_index(identifier)]
+    }
+
+    /// Returns the index of the canvas that a packet with the given echo identifier is meant for.
+    /// Identifiers that belong to no canvas, including all of them with only one canvas, select the first canvas,
+    /// so that clients which don’t know about multiple canvases still draw somewhere.
+    fn canvas_index(&self, identifier: u16) -> usize {
+        let index = usize::from(identifier);
+        if index < self.canvases.len() {
+            index
+        } else {
+            0
+        }
+    }
+
+    /// Reads the configuration again and applies the settings that can change at runtime:
+    /// rate limits, allowed and denied prefixes, protected regions, overlays and the snapshot directory.
+    /// Prefixes and regions changed through the admin API are replaced by the configured ones.
+    fn reload(&self) -> Result<()> {
+        // A broken configuration keeps the current settings instead of ending the server.
+        let arguments = config::parse_arguments()?;
+        self.get_pixel_limiter.set_limit(arguments.get_pixel_rate);
+        self.size_request_limiter
+            .set_limit(arguments.size_request_rate);
+        self.access
+            .replace(arguments.allow.clone(), arguments.deny.clone());
+        for (index, canvas) in self.canvases.iter().enumerate() {
+            canvas.set_protected_regions(ProtectedRegion::for_canvas(&arguments.protect, index));
+        }
+        *self.settings.write() = arguments;
+        info!("configuration reloaded");
+        Ok(())
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
+    let arguments = match config::parse_arguments() {
+        Ok(arguments) => arguments,
+        // Let clap print help and usage errors in its own format.
+        Err(why) => match why.downcast::<clap::Error>() {
+            Ok(clap_error) => clap_error.exit(),
+            Err(why) => return Err(why),
+        },
+    };
+
+    if arguments.palette.len() > Palette::MAX_COLORS {
+        bail!("palettes have at most {} colors", Palette::MAX_COLORS);
+    }
+    if arguments.team.len() >= usize::from(u8::MAX) {
+        bail!("at most {} teams are supported", u8::MAX - 1);
+    }
+    if cfg!(not(target_os = "linux")) && arguments.shared_memory.is_some() {
+        bail!("shared memory export is only supported on Linux");
+    }
+
+    let background = Background {
+        color: arguments.background_color,
+        image: arguments
+            .background_image
+            .as_ref()
+            .map(|path| {
+                image::open(path)
+                    .with_context(|| format!("could not load background image {}", path.display()))
+            })
+            .transpose()?
+            .map(|image| image.into_rgba8()),
+    };
+
+    if arguments.bench_ingest {
+        return run_benchmark(arguments, background).await;
+    }
+    if arguments.tui {
+        return tui::run(arguments, background).await;
+    }
+
+    let watermark = arguments
+        .watermark
+        .as_ref()
+        .map(|path| {
+            image::open(path)
+                .with_context(|| format!("could not load watermark {}", path.display()))
+        })
+        .transpose()?
+        .map(|image| image.into_rgba8());
+
+    let attract_content = match arguments.attract_after {
+        Some(_) => Some(AttractContent {
+            logo: arguments
+                .attract_logo
+                .as_ref()
+                .map(|path| {
+                    image::open(path)
+                        .with_context(|| format!("could not load attract logo {}", path.display()))
+                })
+                .transpose()?
+                .map(|image| image.into_rgba8()),
+            text: arguments
+                .attract_text
+                .clone()
+                .unwrap_or_else(attract::default_text),
+        }),
+        None => None,
+    };
 
-    let event_loop = EventLoop::new().unwrap();
-    let mut app = App::new(WIDTH, HEIGHT);
+    let event_loop = EventLoop::with_user_event().build().unwrap();
+    let proxy = event_loop.create_proxy();
+    tokio::spawn(async move {
+        match wait_for_termination().await {
+            Ok(()) => {
+                info!("shutting down");
+                let _ = proxy.send_event(AppEvent::Shutdown);
+            }
+            Err(why) => error!("could not listen for termination signals: {}", why),
+        }
+    });
+
+    let mut app = App::new(arguments, background, watermark, attract_content);
     event_loop.run_app(&mut app)?;
     Ok(())
 }
 
-fn decode_pingxelflut_packet(
-    raw_packet: &[u8],
+fn decode_pingxelflut_packet<'a>(
+    raw_packet: &'a [u8],
     address: SocketAddr,
     is_ipv4: bool,
-) -> Option<(Packet, IpAddr)> {
+    magic: &[u8],
+) -> Option<(Packet<'a>, SocketAddr, u16)> {
     // For some reason, under IPv4 we get an IP packet, while under IPv6 we get the ICMPv6 packet directly.
-    // FIXME: this means we don’t know who sent the IPv6 packet! We just send the response to localhost.
+    // Therefore, the sender address always comes from the listener, which gets it from recvfrom.
+    // Raw sockets have no ports, but the IPv6 scope must be retained so that link-local senders can be answered.
+    let mut sender = address;
+    sender.set_port(0);
     let transport_packet = if is_ipv4 {
         let parsed_packet = SlicedPacket::from_ip(raw_packet).ok()?;
         parsed_packet.transport?
@@ -55,8 +649,8 @@ fn decode_pingxelflut_packet(
             let payload = data.payload();
             let packet_type = data.icmp_type();
             match packet_type {
-                Icmpv4Type::EchoRequest(_) => {
-                    Packet::from_bytes(payload).map(|p| (p, address.ip()))
+                Icmpv4Type::EchoRequest(echo) => {
+                    Packet::from_bytes(payload.strip_prefix(magic)?).map(|p| (p, sender, echo.id))
                 }
                 _ => None,
             }
@@ -65,8 +659,8 @@ fn decode_pingxelflut_packet(
             let payload = data.payload();
             let packet_type = data.icmp_type();
             match packet_type {
-                Icmpv6Type::EchoRequest(_) => {
-                    Packet::from_bytes(payload).map(|p| (p, address.ip()))
+                Icmpv6Type::EchoRequest(echo) => {
+                    Packet::from_bytes(payload.strip_prefix(magic)?).map(|p| (p, sender, echo.id))
                 }
                 _ => None,
             }
@@ -75,50 +669,485 @@ fn decode_pingxelflut_packet(
     }
 }
 
-async fn ip_ping_handler(canvas: Canvas, is_ipv4: bool) -> Result<()> {
-    let mut icmp4_listener = IcmpListener::new(is_ipv4)?;
-    let receive_queue = icmp4_listener.receive_queue.clone();
+/// Maximum number of packets a decode worker takes from the receive queue at once.
+const DECODE_BATCH_SIZE: usize = 256;
+/// Largest pingxelflut packet that fits into the receive buffers of all listeners.
+/// ICMP packets are received with up to 60 bytes of IPv4 header and 8 bytes of ICMP header in the 2048 byte buffer.
+const MAX_PACKET_SIZE: u16 = 2048 - 60 - 8;
 
-    thread::spawn(move || icmp4_listener.run());
+async fn ip_ping_handler(
+    state: SharedState,
+    listener: Result<IcmpListener>,
+    is_ipv4: bool,
+    workers: usize,
+) -> Result<()> {
+    let mut listener = listener?;
+    let receive_queue = listener.receive_queue.clone();
+    let _running = state.liveness.listener_started();
 
-    let stream = receive_queue.filter_map(|(data, addr)| {
-        futures::future::ready(decode_pingxelflut_packet(&data, addr, is_ipv4))
-    });
+    let listener = tokio::task::spawn_blocking(move || listener.run());
 
-    stream
-        .for_each(move |(packet, target_addr)| {
-            let mut canvas = canvas.clone();
-            tokio::spawn(async move {
-                match packet {
-                    Packet::SizeRequest => {
-                        // TODO: Figure out if the identifier is important for getting the packet delivered.
-                        let mut response =
-                            Icmp::new(SocketAddr::new(target_addr, 0), 0, EchoDirection::Reply);
-                        response.set_payload(
-                            Packet::SizeResponse {
-                                width: WIDTH,
-                                height: HEIGHT,
-                            }
-                            .to_bytes(),
-                        );
-                        let result = response.send();
-                        match result {
-                            Ok(_) => {}
-                            Err(why) => {
-                                warn!("size response error: {}", why)
-                            }
-                        }
-                    }
-                    // ignore
-                    Packet::SizeResponse { .. } => {}
-                    Packet::SetPixel { x, y, color } => {
-                        canvas.set_pixel(x, y, color);
-                    }
+    // Decoding and dispatching is cheap per packet, so it runs on dedicated threads instead of one async task per packet.
+    // Workers finish once the listener stops and closes the receive queue.
+    let workers = (0..workers).map(|index| {
+        let state = state.clone();
+        let receive_queue = receive_queue.clone();
+        supervisor::supervise(
+            format!("decode worker {index}"),
+            state.shutdown.clone(),
+            move || {
+                let state = state.clone();
+                let receive_queue = receive_queue.clone();
+                async move {
+                    tokio::task::spawn_blocking(move || {
+                        decode_worker(&state, &receive_queue, is_ipv4)
+                    })
+                    .await?;
+                    Ok(())
                 }
+            },
+        )
+    });
+    futures::future::join_all(workers).await;
+    listener.await??;
+    if !state.shutdown.is_shut_down() {
+        bail!("listener stopped unexpectedly");
+    }
+    Ok(())
+}
+
+/// What a worker counts while handling packets, which is recorded when it is flushed.
+struct Tallies {
+    pixels: Tally,
+    clients: Option<ClientTally>,
+}
+
+impl Tallies {
+    fn new(state: &SharedState) -> Self {
+        Self {
+            pixels: Tally::default(),
+            clients: state.client_stats.clone().map(ClientTally::new),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.pixels.flush();
+        if let Some(clients) = &mut self.clients {
+            clients.flush();
+        }
+    }
+}
+
+/// Decodes and handles received packets in batches until the listener stops.
+fn decode_worker(
+    state: &SharedState,
+    receive_queue: &async_channel::Receiver<(Vec<u8>, SocketAddr)>,
+    is_ipv4: bool,
+) {
+    let mut batch = Vec::with_capacity(DECODE_BATCH_SIZE);
+    let mut tallies = Tallies::new(state);
+    while let Ok(first) = receive_queue.recv_blocking() {
+        batch.push(first);
+        while batch.len() < DECODE_BATCH_SIZE {
+            match receive_queue.try_recv() {
+                Ok(raw_packet) => batch.push(raw_packet),
+                Err(_) => break,
+            }
+        }
+
+        for (data, address) in batch.drain(..) {
+            if let Some((packet, sender, identifier)) =
+                decode_pingxelflut_packet(&data, address, is_ipv4, &state.magic)
+            {
+                dispatch_packet(
+                    state,
+                    packet,
+                    data.len(),
+                    sender,
+                    identifier,
+                    &Transport::Icmp,
+                    &mut tallies,
+                );
+            }
+        }
+        tallies.flush();
+    }
+}
+
+/// The way a packet was received, which is also the way responses are sent back.
+#[derive(Clone)]
+enum Transport {
+    Icmp,
+    Udp(Arc<UdpSocket>),
+}
+
+impl Transport {
+    fn respond(&self, target: SocketAddr, identifier: u16, response: Packet) -> io::Result<()> {
+        match self {
+            Transport::Icmp => {
+                let mut icmp = Icmp::new(target, identifier, EchoDirection::Reply);
+                icmp.set_payload(response.to_bytes());
+                icmp.send().map(|_| ())
+            }
+            Transport::Udp(socket) => socket.send_to(&response.to_bytes(), target).map(|_| ()),
+        }
+    }
+}
+
+/// Handles a decoded packet, unless chaos mode drops it or handles it later.
+fn dispatch_packet(
+    state: &SharedState,
+    packet: Packet,
+    size: usize,
+    sender: SocketAddr,
+    identifier: u16,
+    transport: &Transport,
+    tallies: &mut Tallies,
+) {
+    let Some(chaos) = &state.chaos else {
+        handle_packet(state, packet, size, sender, identifier, transport, tallies);
+        return;
+    };
+    match chaos.fate() {
+        Fate::Deliver => handle_packet(state, packet, size, sender, identifier, transport, tallies),
+        Fate::Drop => {}
+        Fate::Delay(delay) => {
+            let state = state.clone();
+            let transport = transport.clone();
+            // The packet borrows from the receive buffer, so it is delayed in its encoded form.
+            let bytes = packet.to_bytes();
+            chaos.delay(delay, move || {
+                let Some(packet) = Packet::from_bytes(&bytes) else {
+                    return;
+                };
+                let mut tallies = Tallies::new(&state);
+                handle_packet(
+                    &state,
+                    packet,
+                    size,
+                    sender,
+                    identifier,
+                    &transport,
+                    &mut tallies,
+                );
+                tallies.flush();
             });
-            futures::future::ready(())
-        })
-        .await;
+        }
+    }
+}
+
+/// Handles a decoded packet. The size of the raw packet is only used for statistics.
+/// Pixels written directly to the canvas and client activity are counted in the tallies, which the caller flushes
+/// regularly.
+fn handle_packet(
+    state: &SharedState,
+    packet: Packet,
+    size: usize,
+    sender: SocketAddr,
+    identifier: u16,
+    transport: &Transport,
+    tallies: &mut Tallies,
+) {
+    if !state.access.is_allowed(sender.ip()) {
+        return;
+    }
+    state.received_packets.fetch_add(1, Ordering::Relaxed);
+    if let Some(activity) = &state.activity {
+        activity.record_packet(sender.ip(), size);
+    }
+    if let Some(clients) = &mut tallies.clients {
+        clients.add(sender.ip(), identifier, &packet, size);
+    }
+    let tally = &mut tallies.pixels;
+    let canvas = state.canvas_for(identifier);
+    match packet {
+        Packet::SizeRequest => {
+            let key = (sender.ip(), state.canvas_index(identifier));
+            if !state.size_request_limiter.check(key) {
+                return;
+            }
+            // Echo the identifier, so that clients of multi-canvas servers can tell the responses apart.
+            let result = transport.respond(
+                sender,
+                identifier,
+                Packet::SizeResponse {
+                    width: canvas.width,
+                    height: canvas.height,
+                },
+            );
+            match result {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!("size response error: {}", why)
+                }
+            }
+        }
+        Packet::CapabilitiesRequest => {
+            // Capabilities are requested as rarely as the size, so they share the limit.
+            let key = (sender.ip(), state.canvas_index(identifier));
+            if !state.size_request_limiter.check(key) {
+                return;
+            }
+            let get_pixel_rate = state.get_pixel_limiter.limit();
+            let mut extensions = Extensions::SET_PIXELS | Extensions::BLIT;
+            if get_pixel_rate > 0 {
+                extensions = extensions | Extensions::GET_PIXEL;
+            }
+            if state.max_fill_area > 0 {
+                extensions = extensions | Extensions::FILL_RECT;
+            }
+            if !state.palette.is_empty() {
+                extensions = extensions | Extensions::PALETTE;
+            }
+            if state.locks.is_enabled() {
+                extensions = extensions | Extensions::LOCK_REGION;
+            }
+            let result = transport.respond(
+                sender,
+                identifier,
+                Packet::CapabilitiesResponse(Capabilities {
+                    extensions,
+                    max_packet_size: MAX_PACKET_SIZE
+                        .saturating_sub(state.magic.len().try_into().unwrap_or(u16::MAX)),
+                    canvases: state.canvases.len() as u16,
+                    get_pixel_rate,
+                    max_fill_area: state.max_fill_area,
+                }),
+            );
+            if let Err(why) = result {
+                warn!("capabilities response error: {}", why)
+            }
+        }
+        Packet::PaletteRequest => {
+            if state.palette.is_empty() {
+                return;
+            }
+            // The palette is requested once per client like the capabilities, so it shares their limit.
+            let key = (sender.ip(), state.canvas_index(identifier));
+            if !state.size_request_limiter.check(key) {
+                return;
+            }
+            let palette = Palette::new(&state.palette).expect("the palette was checked at startup");
+            let result = transport.respond(sender, identifier, Packet::PaletteResponse(palette));
+            if let Err(why) = result {
+                warn!("palette response error: {}", why)
+            }
+        }
+        Packet::LockRegion {
+            x,
+            y,
+            width,
+            height,
+            lease,
+        } => {
+            if !state.locks.is_enabled() {
+                return;
+            }
+            // Clients renew their reservation every few seconds at most, so it shares the limit of size requests.
+            let key = (sender.ip(), state.canvas_index(identifier));
+            if !state.size_request_limiter.check(key) {
+                return;
+            }
+            let granted = state.locks.lock(
+                sender.ip(),
+                state.canvas_index(identifier),
+                Region {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                Duration::from_secs(lease.into()),
+            );
+            let result = transport.respond(
+                sender,
+                identifier,
+                Packet::LockResponse {
+                    x,
+                    y,
+                    width,
+                    height,
+                    lease: granted.as_secs() as u16,
+                },
+            );
+            if let Err(why) = result {
+                warn!("lock response error: {}", why)
+            }
+        }
+        Packet::UnlockRegion => state.locks.unlock(sender.ip()),
+        // ignore
+        Packet::SizeResponse { .. }
+        | Packet::PixelResponse { .. }
+        | Packet::CapabilitiesResponse { .. }
+        | Packet::PaletteResponse(_)
+        | Packet::LockResponse { .. } => {}
+        Packet::SetPixel { x, y, color } => {
+            set_pixel(state, identifier, x, y, color, sender.ip(), tally);
+        }
+        Packet::SetPixels(pixels) => {
+            for (x, y, color) in pixels.iter() {
+                set_pixel(state, identifier, x, y, color, sender.ip(), tally);
+            }
+        }
+        Packet::Blit { x, y, colors } => {
+            for (x, color) in (x..canvas.width).zip(colors.iter()) {
+                set_pixel(state, identifier, x, y, color, sender.ip(), tally);
+            }
+        }
+        Packet::IndexedBlit { x, y, indices } => {
+            let palette = Palette::new(&state.palette).expect("the palette was checked at startup");
+            for (x, &index) in (x..canvas.width).zip(indices) {
+                // Indices beyond the palette are ignored like pixels outside the canvas.
+                if let Some(color) = palette.get(index) {
+                    set_pixel(state, identifier, x, y, color, sender.ip(), tally);
+                }
+            }
+        }
+        Packet::FillRect {
+            x,
+            y,
+            width,
+            height,
+            color,
+        } => {
+            if u32::from(width) * u32::from(height) > state.max_fill_area {
+                return;
+            }
+            for y in y..y.saturating_add(height).min(canvas.height) {
+                for x in x..x.saturating_add(width).min(canvas.width) {
+                    set_pixel(state, identifier, x, y, color, sender.ip(), tally);
+                }
+            }
+        }
+        Packet::GetPixel { x, y } => {
+            if !state.get_pixel_limiter.check(sender.ip()) {
+                return;
+            }
+            let Some(color) = canvas.get_pixel(x, y) else {
+                return;
+            };
+            let result =
+                transport.respond(sender, identifier, Packet::PixelResponse { x, y, color });
+            match result {
+                Ok(_) => {}
+                Err(why) => {
+                    warn!("pixel response error: {}", why)
+                }
+            }
+        }
+    }
+}
+
+/// Sets a pixel that a participant sent, on its own or as part of a larger packet.
+fn set_pixel(
+    state: &SharedState,
+    identifier: u16,
+    x: u16,
+    y: u16,
+    color: Color,
+    source: IpAddr,
+    tally: &mut Tally,
+) {
+    if !state
+        .locks
+        .allows(source, state.canvas_index(identifier), x, y)
+    {
+        return;
+    }
+    let canvas = state.canvas_for(identifier);
+    if let Some(mirror) = &state.mirror {
+        if canvas.accepts_pixel(x, y, color) {
+            mirror.forward(state.canvas_index(identifier) as u16, x, y, color);
+        }
+    }
+    let dropped_source = canvas.set_pixel(x, y, color, source, tally);
+    if let (Some(activity), Some(dropped_source)) = (&state.activity, dropped_source) {
+        activity.record_dropped(dropped_source);
+    }
+}
+
+/// Receives packets as UDP datagrams, for networks where ICMP is filtered.
+/// UDP has no echo identifier, so datagrams are always meant for the first canvas.
+async fn udp_handler(
+    state: SharedState,
+    socket: io::Result<UdpSocket>,
+    address: SocketAddr,
+) -> Result<()> {
+    let socket = Arc::new(socket?);
+    socket.set_read_timeout(Some(IcmpListener::SHUTDOWN_POLL_INTERVAL))?;
+    info!("UDP listener on {}", address);
+    let _running = state.liveness.listener_started();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let mut buffer = [0; 2048];
+        let transport = Transport::Udp(socket.clone());
+        let mut tallies = Tallies::new(&state);
+        let mut unflushed_packets = 0;
+        while !state.shutdown.is_shut_down() {
+            let (length, sender) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(why) if matches!(why.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    tallies.flush();
+                    continue;
+                }
+                Err(why) => return Err(why.into()),
+            };
+            let payload = buffer[..length].strip_prefix(&*state.magic);
+            if let Some(packet) = payload.and_then(Packet::from_bytes) {
+                dispatch_packet(&state, packet, length, sender, 0, &transport, &mut tallies);
+            }
+            unflushed_packets += 1;
+            if unflushed_packets == DECODE_BATCH_SIZE {
+                tallies.flush();
+                unflushed_packets = 0;
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// Waits until the process is asked to terminate with Ctrl+C or SIGTERM.
+#[cfg(unix)]
+async fn wait_for_termination() -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn wait_for_termination() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+/// Runs the ingest benchmark, which reads the CPU time and kernel drops from `/proc`.
+#[cfg(target_os = "linux")]
+async fn run_benchmark(arguments: Arguments, background: Background) -> Result<()> {
+    bench::run(arguments, background).await
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn run_benchmark(_arguments: Arguments, _background: Background) -> Result<()> {
+    bail!("the ingest benchmark is only supported on Linux")
+}
+
+/// Reloads the configuration whenever the process receives SIGHUP.
+#[cfg(unix)]
+async fn reload_on_hangup(state: SharedState) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        if let Err(why) = state.reload() {
+            error!("could not reload configuration: {}", why);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_state: SharedState) -> Result<()> {
     Ok(())
 }
 
@@ -133,10 +1162,192 @@ async fn handle_error(future: impl Future<Output = Result<()>>) {
     }
 }
 
-async fn ping_handler(canvas: Canvas) {
-    futures::future::join(
-        handle_error(ip_ping_handler(canvas.clone(), true)),
-        handle_error(ip_ping_handler(canvas, false)),
-    )
-    .await;
+/// Runs all listeners until they are shut down through the handle.
+async fn ping_handler(
+    canvases: Vec<Canvas>,
+    settings: Arc<RwLock<Arguments>>,
+    shutdown: ShutdownHandle,
+    liveness: Arc<Liveness>,
+    received_packets: Arc<AtomicU64>,
+    webhooks: Arc<Webhooks>,
+) {
+    let arguments = settings.read().clone();
+    let mirror = if arguments.mirror.is_empty() {
+        None
+    } else {
+        match Mirror::start(arguments.mirror.clone(), arguments.mirror_rate) {
+            Ok(mirror) => Some(Arc::new(mirror)),
+            Err(why) => {
+                error!("could not start mirroring: {}", why);
+                None
+            }
+        }
+    };
+    let state = SharedState {
+        settings,
+        shutdown,
+        liveness,
+        received_packets,
+        activity: arguments.activity_log.as_ref().map(|_| Arc::default()),
+        client_stats: arguments.client_stats.then(Arc::default),
+        canvases: canvases.into(),
+        access: Arc::new(AccessList::new(arguments.allow, arguments.deny)),
+        get_pixel_limiter: Arc::new(RateLimiter::new(
+            arguments.get_pixel_rate,
+            Duration::from_secs(1),
+        )),
+        size_request_limiter: Arc::new(RateLimiter::new(
+            arguments.size_request_rate,
+            Duration::from_secs(1),
+        )),
+        webhooks,
+        mirror,
+        chaos: (arguments.chaos_drop.is_some() || arguments.chaos_delay.is_some()).then(|| {
+            warn!("chaos mode is enabled, packets will be dropped and delayed on purpose");
+            Arc::new(Chaos::start(
+                arguments.chaos_drop.unwrap_or(0.0),
+                arguments.chaos_delay.unwrap_or(0.0),
+                arguments.chaos_max_delay,
+            ))
+        }),
+        magic: arguments.magic.clone().unwrap_or_default().into(),
+        max_fill_area: arguments.max_fill_area,
+        palette: arguments
+            .palette
+            .iter()
+            .flat_map(|&color| Palette::encode(color))
+            .collect(),
+        locks: Arc::new(Locks::new(
+            Duration::from_secs(arguments.max_lock_lease.into()),
+            arguments.max_lock_area,
+        )),
+    };
+
+    if let (Some(address), Some(token)) = (arguments.admin_address, arguments.admin_token) {
+        tokio::spawn(handle_error(admin::serve(address, token, state.clone())));
+    }
+
+    tokio::spawn(handle_error(reload_on_hangup(state.clone())));
+
+    if let (Some(path), Some(activity)) = (arguments.activity_log, state.activity.clone()) {
+        tokio::spawn(handle_error(activity::write_periodically(
+            state.clone(),
+            activity,
+            path,
+            arguments.activity_log_interval,
+            arguments.activity_log_max_size,
+        )));
+    }
+
+    if let Some(directory) = arguments.history_directory.clone() {
+        tokio::spawn(handle_error(history::record_periodically(
+            state.clone(),
+            directory,
+            arguments.history_interval,
+        )));
+    }
+
+    if let Some(url) = arguments.stream_url.clone() {
+        match state.canvases.get(arguments.stream_canvas) {
+            Some(canvas) => stream::start(
+                canvas.clone(),
+                StreamSettings {
+                    url,
+                    ffmpeg: arguments.ffmpeg.clone(),
+                    encoder: arguments.stream_encoder,
+                    fps: arguments.stream_fps,
+                    bitrate: arguments.stream_bitrate,
+                },
+            ),
+            None => error!(
+                "canvas {} does not exist, not streaming",
+                arguments.stream_canvas
+            ),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(name) = &arguments.shared_memory {
+        for (index, canvas) in state.canvases.iter().enumerate() {
+            shared_memory::start(
+                canvas.clone(),
+                shared_memory::segment_path(name, index),
+                arguments.shared_memory_fps,
+            );
+        }
+    }
+
+    if let Some(command) = arguments.moderation_command {
+        tokio::spawn(handle_error(moderation::moderate_periodically(
+            state.clone(),
+            Arc::new(CommandModerator::new(command)),
+            arguments.moderation_interval,
+        )));
+    }
+
+    let workers = arguments.decode_workers.unwrap_or_else(|| {
+        thread::available_parallelism()
+            .map(NonZeroUsize::get)
+            .unwrap_or(1)
+    });
+    // Open all sockets before reporting readiness.
+    // Sockets that can’t be opened at all are reported once, instead of being retried like failing listeners.
+    let icmp_listeners: Vec<_> = Listener::all(&arguments.listen)
+        .into_iter()
+        .filter_map(|listener| match listener.open(&state.shutdown) {
+            Ok(socket) => Some((listener, socket)),
+            Err(why) => {
+                error!("{:#}", why);
+                None
+            }
+        })
+        .collect();
+    let udp_sockets: Vec<_> = arguments
+        .udp_address
+        .iter()
+        .filter_map(|&address| match UdpSocket::bind(address) {
+            Ok(socket) => Some((socket, address)),
+            Err(why) => {
+                error!("could not open UDP listener on {}: {}", address, why);
+                None
+            }
+        })
+        .collect();
+    systemd::notify_ready();
+    if !arguments.webhook.is_empty() {
+        state.webhooks.notify(Event::Started);
+        tokio::spawn(handle_error(webhook::watch_milestones(
+            state.clone(),
+            arguments.pixel_milestone,
+        )));
+    }
+    tokio::spawn(handle_error(systemd::supervise(state.clone())));
+
+    // Listeners that fail are opened again, while their first run uses the sockets opened above.
+    let udp_listeners =
+        futures::future::join_all(udp_sockets.into_iter().map(|(socket, address)| {
+            let state = state.clone();
+            let mut socket = Some(socket);
+            supervisor::supervise(
+                format!("UDP listener on {address}"),
+                state.shutdown.clone(),
+                move || {
+                    let socket = socket.take().map_or_else(|| UdpSocket::bind(address), Ok);
+                    udp_handler(state.clone(), socket, address)
+                },
+            )
+        }));
+    let icmp_listeners =
+        futures::future::join_all(icmp_listeners.into_iter().map(|(listener, socket)| {
+            let state = state.clone();
+            let mut socket = Some(socket);
+            supervisor::supervise(listener.to_string(), state.shutdown.clone(), move || {
+                let socket = socket
+                    .take()
+                    .map_or_else(|| listener.open(&state.shutdown), Ok);
+                ip_ping_handler(state.clone(), socket, listener.is_ipv4, workers)
+            })
+        }));
+
+    futures::future::join(icmp_listeners, udp_listeners).await;
 }
@@ -0,0 +1,86 @@
+//! Mirror mode, which forwards the pixels participants set to other Pingxelflut servers, for example a secondary display.
+//!
+//! Pixels are sent as ICMP echo requests with the canvas index as the echo identifier, at a limited rate.
+//! Pixels that arrive faster than that are dropped, since a mirror only has to roughly keep up.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_channel::{Receiver, Sender};
+use log::{info, warn};
+use pingxelflut::{
+    format::{Color, Packet},
+    icmp::{EchoDirection, Icmp},
+};
+use socket2::Socket;
+
+/// How many pixels are sent at once at most.
+const BATCH_SIZE: usize = 256;
+
+/// A pixel to forward: canvas index, position and color.
+type ForwardedPixel = (u16, u16, u16, Color);
+
+/// Forwards pixels to the mirror servers from a background thread.
+#[derive(Debug)]
+pub struct Mirror {
+    queue: Sender<ForwardedPixel>,
+}
+
+impl Mirror {
+    /// Starts forwarding to the targets, sending at most `rate` pixels per second to each of them.
+    pub fn start(targets: Vec<IpAddr>, rate: u32) -> io::Result<Self> {
+        let sockets = targets
+            .into_iter()
+            .map(|address| {
+                let target = SocketAddr::new(address, 0);
+                Icmp::socket_for(target).map(|socket| (target, socket))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        // Up to one second of pixels is buffered.
+        let (queue, receiver) = async_channel::bounded(rate.max(1) as usize);
+        for (target, _) in &sockets {
+            info!("mirroring pixels to {}", target.ip());
+        }
+        thread::spawn(move || forward(receiver, sockets, rate));
+        Ok(Self { queue })
+    }
+
+    /// Queues a pixel for the mirrors, unless they are already behind.
+    pub fn forward(&self, canvas: u16, x: u16, y: u16, color: Color) {
+        let _ = self.queue.try_send((canvas, x, y, color));
+    }
+}
+
+/// Sends queued pixels to all mirrors in batches, pausing between batches to stay within the rate.
+fn forward(receiver: Receiver<ForwardedPixel>, sockets: Vec<(SocketAddr, Socket)>, rate: u32) {
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    while let Ok(first) = receiver.recv_blocking() {
+        let started = Instant::now();
+        batch.push(first);
+        while batch.len() < BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(pixel) => batch.push(pixel),
+                Err(_) => break,
+            }
+        }
+
+        for (target, socket) in &sockets {
+            for &(canvas, x, y, color) in &batch {
+                let mut icmp = Icmp::new(*target, canvas, EchoDirection::Request);
+                icmp.set_payload(Packet::SetPixel { x, y, color }.to_bytes());
+                if let Err(why) = icmp.send_on(socket) {
+                    warn!("could not mirror pixel to {}: {}", target.ip(), why);
+                    break;
+                }
+            }
+        }
+
+        let budget = Duration::from_secs_f64(batch.len() as f64 / rate.max(1) as f64);
+        thread::sleep(budget.saturating_sub(started.elapsed()));
+        batch.clear();
+    }
+}
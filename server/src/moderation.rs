@@ -0,0 +1,166 @@
+//! Hook for reviewing what participants draw, so that offending content can be removed automatically.
+//!
+//! Every interval, the tiles of the canvas that participants drew on are downsampled and handed to a [`Moderator`],
+//! which decides whether each tile stays, is blanked or is frozen. Frozen tiles become protected regions,
+//! which are released by reloading the configuration or through the admin API.
+
+use std::{
+    io::{Cursor, Write},
+    path::PathBuf,
+    process::{Command, Stdio},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use image::{imageops, ImageFormat, RgbaImage};
+use log::{info, warn};
+
+use crate::{canvas::Region, SharedState};
+
+/// Tiles are downsampled by this factor before they are reviewed.
+const DOWNSAMPLING: u32 = 2;
+
+/// What happens to a reviewed tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Leave the tile as it is.
+    Keep,
+    /// Reset the tile to the background.
+    Blank,
+    /// Keep the tile as it is, but stop participants from drawing on it, for example until somebody reviewed it manually.
+    Freeze,
+}
+
+impl FromStr for Verdict {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        match text {
+            "keep" => Ok(Self::Keep),
+            "blank" => Ok(Self::Blank),
+            "freeze" => Ok(Self::Freeze),
+            _ => bail!("unknown verdict: {text}"),
+        }
+    }
+}
+
+/// A part of a canvas that participants drew on recently.
+#[derive(Debug)]
+pub struct Tile {
+    /// Index of the canvas.
+    pub canvas: usize,
+    /// Where the tile is on the canvas, in canvas pixels.
+    pub region: Region,
+    /// The downsampled contents of the tile.
+    pub image: RgbaImage,
+}
+
+/// Decides what happens to tiles that participants drew on.
+pub trait Moderator: Send + Sync {
+    fn review(&self, tile: &Tile) -> Result<Verdict>;
+}
+
+/// Runs an external command for every tile, such as a classifier or a tool for manual review.
+///
+/// The command is called with the canvas index and the tile’s X, Y, width and height as arguments,
+/// and receives the downsampled tile as a PNG image on standard input.
+/// It prints `keep`, `blank` or `freeze` to standard output.
+#[derive(Debug)]
+pub struct CommandModerator {
+    command: PathBuf,
+}
+
+impl CommandModerator {
+    pub fn new(command: PathBuf) -> Self {
+        Self { command }
+    }
+}
+
+impl Moderator for CommandModerator {
+    fn review(&self, tile: &Tile) -> Result<Verdict> {
+        let mut png = Vec::new();
+        tile.image
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let mut child = Command::new(&self.command)
+            .args([
+                tile.canvas.to_string(),
+                tile.region.x.to_string(),
+                tile.region.y.to_string(),
+                tile.region.width.to_string(),
+                tile.region.height.to_string(),
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        // Closing standard input tells the command that the image is complete.
+        child
+            .stdin
+            .take()
+            .expect("standard input is piped")
+            .write_all(&png)?;
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            bail!("moderation command failed: {}", output.status);
+        }
+        String::from_utf8_lossy(&output.stdout).trim().parse()
+    }
+}
+
+/// Reviews the tiles that participants drew on every interval.
+pub async fn moderate_periodically(
+    state: SharedState,
+    moderator: Arc<dyn Moderator>,
+    interval: Duration,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let state = state.clone();
+        let moderator = moderator.clone();
+        tokio::task::spawn_blocking(move || review_changes(&state, &*moderator)).await?;
+    }
+}
+
+fn review_changes(state: &SharedState, moderator: &dyn Moderator) {
+    for (index, canvas) in state.canvases.iter().enumerate() {
+        let changed = canvas.take_changed_tiles();
+        if changed.is_empty() {
+            continue;
+        }
+        let image = canvas.to_image();
+        for region in changed {
+            let tile_image = imageops::crop_imm(
+                &image,
+                region.x.into(),
+                region.y.into(),
+                region.width.into(),
+                region.height.into(),
+            )
+            .to_image();
+            let tile = Tile {
+                canvas: index,
+                region,
+                image: imageops::thumbnail(
+                    &tile_image,
+                    (u32::from(region.width) / DOWNSAMPLING).max(1),
+                    (u32::from(region.height) / DOWNSAMPLING).max(1),
+                ),
+            };
+            match moderator.review(&tile) {
+                Ok(Verdict::Keep) => {}
+                Ok(Verdict::Blank) => {
+                    canvas.restore_background(region);
+                    info!("blanked {} on canvas {} after moderation", region, index);
+                }
+                Ok(Verdict::Freeze) => {
+                    canvas.protect(region);
+                    info!("froze {} on canvas {} after moderation", region, index);
+                }
+                Err(why) => warn!("could not review {} on canvas {}: {}", region, index, why),
+            }
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Overlays that are drawn on top of the canvas at render time.
+
+use std::convert::Infallible;
+
+use clap::ValueEnum;
+use embedded_graphics::{
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use image::RgbaImage;
+use pingxelflut::format::{Color, ColorExt, COLOR_SIZE};
+use rgb::ComponentSlice;
+
+use crate::{canvas::DirtyRect, leaderboard::Leaderboard};
+
+/// Where an image overlay is placed on the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Placement {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+/// Distance of overlays from the edge of the frame and padding around overlay contents.
+const MARGIN: i32 = 10;
+
+/// An RGBA frame buffer that embedded-graphics can draw into.
+pub struct FrameTarget<'a> {
+    frame: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+
+impl<'a> FrameTarget<'a> {
+    pub fn new(frame: &'a mut [u8], width: u32, height: u32) -> Self {
+        Self {
+            frame,
+            width,
+            height,
+        }
+    }
+
+    /// Darkens an area of the frame, so that text drawn on top of it stays legible.
+    pub fn shade(&mut self, area: Rectangle) {
+        let area = area.intersection(&self.bounding_box());
+        for point in area.points() {
+            let pixel_pos =
+                (point.x as usize + point.y as usize * self.width as usize) * COLOR_SIZE;
+            for channel in &mut self.frame[pixel_pos..pixel_pos + 3] {
+                *channel /= 4;
+            }
+        }
+    }
+}
+
+impl OriginDimensions for FrameTarget<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+}
+
+impl DrawTarget for FrameTarget<'_> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0
+                || point.y < 0
+                || point.x as u32 >= self.width
+                || point.y as u32 >= self.height
+            {
+                continue;
+            }
+            let pixel_pos =
+                (point.x as usize + point.y as usize * self.width as usize) * COLOR_SIZE;
+            self.frame[pixel_pos..pixel_pos + COLOR_SIZE].copy_from_slice(&[
+                color.r(),
+                color.g(),
+                color.b(),
+                0xff,
+            ]);
+        }
+        Ok(())
+    }
+}
+
+impl Placement {
+    /// Returns where an overlay of the given size starts within the frame.
+    fn origin(self, target: &FrameTarget, size: Size) -> Point {
+        let free_x = target.width as i32 - size.width as i32;
+        let free_y = target.height as i32 - size.height as i32;
+        match self {
+            Placement::TopLeft => Point::new(MARGIN, MARGIN),
+            Placement::TopRight => Point::new(free_x - MARGIN, MARGIN),
+            Placement::BottomLeft => Point::new(MARGIN, free_y - MARGIN),
+            Placement::BottomRight => Point::new(free_x - MARGIN, free_y - MARGIN),
+            Placement::Center => Point::new(free_x / 2, free_y / 2),
+        }
+    }
+}
+
+/// Size of the shaded box that [`draw_text_box`] draws for the lines.
+pub fn text_box_size(lines: &[String]) -> Size {
+    let font = &FONT_10X20;
+    let longest_line = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0) as u32;
+    Size::new(
+        longest_line * font.character_size.width + 2 * MARGIN as u32,
+        lines.len() as u32 * font.character_size.height + 2 * MARGIN as u32,
+    )
+}
+
+/// Draws lines of text on a shaded box. Returns the area covered by the box.
+pub fn draw_text_box(
+    target: &mut FrameTarget,
+    lines: &[String],
+    placement: Placement,
+) -> Rectangle {
+    let box_origin = placement.origin(target, text_box_size(lines));
+    draw_text_box_at(target, lines, box_origin)
+}
+
+/// Draws lines of text on a shaded box starting at the given point. Returns the area covered by the box.
+pub fn draw_text_box_at(
+    target: &mut FrameTarget,
+    lines: &[String],
+    box_origin: Point,
+) -> Rectangle {
+    let font = &FONT_10X20;
+    let box_size = text_box_size(lines);
+    let area = Rectangle::new(box_origin, box_size).intersection(&target.bounding_box());
+    target.shade(area);
+
+    let style = MonoTextStyle::new(font, Rgb888::WHITE);
+    for (index, line) in lines.iter().enumerate() {
+        let position = box_origin
+            + Point::new(
+                MARGIN,
+                MARGIN + index as i32 * font.character_size.height as i32,
+            );
+        let _ = Text::with_baseline(line, position, style, Baseline::Top).draw(target);
+    }
+    area
+}
+
+/// Composites an image onto the frame, respecting its transparency. Returns the area covered by the image.
+pub fn draw_image(target: &mut FrameTarget, image: &RgbaImage, placement: Placement) -> Rectangle {
+    let origin = placement.origin(target, Size::new(image.width(), image.height()));
+    draw_image_at(target, image, origin)
+}
+
+/// Composites an image onto the frame starting at the given point. Returns the area covered by the image.
+pub fn draw_image_at(target: &mut FrameTarget, image: &RgbaImage, origin: Point) -> Rectangle {
+    let size = Size::new(image.width(), image.height());
+    let area = Rectangle::new(origin, size).intersection(&target.bounding_box());
+    for point in area.points() {
+        let image_point = point - origin;
+        let color = Color::from(
+            image
+                .get_pixel(image_point.x as u32, image_point.y as u32)
+                .0,
+        );
+        let pixel_pos = (point.x as usize + point.y as usize * target.width as usize) * COLOR_SIZE;
+        let pixel = &mut target.frame[pixel_pos..pixel_pos + COLOR_SIZE];
+        let below = Color::from(<[u8; COLOR_SIZE]>::try_from(&*pixel).unwrap());
+        pixel.copy_from_slice(color.blend_over(below).as_slice());
+    }
+    area
+}
+
+/// Draws the top sources of the leaderboard. Returns the area covered by the leaderboard, if it was drawn.
+pub fn draw_leaderboard(
+    target: &mut FrameTarget,
+    leaderboard: &Leaderboard,
+    count: usize,
+) -> Option<Rectangle> {
+    let entries = leaderboard.top(count);
+    if entries.is_empty() {
+        return None;
+    }
+    let lines: Vec<_> = entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, (source, pixels))| {
+            format!(
+                "{:>2}. {:<20} {:>12}",
+                index + 1,
+                leaderboard.display_entry(source),
+                pixels
+            )
+        })
+        .collect();
+    Some(draw_text_box(target, &lines, Placement::TopRight))
+}
+
+/// Draws the share of the canvas that every team owns, given the scores in pixels. Returns the area covered by the scoreboard.
+pub fn draw_scoreboard(
+    target: &mut FrameTarget,
+    scores: &[(&str, u64)],
+    pixel_count: u64,
+    placement: Placement,
+) -> Rectangle {
+    let lines: Vec<_> = scores
+        .iter()
+        .map(|(name, pixels)| {
+            format!(
+                "{:<16} {:>5.1}%",
+                name,
+                *pixels as f64 * 100.0 / pixel_count.max(1) as f64
+            )
+        })
+        .collect();
+    draw_text_box(target, &lines, placement)
+}
+
+/// Draws a timeline across the bottom of the frame, with a marker at the progress from 0 to 1 and a label above it.
+/// Returns the area covered by the timeline.
+pub fn draw_timeline(target: &mut FrameTarget, progress: f32, label: &str) -> Rectangle {
+    let font = &FONT_10X20;
+    const TRACK_HEIGHT: u32 = 4;
+    const MARKER_SIZE: Size = Size::new(8, 16);
+    let box_size = Size::new(
+        (target.width as i32 - 2 * MARGIN).max(0) as u32,
+        font.character_size.height + MARKER_SIZE.height + 3 * MARGIN as u32,
+    );
+    let box_origin = Placement::BottomLeft.origin(target, box_size);
+    let area = Rectangle::new(box_origin, box_size).intersection(&target.bounding_box());
+    target.shade(area);
+
+    let style = MonoTextStyle::new(font, Rgb888::WHITE);
+    let _ = Text::with_baseline(
+        label,
+        box_origin + Point::new(MARGIN, MARGIN),
+        style,
+        Baseline::Top,
+    )
+    .draw(target);
+
+    let track_width = box_size.width.saturating_sub(2 * MARGIN as u32);
+    let marker_top = box_origin.y + 2 * MARGIN + font.character_size.height as i32;
+    let track = Rectangle::new(
+        Point::new(
+            box_origin.x + MARGIN,
+            marker_top + (MARKER_SIZE.height - TRACK_HEIGHT) as i32 / 2,
+        ),
+        Size::new(track_width, TRACK_HEIGHT),
+    );
+    let _ = track
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::new(0x80, 0x80, 0x80)))
+        .draw(target);
+    let marker_x = box_origin.x + MARGIN + (progress.clamp(0.0, 1.0) * track_width as f32) as i32
+        - MARKER_SIZE.width as i32 / 2;
+    let _ = Rectangle::new(Point::new(marker_x, marker_top), MARKER_SIZE)
+        .into_styled(PrimitiveStyle::with_fill(Rgb888::WHITE))
+        .draw(target);
+    area
+}
+
+/// Draws a line of text on a shaded band across the bottom of the frame, starting at the given horizontal position.
+/// Returns the area covered by the band.
+pub fn draw_ticker(target: &mut FrameTarget, text: &str, x: i32) -> Rectangle {
+    let font = &FONT_10X20;
+    let band_height = font.character_size.height + 2 * MARGIN as u32;
+    let area = Rectangle::new(
+        Point::new(0, target.height as i32 - band_height as i32),
+        Size::new(target.width, band_height),
+    )
+    .intersection(&target.bounding_box());
+    target.shade(area);
+    let style = MonoTextStyle::new(font, Rgb888::WHITE);
+    let _ = Text::with_baseline(
+        text,
+        Point::new(x, area.top_left.y + MARGIN),
+        style,
+        Baseline::Top,
+    )
+    .draw(target);
+    area
+}
+
+/// Width of a line of text in the overlay font.
+pub fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * FONT_10X20.character_size.width
+}
+
+/// Converts an area covered by an overlay to the area of the frame that needs to be redrawn.
+pub fn dirty_rect(area: Rectangle) -> Option<DirtyRect> {
+    let bottom_right = area.bottom_right()?;
+    Some(DirtyRect {
+        start_x: area.top_left.x.max(0) as usize,
+        start_y: area.top_left.y.max(0) as usize,
+        end_x: (bottom_right.x + 1).max(0) as usize,
+        end_y: (bottom_right.y + 1).max(0) as usize,
+    })
+}
@@ -0,0 +1,144 @@
+//! Downscaled previews of the canvas at 1/2, 1/4 and 1/8 of its size, for viewers that don’t need every pixel.
+//!
+//! The previews are only computed once they are first requested. After that, every request only recomputes
+//! the areas that changed since the previous request, instead of scaling down the whole canvas again.
+
+use std::ops::Range;
+
+use image::RgbaImage;
+use parking_lot::Mutex;
+use pingxelflut::format::COLOR_SIZE;
+
+use crate::canvas::{DirtyRect, Frame};
+
+/// Number of preview levels, each half the size of the previous one.
+pub const LEVELS: usize = 3;
+
+/// One downscaled copy of the canvas.
+#[derive(Debug)]
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Level {
+    fn half_of(width: usize, height: usize) -> Self {
+        let (width, height) = (width.div_ceil(2), height.div_ceil(2));
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height * COLOR_SIZE],
+        }
+    }
+
+    fn row(&self, y: usize, columns: Range<usize>) -> Vec<u8> {
+        let start = y * self.width;
+        self.pixels[(start + columns.start) * COLOR_SIZE..(start + columns.end) * COLOR_SIZE]
+            .to_vec()
+    }
+
+    /// Recomputes the area of this level that covers an area of the level above it, which has twice the size.
+    /// Every pixel is the average of the up to four pixels it covers. Returns the recomputed area.
+    fn update_from(
+        &mut self,
+        above_row: impl Fn(usize, Range<usize>) -> Vec<u8>,
+        (above_width, above_height): (usize, usize),
+        area: DirtyRect,
+    ) -> DirtyRect {
+        let target = DirtyRect {
+            start_x: area.start_x / 2,
+            start_y: area.start_y / 2,
+            end_x: area.end_x.div_ceil(2).min(self.width),
+            end_y: area.end_y.div_ceil(2).min(self.height),
+        };
+        // The level above may have an odd size, whose last column or row is covered alone.
+        let above_columns = target.start_x * 2..(target.end_x * 2).min(above_width);
+        for y in target.start_y..target.end_y {
+            let rows: Vec<_> = (y * 2..(y * 2 + 2).min(above_height))
+                .map(|above_y| above_row(above_y, above_columns.clone()))
+                .collect();
+            for x in target.start_x..target.end_x {
+                let mut sum = [0u32; COLOR_SIZE];
+                let mut count = 0;
+                for row in &rows {
+                    let start = (x * 2 - above_columns.start) * COLOR_SIZE;
+                    for pixel in
+                        row[start..(start + 2 * COLOR_SIZE).min(row.len())].chunks_exact(COLOR_SIZE)
+                    {
+                        for (sum, &channel) in sum.iter_mut().zip(pixel) {
+                            *sum += u32::from(channel);
+                        }
+                        count += 1;
+                    }
+                }
+                let offset = (y * self.width + x) * COLOR_SIZE;
+                for (target, sum) in self.pixels[offset..offset + COLOR_SIZE].iter_mut().zip(sum) {
+                    *target = (sum / count.max(1)) as u8;
+                }
+            }
+        }
+        target
+    }
+
+    fn to_image(&self) -> RgbaImage {
+        RgbaImage::from_raw(self.width as u32, self.height as u32, self.pixels.clone())
+            .expect("pixels have the level size")
+    }
+}
+
+/// The preview levels of a canvas, and what changed on the canvas since they were last updated.
+#[derive(Debug, Default)]
+pub struct Pyramid {
+    /// Levels from the largest to the smallest, once they were first requested.
+    levels: Mutex<Option<Vec<Level>>>,
+    /// Area of the canvas that changed since the levels were last updated.
+    /// This has its own lock, so that marking changes never waits for an update.
+    pending: Mutex<Option<DirtyRect>>,
+}
+
+impl Pyramid {
+    /// Records that an area of the canvas changed.
+    pub fn mark(&self, area: DirtyRect) {
+        area.add_to(&mut self.pending.lock());
+    }
+
+    /// Returns the preview at a level from 1 to [`LEVELS`], where level N is scaled down by 2^N.
+    pub fn level(&self, frame: &Frame, width: u16, height: u16, level: usize) -> RgbaImage {
+        assert!((1..=LEVELS).contains(&level), "no such preview level");
+        let mut levels = self.levels.lock();
+        let levels = levels.get_or_insert_with(|| {
+            // Building the levels for the first time needs to cover the whole canvas.
+            self.pending.lock().replace(DirtyRect::full(width, height));
+            let mut levels: Vec<Level> = Vec::with_capacity(LEVELS);
+            for _ in 0..LEVELS {
+                let (above_width, above_height) = levels
+                    .last()
+                    .map_or((usize::from(width), usize::from(height)), |above| {
+                        (above.width, above.height)
+                    });
+                levels.push(Level::half_of(above_width, above_height));
+            }
+            levels
+        });
+
+        let (width, height) = (usize::from(width), usize::from(height));
+        if let Some(mut area) = self.pending.lock().take() {
+            area = levels[0].update_from(
+                |y, columns| frame.bytes(y * width + columns.start..y * width + columns.end),
+                (width, height),
+                area,
+            );
+            for index in 1..LEVELS {
+                let (above, below) = levels.split_at_mut(index);
+                let above = &above[index - 1];
+                area = below[0].update_from(
+                    |y, columns| above.row(y, columns),
+                    (above.width, above.height),
+                    area,
+                );
+            }
+        }
+        levels[level - 1].to_image()
+    }
+}
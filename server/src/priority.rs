@@ -0,0 +1,106 @@
+//! Prioritization of sources while the pixel queue has a backlog, for fairness beyond a flat rate limit.
+//!
+//! When more pixels are queued than can be applied in a frame, pixels of deprioritized sources are moved to the back of the queue,
+//! so that the pixels of other sources are applied first. Without a backlog, all pixels are applied in order.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use parking_lot::Mutex;
+
+/// How long the shares of sources are measured for.
+const SHARE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Decides which sources yield to others while the queue has a backlog.
+pub trait Prioritization: Send + Sync + Debug {
+    /// Whether pixels of the source should wait for the pixels of other sources.
+    fn is_deprioritized(&self, source: IpAddr) -> bool;
+
+    /// Called for every pixel that was applied.
+    fn record_applied(&self, _source: IpAddr) {}
+}
+
+/// Deprioritizes sources within the prefixes, for example LAN hosts in favor of remote participants.
+#[derive(Debug)]
+pub struct LowPriorityPrefixes(pub Vec<IpNet>);
+
+impl Prioritization for LowPriorityPrefixes {
+    fn is_deprioritized(&self, source: IpAddr) -> bool {
+        self.0.iter().any(|prefix| prefix.contains(&source))
+    }
+}
+
+/// Pixels applied per source in one window.
+#[derive(Debug)]
+struct ShareWindow {
+    started: Instant,
+    current: HashMap<IpAddr, u64>,
+    /// Share of every source in the previous window, which decisions are based on.
+    previous: HashMap<IpAddr, f64>,
+}
+
+/// Deprioritizes sources that had more than a share of the applied pixels in the last second.
+#[derive(Debug)]
+pub struct ShareThreshold {
+    /// Maximum share from 0 to 1.
+    threshold: f64,
+    window: Mutex<ShareWindow>,
+}
+
+impl ShareThreshold {
+    pub fn new(threshold: f64) -> Self {
+        Self {
+            threshold,
+            window: Mutex::new(ShareWindow {
+                started: Instant::now(),
+                current: HashMap::new(),
+                previous: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl Prioritization for ShareThreshold {
+    fn is_deprioritized(&self, source: IpAddr) -> bool {
+        self.window
+            .lock()
+            .previous
+            .get(&source)
+            .is_some_and(|&share| share > self.threshold)
+    }
+
+    fn record_applied(&self, source: IpAddr) {
+        let mut window = self.window.lock();
+        if window.started.elapsed() >= SHARE_WINDOW {
+            let total = window.current.values().sum::<u64>() as f64;
+            window.previous = window
+                .current
+                .drain()
+                .map(|(source, pixels)| (source, pixels as f64 / total))
+                .collect();
+            window.started = Instant::now();
+        }
+        *window.current.entry(source).or_default() += 1;
+    }
+}
+
+/// Deprioritizes sources that any of the policies deprioritizes.
+#[derive(Debug)]
+pub struct Policies(pub Vec<Box<dyn Prioritization>>);
+
+impl Prioritization for Policies {
+    fn is_deprioritized(&self, source: IpAddr) -> bool {
+        self.0.iter().any(|policy| policy.is_deprioritized(source))
+    }
+
+    fn record_applied(&self, source: IpAddr) {
+        for policy in &self.0 {
+            policy.record_applied(source);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Fixed-window rate limiter that tracks every source address separately,
+/// or every other key such as a source address combined with what it asks for.
+#[derive(Debug)]
+pub struct RateLimiter<K = IpAddr> {
+    /// Maximum number of allowed actions per source and window.
+    limit: AtomicU32,
+    window: Duration,
+    sources: Mutex<Sources<K>>,
+}
+
+#[derive(Debug)]
+struct Sources<K> {
+    /// Start of the current window and number of actions within it, per source.
+    windows: HashMap<K, (Instant, u32)>,
+    /// When sources whose window ended were last removed, which happens at most once per window
+    /// so that checks stay cheap however many sources there are.
+    last_sweep: Instant,
+}
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit: AtomicU32::new(limit),
+            window,
+            sources: Mutex::new(Sources {
+                windows: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Maximum number of allowed actions per source and window.
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Record an action by the source, and return whether it is within the rate limit.
+    pub fn check(&self, source: K) -> bool {
+        let now = Instant::now();
+        let mut sources = self.sources.lock();
+        if now.duration_since(sources.last_sweep) >= self.window {
+            sources
+                .windows
+                .retain(|_, (window_start, _)| now.duration_since(*window_start) < self.window);
+            sources.last_sweep = now;
+        }
+
+        let (window_start, count) = sources.windows.entry(source).or_insert((now, 0));
+        if now.duration_since(*window_start) >= self.window {
+            *window_start = now;
+            *count = 0;
+        }
+        if *count >= self.limit() {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_every_source_per_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let (first, second): (IpAddr, IpAddr) = ([10, 0, 0, 1].into(), [10, 0, 0, 2].into());
+        assert!(limiter.check(first));
+        assert!(limiter.check(first));
+        assert!(!limiter.check(first));
+        assert!(limiter.check(second));
+        limiter.set_limit(3);
+        assert!(limiter.check(first));
+        assert!(!limiter.check(first));
+    }
+
+    #[test]
+    fn windows_start_over() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+        let source: IpAddr = [10, 0, 0, 1].into();
+        assert!(limiter.check(source));
+        assert!(!limiter.check(source));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(limiter.check(source));
+    }
+
+    #[test]
+    fn ended_windows_are_swept() {
+        let limiter = RateLimiter::new(1, Duration::from_millis(10));
+        for last in 0..100u8 {
+            limiter.check(IpAddr::from([10, 0, 0, last]));
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        limiter.check(IpAddr::from([10, 0, 1, 0]));
+        assert_eq!(limiter.sources.lock().windows.len(), 1);
+    }
+}
@@ -0,0 +1,466 @@
+//! Renders the canvas onto the window surface.
+//!
+//! The scaling renderer built into `pixels` only scales by integer factors and never downscales,
+//! which leaves most of the screen empty (or cuts off the canvas) on displays that don’t happen to match the canvas size.
+//! This renderer works the same way, but scales by arbitrary factors while preserving the aspect ratio.
+//!
+//! The renderer also owns the canvas texture instead of using the one from `pixels`, which is re-uploaded in full every frame.
+//! Only the areas of the frame that actually changed are uploaded here.
+
+use clap::ValueEnum;
+use pixels::{wgpu, wgpu::util::DeviceExt, Pixels};
+
+use crate::canvas::DirtyRect;
+
+/// Texture filter used when scaling the canvas to the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ScalingFilter {
+    /// Crisp pixels, best for integer scaling factors.
+    #[default]
+    Nearest,
+    /// Smooth interpolation between pixels.
+    Bilinear,
+}
+
+impl From<ScalingFilter> for wgpu::FilterMode {
+    fn from(filter: ScalingFilter) -> Self {
+        match filter {
+            ScalingFilter::Nearest => wgpu::FilterMode::Nearest,
+            ScalingFilter::Bilinear => wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+/// Color correction applied when the canvas is drawn, for example to compensate for projectors that wash out colors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorCorrection {
+    /// Values above 1 brighten dark colors, values below 1 darken them.
+    pub gamma: f32,
+    /// Factors for the red, green and blue channels.
+    pub gain: [f32; 3],
+}
+
+impl ColorCorrection {
+    pub const NONE: Self = Self {
+        gamma: 1.0,
+        gain: [1.0; 3],
+    };
+
+    fn uniform(self) -> [f32; 4] {
+        let [r, g, b] = self.gain;
+        [r, g, b, self.gamma]
+    }
+}
+
+#[derive(Debug)]
+pub struct CanvasRenderer {
+    texture: wgpu::Texture,
+    vertex_buffer: wgpu::Buffer,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+    clear_color: wgpu::Color,
+    texture_size: (f32, f32),
+    surface_size: (f32, f32),
+    viewport: Viewport,
+    view: View,
+    clip_rect: (u32, u32, u32, u32),
+    color_correction: ColorCorrection,
+}
+
+/// Offset of the color correction in the uniform buffer, after the transformation matrix.
+const COLOR_CORRECTION_OFFSET: wgpu::BufferAddress = 16 * 4;
+
+impl CanvasRenderer {
+    pub fn new(
+        pixels: &Pixels,
+        texture_size: (u32, u32),
+        surface_size: (u32, u32),
+        filter: ScalingFilter,
+        clear_color: wgpu::Color,
+        storage: bool,
+    ) -> Self {
+        let device = &pixels.context().device;
+        let module = device.create_shader_module(wgpu::include_wgsl!("../shaders/scale.wgsl"));
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("canvas_renderer_texture"),
+            size: wgpu::Extent3d {
+                width: texture_size.0,
+                height: texture_size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // sRGB textures can’t be storage textures, so those are sampled through an sRGB view instead.
+            format: if storage {
+                wgpu::TextureFormat::Rgba8Unorm
+            } else {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            },
+            usage: if storage {
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::STORAGE_BINDING
+            } else {
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST
+            },
+            view_formats: if storage {
+                &[wgpu::TextureFormat::Rgba8UnormSrgb]
+            } else {
+                &[]
+            },
+        });
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(wgpu::TextureFormat::Rgba8UnormSrgb),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("canvas_renderer_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter.into(),
+            min_filter: filter.into(),
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 1.0,
+            compare: None,
+            anisotropy_clamp: 1,
+            border_color: None,
+        });
+
+        // One triangle covering the entire surface, see https://github.com/parasyte/pixels/issues/180
+        let vertex_data: [[f32; 2]; 3] = [[-1.0, -1.0], [3.0, -1.0], [-1.0, 3.0]];
+        let vertex_data_slice: &[u8] = bytemuck::cast_slice(&vertex_data);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("canvas_renderer_vertex_buffer"),
+            contents: vertex_data_slice,
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let vertex_buffer_layout = wgpu::VertexBufferLayout {
+            array_stride: (vertex_data_slice.len() / vertex_data.len()) as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x2,
+                offset: 0,
+                shader_location: 0,
+            }],
+        };
+
+        let texture_size = (texture_size.0 as f32, texture_size.1 as f32);
+        let surface_size = (surface_size.0 as f32, surface_size.1 as f32);
+        let viewport = Viewport::full(texture_size.0, texture_size.1);
+        let (transform, clip_rect) = fit_transform(texture_size, surface_size, viewport, View::FIT);
+        let mut locals = transform.to_vec();
+        locals.extend(ColorCorrection::NONE.uniform());
+        let locals_bytes: &[u8] = bytemuck::cast_slice(&locals);
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("canvas_renderer_uniform_buffer"),
+            contents: locals_bytes,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("canvas_renderer_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(locals_bytes.len() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("canvas_renderer_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("canvas_renderer_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("canvas_renderer_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[vertex_buffer_layout],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: pixels.render_texture_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            texture,
+            vertex_buffer,
+            uniform_buffer,
+            bind_group,
+            render_pipeline,
+            clear_color,
+            texture_size,
+            surface_size,
+            viewport,
+            view: View::FIT,
+            clip_rect,
+            color_correction: ColorCorrection::NONE,
+        }
+    }
+
+    /// The canvas texture, which is a storage texture if the renderer was created with `storage`.
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// Uploads an area of the RGBA frame to the canvas texture.
+    pub fn upload(&self, queue: &wgpu::Queue, frame: &[u8], area: DirtyRect) {
+        let row_length = self.texture_size.0 as usize * 4;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d {
+                    x: area.start_x as u32,
+                    y: area.start_y as u32,
+                    z: 0,
+                },
+                aspect: wgpu::TextureAspect::All,
+            },
+            &frame[area.start_y * row_length + area.start_x * 4..],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(row_length as u32),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: area.width() as u32,
+                height: area.height() as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Adapts the scaling to a new surface size.
+    pub fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.surface_size = (width as f32, height as f32);
+        self.update_transform(queue);
+    }
+
+    /// Changes the part of the canvas that is shown.
+    pub fn set_viewport(&mut self, queue: &wgpu::Queue, viewport: Viewport) {
+        if self.viewport != viewport {
+            self.viewport = viewport;
+            self.update_transform(queue);
+        }
+    }
+
+    /// Magnifies the canvas by a factor, keeping the canvas point under the cursor in place.
+    pub fn zoom_at(&mut self, queue: &wgpu::Queue, factor: f32, cursor: (f32, f32)) {
+        let old_zoom = self.view.zoom;
+        self.view.zoom = (old_zoom * factor).clamp(View::MIN_ZOOM, View::MAX_ZOOM);
+        let applied_factor = self.view.zoom / old_zoom;
+        // Cursor position relative to the surface center.
+        let cursor_x = cursor.0 - self.surface_size.0 / 2.0;
+        let cursor_y = cursor.1 - self.surface_size.1 / 2.0;
+        let (pan_x, pan_y) = self.view.pan;
+        self.view.pan = (
+            cursor_x - (cursor_x - pan_x) * applied_factor,
+            cursor_y - (cursor_y - pan_y) * applied_factor,
+        );
+        self.update_transform(queue);
+    }
+
+    /// Moves the canvas by some surface pixels.
+    pub fn pan_by(&mut self, queue: &wgpu::Queue, delta_x: f32, delta_y: f32) {
+        self.view.pan.0 += delta_x;
+        self.view.pan.1 += delta_y;
+        self.update_transform(queue);
+    }
+
+    /// Changes the color correction, which may change at any time when the configuration is reloaded.
+    pub fn set_color_correction(&mut self, queue: &wgpu::Queue, color_correction: ColorCorrection) {
+        if color_correction != self.color_correction {
+            self.color_correction = color_correction;
+            queue.write_buffer(
+                &self.uniform_buffer,
+                COLOR_CORRECTION_OFFSET,
+                bytemuck::cast_slice(&color_correction.uniform()),
+            );
+        }
+    }
+
+    /// Goes back to fitting the whole canvas into the surface.
+    pub fn reset_view(&mut self, queue: &wgpu::Queue) {
+        self.view = View::FIT;
+        self.update_transform(queue);
+    }
+
+    fn update_transform(&mut self, queue: &wgpu::Queue) {
+        let (transform, clip_rect) = fit_transform(
+            self.texture_size,
+            self.surface_size,
+            self.viewport,
+            self.view,
+        );
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&transform));
+        self.clip_rect = clip_rect;
+    }
+
+    /// Draws the canvas texture to the render target, with bars of the clear color where the aspect ratios differ.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, render_target: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("canvas_renderer_render_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: render_target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear_color),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        let (x, y, width, height) = self.clip_rect;
+        if width > 0 && height > 0 {
+            render_pass.set_scissor_rect(x, y, width, height);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+}
+
+/// The part of the canvas that is fit into the surface, in canvas pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    /// A viewport showing the entire canvas.
+    pub fn full(width: f32, height: f32) -> Self {
+        Self {
+            x: 0.0,
+            y: 0.0,
+            width,
+            height,
+        }
+    }
+}
+
+/// Zoom and pan applied on top of fitting the canvas to the surface.
+#[derive(Debug, Clone, Copy)]
+struct View {
+    /// Magnification relative to fitting the viewport into the surface.
+    zoom: f32,
+    /// Offset of the viewport center from the surface center, in surface pixels.
+    pan: (f32, f32),
+}
+
+impl View {
+    const FIT: Self = Self {
+        zoom: 1.0,
+        pan: (0.0, 0.0),
+    };
+    const MIN_ZOOM: f32 = 0.5;
+    const MAX_ZOOM: f32 = 256.0;
+}
+
+/// Computes the column-major transformation matrix that fits the viewport into the surface while preserving the aspect ratio,
+/// as well as the surface area that the viewport covers.
+fn fit_transform(
+    texture_size: (f32, f32),
+    surface_size: (f32, f32),
+    viewport: Viewport,
+    view: View,
+) -> ([f32; 16], (u32, u32, u32, u32)) {
+    let (texture_width, texture_height) = texture_size;
+    let (surface_width, surface_height) = surface_size;
+    let scale = (surface_width / viewport.width).min(surface_height / viewport.height) * view.zoom;
+    let scaled_width = viewport.width * scale;
+    let scaled_height = viewport.height * scale;
+    // The whole texture is drawn, but moved so that the viewport center ends up at the surface center.
+    let pan_x = view.pan.0 + (texture_width / 2.0 - viewport.x - viewport.width / 2.0) * scale;
+    let pan_y = view.pan.1 + (texture_height / 2.0 - viewport.y - viewport.height / 2.0) * scale;
+
+    let scale_x = texture_width * scale / surface_width;
+    let scale_y = texture_height * scale / surface_height;
+    // Avoid sampling exactly between two pixels on surfaces with odd sizes.
+    // Surface pixels go down, while normalized device coordinates go up.
+    let translate_x = ((surface_width / 2.0).fract() + 2.0 * pan_x) / surface_width;
+    let translate_y = ((surface_height / 2.0).fract() - 2.0 * pan_y) / surface_height;
+    #[rustfmt::skip]
+    let transform = [
+        scale_x,     0.0,         0.0, 0.0,
+        0.0,         scale_y,     0.0, 0.0,
+        0.0,         0.0,         1.0, 0.0,
+        translate_x, translate_y, 0.0, 1.0,
+    ];
+
+    // Only the viewport is shown, and the scissor rectangle needs to lie within the surface.
+    let (pan_x, pan_y) = view.pan;
+    let left = ((surface_width - scaled_width) / 2.0 + pan_x).clamp(0.0, surface_width);
+    let top = ((surface_height - scaled_height) / 2.0 + pan_y).clamp(0.0, surface_height);
+    let right = ((surface_width + scaled_width) / 2.0 + pan_x).clamp(0.0, surface_width);
+    let bottom = ((surface_height + scaled_height) / 2.0 + pan_y).clamp(0.0, surface_height);
+    let clip_rect = (
+        left as u32,
+        top as u32,
+        (right - left) as u32,
+        (bottom - top) as u32,
+    );
+    (transform, clip_rect)
+}
@@ -0,0 +1,132 @@
+//! Shared-memory export, which makes the canvases available to other processes on the same host,
+//! such as OBS plugins, LED controllers or analytics, without going through the network.
+//!
+//! Every canvas is written to a file in `/dev/shm`, which readers map into their memory.
+//! The file starts with a header of little-endian fields, followed by the pixels in RGBA byte order:
+//!
+//! | Offset | Size | Field                                                          |
+//! |--------|------|----------------------------------------------------------------|
+//! | 0      | 4    | Magic bytes `PXFL`                                             |
+//! | 4      | 4    | Header version, currently 1                                    |
+//! | 8      | 4    | Width in pixels                                                |
+//! | 12     | 4    | Height in pixels                                               |
+//! | 16     | 4    | Stride, the distance between rows in bytes                     |
+//! | 20     | 4    | Offset of the pixels from the start of the file in bytes       |
+//! | 24     | 8    | Frame counter, odd while a frame is being written              |
+//!
+//! Readers should read the frame counter before and after copying the pixels,
+//! and retry if it was odd or changed in between.
+
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{error, info};
+use pingxelflut::format::COLOR_SIZE;
+
+use crate::canvas::{Canvas, DirtyRect};
+
+const MAGIC: &[u8; 4] = b"PXFL";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u64 = 32;
+const FRAME_COUNTER_OFFSET: u64 = 24;
+/// Directory of POSIX shared memory objects on Linux.
+const SHARED_MEMORY_DIRECTORY: &str = "/dev/shm";
+
+/// Path of the shared memory segment of a canvas.
+pub fn segment_path(name: &str, canvas_index: usize) -> PathBuf {
+    Path::new(SHARED_MEMORY_DIRECTORY).join(format!("{name}-{canvas_index}"))
+}
+
+/// A canvas exported into a shared memory segment.
+struct Segment {
+    file: File,
+    frame_counter: u64,
+    /// The pixels of the canvas, kept to avoid allocating for every frame.
+    buffer: Vec<u8>,
+}
+
+impl Segment {
+    fn create(path: &Path, canvas: &Canvas) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let width = u32::from(canvas.width);
+        let height = u32::from(canvas.height);
+        let stride = width * COLOR_SIZE as u32;
+        let buffer = vec![0; stride as usize * height as usize];
+        file.set_len(HEADER_SIZE + buffer.len() as u64)?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        for field in [VERSION, width, height, stride, HEADER_SIZE as u32] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header.extend_from_slice(&0u64.to_le_bytes());
+        file.write_all_at(&header, 0)?;
+        Ok(Self {
+            file,
+            frame_counter: 0,
+            buffer,
+        })
+    }
+
+    fn write_frame_counter(&self) -> io::Result<()> {
+        self.file
+            .write_all_at(&self.frame_counter.to_le_bytes(), FRAME_COUNTER_OFFSET)
+    }
+
+    /// Writes the current canvas contents, marking the frame as incomplete while doing so.
+    fn write_frame(&mut self, canvas: &Canvas) -> io::Result<()> {
+        canvas.draw_to(
+            &mut self.buffer,
+            DirtyRect::full(canvas.width, canvas.height),
+        );
+        self.frame_counter += 1;
+        self.write_frame_counter()?;
+        self.file.write_all_at(&self.buffer, HEADER_SIZE)?;
+        self.frame_counter += 1;
+        self.write_frame_counter()
+    }
+}
+
+/// Exports the canvas from a background thread at the given frame rate.
+pub fn start(canvas: Canvas, path: PathBuf, fps: u32) {
+    let mut segment = match Segment::create(&path, &canvas) {
+        Ok(segment) => segment,
+        Err(why) => {
+            error!(
+                "could not create shared memory segment {}: {}",
+                path.display(),
+                why
+            );
+            return;
+        }
+    };
+    info!("exporting canvas to shared memory at {}", path.display());
+    thread::spawn(move || {
+        let frame_interval = Duration::from_secs(1) / fps.max(1);
+        let mut next_frame = Instant::now();
+        loop {
+            if let Err(why) = segment.write_frame(&canvas) {
+                error!(
+                    "could not write to shared memory segment {}: {}",
+                    path.display(),
+                    why
+                );
+                return;
+            }
+            next_frame += frame_interval;
+            next_frame = next_frame.max(Instant::now());
+            thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+        }
+    });
+}
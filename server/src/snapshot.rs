@@ -0,0 +1,26 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use log::info;
+
+use crate::canvas::Canvas;
+
+/// Saves the canvas as a PNG image into the directory, named after the current time.
+/// Snapshots of canvases other than the first are additionally named after the canvas index,
+/// so that snapshots of all canvases taken at once don’t overwrite each other.
+/// Returns the path of the written file.
+pub fn save_snapshot(canvas: &Canvas, index: usize, directory: &Path) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+    let name = if index == 0 {
+        format!("snapshot-{timestamp}.png")
+    } else {
+        format!("snapshot-{timestamp}-canvas{index}.png")
+    };
+    let path = directory.join(name);
+    canvas.to_image().save(&path)?;
+    info!("saved snapshot to {}", path.display());
+    Ok(path)
+}
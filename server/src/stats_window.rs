@@ -0,0 +1,251 @@
+//! Statistics window, which plots packet and pixel rates, the queue depth and kernel drops over time,
+//! so that operators can see bottlenecks develop.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_10X20, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle},
+    text::{Baseline, Text},
+};
+use pixels::{Pixels, PixelsBuilder, SurfaceTexture};
+use winit::{
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowId},
+};
+
+use crate::{canvas::Canvas, overlay::FrameTarget};
+
+/// Size of the rendered statistics, which is scaled to the window.
+const WIDTH: u32 = 800;
+const HEIGHT: u32 = 600;
+/// How often a sample is taken.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many samples are plotted, which is the plotted time span in seconds.
+const SAMPLE_COUNT: usize = 300;
+const PANEL_MARGIN: i32 = 10;
+
+/// A plotted statistic, with its title and the color of its graph.
+type Panel = (&'static str, fn(&Sample) -> f64, Rgb888);
+
+const PANELS: [Panel; 4] = [
+    (
+        "Packets/s",
+        |sample| sample.packets_per_second,
+        Rgb888::CSS_DODGER_BLUE,
+    ),
+    (
+        "Applied pixels/s",
+        |sample| sample.pixels_per_second,
+        Rgb888::CSS_LIME_GREEN,
+    ),
+    (
+        "Queued pixels",
+        |sample| sample.queued_pixels,
+        Rgb888::CSS_ORANGE,
+    ),
+    (
+        "Kernel drops/s",
+        |sample| sample.kernel_drops_per_second,
+        Rgb888::CSS_RED,
+    ),
+];
+
+/// Statistics at one point in time.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sample {
+    packets_per_second: f64,
+    pixels_per_second: f64,
+    queued_pixels: f64,
+    kernel_drops_per_second: f64,
+}
+
+/// Counters that rates are computed from.
+#[derive(Debug, Clone, Copy)]
+struct Counters {
+    time: Instant,
+    packets: u64,
+    pixels: u64,
+    kernel_drops: u64,
+}
+
+/// The inode and the number of packets that the kernel dropped because the receive buffer was full, for every raw socket.
+/// Only available on Linux; elsewhere, there are no sockets.
+pub(crate) fn raw_socket_drops() -> Vec<(u64, u64)> {
+    ["/proc/net/raw", "/proc/net/raw6"]
+        .into_iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|table| {
+            table
+                .lines()
+                .skip(1)
+                .filter_map(|line| {
+                    let fields: Vec<_> = line.split_whitespace().collect();
+                    Some((fields.get(9)?.parse().ok()?, fields.last()?.parse().ok()?))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Sums the packets that the kernel dropped on all raw sockets because their receive buffers were full.
+fn kernel_drops() -> u64 {
+    raw_socket_drops().into_iter().map(|(_, drops)| drops).sum()
+}
+
+/// A window plotting the server statistics.
+pub struct StatsWindow {
+    window: Arc<Window>,
+    pixels: Pixels,
+    received_packets: Arc<AtomicU64>,
+    last: Counters,
+    samples: VecDeque<Sample>,
+}
+
+impl StatsWindow {
+    pub fn new(event_loop: &ActiveEventLoop, received_packets: Arc<AtomicU64>) -> Self {
+        let window = Arc::new(
+            event_loop
+                .create_window(
+                    Window::default_attributes()
+                        .with_title("Pingxelflut statistics")
+                        .with_inner_size(winit::dpi::PhysicalSize::new(WIDTH, HEIGHT)),
+                )
+                .unwrap(),
+        );
+        let surface_size = window.inner_size();
+        let surface_texture = SurfaceTexture::new(surface_size.width, surface_size.height, &window);
+        let pixels = PixelsBuilder::new(WIDTH, HEIGHT, surface_texture)
+            .build()
+            .unwrap();
+        Self {
+            window,
+            pixels,
+            last: Counters {
+                time: Instant::now(),
+                packets: received_packets.load(Ordering::Relaxed),
+                pixels: 0,
+                kernel_drops: kernel_drops(),
+            },
+            received_packets,
+            samples: VecDeque::with_capacity(SAMPLE_COUNT),
+        }
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    /// Takes a sample of the canvases once the sample interval passed, and then redraws the window.
+    pub fn update<'a>(&mut self, canvases: impl Iterator<Item = &'a Canvas>) {
+        let elapsed = self.last.time.elapsed();
+        if elapsed < SAMPLE_INTERVAL {
+            return;
+        }
+        let mut applied_pixels = 0;
+        let mut queued_pixels = 0;
+        for canvas in canvases {
+            applied_pixels += canvas.leaderboard.lock().total();
+            queued_pixels += canvas.pixel_queue_out.len();
+        }
+        let now = Counters {
+            time: Instant::now(),
+            packets: self.received_packets.load(Ordering::Relaxed),
+            pixels: applied_pixels,
+            kernel_drops: kernel_drops(),
+        };
+        let seconds = elapsed.as_secs_f64();
+        let rate = |now: u64, last: u64| now.saturating_sub(last) as f64 / seconds;
+        if self.samples.len() == SAMPLE_COUNT {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            packets_per_second: rate(now.packets, self.last.packets),
+            pixels_per_second: rate(now.pixels, self.last.pixels),
+            queued_pixels: queued_pixels as f64,
+            kernel_drops_per_second: rate(now.kernel_drops, self.last.kernel_drops),
+        });
+        self.last = now;
+        self.window.request_redraw();
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            let _ = self.pixels.resize_surface(width, height);
+        }
+    }
+
+    pub fn redraw(&mut self) -> Result<(), pixels::Error> {
+        let frame = self.pixels.frame_mut();
+        frame.fill(0);
+        let mut target = FrameTarget::new(frame, WIDTH, HEIGHT);
+        let panel_height = HEIGHT as i32 / PANELS.len() as i32;
+        for (index, &(title, value, color)) in PANELS.iter().enumerate() {
+            let top = index as i32 * panel_height;
+            let values: Vec<f64> = self.samples.iter().map(value).collect();
+            draw_panel(&mut target, top, panel_height, title, &values, color);
+        }
+        self.pixels.render()
+    }
+}
+
+/// Draws one graph with its title, current and maximum value.
+fn draw_panel(
+    target: &mut FrameTarget,
+    top: i32,
+    height: i32,
+    title: &str,
+    values: &[f64],
+    color: Rgb888,
+) {
+    let current = values.last().copied().unwrap_or(0.0);
+    let max = values.iter().copied().fold(0.0, f64::max);
+    let style = MonoTextStyle::new(&FONT_10X20, Rgb888::WHITE);
+    let _ = Text::with_baseline(
+        &format!("{title}: {current:.0} (max {max:.0})"),
+        Point::new(PANEL_MARGIN, top + PANEL_MARGIN),
+        style,
+        Baseline::Top,
+    )
+    .draw(target);
+
+    let graph_top = top + 2 * PANEL_MARGIN + FONT_10X20.character_size.height as i32;
+    let graph_bottom = top + height - PANEL_MARGIN;
+    let graph_width = WIDTH as i32 - 2 * PANEL_MARGIN;
+    let _ = Line::new(
+        Point::new(PANEL_MARGIN, graph_bottom),
+        Point::new(PANEL_MARGIN + graph_width, graph_bottom),
+    )
+    .into_styled(PrimitiveStyle::with_stroke(Rgb888::CSS_GRAY, 1))
+    .draw(target);
+
+    // The newest sample is at the right edge, older ones scroll out to the left.
+    let scale = f64::from(graph_bottom - graph_top) / max.max(1.0);
+    let step = graph_width as f64 / (SAMPLE_COUNT - 1) as f64;
+    let offset = SAMPLE_COUNT - values.len();
+    let points: Vec<_> = values
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            Point::new(
+                PANEL_MARGIN + ((offset + index) as f64 * step) as i32,
+                graph_bottom - (value * scale) as i32,
+            )
+        })
+        .collect();
+    for pair in points.windows(2) {
+        let _ = Line::new(pair[0], pair[1])
+            .into_styled(PrimitiveStyle::with_stroke(color, 2))
+            .draw(target);
+    }
+}
@@ -0,0 +1,119 @@
+//! Live stream output, which pushes the canvas to an RTMP or SRT endpoint such as Twitch or YouTube.
+//!
+//! Encoding and streaming is left to FFmpeg, which receives raw RGBA frames on standard input.
+//! Streams to `srt://` URLs are sent as MPEG-TS, all others as FLV.
+
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use log::{error, info};
+
+use crate::canvas::Canvas;
+
+/// How long to wait before restarting FFmpeg after the stream failed.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+/// H.264 encoder that FFmpeg uses for the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Encoder {
+    #[default]
+    X264,
+    Openh264,
+}
+
+impl Encoder {
+    fn ffmpeg_name(self) -> &'static str {
+        match self {
+            Encoder::X264 => "libx264",
+            Encoder::Openh264 => "libopenh264",
+        }
+    }
+}
+
+/// Settings of the live stream.
+#[derive(Debug, Clone)]
+pub struct StreamSettings {
+    pub url: String,
+    pub ffmpeg: PathBuf,
+    pub encoder: Encoder,
+    pub fps: u32,
+    /// Video bitrate in kilobits per second.
+    pub bitrate: u32,
+}
+
+/// Streams the canvas from a background thread, restarting FFmpeg whenever the stream fails.
+pub fn start(canvas: Canvas, settings: StreamSettings) {
+    thread::spawn(move || loop {
+        if let Err(why) = stream(&canvas, &settings) {
+            error!("live stream failed: {:#}", why);
+        }
+        thread::sleep(RESTART_DELAY);
+    });
+}
+
+fn spawn_ffmpeg(canvas: &Canvas, settings: &StreamSettings) -> Result<Child> {
+    let format = if settings.url.starts_with("srt://") {
+        "mpegts"
+    } else {
+        "flv"
+    };
+    let bitrate = format!("{}k", settings.bitrate);
+    Command::new(&settings.ffmpeg)
+        .args([
+            "-loglevel",
+            "error",
+            "-f",
+            "rawvideo",
+            "-pixel_format",
+            "rgba",
+        ])
+        .args([
+            "-video_size",
+            &format!("{}x{}", canvas.width, canvas.height),
+        ])
+        .args(["-framerate", &settings.fps.to_string(), "-i", "-"])
+        // H.264 with 4:2:0 chroma subsampling needs even dimensions.
+        .args([
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+        ])
+        .args(["-c:v", settings.encoder.ffmpeg_name()])
+        .args(["-b:v", &bitrate, "-maxrate", &bitrate, "-bufsize", &bitrate])
+        // A keyframe every two seconds, as streaming platforms expect.
+        .args(["-g", &(settings.fps * 2).to_string()])
+        .args(["-f", format, &settings.url])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("could not start {}", settings.ffmpeg.display()))
+}
+
+/// Feeds frames to FFmpeg at the frame rate until writing fails.
+fn stream(canvas: &Canvas, settings: &StreamSettings) -> Result<()> {
+    let mut ffmpeg = spawn_ffmpeg(canvas, settings)?;
+    // The URL usually contains the stream key, so it isn’t logged.
+    info!("live stream started");
+    let mut input = ffmpeg.stdin.take().expect("standard input is piped");
+    let frame_interval = Duration::from_secs(1) / settings.fps.max(1);
+    let mut next_frame = Instant::now();
+    let result = loop {
+        if let Err(why) = input.write_all(canvas.to_image().as_raw()) {
+            break Err(why).context("FFmpeg stopped accepting frames");
+        }
+        next_frame += frame_interval;
+        // Frames are dropped rather than sent in a burst if the encoder falls behind.
+        next_frame = next_frame.max(Instant::now());
+        thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+    };
+    drop(input);
+    let _ = ffmpeg.wait();
+    result
+}
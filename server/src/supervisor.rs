@@ -0,0 +1,50 @@
+//! Supervision of listeners and decode workers, which restarts them when they fail,
+//! so that a panic or socket error doesn’t leave the server running with a frozen canvas.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::error;
+use pingxelflut::icmp::ShutdownHandle;
+
+/// Delay before the first restart after a failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Longest delay between restarts of a task that keeps failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Tasks that ran at least this long before failing are restarted after the initial backoff again.
+const STABLE_RUNTIME: Duration = Duration::from_secs(60);
+
+/// Runs a task until it finishes successfully or the server shuts down.
+/// Whenever the task fails or panics, it is started again after an exponentially growing delay.
+pub async fn supervise<F, T>(name: String, shutdown: ShutdownHandle, mut start: F)
+where
+    F: FnMut() -> T,
+    T: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        let failure = match tokio::spawn(start()).await {
+            Ok(Ok(())) => return,
+            Ok(Err(why)) => format!("{why:#}"),
+            Err(why) => why.to_string(),
+        };
+        if shutdown.is_shut_down() {
+            return;
+        }
+        if started.elapsed() >= STABLE_RUNTIME {
+            backoff = INITIAL_BACKOFF;
+        }
+        error!(
+            "{} failed, restarting in {} seconds: {}",
+            name,
+            backoff.as_secs(),
+            failure
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,126 @@
+//! Supervision by systemd, for services with `Type=notify`.
+//!
+//! The server reports readiness once its listeners are up, regularly reports its status,
+//! and notifies the watchdog as long as both the render loop and the listeners are alive.
+//! Outside of systemd, none of this does anything.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use sd_notify::NotifyState;
+
+use crate::SharedState;
+
+/// How often the status is reported if the watchdog doesn’t need more frequent notifications.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks whether the parts of the server that can stall are still working.
+#[derive(Debug)]
+pub struct Liveness {
+    last_frame: Mutex<Instant>,
+    running_listeners: AtomicUsize,
+}
+
+impl Default for Liveness {
+    fn default() -> Self {
+        Self {
+            last_frame: Mutex::new(Instant::now()),
+            running_listeners: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Liveness {
+    /// Record that the render loop finished a frame.
+    pub fn frame_rendered(&self) {
+        *self.last_frame.lock() = Instant::now();
+    }
+
+    /// Record that a listener started. The listener counts as running until the guard is dropped.
+    pub fn listener_started(self: &Arc<Self>) -> ListenerGuard {
+        self.running_listeners.fetch_add(1, Ordering::Relaxed);
+        ListenerGuard(self.clone())
+    }
+
+    /// Whether a frame was rendered within the timeout and at least one listener is running.
+    fn is_alive(&self, timeout: Duration) -> bool {
+        self.last_frame.lock().elapsed() < timeout
+            && self.running_listeners.load(Ordering::Relaxed) > 0
+    }
+}
+
+/// Marks a listener as running while it exists.
+pub struct ListenerGuard(Arc<Liveness>);
+
+impl Drop for ListenerGuard {
+    fn drop(&mut self) {
+        self.0.running_listeners.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn notify(states: &[NotifyState]) {
+    if let Err(why) = sd_notify::notify(false, states) {
+        debug!("could not notify systemd: {}", why);
+    }
+}
+
+/// Tell systemd that the server is ready to receive packets.
+pub fn notify_ready() {
+    notify(&[NotifyState::Ready]);
+}
+
+/// Tell systemd that the server is shutting down.
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+/// Reports the status and notifies the watchdog until the server stops.
+pub async fn supervise(state: SharedState) -> Result<()> {
+    if std::env::var_os("NOTIFY_SOCKET").is_none() {
+        return Ok(());
+    }
+    let mut watchdog_usec = 0;
+    let watchdog_timeout = sd_notify::watchdog_enabled(false, &mut watchdog_usec)
+        .then(|| Duration::from_micros(watchdog_usec));
+    let interval = watchdog_timeout
+        .map(|timeout| (timeout / 2).min(STATUS_INTERVAL))
+        .unwrap_or(STATUS_INTERVAL);
+
+    let mut ticker = tokio::time::interval(interval);
+    let mut last_packets = state.received_packets.load(Ordering::Relaxed);
+    let mut last_tick = Instant::now();
+    loop {
+        ticker.tick().await;
+        let packets = state.received_packets.load(Ordering::Relaxed);
+        let packets_per_second =
+            (packets - last_packets) as f64 / last_tick.elapsed().as_secs_f64();
+        last_packets = packets;
+        last_tick = Instant::now();
+        let sources: Option<usize> = state
+            .canvases
+            .iter()
+            .map(|canvas| canvas.leaderboard.lock().source_count())
+            .sum();
+        let status = match sources {
+            Some(sources) => format!("{packets_per_second:.0} packets/s, {sources} sources"),
+            None => format!("{packets_per_second:.0} packets/s"),
+        };
+        notify(&[NotifyState::Status(&status)]);
+
+        if let Some(timeout) = watchdog_timeout {
+            if state.liveness.is_alive(timeout) {
+                notify(&[NotifyState::Watchdog]);
+            } else {
+                warn!("render loop or listeners stalled, not notifying the watchdog");
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Team competition, which turns the canvas into a territory-control game.
+//!
+//! Sources belong to teams by their address prefix. Every pixel belongs to the team of whoever set it last,
+//! and the score of a team is the number of pixels it currently owns.
+
+use std::{
+    cmp::Reverse,
+    net::IpAddr,
+    str::FromStr,
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+};
+
+use ipnet::IpNet;
+
+use crate::access::parse_prefix;
+
+/// A team and the prefixes of its members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Team {
+    pub name: String,
+    prefixes: Vec<IpNet>,
+}
+
+impl FromStr for Team {
+    type Err = String;
+
+    /// Parse a team like `red=10.0.1.0/24,10.0.2.0/24`.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (name, prefixes) = text
+            .split_once('=')
+            .ok_or_else(|| format!("team must be given as NAME=PREFIX[,PREFIX…]: {text}"))?;
+        Ok(Self {
+            name: name.to_string(),
+            prefixes: prefixes
+                .split(',')
+                .map(parse_prefix)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// Which team owns every pixel of a canvas, and how many pixels each team owns.
+#[derive(Debug)]
+pub struct Territory {
+    teams: Vec<Team>,
+    /// Team index plus one for every pixel, or 0 for pixels that no team owns.
+    owners: Box<[AtomicU8]>,
+    /// Number of owned pixels for each team, by team index.
+    scores: Box<[AtomicU64]>,
+}
+
+impl Territory {
+    /// Tracks the territory of up to 255 teams on a canvas with the given number of pixels.
+    pub fn new(teams: Vec<Team>, pixel_count: usize) -> Self {
+        assert!(teams.len() < usize::from(u8::MAX), "too many teams");
+        Self {
+            owners: (0..pixel_count).map(|_| AtomicU8::new(0)).collect(),
+            scores: teams.iter().map(|_| AtomicU64::new(0)).collect(),
+            teams,
+        }
+    }
+
+    /// The owner value of the team the source belongs to, or 0 if it belongs to none.
+    fn owner_of(&self, source: IpAddr) -> u8 {
+        self.teams
+            .iter()
+            .position(|team| team.prefixes.iter().any(|prefix| prefix.contains(&source)))
+            .map_or(0, |index| index as u8 + 1)
+    }
+
+    /// Hands a pixel to the team of the source that set it. Sources without a team make the pixel neutral.
+    pub fn claim(&self, pixel_index: usize, source: IpAddr) {
+        self.set_owner(pixel_index, self.owner_of(source));
+    }
+
+    /// Makes a pixel neutral, for example because it was reset to the background.
+    pub fn release(&self, pixel_index: usize) {
+        self.set_owner(pixel_index, 0);
+    }
+
+    fn set_owner(&self, pixel_index: usize, owner: u8) {
+        let previous = self.owners[pixel_index].swap(owner, Ordering::Relaxed);
+        if previous == owner {
+            return;
+        }
+        if previous > 0 {
+            // Saturating, since a concurrent reset may have zeroed the score already.
+            let _ = self.scores[usize::from(previous - 1)].fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |score| Some(score.saturating_sub(1)),
+            );
+        }
+        if owner > 0 {
+            self.scores[usize::from(owner - 1)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Makes every pixel neutral.
+    pub fn release_all(&self) {
+        for owner in self.owners.iter() {
+            owner.store(0, Ordering::Relaxed);
+        }
+        for score in self.scores.iter() {
+            score.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// The name and number of owned pixels of every team, with the highest score first.
+    pub fn scores(&self) -> Vec<(&str, u64)> {
+        let mut scores: Vec<_> = self
+            .teams
+            .iter()
+            .zip(self.scores.iter())
+            .map(|(team, score)| (team.name.as_str(), score.load(Ordering::Relaxed)))
+            .collect();
+        scores.sort_by_key(|(_, score)| Reverse(*score));
+        scores
+    }
+}
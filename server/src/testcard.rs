@@ -0,0 +1,90 @@
+//! Test card for checking the projector setup before participants start drawing.
+//!
+//! The card has color bars and gradients for checking colors, and a labeled coordinate grid with a border
+//! for checking the geometry and the coordinate system of the canvas.
+
+use embedded_graphics::{
+    mono_font::{ascii::FONT_6X10, MonoTextStyle},
+    pixelcolor::Rgb888,
+    prelude::*,
+    primitives::{Line, PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+use image::{Rgba, RgbaImage};
+
+use crate::overlay::FrameTarget;
+
+/// Distance between grid lines in canvas pixels.
+const GRID_SPACING: u32 = 100;
+
+/// Colors of the bars, in the order of the usual SMPTE bars.
+const BARS: [[u8; 3]; 7] = [
+    [0xc0, 0xc0, 0xc0],
+    [0xc0, 0xc0, 0x00],
+    [0x00, 0xc0, 0xc0],
+    [0x00, 0xc0, 0x00],
+    [0xc0, 0x00, 0xc0],
+    [0xc0, 0x00, 0x00],
+    [0x00, 0x00, 0xc0],
+];
+
+/// Renders a test card of the given size.
+pub fn render(width: u16, height: u16) -> RgbaImage {
+    let (width, height) = (u32::from(width), u32::from(height));
+    // The top half has color bars, the third quarter a gray gradient, and the last quarter red, green and blue gradients.
+    let bars_end = height / 2;
+    let gray_end = height * 3 / 4;
+    let mut image = RgbaImage::from_fn(width, height, |x, y| {
+        let level = (x * 255 / width.saturating_sub(1).max(1)) as u8;
+        if y < bars_end {
+            let [r, g, b] = BARS[(x * BARS.len() as u32 / width) as usize];
+            Rgba([r, g, b, 0xff])
+        } else if y < gray_end {
+            Rgba([level, level, level, 0xff])
+        } else {
+            let mut color = [0, 0, 0, 0xff];
+            color[((y - gray_end) * 3 / (height - gray_end).max(1)).min(2) as usize] = level;
+            Rgba(color)
+        }
+    });
+
+    let mut target = FrameTarget::new(&mut image, width, height);
+    let line_style = PrimitiveStyle::with_stroke(Rgb888::WHITE, 1);
+    let label_style = MonoTextStyle::new(&FONT_6X10, Rgb888::WHITE);
+    for x in (GRID_SPACING..width).step_by(GRID_SPACING as usize) {
+        let _ = Line::new(Point::new(x as i32, 0), Point::new(x as i32, height as i32))
+            .into_styled(line_style)
+            .draw(&mut target);
+        let _ = Text::with_baseline(
+            &x.to_string(),
+            Point::new(x as i32 + 2, 2),
+            label_style,
+            Baseline::Top,
+        )
+        .draw(&mut target);
+    }
+    for y in (GRID_SPACING..height).step_by(GRID_SPACING as usize) {
+        let _ = Line::new(Point::new(0, y as i32), Point::new(width as i32, y as i32))
+            .into_styled(line_style)
+            .draw(&mut target);
+        let _ = Text::with_baseline(
+            &y.to_string(),
+            Point::new(2, y as i32 + 2),
+            label_style,
+            Baseline::Top,
+        )
+        .draw(&mut target);
+    }
+    let _ = Rectangle::new(Point::zero(), Size::new(width, height))
+        .into_styled(line_style)
+        .draw(&mut target);
+    // The origin is labeled too, so that a flipped or shifted picture is easy to spot.
+    let _ = Text::with_baseline(
+        &format!("0,0  {width}x{height}"),
+        Point::new(2, 2),
+        label_style,
+        Baseline::Top,
+    )
+    .draw(&mut target);
+    image
+}
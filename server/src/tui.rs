@@ -0,0 +1,192 @@
+//! Terminal output, which renders a downscaled canvas with half-block characters and 24-bit ANSI colors,
+//! so that the server can run and be checked over SSH on machines without a display or GPU.
+//!
+//! Every character cell shows two pixels: the upper half block is drawn in the foreground color
+//! of the upper pixel, on the background color of the lower pixel.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use image::{imageops, RgbaImage};
+use log::{error, info, warn};
+use parking_lot::RwLock;
+use pingxelflut::icmp::ShutdownHandle;
+use terminal_size::{terminal_size, Height, Width};
+
+use crate::{
+    canvas::{Background, Canvas, Fade},
+    ping_handler,
+    snapshot::save_snapshot,
+    systemd::{self, Liveness},
+    wait_for_termination,
+    webhook::Webhooks,
+    window::{create_canvas, SHUTDOWN_TIMEOUT},
+    Arguments,
+};
+
+/// Terminal size used when it can’t be determined, such as when the output is redirected.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+/// Switches to the alternate screen and hides the cursor, so that the shell is restored afterwards.
+const ENTER: &str = "\x1b[?1049h\x1b[?25l";
+const LEAVE: &str = "\x1b[0m\x1b[?25h\x1b[?1049l";
+
+/// Renders one frame of the canvas for a terminal with the given number of columns and rows.
+/// The last row is left for the status line.
+fn render(image: &RgbaImage, (columns, rows): (u16, u16), status: &str) -> String {
+    let (width, height) = image.dimensions();
+    let available_width = u32::from(columns).max(1);
+    let available_height = u32::from(rows.saturating_sub(1)).max(1) * 2;
+    let scale =
+        (available_width as f64 / width as f64).min(available_height as f64 / height as f64);
+    let scaled = imageops::thumbnail(
+        image,
+        ((width as f64 * scale) as u32).clamp(1, available_width),
+        ((height as f64 * scale) as u32).clamp(1, available_height),
+    );
+
+    let mut frame = String::from("\x1b[H");
+    let mut colors = None;
+    for y in (0..scaled.height()).step_by(2) {
+        for x in 0..scaled.width() {
+            let upper = scaled.get_pixel(x, y).0;
+            // Images with an odd height leave the lower half of the last row empty.
+            let lower = if y + 1 < scaled.height() {
+                scaled.get_pixel(x, y + 1).0
+            } else {
+                [0, 0, 0, 0xff]
+            };
+            if colors != Some((upper, lower)) {
+                let _ = write!(
+                    frame,
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                    upper[0], upper[1], upper[2], lower[0], lower[1], lower[2]
+                );
+                colors = Some((upper, lower));
+            }
+            frame.push('▀');
+        }
+        frame.push_str("\x1b[0m\x1b[K\r\n");
+        colors = None;
+    }
+    let _ = write!(frame, "\x1b[J{status}");
+    frame
+}
+
+/// Applies queued pixels like a window would for every frame.
+fn apply(canvas: &Canvas, fade: Option<&mut Fade>, budget: Option<usize>) {
+    if let Some(fade) = fade {
+        if canvas.is_paused() {
+            fade.hold();
+        } else {
+            fade.update(canvas);
+        }
+    }
+    canvas.set_queue_pixels(budget);
+    canvas.take_dirty();
+}
+
+/// Runs the server with the first canvas rendered to the terminal instead of windows, until it is asked to terminate.
+pub async fn run(arguments: Arguments, background: Background) -> Result<()> {
+    let canvases: Vec<Canvas> = (0..arguments.canvases.len())
+        .map(|index| create_canvas(&arguments, index, &background))
+        .collect();
+    let shutdown = ShutdownHandle::new();
+    let liveness = Arc::<Liveness>::default();
+    let received_packets = Arc::<AtomicU64>::default();
+    let webhooks = Arc::new(Webhooks::new(arguments.webhook.clone()));
+    let snapshot_directory = arguments.snapshot_directory.clone();
+    let frame_interval = Duration::from_secs(1) / arguments.tui_fps;
+    let budget = Some(arguments.max_pixels_per_frame).filter(|&budget| budget > 0);
+    let mut fades: Vec<_> = canvases
+        .iter()
+        .map(|_| arguments.fade_half_life.map(Fade::new))
+        .collect();
+
+    let listeners = tokio::spawn(ping_handler(
+        canvases.clone(),
+        Arc::new(RwLock::new(arguments)),
+        shutdown.clone(),
+        liveness.clone(),
+        received_packets.clone(),
+        webhooks.clone(),
+    ));
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let renderer = {
+        let canvases = canvases.clone();
+        let stop = stop.clone();
+        thread::spawn(move || -> io::Result<()> {
+            let mut stdout = io::stdout();
+            stdout.write_all(ENTER.as_bytes())?;
+            let mut last_packets = received_packets.load(Ordering::Relaxed);
+            let mut last_frame = Instant::now();
+            while !stop.load(Ordering::Relaxed) {
+                for (canvas, fade) in canvases.iter().zip(&mut fades) {
+                    apply(canvas, fade.as_mut(), budget);
+                }
+                liveness.frame_rendered();
+
+                let packets = received_packets.load(Ordering::Relaxed);
+                let packets_per_second =
+                    (packets - last_packets) as f64 / last_frame.elapsed().as_secs_f64();
+                last_packets = packets;
+                last_frame = Instant::now();
+                let sources = canvases[0]
+                    .leaderboard
+                    .lock()
+                    .source_count()
+                    .unwrap_or_default();
+                let size = terminal_size()
+                    .map(|(Width(columns), Height(rows))| (columns, rows))
+                    .unwrap_or(DEFAULT_SIZE);
+                let status =
+                    format!("{packets_per_second:.0} packets/s, {sources} sources, Ctrl+C to stop");
+                let frame = render(&canvases[0].to_image(), size, &status);
+                stdout.write_all(frame.as_bytes())?;
+                stdout.flush()?;
+                thread::sleep(frame_interval);
+            }
+            stdout.write_all(LEAVE.as_bytes())?;
+            stdout.flush()
+        })
+    };
+
+    if let Err(why) = wait_for_termination().await {
+        error!("could not listen for termination signals: {}", why);
+    }
+    stop.store(true, Ordering::Relaxed);
+    if let Ok(Err(why)) = renderer.join() {
+        error!("could not render to the terminal: {}", why);
+    }
+    info!("shutting down");
+
+    systemd::notify_stopping();
+    shutdown.shutdown();
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, listeners)
+        .await
+        .is_err()
+    {
+        warn!("listeners did not stop in time");
+    }
+    let mut notifications = Vec::new();
+    for (index, canvas) in canvases.iter().enumerate() {
+        canvas.set_queue_pixels(None);
+        match save_snapshot(canvas, index, &snapshot_directory) {
+            Ok(path) => notifications.push(webhooks.snapshot_saved(index, &path)),
+            Err(why) => error!("could not save final snapshot: {}", why),
+        }
+    }
+    for notification in notifications {
+        let _ = notification.join();
+    }
+    Ok(())
+}
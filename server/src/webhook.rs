@@ -0,0 +1,190 @@
+//! Notifications about server events for chat channels and monitoring, sent as HTTP POST requests.
+//!
+//! Discord and Slack webhooks receive a chat message, generic webhooks a JSON object with the event name,
+//! the message and the event’s details.
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    str::FromStr,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::SharedState;
+
+/// How long a webhook may take to accept a notification.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the pixel and source counts are checked for milestones.
+const MILESTONE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which kind of service a webhook belongs to, which determines the request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookKind {
+    /// A JSON object with the event details.
+    Generic,
+    Discord,
+    Slack,
+}
+
+/// A configured webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    kind: WebhookKind,
+    url: String,
+}
+
+impl FromStr for Webhook {
+    type Err = String;
+
+    /// Parse a webhook URL, optionally prefixed by its kind like `discord=https://…`.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (kind, url) = match text.split_once('=') {
+            Some(("generic", url)) => (WebhookKind::Generic, url),
+            Some(("discord", url)) => (WebhookKind::Discord, url),
+            Some(("slack", url)) => (WebhookKind::Slack, url),
+            _ => (WebhookKind::Generic, text),
+        };
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(format!("webhook URL must use HTTP or HTTPS: {url}"));
+        }
+        Ok(Self {
+            kind,
+            url: url.to_string(),
+        })
+    }
+}
+
+/// Something that happened on the server.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Started,
+    /// The total number of applied pixels passed a multiple of the pixel milestone.
+    PixelMilestone {
+        pixels: u64,
+    },
+    /// The number of sources that drew reached a new power of ten.
+    SourceRecord {
+        sources: usize,
+    },
+    SnapshotSaved {
+        canvas: usize,
+        path: String,
+    },
+}
+
+impl Event {
+    fn message(&self) -> String {
+        match self {
+            Event::Started => "Pingxelflut server started".to_string(),
+            Event::PixelMilestone { pixels } => format!("{pixels} pixels drawn!"),
+            Event::SourceRecord { sources } => format!("{sources} sources have drawn!"),
+            Event::SnapshotSaved { canvas, path } => {
+                format!("Snapshot of canvas {canvas} saved to {path}")
+            }
+        }
+    }
+}
+
+/// Sends events to all configured webhooks.
+#[derive(Debug, Default)]
+pub struct Webhooks {
+    hooks: Vec<Webhook>,
+}
+
+impl Webhooks {
+    pub fn new(hooks: Vec<Webhook>) -> Self {
+        Self { hooks }
+    }
+
+    /// Sends the event to all webhooks in the background. Failures are only logged.
+    /// The returned thread finishes once all webhooks were notified.
+    pub fn notify(&self, event: Event) -> JoinHandle<()> {
+        let hooks = self.hooks.clone();
+        thread::spawn(move || {
+            let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+            let message = event.message();
+            for hook in hooks {
+                let body = match hook.kind {
+                    WebhookKind::Generic => {
+                        let mut body =
+                            serde_json::to_value(&event).expect("events are serializable");
+                        body["message"] = message.clone().into();
+                        body
+                    }
+                    WebhookKind::Discord => json!({ "content": message }),
+                    WebhookKind::Slack => json!({ "text": message }),
+                };
+                if let Err(why) = agent.post(&hook.url).send_json(body) {
+                    warn!("webhook {} failed: {}", hook.url, why);
+                }
+            }
+        })
+    }
+
+    /// Notifies about a snapshot that was saved.
+    pub fn snapshot_saved(&self, canvas: usize, path: &Path) -> JoinHandle<()> {
+        self.notify(Event::SnapshotSaved {
+            canvas,
+            path: path.display().to_string(),
+        })
+    }
+}
+
+/// Notifies about pixel and source milestones until an error occurs.
+/// A pixel milestone of 0 only disables the pixel milestones.
+pub async fn watch_milestones(state: SharedState, pixel_milestone: u64) -> Result<()> {
+    let mut ticker = tokio::time::interval(MILESTONE_INTERVAL);
+    let mut pixels_reached = 0;
+    let mut next_source_record = 10;
+    loop {
+        ticker.tick().await;
+        let pixels: u64 = state
+            .canvases
+            .iter()
+            .map(|canvas| canvas.leaderboard.lock().total())
+            .sum();
+        if pixel_milestone > 0 && pixels / pixel_milestone > pixels_reached {
+            pixels_reached = pixels / pixel_milestone;
+            state.webhooks.notify(Event::PixelMilestone {
+                pixels: pixels_reached * pixel_milestone,
+            });
+        }
+
+        // Sources drawing on several canvases are counted once, which is only worth the effort near a record.
+        let source_bound: usize = state
+            .canvases
+            .iter()
+            .filter_map(|canvas| canvas.leaderboard.lock().source_count())
+            .sum();
+        if source_bound < next_source_record {
+            continue;
+        }
+        let sources = if state.canvases.len() == 1 {
+            source_bound
+        } else {
+            state
+                .canvases
+                .iter()
+                .flat_map(|canvas| canvas.leaderboard.lock().sources())
+                .collect::<HashSet<_>>()
+                .len()
+        };
+        if sources >= next_source_record {
+            while sources >= next_source_record * 10 {
+                next_source_record *= 10;
+            }
+            state.webhooks.notify(Event::SourceRecord {
+                sources: next_source_record,
+            });
+            next_source_record *= 10;
+        }
+    }
+}
@@ -1,103 +1,927 @@
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, mpsc, Arc},
+    time::{Duration, Instant},
+};
 
-use crate::{canvas::Canvas, ping_handler};
-use log::error;
-use parking_lot::RwLock;
-use pixels::{wgpu::Color, Pixels, SurfaceTexture};
+use crate::{
+    attract::{Attract, AttractContent},
+    canvas::{Background, Canvas, DirtyRect, Fade, Ingest, ProtectedRegion},
+    compositor::{Compositing, GpuCompositor},
+    history::HistoryView,
+    leaderboard::Leaderboard,
+    overlay::{self, FrameTarget, Placement},
+    ping_handler,
+    priority::{LowPriorityPrefixes, Policies, Prioritization, ShareThreshold},
+    renderer::{CanvasRenderer, ColorCorrection, Viewport},
+    snapshot::save_snapshot,
+    stats_window::StatsWindow,
+    systemd::{self, Liveness},
+    testcard,
+    webhook::Webhooks,
+    Arguments,
+};
+use clap::ValueEnum;
+use image::RgbaImage;
+use log::{debug, error, warn};
+use parking_lot::{Mutex, RwLock};
+use pingxelflut::{format::COLOR_SIZE, icmp::ShutdownHandle};
+use pixels::{wgpu::Color, Pixels, PixelsBuilder, SurfaceTexture};
 use winit::{
     application::ApplicationHandler,
-    event::WindowEvent,
-    event_loop::ActiveEventLoop,
-    window::{Window, WindowId},
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow},
+    keyboard::{Key, NamedKey},
+    monitor::MonitorHandle,
+    window::{Fullscreen, Window, WindowId},
 };
 
+/// How long to wait for listeners to stop on shutdown.
+pub(crate) const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// Magnification per line scrolled with the mouse wheel.
+const ZOOM_PER_SCROLL_LINE: f32 = 1.2;
+/// Scroll distance of touchpads that counts as one line.
+const PIXELS_PER_SCROLL_LINE: f32 = 40.0;
+/// How long notices like screenshot confirmations are shown.
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+/// How many keyframes Page Up and Page Down skip in the history view.
+const HISTORY_PAGE: isize = 10;
+
+/// How canvas windows are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum DisplayMode {
+    /// A normal, decorated window.
+    #[default]
+    Windowed,
+    /// A borderless window covering the entire monitor.
+    Borderless,
+    /// Exclusive fullscreen with the monitor’s largest video mode.
+    Fullscreen,
+}
+
+impl DisplayMode {
+    fn fullscreen(self, monitor: Option<MonitorHandle>) -> Option<Fullscreen> {
+        match self {
+            DisplayMode::Windowed => None,
+            DisplayMode::Borderless => Some(Fullscreen::Borderless(monitor)),
+            DisplayMode::Fullscreen => {
+                let video_mode = monitor.and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                });
+                match video_mode {
+                    Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                    None => {
+                        warn!("no video mode available for exclusive fullscreen, using borderless fullscreen");
+                        Some(Fullscreen::Borderless(None))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Scrolls the viewport across the canvas, bouncing off the canvas edges.
+struct ViewportScroll {
+    viewport: Viewport,
+    /// Speed in canvas pixels per second.
+    velocity: (f32, f32),
+    canvas_size: (f32, f32),
+    last_update: Instant,
+}
+
+impl ViewportScroll {
+    /// Moves the viewport according to the time since the last update and returns it.
+    fn update(&mut self) -> Viewport {
+        let elapsed = self.last_update.elapsed().as_secs_f32();
+        self.last_update = Instant::now();
+        let max_x = self.canvas_size.0 - self.viewport.width;
+        let max_y = self.canvas_size.1 - self.viewport.height;
+        (self.viewport.x, self.velocity.0) = bounce(
+            self.viewport.x + self.velocity.0 * elapsed,
+            self.velocity.0,
+            max_x,
+        );
+        (self.viewport.y, self.velocity.1) = bounce(
+            self.viewport.y + self.velocity.1 * elapsed,
+            self.velocity.1,
+            max_y,
+        );
+        self.viewport
+    }
+}
+
+/// Keeps a position within 0 and the maximum, reversing the velocity when it hits either end.
+pub(crate) fn bounce(position: f32, velocity: f32, max: f32) -> (f32, f32) {
+    if position < 0.0 {
+        (0.0, velocity.abs())
+    } else if position > max {
+        (max.max(0.0), -velocity.abs())
+    } else {
+        (position, velocity)
+    }
+}
+
+/// The prioritization policies configured for a canvas, if any. Every canvas measures the shares of its sources separately.
+fn prioritization(arguments: &Arguments) -> Option<Arc<dyn Prioritization>> {
+    let mut policies: Vec<Box<dyn Prioritization>> = Vec::new();
+    if !arguments.low_priority.is_empty() {
+        policies.push(Box::new(LowPriorityPrefixes(
+            arguments.low_priority.clone(),
+        )));
+    }
+    if let Some(share_threshold) = arguments.share_threshold {
+        policies.push(Box::new(ShareThreshold::new(share_threshold / 100.0)));
+    }
+    match policies.len() {
+        0 => None,
+        1 => policies.pop().map(Arc::from),
+        _ => Some(Arc::new(Policies(policies))),
+    }
+}
+
+/// Counts applied pixels per source only for what shows them: the activity log, webhooks, the admin API and the
+/// terminal view.
+fn leaderboard(arguments: &Arguments) -> Leaderboard {
+    Leaderboard::default()
+        .with_sources(
+            arguments.activity_log.is_some()
+                || !arguments.webhook.is_empty()
+                || arguments.admin_address.is_some()
+                || arguments.tui,
+        )
+        .with_ranking(arguments.leaderboard, arguments.leaderboard_anonymization)
+}
+
+/// Creates a canvas as configured, with the index of the canvas among all canvases.
+pub(crate) fn create_canvas(
+    arguments: &Arguments,
+    index: usize,
+    background: &Background,
+) -> Canvas {
+    let (width, height) = arguments.canvases[index];
+    let canvas = Canvas::new(
+        width,
+        height,
+        arguments.blend_mode,
+        background,
+        arguments.ingest,
+        arguments.queue_capacity,
+        arguments.overflow_policy,
+    )
+    .with_teams(arguments.team.clone())
+    .with_prioritization(prioritization(arguments))
+    .with_leaderboard(leaderboard(arguments))
+    .with_fading(arguments.fade_half_life.is_some());
+    canvas.set_protected_regions(ProtectedRegion::for_canvas(&arguments.protect, index));
+    if arguments.test_card {
+        canvas.draw_image(&testcard::render(width, height), 0, 0);
+    }
+    canvas
+}
+
+/// A window displaying one canvas.
+struct Output {
+    window: Arc<Window>,
+    pixels: Pixels,
+    renderer: CanvasRenderer,
+    /// Applies queued pixels on the GPU, if GPU compositing is enabled.
+    compositor: Option<GpuCompositor>,
+    /// Index of the canvas, which also selects it through the echo identifier.
+    index: usize,
+    canvas: Canvas,
+    fade: Option<Fade>,
+    /// The canvas with overlays on top, as last uploaded to the renderer.
+    frame: Vec<u8>,
+    /// Area covered by overlays in the last frame, which needs to be restored from the canvas.
+    overlay_area: Option<DirtyRect>,
+    viewport_scroll: Option<ViewportScroll>,
+    /// Last known cursor position within the window, for zooming and dragging.
+    cursor_position: (f32, f32),
+    /// Whether the canvas is being dragged with the left mouse button.
+    dragging: bool,
+    /// A short message shown in the corner until it expires, such as the confirmation of a screenshot.
+    notice: Arc<Mutex<Option<(String, Instant)>>>,
+    /// Shows a past keyframe instead of the live canvas while set.
+    history: Option<HistoryView>,
+    /// When participants last sent a pixel, or the window opened.
+    last_pixel: Instant,
+    /// The attract animation, while nobody draws.
+    attract: Option<Attract>,
+}
+
+impl Output {
+    fn redraw(
+        &mut self,
+        arguments: &Arguments,
+        watermark: Option<&RgbaImage>,
+        attract_content: Option<&AttractContent>,
+    ) -> Result<(), pixels::Error> {
+        if self.canvas.take_pixel_arrived() {
+            self.last_pixel = Instant::now();
+            // The animation covered the whole frame, which the overlay area restores from the canvas.
+            self.attract = None;
+        } else if self.attract.is_none()
+            && attract_content.is_some()
+            && arguments
+                .attract_after
+                .is_some_and(|attract_after| self.last_pixel.elapsed() >= attract_after)
+        {
+            self.attract = Some(Attract::new());
+        }
+
+        if let Some(fade) = self.fade.as_mut() {
+            if self.canvas.is_paused() {
+                fade.hold();
+            } else if let Some(compositor) = self.compositor.as_mut() {
+                compositor.fade(fade.due_steps(), fade.retain());
+            } else {
+                fade.update(&self.canvas);
+            }
+        }
+        let budget = Some(arguments.max_pixels_per_frame).filter(|&budget| budget > 0);
+        let backlog = match self.compositor.as_mut() {
+            Some(compositor) => {
+                let budget = compositor.budget(budget);
+                self.canvas.drain_queue(Some(budget), |pixel_pos, color| {
+                    compositor.queue_pixel(pixel_pos, color)
+                })
+            }
+            None => self.canvas.set_queue_pixels(budget),
+        };
+        if backlog > 0 {
+            debug!("{} pixels carried over to the next frame", backlog);
+        }
+
+        if let Some(viewport_scroll) = self.viewport_scroll.as_mut() {
+            let viewport = viewport_scroll.update();
+            self.renderer
+                .set_viewport(&self.pixels.context().queue, viewport);
+        }
+
+        self.renderer.set_color_correction(
+            &self.pixels.context().queue,
+            ColorCorrection {
+                gamma: arguments.gamma,
+                gain: arguments.color_gain,
+            },
+        );
+
+        let mut dirty = self.canvas.take_dirty();
+        if let (Some(compositor), Some(dirty)) = (self.compositor.as_mut(), dirty) {
+            compositor.upload(&self.pixels, &self.canvas, dirty);
+        }
+        if let Some(overlay_area) = self.overlay_area.take() {
+            overlay_area.add_to(&mut dirty);
+        }
+        let canvas_area = DirtyRect::full(self.canvas.width, self.canvas.height);
+        match self.history.as_mut().and_then(HistoryView::shown) {
+            // The whole frame shows the keyframe, so it counts as an overlay that hides the live canvas.
+            Some(keyframe) => {
+                self.frame.copy_from_slice(keyframe.as_raw());
+                self.overlay_area = Some(canvas_area);
+            }
+            None => {
+                if let Some(dirty) = dirty {
+                    self.canvas.draw_to(&mut self.frame, dirty);
+                }
+            }
+        }
+
+        let mut target = FrameTarget::new(
+            &mut self.frame,
+            self.canvas.width.into(),
+            self.canvas.height.into(),
+        );
+        if let (Some(attract), Some(attract_content)) = (self.attract.as_mut(), attract_content) {
+            attract.draw(&mut target, attract_content);
+            self.overlay_area = Some(canvas_area);
+        }
+        if let Some(watermark) = watermark {
+            let area = overlay::draw_image(&mut target, watermark, arguments.watermark_position);
+            if let Some(area) = overlay::dirty_rect(area) {
+                area.add_to(&mut self.overlay_area);
+            }
+        }
+        let mut notice = self.notice.lock();
+        if notice
+            .as_ref()
+            .is_some_and(|(_, shown_since)| shown_since.elapsed() >= NOTICE_DURATION)
+        {
+            *notice = None;
+        }
+        if let Some((text, _)) = notice.as_ref() {
+            let area =
+                overlay::draw_text_box(&mut target, std::slice::from_ref(text), Placement::TopLeft);
+            if let Some(area) = overlay::dirty_rect(area) {
+                area.add_to(&mut self.overlay_area);
+            }
+        }
+        drop(notice);
+        if let Some(history) = self.history.as_ref() {
+            let area = overlay::draw_timeline(&mut target, history.progress(), &history.label());
+            if let Some(area) = overlay::dirty_rect(area) {
+                area.add_to(&mut self.overlay_area);
+            }
+        } else if self.canvas.is_paused() {
+            let area =
+                overlay::draw_text_box(&mut target, &["Paused".to_string()], Placement::BottomLeft);
+            if let Some(area) = overlay::dirty_rect(area) {
+                area.add_to(&mut self.overlay_area);
+            }
+        }
+        if let Some(territory) = &self.canvas.territory {
+            let area = overlay::draw_scoreboard(
+                &mut target,
+                &territory.scores(),
+                self.canvas.width as u64 * self.canvas.height as u64,
+                arguments.scoreboard_position,
+            );
+            if let Some(area) = overlay::dirty_rect(area) {
+                area.add_to(&mut self.overlay_area);
+            }
+        }
+        if arguments.leaderboard > 0 {
+            let area = overlay::draw_leaderboard(
+                &mut target,
+                &self.canvas.leaderboard.lock(),
+                arguments.leaderboard,
+            );
+            if let Some(area) = area.and_then(overlay::dirty_rect) {
+                area.add_to(&mut self.overlay_area);
+            }
+        }
+
+        if let Some(compositor) = self.compositor.as_mut() {
+            // The compositor writes everything except the overlays into the texture.
+            dirty = self.overlay_area;
+            compositor.prepare(&self.pixels, self.overlay_area);
+        } else if let Some(overlay_area) = self.overlay_area {
+            overlay_area.add_to(&mut dirty);
+        }
+        if let Some(dirty) = dirty {
+            self.renderer
+                .upload(&self.pixels.context().queue, &self.frame, dirty);
+        }
+
+        let renderer = &self.renderer;
+        let mut compositor = self.compositor.as_mut();
+        let result = self.pixels.render_with(|encoder, render_target, _context| {
+            if let Some(compositor) = compositor.as_mut() {
+                compositor.encode(encoder);
+            }
+            renderer.render(encoder, render_target);
+            Ok(())
+        });
+        if let Some(compositor) = self.compositor.as_mut() {
+            compositor.continue_readback(&self.pixels, &self.canvas);
+        }
+        result
+    }
+
+    /// Switches between the live canvas and the history view, which starts at the latest keyframe.
+    fn toggle_history(&mut self, history_directory: Option<&Path>) {
+        if self.history.take().is_some() {
+            // The keyframe still in the frame needs to be replaced by the live canvas.
+            self.overlay_area = Some(DirtyRect::full(self.canvas.width, self.canvas.height));
+            return;
+        }
+        let size = (self.canvas.width.into(), self.canvas.height.into());
+        self.history =
+            history_directory.and_then(|directory| HistoryView::open(directory, self.index, size));
+        if self.history.is_none() {
+            *self.notice.lock() = Some(("No history recorded".to_string(), Instant::now()));
+        }
+    }
+
+    /// Saves a snapshot of the canvas in the background and briefly shows where it was saved.
+    fn screenshot(&self, snapshot_directory: PathBuf, webhooks: Arc<Webhooks>) {
+        let canvas = self.canvas.clone();
+        let index = self.index;
+        let notice = self.notice.clone();
+        tokio::task::spawn_blocking(move || {
+            let text = match save_snapshot(&canvas, index, &snapshot_directory) {
+                Ok(path) => {
+                    webhooks.snapshot_saved(index, &path);
+                    format!("Saved {}", path.display())
+                }
+                Err(why) => {
+                    error!("could not save screenshot: {}", why);
+                    "Screenshot failed".to_string()
+                }
+            };
+            *notice.lock() = Some((text, Instant::now()));
+        });
+    }
+
+    fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            // Minimized windows have no surface to render to.
+            return;
+        }
+        if let Err(err) = self.pixels.resize_surface(width, height) {
+            error!("pixels.resize_surface: {}", err);
+            return;
+        }
+        self.renderer
+            .resize(&self.pixels.context().queue, width, height);
+    }
+}
+
+/// Converts an sRGB color to the linear color that the render pass clears with.
+fn clear_color(color: pingxelflut::format::Color) -> Color {
+    let linear = |channel: u8| {
+        let channel = f64::from(channel) / 255.;
+        if channel <= 0.04045 {
+            channel / 12.92
+        } else {
+            ((channel + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Color {
+        r: linear(color.r),
+        g: linear(color.g),
+        b: linear(color.b),
+        a: 1.,
+    }
+}
+
+/// Events sent to the window event loop from other threads.
+#[derive(Debug)]
+pub enum AppEvent {
+    /// Stop the server gracefully, keeping what was drawn.
+    Shutdown,
+}
+
 pub struct App {
-    window_id: Option<WindowId>,
-    window: Option<Arc<Window>>,
-    pixels: Option<Arc<RwLock<Pixels>>>,
-    canvas: Option<Canvas>,
-    width: u16,
-    height: u16,
+    outputs: HashMap<WindowId, Output>,
+    /// The current configuration, which may be reloaded while the server runs.
+    settings: Arc<RwLock<Arguments>>,
+    background: Background,
+    /// Image composited over every canvas, which participants can’t draw over.
+    watermark: Option<RgbaImage>,
+    /// What the attract animation shows, if it is enabled.
+    attract_content: Option<AttractContent>,
+    shutdown: ShutdownHandle,
+    liveness: Arc<Liveness>,
+    /// Number of packets received by all listeners.
+    received_packets: Arc<AtomicU64>,
+    stats_window: Option<StatsWindow>,
+    /// Notified once all listeners have stopped after a shutdown.
+    listeners_stopped: Option<mpsc::Receiver<()>>,
+    /// When the windows are redrawn next, if the frame rate is capped.
+    next_frame: Instant,
+    webhooks: Arc<Webhooks>,
 }
 
 impl App {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(
+        arguments: Arguments,
+        background: Background,
+        watermark: Option<RgbaImage>,
+        attract_content: Option<AttractContent>,
+    ) -> Self {
+        let webhooks = Arc::new(Webhooks::new(arguments.webhook.clone()));
         Self {
-            window_id: None,
-            window: None,
-            pixels: None,
-            canvas: None,
-            width,
-            height,
+            outputs: HashMap::new(),
+            settings: Arc::new(RwLock::new(arguments)),
+            background,
+            watermark,
+            attract_content,
+            shutdown: ShutdownHandle::new(),
+            liveness: Arc::default(),
+            received_packets: Arc::default(),
+            stats_window: None,
+            listeners_stopped: None,
+            next_frame: Instant::now(),
+            webhooks,
         }
     }
 }
 
-impl ApplicationHandler for App {
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        if let Some(window) = self.window.as_ref() {
-            window.request_redraw();
+impl App {
+    /// Stops the listeners, applies the pixels they received and saves a final snapshot of every canvas before exiting.
+    fn shut_down(&mut self, event_loop: &ActiveEventLoop) {
+        systemd::notify_stopping();
+        self.shutdown.shutdown();
+        if let Some(listeners_stopped) = self.listeners_stopped.take() {
+            if listeners_stopped.recv_timeout(SHUTDOWN_TIMEOUT).is_err() {
+                warn!("listeners did not stop in time");
+            }
+        }
+
+        let snapshot_directory = self.settings.read().snapshot_directory.clone();
+        let mut canvases: Vec<_> = self
+            .outputs
+            .drain()
+            .map(|(_, mut output)| {
+                // Pixels applied on the GPU since the last readback would be missing from the snapshot.
+                if let Some(compositor) = output.compositor.as_mut() {
+                    compositor.read_back(&output.pixels, &output.canvas);
+                }
+                (output.index, output.canvas)
+            })
+            .collect();
+        canvases.sort_by_key(|(index, _)| *index);
+        let mut notifications = Vec::new();
+        for (index, canvas) in canvases {
+            canvas.set_queue_pixels(None);
+            match save_snapshot(&canvas, index, &snapshot_directory) {
+                Ok(path) => notifications.push(self.webhooks.snapshot_saved(index, &path)),
+                Err(why) => error!("could not save final snapshot: {}", why),
+            }
+        }
+        // Notifications are sent from their own threads, which would be cut short by exiting.
+        for notification in notifications {
+            let _ = notification.join();
+        }
+        event_loop.exit();
+    }
+}
+
+impl ApplicationHandler<AppEvent> for App {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let max_fps = self.settings.read().max_fps;
+        match max_fps {
+            Some(max_fps) => {
+                let now = Instant::now();
+                if now < self.next_frame {
+                    event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+                    return;
+                }
+                // Frames missed while the event loop was busy are not caught up on.
+                let frame_interval = Duration::from_secs(1) / max_fps.get();
+                self.next_frame = (self.next_frame + frame_interval).max(now);
+                event_loop.set_control_flow(ControlFlow::WaitUntil(self.next_frame));
+            }
+            None => event_loop.set_control_flow(ControlFlow::Wait),
+        }
+        for output in self.outputs.values() {
+            output.window.request_redraw();
+        }
+        if let Some(stats_window) = self.stats_window.as_mut() {
+            stats_window.update(self.outputs.values().map(|output| &output.canvas));
         }
     }
 
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let window_attributes = Window::default_attributes()
-            .with_title("Pingxelflut")
-            .with_inner_size(winit::dpi::PhysicalSize::new(self.width, self.height));
-
-        let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
-        self.window_id = Some(window.id());
-        self.window = Some(window.clone());
-
-        let window = self.window.as_ref().unwrap().clone();
-        let mut pixels = {
-            let surface_texture =
-                SurfaceTexture::new(self.width as u32, self.height as u32, &window);
-            Pixels::new(self.width as u32, self.height as u32, surface_texture).unwrap()
-        };
-        pixels.clear_color(Color::BLACK);
-        self.pixels = Some(Arc::new(RwLock::new(pixels)));
+        if !self.outputs.is_empty() {
+            return;
+        }
 
-        let canvas = Canvas::new(
-            self.pixels.as_ref().unwrap().clone(),
-            self.width,
-            self.height,
-        );
-        self.canvas = Some(canvas.clone());
+        let arguments = self.settings.read().clone();
+        let mut canvases = Vec::new();
+        for (index, &(width, height)) in arguments.canvases.iter().enumerate() {
+            let title = if arguments.canvases.len() > 1 {
+                format!("Pingxelflut (canvas {index})")
+            } else {
+                "Pingxelflut".to_string()
+            };
+            let monitor = match arguments.monitors.get(index) {
+                Some(&monitor_index) => {
+                    let monitor = event_loop.available_monitors().nth(monitor_index);
+                    if monitor.is_none() {
+                        warn!(
+                            "monitor {} does not exist, using the primary monitor",
+                            monitor_index
+                        );
+                    }
+                    monitor.or_else(|| event_loop.primary_monitor())
+                }
+                None => event_loop.primary_monitor(),
+            };
+            let viewport = match arguments.viewport {
+                Some((viewport_width, viewport_height)) => {
+                    let viewport_width = viewport_width.min(width);
+                    let viewport_height = viewport_height.min(height);
+                    Viewport {
+                        x: arguments.viewport_position.0.min(width - viewport_width) as f32,
+                        y: arguments.viewport_position.1.min(height - viewport_height) as f32,
+                        width: viewport_width as f32,
+                        height: viewport_height as f32,
+                    }
+                }
+                None => Viewport::full(width as f32, height as f32),
+            };
+            let mut window_attributes = Window::default_attributes()
+                .with_title(title)
+                .with_inner_size(winit::dpi::PhysicalSize::new(
+                    viewport.width as u32,
+                    viewport.height as u32,
+                ))
+                .with_fullscreen(arguments.display_mode.fullscreen(monitor.clone()));
+            if let Some(monitor) = monitor {
+                window_attributes = window_attributes.with_position(monitor.position());
+            }
+            let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+
+            // The window may not have the requested size, for example in fullscreen or on small monitors.
+            let surface_size = window.inner_size();
+            let pixels = {
+                let surface_texture =
+                    SurfaceTexture::new(surface_size.width, surface_size.height, &window);
+                // The canvas renderer brings its own texture, so the pixel buffer of `pixels` is never used.
+                PixelsBuilder::new(1, 1, surface_texture)
+                    .enable_vsync(!arguments.no_vsync)
+                    .build()
+                    .unwrap()
+            };
+            let mut renderer = CanvasRenderer::new(
+                &pixels,
+                (width as u32, height as u32),
+                (surface_size.width, surface_size.height),
+                arguments.scaling_filter,
+                clear_color(self.background.color),
+                arguments.compositing == Compositing::Gpu,
+            );
+            renderer.set_viewport(&pixels.context().queue, viewport);
+            let viewport_scroll =
+                (arguments.viewport_scroll != (0.0, 0.0)).then(|| ViewportScroll {
+                    viewport,
+                    velocity: arguments.viewport_scroll,
+                    canvas_size: (width as f32, height as f32),
+                    last_update: Instant::now(),
+                });
+
+            let canvas = create_canvas(&arguments, index, &self.background);
+            let compositor = match arguments.compositing {
+                Compositing::Cpu => None,
+                // The GPU only learns about pixels through the queue.
+                Compositing::Gpu if arguments.ingest == Ingest::Direct => {
+                    warn!("GPU compositing needs queued ingest, using the CPU instead");
+                    None
+                }
+                Compositing::Gpu => {
+                    match GpuCompositor::new(&pixels, renderer.texture(), &canvas) {
+                        Ok(compositor) => Some(compositor),
+                        Err(why) => {
+                            warn!(
+                                "GPU compositing is unavailable, using the CPU instead: {}",
+                                why
+                            );
+                            None
+                        }
+                    }
+                }
+            };
+            canvases.push(canvas.clone());
+            self.outputs.insert(
+                window.id(),
+                Output {
+                    window,
+                    pixels,
+                    renderer,
+                    compositor,
+                    index,
+                    canvas,
+                    fade: arguments.fade_half_life.map(Fade::new),
+                    frame: vec![0; width as usize * height as usize * COLOR_SIZE],
+                    overlay_area: None,
+                    notice: Arc::default(),
+                    viewport_scroll,
+                    cursor_position: (0.0, 0.0),
+                    dragging: false,
+                    history: None,
+                    last_pixel: Instant::now(),
+                    attract: None,
+                },
+            );
+        }
+
+        if arguments.stats_window {
+            self.stats_window = Some(StatsWindow::new(event_loop, self.received_packets.clone()));
+        }
+
+        let settings = self.settings.clone();
+        let shutdown = self.shutdown.clone();
+        let liveness = self.liveness.clone();
+        let received_packets = self.received_packets.clone();
+        let webhooks = self.webhooks.clone();
+        let (stopped_sender, stopped_receiver) = mpsc::channel();
+        self.listeners_stopped = Some(stopped_receiver);
         tokio::spawn(async move {
-            ping_handler(canvas).await;
+            ping_handler(
+                canvases,
+                settings,
+                shutdown,
+                liveness,
+                received_packets,
+                webhooks,
+            )
+            .await;
+            let _ = stopped_sender.send(());
         });
     }
 
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: AppEvent) {
+        match event {
+            AppEvent::Shutdown => self.shut_down(event_loop),
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
         window_id: WindowId,
         event: WindowEvent,
     ) {
-        if event == WindowEvent::Destroyed && self.window_id == Some(window_id) {
-            log::info!("window {:?} destroyed", window_id);
-            self.window_id = None;
+        if event == WindowEvent::Destroyed && self.outputs.is_empty() {
+            log::info!("last window {:?} destroyed", window_id);
             event_loop.exit();
             return;
         }
 
-        let window = match self.window.as_mut() {
-            Some(window) => window,
+        if let Some(stats_window) = self
+            .stats_window
+            .as_mut()
+            .filter(|stats_window| stats_window.id() == window_id)
+        {
+            match event {
+                WindowEvent::CloseRequested => self.stats_window = None,
+                WindowEvent::Resized(size) => stats_window.resize(size.width, size.height),
+                WindowEvent::RedrawRequested => {
+                    if let Err(why) = stats_window.redraw() {
+                        error!("could not draw statistics: {}", why);
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let output = match self.outputs.get_mut(&window_id) {
+            Some(output) => output,
             None => return,
         };
 
         match event {
             WindowEvent::CloseRequested => {
-                log::debug!("window {:?} closed", window.id());
-                self.window = None;
+                log::debug!("window {:?} closed", window_id);
+                self.outputs.remove(&window_id);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Named(NamedKey::F11),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                let window = &output.window;
+                if window.fullscreen().is_some() {
+                    window.set_fullscreen(None);
+                } else {
+                    window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+                }
+            }
+            WindowEvent::Resized(size) => {
+                output.resize(size.width, size.height);
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, lines) => lines,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        position.y as f32 / PIXELS_PER_SCROLL_LINE
+                    }
+                };
+                output.renderer.zoom_at(
+                    &output.pixels.context().queue,
+                    ZOOM_PER_SCROLL_LINE.powf(steps),
+                    output.cursor_position,
+                );
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                output.dragging = state == ElementState::Pressed;
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = (position.x as f32, position.y as f32);
+                if output.dragging {
+                    output.renderer.pan_by(
+                        &output.pixels.context().queue,
+                        position.0 - output.cursor_position.0,
+                        position.1 - output.cursor_position.1,
+                    );
+                }
+                output.cursor_position = position;
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Named(NamedKey::Home),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                output.renderer.reset_view(&output.pixels.context().queue);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Named(NamedKey::F12),
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => {
+                output.screenshot(
+                    self.settings.read().snapshot_directory.clone(),
+                    self.webhooks.clone(),
+                );
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key,
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if logical_key == Key::Named(NamedKey::Pause)
+                || logical_key.as_ref() == Key::Character("p") =>
+            {
+                let paused = !output.canvas.is_paused();
+                output.canvas.set_paused(paused);
+                log::info!(
+                    "canvas {} {}",
+                    output.index,
+                    if paused { "paused" } else { "resumed" }
+                );
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key,
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if logical_key.as_ref() == Key::Character("t") => {
+                let canvas = &output.canvas;
+                canvas.draw_image(&testcard::render(canvas.width, canvas.height), 0, 0);
+                log::info!("test card drawn on canvas {}", output.index);
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key,
+                        state: ElementState::Pressed,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } if logical_key.as_ref() == Key::Character("h") => {
+                output.toggle_history(self.settings.read().history_directory.as_deref());
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Named(key),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if output.history.is_some()
+                && matches!(
+                    key,
+                    NamedKey::ArrowLeft
+                        | NamedKey::ArrowRight
+                        | NamedKey::PageUp
+                        | NamedKey::PageDown
+                ) =>
+            {
+                let keyframes = match key {
+                    NamedKey::ArrowLeft => -1,
+                    NamedKey::ArrowRight => 1,
+                    NamedKey::PageUp => -HISTORY_PAGE,
+                    _ => HISTORY_PAGE,
+                };
+                if let Some(history) = output.history.as_mut() {
+                    history.step(keyframes);
+                }
             }
             WindowEvent::RedrawRequested => {
-                self.canvas.as_mut().unwrap().set_queue_pixels();
-                if let Err(err) = self.pixels.as_ref().unwrap().read().render() {
+                if let Err(err) = output.redraw(
+                    &self.settings.read(),
+                    self.watermark.as_ref(),
+                    self.attract_content.as_ref(),
+                ) {
                     error!("pixels.render: {}", err);
                     event_loop.exit();
                 }
+                self.liveness.frame_rendered();
             }
             _ => (),
         }